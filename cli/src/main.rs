@@ -11,6 +11,7 @@ use std::io::prelude::*;
 
 #[derive(Serialize, Deserialize)]
 struct Session {
+    #[serde(serialize_with = "serialize_atlas", deserialize_with = "deserialize_atlas")]
     atlas: AtlasAllocator,
     names: std::collections::HashMap<String, AllocId>,
     next_id: u32,
@@ -73,6 +74,61 @@ fn main() {
                 .takes_value(true)
                 .required(false)
             )
+            .arg(Arg::with_name("PADDING")
+                .long("padding")
+                .help("Number of pixels added on each side of the allocated rectangles.")
+                .value_name("PADDING")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("ALLOW_ROTATION")
+                .long("allow-rotation")
+                .help("Allow rotating the allocated rectangles by 90 degrees.")
+                .required(false)
+            )
+            .arg(Arg::with_name("FIT_POLICY")
+                .long("fit-policy")
+                .help("Heuristic used to select free rectangles (adaptive, worst-fit, best-area-fit, best-short-side-fit or first-fit).")
+                .value_name("FIT_POLICY")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("SPLIT_POLICY")
+                .long("split-policy")
+                .help("Rule used to split free rectangles (shorter-leftover-axis, longer-leftover-axis, minimize-area or maximize-area).")
+                .value_name("SPLIT_POLICY")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("MIN_FREE_SIZE")
+                .long("min-free-size")
+                .help("Free rectangles thinner than this value are not tracked.")
+                .value_name("MIN_FREE_SIZE")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("NEXT_FIT")
+                .long("next-fit")
+                .help("Try the free space next to the previous allocation first.")
+                .required(false)
+            )
+            .arg(Arg::with_name("PLACEMENT_BIAS")
+                .long("placement-bias")
+                .help("Preferred position among equally good free rectangles (none, top-left, top or left).")
+                .value_name("PLACEMENT_BIAS")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("POWER_OF_TWO_SIZES")
+                .long("power-of-two-sizes")
+                .help("Round the allocated sizes up to powers of two.")
+                .required(false)
+            )
+            .arg(Arg::with_name("DEFERRED_COALESCING")
+                .long("deferred-coalescing")
+                .help("Only merge free rectangles when an allocation fails.")
+                .required(false)
+            )
             .arg(Arg::with_name("ATLAS")
                 .short("a")
                 .long("atlas")
@@ -235,6 +291,26 @@ fn main() {
                 .takes_value(true)
                 .required(false)
             )
+            .arg(Arg::with_name("LABELS")
+                .long("labels")
+                .help("Write the id and size of each allocation.")
+                .required(false)
+            )
+            .arg(Arg::with_name("SCALE")
+                .long("scale")
+                .help("Scale the output.")
+                .value_name("SCALE")
+                .takes_value(true)
+                .required(false)
+            )
+            .arg(Arg::with_name("HIGHLIGHT")
+                .long("highlight")
+                .help("Highlight the allocation with the provided name.")
+                .value_name("NAME")
+                .takes_value(true)
+                .multiple(true)
+                .required(false)
+            )
         )
         .subcommand(
             SubCommand::with_name("list")
@@ -250,22 +326,33 @@ fn main() {
         .get_matches();
 
     if let Some(cmd) = matches.subcommand_matches("init") {
-        init(&cmd);
+        init(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("allocate") {
-        allocate(&cmd);
+        allocate(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("deallocate") {
-        deallocate(&cmd);
+        deallocate(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("rearrange") {
-        rearrange(&cmd);
+        rearrange(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("grow") {
-        grow(&cmd);
+        grow(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("svg") {
-        svg(&cmd);
+        svg(cmd);
     } else if let Some(cmd) = matches.subcommand_matches("list") {
-        list(&cmd);
+        list(cmd);
     }
 }
 
+// The atlas is stored with the version of its format so that files written by older
+// versions of the crate can still be read.
+fn serialize_atlas<S: serde::Serializer>(atlas: &AtlasAllocator, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&VersionedAtlas::new(atlas.clone()), serializer)
+}
+
+fn deserialize_atlas<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<AtlasAllocator, D::Error> {
+    let versioned: VersionedAtlas = serde::Deserialize::deserialize(deserializer)?;
+    versioned.into_atlas().map_err(serde::de::Error::custom)
+}
+
 fn read_atlas(args: &ArgMatches) -> Session {
     let atlas_file_name = args.value_of("ATLAS").unwrap_or("atlas.ron");
     let file = OpenOptions::new()
@@ -323,6 +410,49 @@ fn init(args: &ArgMatches) {
             .value_of("LARGE")
             .map(|s| s.parse::<i32>().unwrap())
             .unwrap_or(default_options.large_size_threshold),
+        padding: args
+            .value_of("PADDING")
+            .map(|s| s.parse::<i32>().unwrap())
+            .unwrap_or(default_options.padding),
+        allow_rotation: args.is_present("ALLOW_ROTATION") || default_options.allow_rotation,
+        fit_policy: args
+            .value_of("FIT_POLICY")
+            .map(|s| match s {
+                "adaptive" => guillotiere::FitPolicy::Adaptive,
+                "worst-fit" => guillotiere::FitPolicy::WorstFit,
+                "best-area-fit" => guillotiere::FitPolicy::BestAreaFit,
+                "best-short-side-fit" => guillotiere::FitPolicy::BestShortSideFit,
+                "first-fit" => guillotiere::FitPolicy::FirstFit,
+                _ => panic!("Unknown fit policy {:?}", s),
+            })
+            .unwrap_or(default_options.fit_policy),
+        split_policy: args
+            .value_of("SPLIT_POLICY")
+            .map(|s| match s {
+                "shorter-leftover-axis" => guillotiere::SplitPolicy::ShorterLeftoverAxis,
+                "longer-leftover-axis" => guillotiere::SplitPolicy::LongerLeftoverAxis,
+                "minimize-area" => guillotiere::SplitPolicy::MinimizeArea,
+                "maximize-area" => guillotiere::SplitPolicy::MaximizeArea,
+                _ => panic!("Unknown split policy {:?}", s),
+            })
+            .unwrap_or(default_options.split_policy),
+        deferred_coalescing: args.is_present("DEFERRED_COALESCING") || default_options.deferred_coalescing,
+        min_free_size: args
+            .value_of("MIN_FREE_SIZE")
+            .map(|s| s.parse::<i32>().unwrap())
+            .unwrap_or(default_options.min_free_size),
+        next_fit: args.is_present("NEXT_FIT") || default_options.next_fit,
+        placement_bias: args
+            .value_of("PLACEMENT_BIAS")
+            .map(|s| match s {
+                "none" => guillotiere::PlacementBias::None,
+                "top-left" => guillotiere::PlacementBias::TopLeft,
+                "top" => guillotiere::PlacementBias::Top,
+                "left" => guillotiere::PlacementBias::Left,
+                _ => panic!("Unknown placement bias {:?}", s),
+            })
+            .unwrap_or(default_options.placement_bias),
+        power_of_two_sizes: args.is_present("POWER_OF_TWO_SIZES") || default_options.power_of_two_sizes,
     };
 
     let session = Session {
@@ -331,7 +461,7 @@ fn init(args: &ArgMatches) {
         next_id: 0,
     };
 
-    write_atlas(&session, &args);
+    write_atlas(&session, args);
 
     if args.is_present("SVG_OUTPUT") {
         svg(args);
@@ -469,7 +599,17 @@ fn svg(args: &ArgMatches) {
     let svg_file_name = args.value_of("SVG_OUTPUT").unwrap_or("atlas.svg");
     let mut svg_file = File::create(svg_file_name).expect("Failed to open the SVG file.");
 
-    guillotiere::dump_svg(&session.atlas, &mut svg_file)
+    let options = guillotiere::SvgOptions {
+        labels: args.is_present("LABELS"),
+        scale: args.value_of("SCALE").map(|s| s.parse::<f32>().unwrap()).unwrap_or(1.0),
+        highlighted: args
+            .values_of("HIGHLIGHT")
+            .map(|names| names.map(|name| session.names[name]).collect())
+            .unwrap_or_default(),
+        ..guillotiere::SvgOptions::default()
+    };
+
+    guillotiere::dump_svg_with_options(&session.atlas, &options, &mut svg_file)
         .expect("Failed to write into the SVG file.");
 }
 
@@ -1,21 +1,41 @@
 //! C-compatible foreign function interface for guillotiere, that can be easily fed to cbindgen.
 
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
 use guillotiere::*;
-use std::mem::transmute;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
 use guillotiere::AtlasAllocator as guillotiere_atlas_allocator_t;
-use guillotiere::ChangeList as guillotiere_change_list_t;
+use guillotiere::Axis as guillotiere_axis_t;
+use guillotiere::FitPolicy as guillotiere_fit_policy_t;
+use guillotiere::PlacementBias as guillotiere_placement_bias_t;
+use guillotiere::SplitPolicy as guillotiere_split_policy_t;
 use guillotiere::SimpleAtlasAllocator as guillotiere_simple_atlas_allocator_t;
 
+#[cfg(feature = "locked")]
+mod locked;
+#[cfg(feature = "locked")]
+pub use crate::locked::*;
+
 #[repr(C)]
-#[no_mangle]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct guillotiere_size_t {
     pub width: i32,
     pub height: i32,
 }
 
 #[repr(C)]
-#[no_mangle]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct guillotiere_point_t {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct guillotiere_rectangle_t {
     pub min_x: i32,
     pub min_y: i32,
@@ -24,46 +44,237 @@ pub struct guillotiere_rectangle_t {
 }
 
 #[repr(C)]
-#[no_mangle]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct guillotiere_change_t {
     pub old_alloc: guillotiere_allocation_t,
     pub new_alloc: guillotiere_allocation_t,
 }
 
 #[repr(C)]
-#[no_mangle]
 pub struct guillotiere_changes_t {
     pub changes: *const guillotiere_change_t,
     pub count: usize,
 }
 
 #[repr(C)]
-#[no_mangle]
 pub struct guillotiere_failures_t {
     pub failures: *const guillotiere_allocation_t,
     pub count: usize,
 }
 
+/// Allocation ids are 32 bits wide, or 64 bits wide if the `wide_ids` feature of guillotiere
+/// is enabled, in which case `GUILLOTIERE_WIDE_IDS` must be defined before including the header.
 #[repr(C)]
-#[no_mangle]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct guillotiere_alloc_id_t {
-    id: u32,
+    id: AllocIdRepr,
 }
 
 #[repr(C)]
-#[no_mangle]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct guillotiere_allocation_t {
     pub id: guillotiere_alloc_id_t,
     pub rectangle: guillotiere_rectangle_t,
+    pub content: guillotiere_rectangle_t,
+    pub rotated: bool,
+}
+
+/// The changes of a rearrange, see `guillotiere_atlas_allocator_rearrange`.
+///
+/// The changes and failures are stored in their C representation so that they can be
+/// handed out as arrays.
+pub struct guillotiere_change_list_t {
+    changes: Vec<guillotiere_change_t>,
+    failures: Vec<guillotiere_allocation_t>,
+}
+
+impl From<ChangeList> for guillotiere_change_list_t {
+    fn from(change_list: ChangeList) -> Self {
+        guillotiere_change_list_t {
+            changes: change_list.changes.iter().map(|change| (*change).into()).collect(),
+            failures: change_list.failures.iter().map(|failure| (*failure).into()).collect(),
+        }
+    }
+}
+
+impl From<Size> for guillotiere_size_t {
+    fn from(size: Size) -> Self {
+        guillotiere_size_t {
+            width: size.width,
+            height: size.height,
+        }
+    }
+}
+
+impl From<guillotiere_size_t> for Size {
+    fn from(size: guillotiere_size_t) -> Self {
+        size2(size.width, size.height)
+    }
+}
+
+impl From<Point> for guillotiere_point_t {
+    fn from(point: Point) -> Self {
+        guillotiere_point_t { x: point.x, y: point.y }
+    }
+}
+
+impl From<guillotiere_point_t> for Point {
+    fn from(point: guillotiere_point_t) -> Self {
+        point2(point.x, point.y)
+    }
+}
+
+impl From<guillotiere_rectangle_t> for Rectangle {
+    fn from(rect: guillotiere_rectangle_t) -> Self {
+        Rectangle {
+            min: point2(rect.min_x, rect.min_y),
+            max: point2(rect.max_x, rect.max_y),
+        }
+    }
 }
 
+impl From<Rectangle> for guillotiere_rectangle_t {
+    fn from(rect: Rectangle) -> Self {
+        guillotiere_rectangle_t {
+            min_x: rect.min.x,
+            min_y: rect.min.y,
+            max_x: rect.max.x,
+            max_y: rect.max.y,
+        }
+    }
+}
+
+impl From<AllocId> for guillotiere_alloc_id_t {
+    fn from(id: AllocId) -> Self {
+        guillotiere_alloc_id_t {
+            id: id.serialize(),
+        }
+    }
+}
+
+impl From<guillotiere_alloc_id_t> for AllocId {
+    fn from(id: guillotiere_alloc_id_t) -> Self {
+        AllocId::deserialize(id.id)
+    }
+}
+
+impl From<Allocation> for guillotiere_allocation_t {
+    fn from(alloc: Allocation) -> Self {
+        guillotiere_allocation_t {
+            id: alloc.id.into(),
+            rectangle: alloc.rectangle.into(),
+            content: alloc.content.into(),
+            rotated: alloc.rotated,
+        }
+    }
+}
+
+impl From<Change> for guillotiere_change_t {
+    fn from(change: Change) -> Self {
+        guillotiere_change_t {
+            old_alloc: change.old.into(),
+            new_alloc: change.new.into(),
+        }
+    }
+}
+
+// The layout of the C structures is part of the ABI.
+const _: () = assert!(std::mem::size_of::<guillotiere_size_t>() == 8);
+const _: () = assert!(std::mem::size_of::<guillotiere_rectangle_t>() == 16);
+const ID_SIZE: usize = std::mem::size_of::<AllocIdRepr>();
+const _: () = assert!(std::mem::size_of::<guillotiere_alloc_id_t>() == ID_SIZE);
+const _: () = assert!(std::mem::size_of::<guillotiere_allocation_t>() == if ID_SIZE == 8 { 48 } else { 40 });
+const _: () = assert!(std::mem::size_of::<guillotiere_change_t>() == 2 * std::mem::size_of::<guillotiere_allocation_t>());
+
+/// The result of the functions that don't otherwise report failures.
+///
+/// When a function fails, `guillotiere_last_error_message` describes the error.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum guillotiere_status_t {
+    Ok = 0,
+    /// A size is empty or negative, or smaller than the current size when growing or when
+    /// resizing an atlas that has pinned allocations or reserved regions.
+    InvalidSize = 1,
+    /// The allocation id is not valid in this atlas.
+    InvalidId = 2,
+    /// A pointer is null or the allocator options are invalid.
+    InvalidArgument = 3,
+    /// An unexpected error. The object that was being modified may be in an inconsistent
+    /// state and should be deleted.
+    Panic = 4,
+    /// An output array is too small. Nothing was modified.
+    BufferTooSmall = 5,
+    /// The internal data structure of the atlas is corrupted.
+    ValidationFailed = 6,
+    /// Writing the output failed.
+    IoError = 7,
+}
+
+/// See `guillotiere_atlas_allocator_stats`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct guillotiere_allocator_stats_t {
+    pub free_rect_count: usize,
+    pub largest_free_rect: guillotiere_size_t,
+    pub mean_free_rect_area: f32,
+    pub median_free_rect_area: i64,
+    pub p90_free_rect_area: i64,
+    pub fragmentation: f32,
+}
+
+/// Invoked for each allocated rectangle, see `guillotiere_atlas_allocator_for_each_allocated_rectangle`.
+pub type guillotiere_allocated_rectangle_callback_t =
+    extern "C" fn(user_data: *mut c_void, id: guillotiere_alloc_id_t, rectangle: guillotiere_rectangle_t);
+
+/// Invoked for each free rectangle, see `guillotiere_atlas_allocator_for_each_free_rectangle`.
+pub type guillotiere_free_rectangle_callback_t =
+    extern "C" fn(user_data: *mut c_void, rectangle: guillotiere_rectangle_t);
+
+/// Invoked for each allocation of a query, see `guillotiere_atlas_allocator_allocations_in`.
+pub type guillotiere_alloc_id_callback_t = extern "C" fn(user_data: *mut c_void, id: guillotiere_alloc_id_t);
+
+/// Invoked for each rectangle of a list, see `guillotiere_atlas_allocator_grow_copy_plan`.
+pub type guillotiere_rectangle_callback_t =
+    extern "C" fn(user_data: *mut c_void, rectangle: guillotiere_rectangle_t);
+
+/// Receives a chunk of an SVG dump, see `guillotiere_atlas_allocator_dump_svg_with_callback`.
+pub type guillotiere_write_callback_t =
+    extern "C" fn(user_data: *mut c_void, data: *const c_char, len: usize);
+
 #[repr(C)]
-#[no_mangle]
 pub struct guillotiere_allocator_options_t {
     pub width_alignment: i32,
     pub height_alignment: i32,
     pub small_size_threshold: i32,
     pub large_size_threshold: i32,
+    pub padding: i32,
+    pub allow_rotation: bool,
+    pub fit_policy: guillotiere_fit_policy_t,
+    pub split_policy: guillotiere_split_policy_t,
+    pub deferred_coalescing: bool,
+    pub min_free_size: i32,
+    pub next_fit: bool,
+    pub placement_bias: guillotiere_placement_bias_t,
+    pub power_of_two_sizes: bool,
+}
+
+fn to_ffi_options(options: &AllocatorOptions) -> guillotiere_allocator_options_t {
+    guillotiere_allocator_options_t {
+        width_alignment: options.alignment.width,
+        height_alignment: options.alignment.height,
+        small_size_threshold: options.small_size_threshold,
+        large_size_threshold: options.large_size_threshold,
+        padding: options.padding,
+        allow_rotation: options.allow_rotation,
+        fit_policy: options.fit_policy,
+        split_policy: options.split_policy,
+        deferred_coalescing: options.deferred_coalescing,
+        min_free_size: options.min_free_size,
+        next_fit: options.next_fit,
+        placement_bias: options.placement_bias,
+        power_of_two_sizes: options.power_of_two_sizes,
+    }
 }
 
 fn from_ffi_options(options: &guillotiere_allocator_options_t) -> AllocatorOptions {
@@ -71,40 +282,190 @@ fn from_ffi_options(options: &guillotiere_allocator_options_t) -> AllocatorOptio
         alignment: size2(options.width_alignment, options.height_alignment),
         small_size_threshold: options.small_size_threshold,
         large_size_threshold: options.large_size_threshold,
+        padding: options.padding,
+        allow_rotation: options.allow_rotation,
+        fit_policy: options.fit_policy,
+        split_policy: options.split_policy,
+        deferred_coalescing: options.deferred_coalescing,
+        min_free_size: options.min_free_size,
+        next_fit: options.next_fit,
+        placement_bias: options.placement_bias,
+        power_of_two_sizes: options.power_of_two_sizes,
+    }
+}
+
+// The allocators assert that the options are valid, so they are checked before reaching them.
+fn check_options(options: &guillotiere_allocator_options_t) -> Result<AllocatorOptions, Error> {
+    let options = from_ffi_options(options);
+    if !options.is_valid() {
+        return Err(Error::new(guillotiere_status_t::InvalidArgument, "invalid allocator options"));
+    }
+
+    Ok(options)
+}
+
+struct Error {
+    status: guillotiere_status_t,
+    message: String,
+}
+
+impl Error {
+    fn new(status: guillotiere_status_t, message: impl Into<String>) -> Self {
+        Error {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: Option<String>) {
+    let message = message.map(|message| CString::new(message.replace('\0', "")).unwrap());
+    LAST_ERROR.with(|error| *error.borrow_mut() = message);
+}
+
+// Run the body of an exported function, making sure that panics don't unwind into the host
+// and recording the error message.
+fn guard<T>(body: impl FnOnce() -> Result<T, Error>) -> Result<T, guillotiere_status_t> {
+    set_last_error(None);
+    let error = match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(error)) => error,
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "unknown panic".to_string()
+            };
+            Error::new(guillotiere_status_t::Panic, message)
+        }
+    };
+
+    set_last_error(Some(error.message));
+
+    Err(error.status)
+}
+
+fn status(result: Result<(), guillotiere_status_t>) -> guillotiere_status_t {
+    result.err().unwrap_or(guillotiere_status_t::Ok)
+}
+
+fn check_size(size: &guillotiere_size_t) -> Result<Size, Error> {
+    if size.width <= 0 || size.height <= 0 {
+        return Err(Error::new(
+            guillotiere_status_t::InvalidSize,
+            format!("invalid size {}x{}", size.width, size.height),
+        ));
+    }
+
+    Ok(size2(size.width, size.height))
+}
+
+fn check_growth(old_size: Size, new_size: &guillotiere_size_t) -> Result<Size, Error> {
+    let new_size = check_size(new_size)?;
+    if new_size.width < old_size.width || new_size.height < old_size.height {
+        return Err(Error::new(
+            guillotiere_status_t::InvalidSize,
+            format!("can't grow from {}x{} to {}x{}", old_size.width, old_size.height, new_size.width, new_size.height),
+        ));
+    }
+
+    Ok(new_size)
+}
+
+fn check_id(atlas: &AtlasAllocator, id: guillotiere_alloc_id_t) -> Result<AllocId, Error> {
+    let id: AllocId = id.into();
+    if !atlas.is_valid(id) {
+        return Err(Error::new(
+            guillotiere_status_t::InvalidId,
+            format!("invalid allocation id {}", id.serialize()),
+        ));
+    }
+
+    Ok(id)
+}
+
+unsafe fn check_slice<'l, T>(data: *const T, count: usize) -> Result<&'l [T], Error> {
+    if count == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(Error::new(guillotiere_status_t::InvalidArgument, "null array"));
     }
+
+    Ok(std::slice::from_raw_parts(data, count))
+}
+
+/// The message of the last error of a function called on this thread, or null if the last
+/// function that was called succeeded.
+///
+/// The message is valid until the next function is called on this thread.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|error| match *error.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None => std::ptr::null(),
+    })
 }
 
+/// Returns null if the size is invalid.
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_new(
     size: guillotiere_size_t,
 ) -> *mut guillotiere_atlas_allocator_t {
-    Box::into_raw(Box::new(AtlasAllocator::new(transmute(size))))
+    guard(|| Ok(Box::into_raw(Box::new(AtlasAllocator::new(check_size(&size)?)))))
+        .unwrap_or(std::ptr::null_mut())
 }
 
+/// Returns null if the size or the options are invalid.
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_with_options(
     size: guillotiere_size_t,
     options: &guillotiere_allocator_options_t,
 ) -> *mut guillotiere_atlas_allocator_t {
-    let options = from_ffi_options(options);
-    Box::into_raw(Box::new(AtlasAllocator::with_options(
-        transmute(size),
-        &options,
-    )))
+    guard(|| {
+        let options = check_options(options)?;
+        Ok(Box::into_raw(Box::new(AtlasAllocator::with_options(
+            check_size(&size)?,
+            &options,
+        ))))
+    })
+    .unwrap_or(std::ptr::null_mut())
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_delete(
     atlas: *mut guillotiere_atlas_allocator_t,
 ) {
-    Box::from_raw(atlas);
+    if !atlas.is_null() {
+        drop(Box::from_raw(atlas));
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_clear(
     atlas: &mut guillotiere_atlas_allocator_t,
-) {
-    atlas.clear();
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.clear();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_clear_with_size(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    size: guillotiere_size_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.clear_with_size(check_size(&size)?);
+        Ok(())
+    }))
 }
 
 #[no_mangle]
@@ -112,16 +473,19 @@ pub unsafe extern "C" fn guillotiere_atlas_allocator_reset(
     atlas: &mut guillotiere_atlas_allocator_t,
     size: guillotiere_size_t,
     options: &guillotiere_allocator_options_t,
-) {
-    let options = from_ffi_options(options);
-    atlas.reset(transmute(size), &options);
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let options = check_options(options)?;
+        atlas.reset(check_size(&size)?, &options);
+        Ok(())
+    }))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_size(
     atlas: &guillotiere_atlas_allocator_t,
 ) -> guillotiere_size_t {
-    transmute(atlas.size())
+    atlas.size().into()
 }
 
 #[no_mangle]
@@ -131,42 +495,66 @@ pub unsafe extern "C" fn guillotiere_atlas_allocator_is_empty(
     atlas.is_empty()
 }
 
+/// Returns false if the allocation fails or if the size is invalid, in which case
+/// `guillotiere_last_error_message` is not null.
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate(
     atlas: &mut guillotiere_atlas_allocator_t,
     size: guillotiere_size_t,
     result: &mut guillotiere_allocation_t,
 ) -> bool {
-    if let Some(alloc) = atlas.allocate(transmute(size)) {
-        *result = transmute(alloc);
-        return true;
-    }
+    guard(|| {
+        if let Some(alloc) = atlas.allocate(check_size(&size)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
 
-    false
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_deallocate(
     atlas: &mut guillotiere_atlas_allocator_t,
     id: guillotiere_alloc_id_t,
-) {
-    atlas.deallocate(transmute(id));
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.deallocate(check_id(atlas, id)?);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_coalesce(
+    atlas: &mut guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.coalesce();
+        Ok(())
+    }))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_grow(
     atlas: &mut guillotiere_atlas_allocator_t,
     new_size: guillotiere_size_t,
-) {
-    atlas.grow(transmute(new_size));
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.grow(check_growth(atlas.size(), &new_size)?);
+        Ok(())
+    }))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn guillotiere_atlas_allocator_rearrange(
     atlas: &mut guillotiere_atlas_allocator_t,
     change_list: &mut guillotiere_change_list_t,
-) {
-    std::mem::swap(change_list, &mut atlas.rearrange());
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = atlas.rearrange().into();
+        Ok(())
+    }))
 }
 
 #[no_mangle]
@@ -174,148 +562,1568 @@ pub unsafe extern "C" fn guillotiere_atlas_allocator_resize_and_rearrange(
     atlas: &mut guillotiere_atlas_allocator_t,
     new_size: guillotiere_size_t,
     change_list: &mut guillotiere_change_list_t,
-) {
-    std::mem::swap(
-        change_list,
-        &mut atlas.resize_and_rearrange(transmute(new_size)),
-    );
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = try_resize_and_rearrange(atlas, check_size(&new_size)?)?.into();
+        Ok(())
+    }))
 }
 
+/// Rearrange the atlas, writing the changes and failures into arrays owned by the caller.
+///
+/// Each array must have room for one element per allocation of the atlas. Otherwise the
+/// atlas is not rearranged, both counts receive the required capacity and the function
+/// returns `BufferTooSmall`, so that it can be called a first time with null arrays to size
+/// them.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_change_list_new() -> *mut guillotiere_change_list_t {
-    Box::into_raw(Box::new(ChangeList {
-        changes: Vec::new(),
-        failures: Vec::new(),
+pub unsafe extern "C" fn guillotiere_atlas_allocator_rearrange_into(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    out_changes: *mut guillotiere_change_t,
+    changes_capacity: usize,
+    out_changes_count: &mut usize,
+    out_failures: *mut guillotiere_allocation_t,
+    failures_capacity: usize,
+    out_failures_count: &mut usize,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let mut outputs = ChangeListOutputs {
+            changes: out_changes,
+            changes_capacity,
+            changes_count: out_changes_count,
+            failures: out_failures,
+            failures_capacity,
+            failures_count: out_failures_count,
+        };
+        outputs.check_capacity(atlas.allocation_count())?;
+        outputs.write(&atlas.rearrange());
+        Ok(())
     }))
 }
 
+/// Identical to `guillotiere_atlas_allocator_rearrange_into`, also changing the size of the
+/// atlas.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_change_list_delete(
-    change_list: *mut guillotiere_change_list_t,
-) {
-    Box::from_raw(change_list);
+pub unsafe extern "C" fn guillotiere_atlas_allocator_resize_and_rearrange_into(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    new_size: guillotiere_size_t,
+    out_changes: *mut guillotiere_change_t,
+    changes_capacity: usize,
+    out_changes_count: &mut usize,
+    out_failures: *mut guillotiere_allocation_t,
+    failures_capacity: usize,
+    out_failures_count: &mut usize,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let new_size = check_size(&new_size)?;
+        let mut outputs = ChangeListOutputs {
+            changes: out_changes,
+            changes_capacity,
+            changes_count: out_changes_count,
+            failures: out_failures,
+            failures_capacity,
+            failures_count: out_failures_count,
+        };
+        outputs.check_capacity(atlas.allocation_count())?;
+        outputs.write(&try_resize_and_rearrange(atlas, new_size)?);
+        Ok(())
+    }))
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn guillotiere_change_list_changes(
-    change_list: &guillotiere_change_list_t,
-) -> guillotiere_changes_t {
-    guillotiere_changes_t {
-        changes: transmute(change_list.changes.as_ptr()),
-        count: change_list.changes.len(),
-    }
+// Shrinking an atlas that has pinned allocations or reserved regions is refused without
+// modifying it.
+fn try_resize_and_rearrange(atlas: &mut AtlasAllocator, new_size: Size) -> Result<ChangeList, Error> {
+    atlas
+        .try_resize_and_rearrange(new_size)
+        .map_err(|error| Error::new(guillotiere_status_t::InvalidSize, error.to_string()))
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn guillotiere_change_list_failures(
-    change_list: &guillotiere_change_list_t,
-) -> guillotiere_failures_t {
-    guillotiere_failures_t {
-        failures: transmute(change_list.failures.as_ptr()),
-        count: change_list.failures.len(),
+struct ChangeListOutputs<'l> {
+    changes: *mut guillotiere_change_t,
+    changes_capacity: usize,
+    changes_count: &'l mut usize,
+    failures: *mut guillotiere_allocation_t,
+    failures_capacity: usize,
+    failures_count: &'l mut usize,
+}
+
+impl ChangeListOutputs<'_> {
+    // A rearrange produces at most one change or failure per allocation.
+    fn check_capacity(&mut self, allocation_count: usize) -> Result<(), Error> {
+        let too_small = |array: bool, capacity: usize| allocation_count > 0 && (!array || capacity < allocation_count);
+        if too_small(!self.changes.is_null(), self.changes_capacity)
+            || too_small(!self.failures.is_null(), self.failures_capacity)
+        {
+            *self.changes_count = allocation_count;
+            *self.failures_count = allocation_count;
+            return Err(Error::new(
+                guillotiere_status_t::BufferTooSmall,
+                format!("the change arrays must have room for {} elements", allocation_count),
+            ));
+        }
+
+        Ok(())
+    }
+
+    unsafe fn write(self, change_list: &ChangeList) {
+        for (i, change) in change_list.changes.iter().enumerate() {
+            *self.changes.add(i) = (*change).into();
+        }
+        for (i, failure) in change_list.failures.iter().enumerate() {
+            *self.failures.add(i) = (*failure).into();
+        }
+        *self.changes_count = change_list.changes.len();
+        *self.failures_count = change_list.failures.len();
     }
 }
 
+/// Invoke a callback for each allocated rectangle of the atlas.
+///
+/// `user_data` is passed to the callback as is.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_new(
-    size: guillotiere_size_t,
-) -> *mut guillotiere_simple_atlas_allocator_t {
-    Box::into_raw(Box::new(SimpleAtlasAllocator::new(transmute(size))))
+pub unsafe extern "C" fn guillotiere_atlas_allocator_for_each_allocated_rectangle(
+    atlas: &guillotiere_atlas_allocator_t,
+    callback: guillotiere_allocated_rectangle_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.for_each_allocated_rectangle(|id, rect| {
+            callback(user_data, id.into(), (*rect).into());
+        });
+        Ok(())
+    }))
 }
 
+/// Invoke a callback for each free rectangle of the atlas.
+///
+/// `user_data` is passed to the callback as is.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_with_options(
-    size: guillotiere_size_t,
-    options: &guillotiere_allocator_options_t,
-) -> *mut guillotiere_simple_atlas_allocator_t {
-    let options = from_ffi_options(options);
-    Box::into_raw(Box::new(SimpleAtlasAllocator::with_options(
-        transmute(size),
-        &options,
-    )))
+pub unsafe extern "C" fn guillotiere_atlas_allocator_for_each_free_rectangle(
+    atlas: &guillotiere_atlas_allocator_t,
+    callback: guillotiere_free_rectangle_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.for_each_free_rectangle(|rect| {
+            callback(user_data, (*rect).into());
+        });
+        Ok(())
+    }))
 }
 
+/// Write an SVG visualization of the atlas into a buffer.
+///
+/// `written` receives the size of the whole dump in bytes, which may be larger than the
+/// buffer. If the buffer is too small nothing is written and the function returns
+/// `BufferTooSmall`, so that it can be called a first time with a null buffer to size it.
+/// The dump is not null terminated.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_delete(
-    atlas: *mut guillotiere_simple_atlas_allocator_t,
-) {
-    Box::from_raw(atlas);
+pub unsafe extern "C" fn guillotiere_atlas_allocator_dump_svg(
+    atlas: &guillotiere_atlas_allocator_t,
+    buffer: *mut c_char,
+    capacity: usize,
+    written: &mut usize,
+) -> guillotiere_status_t {
+    *written = 0;
+    status(guard(|| {
+        let mut svg = Vec::new();
+        dump_svg(atlas, &mut svg)
+            .map_err(|error| Error::new(guillotiere_status_t::IoError, error.to_string()))?;
+
+        *written = svg.len();
+        if buffer.is_null() || svg.len() > capacity {
+            return Err(Error::new(
+                guillotiere_status_t::BufferTooSmall,
+                format!("the buffer must have room for {} bytes", svg.len()),
+            ));
+        }
+
+        std::ptr::copy_nonoverlapping(svg.as_ptr(), buffer as *mut u8, svg.len());
+
+        Ok(())
+    }))
 }
 
+/// Write an SVG visualization of the atlas through a callback, in several chunks.
+///
+/// `user_data` is passed to the callback as is.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_clear(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
-) {
-    atlas.clear();
+pub unsafe extern "C" fn guillotiere_atlas_allocator_dump_svg_with_callback(
+    atlas: &guillotiere_atlas_allocator_t,
+    callback: guillotiere_write_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let mut output = CallbackWriter { callback, user_data };
+        // The callback can't fail.
+        let _ = dump_svg(atlas, &mut output);
+        Ok(())
+    }))
+}
+
+struct CallbackWriter {
+    callback: guillotiere_write_callback_t,
+    user_data: *mut c_void,
+}
+
+impl std::io::Write for CallbackWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        (self.callback)(self.user_data, data.as_ptr() as *const c_char, data.len());
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
 }
 
+/// Create an atlas from a list of allocations and their rectangles, keeping their ids.
+///
+/// Returns null if the allocations can't be restored, see `AtlasAllocator::from_allocations`.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_reset(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
+pub unsafe extern "C" fn guillotiere_atlas_allocator_from_allocations(
     size: guillotiere_size_t,
+    ids: *const guillotiere_alloc_id_t,
+    rectangles: *const guillotiere_rectangle_t,
+    count: usize,
     options: &guillotiere_allocator_options_t,
+) -> *mut guillotiere_atlas_allocator_t {
+    guard(|| {
+        let size = check_size(&size)?;
+        let allocations: Vec<(AllocId, Rectangle)> = check_slice(ids, count)?
+            .iter()
+            .zip(check_slice(rectangles, count)?)
+            .map(|(id, rect)| ((*id).into(), (*rect).into()))
+            .collect();
+        let atlas = AtlasAllocator::from_allocations(size, &allocations, &check_options(options)?)
+            .ok_or_else(|| Error::new(guillotiere_status_t::InvalidArgument, "invalid allocations"))?;
+
+        Ok(Box::into_raw(Box::new(atlas)))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Create an atlas from the rectangles allocated with a simple atlas allocator.
+///
+/// `out_ids` must have room for `count` ids, which are written in the same order as the
+/// rectangles. Returns null if the rectangles can't be restored, see
+/// `AtlasAllocator::from_simple`.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_from_simple(
+    simple: &guillotiere_simple_atlas_allocator_t,
+    rectangles: *const guillotiere_rectangle_t,
+    count: usize,
+    out_ids: *mut guillotiere_alloc_id_t,
+) -> *mut guillotiere_atlas_allocator_t {
+    guard(|| {
+        let rects: Vec<Rectangle> = check_slice(rectangles, count)?.iter().map(|rect| (*rect).into()).collect();
+        if count > 0 && out_ids.is_null() {
+            return Err(Error::new(guillotiere_status_t::InvalidArgument, "null id array"));
+        }
+        let (atlas, ids) = AtlasAllocator::from_simple(simple, &rects)
+            .ok_or_else(|| Error::new(guillotiere_status_t::InvalidArgument, "invalid rectangles"))?;
+        for (i, id) in ids.into_iter().enumerate() {
+            *out_ids.add(i) = id.into();
+        }
+
+        Ok(Box::into_raw(Box::new(atlas)))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_options(
+    atlas: &guillotiere_atlas_allocator_t,
+    options: &mut guillotiere_allocator_options_t,
 ) {
-    let options = from_ffi_options(options);
-    atlas.reset(transmute(size), &options);
+    *options = to_ffi_options(&atlas.options());
 }
 
+/// Allocate the best fitting of several sizes, see `AtlasAllocator::allocate_best_of`.
+///
+/// `out_index` receives the index of the allocated size.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_is_empty(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_best_of(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    sizes: *const guillotiere_size_t,
+    count: usize,
+    out_index: &mut usize,
+    result: &mut guillotiere_allocation_t,
 ) -> bool {
-    atlas.is_empty()
+    guard(|| {
+        let sizes = check_slice(sizes, count)?
+            .iter()
+            .map(check_size)
+            .collect::<Result<Vec<Size>, Error>>()?;
+        if let Some((index, alloc)) = atlas.allocate_best_of(&sizes) {
+            *out_index = index;
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_size(
-    atlas: &guillotiere_simple_atlas_allocator_t,
-) -> guillotiere_size_t {
-    transmute(atlas.size())
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_with_alignment(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    size: guillotiere_size_t,
+    alignment: guillotiere_size_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.allocate_with_alignment(check_size(&size)?, check_size(&alignment)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_allocate(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_with_padding(
+    atlas: &mut guillotiere_atlas_allocator_t,
     size: guillotiere_size_t,
-    result: &mut guillotiere_rectangle_t,
+    padding: i32,
+    result: &mut guillotiere_allocation_t,
 ) -> bool {
-    if let Some(alloc) = atlas.allocate(transmute(size)) {
-        *result = transmute(alloc);
-        return true;
-    }
+    guard(|| {
+        if padding < 0 {
+            return Err(Error::new(guillotiere_status_t::InvalidArgument, format!("negative padding {}", padding)));
+        }
+        if let Some(alloc) = atlas.allocate_with_padding(check_size(&size)?, padding) {
+            *result = alloc.into();
+            return Ok(true);
+        }
 
-    false
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_grow(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
-    new_size: guillotiere_size_t,
-) {
-    atlas.grow(transmute(new_size));
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_tagged(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    size: guillotiere_size_t,
+    tag: u32,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.allocate_tagged(check_size(&size)?, tag) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_init_from_allocator(
-    atlas: &mut guillotiere_simple_atlas_allocator_t,
-    src: &guillotiere_atlas_allocator_t,
-) {
-    atlas.init_from_allocator(src);
+pub unsafe extern "C" fn guillotiere_atlas_allocator_release_tag(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    tag: u32,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.release_tag(tag);
+        Ok(())
+    }))
 }
 
+/// Returns false if the allocation has no tag.
 #[no_mangle]
-pub unsafe extern "C" fn guillotiere_allocator_options_default(
-    options: &mut guillotiere_allocator_options_t,
-) {
-    *options = guillotiere_allocator_options_t {
-        width_alignment: DEFAULT_OPTIONS.alignment.width,
-        height_alignment: DEFAULT_OPTIONS.alignment.height,
-        small_size_threshold: DEFAULT_OPTIONS.small_size_threshold,
-        large_size_threshold: DEFAULT_OPTIONS.large_size_threshold,
-    };
+pub unsafe extern "C" fn guillotiere_atlas_allocator_tag(
+    atlas: &guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    out_tag: &mut u32,
+) -> bool {
+    guard(|| {
+        if let Some(tag) = atlas.tag(check_id(atlas, id)?) {
+            *out_tag = tag;
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
 }
 
-// TODO:
-// for_each_free_rectangle
-// for_each_allocated_rectangle
-// svg dump
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_at(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    position: guillotiere_point_t,
+    size: guillotiere_size_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.allocate_at(position.into(), check_size(&size)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocate_in(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    region: guillotiere_rectangle_t,
+    size: guillotiere_size_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.allocate_in(region.into(), check_size(&size)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_reserve_region(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    region: guillotiere_rectangle_t,
+) -> bool {
+    guard(|| Ok(atlas.reserve_region(region.into()))).unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_deallocate_deferred(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    frame_index: u64,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.deallocate_deferred(check_id(atlas, id)?, frame_index);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_flush_deferred(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    frame_index: u64,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.flush_deferred(frame_index);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_coalesce_aggressively(
+    atlas: &mut guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.coalesce_aggressively();
+        Ok(())
+    }))
+}
+
+/// Deallocate several rectangles. Nothing is deallocated if one of the ids is not valid or
+/// if an id is repeated.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_deallocate_many(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    ids: *const guillotiere_alloc_id_t,
+    count: usize,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let ids = check_slice(ids, count)?
+            .iter()
+            .map(|id| check_id(atlas, *id))
+            .collect::<Result<Vec<AllocId>, Error>>()?;
+        let mut sorted = ids.clone();
+        sorted.sort_unstable_by_key(|id| id.serialize());
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(Error::new(
+                guillotiere_status_t::InvalidId,
+                format!("repeated allocation id {}", pair[0].serialize()),
+            ));
+        }
+        atlas.deallocate_many(&ids);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_merge(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    a: guillotiere_alloc_id_t,
+    b: guillotiere_alloc_id_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.merge(check_id(atlas, a)?, check_id(atlas, b)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_split(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    axis: guillotiere_axis_t,
+    offset: i32,
+    first: &mut guillotiere_allocation_t,
+    second: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some((a, b)) = atlas.split(check_id(atlas, id)?, axis, offset) {
+            *first = a.into();
+            *second = b.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_realloc(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    new_size: guillotiere_size_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.realloc(check_id(atlas, id)?, check_size(&new_size)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_set_pinned(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    pinned: bool,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.set_pinned(check_id(atlas, id)?, pinned);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_is_pinned(
+    atlas: &guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+) -> bool {
+    guard(|| Ok(atlas.is_pinned(check_id(atlas, id)?))).unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocation_count(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> usize {
+    atlas.allocation_count()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_free_rect_count(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> usize {
+    atlas.free_rect_count()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_capacity(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> usize {
+    atlas.capacity()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_reserve(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    additional: usize,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.reserve(additional);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_shrink_internal_storage(
+    atlas: &mut guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.shrink_internal_storage();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_canonicalize(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    change_list: &mut guillotiere_change_list_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = atlas.canonicalize().into();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocated_space(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> i64 {
+    atlas.allocated_space()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_free_space(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> i64 {
+    atlas.free_space()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_occupancy(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> f32 {
+    atlas.occupancy()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_stats(
+    atlas: &guillotiere_atlas_allocator_t,
+    stats: &mut guillotiere_allocator_stats_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let s = atlas.stats();
+        *stats = guillotiere_allocator_stats_t {
+            free_rect_count: s.free_rect_count,
+            largest_free_rect: s.largest_free_rect.into(),
+            mean_free_rect_area: s.mean_free_rect_area,
+            median_free_rect_area: s.median_free_rect_area,
+            p90_free_rect_area: s.p90_free_rect_area,
+            fragmentation: s.fragmentation,
+        };
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_is_valid(
+    atlas: &guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+) -> bool {
+    atlas.is_valid(id.into())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_dense_index(
+    atlas: &guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    out_index: &mut usize,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *out_index = atlas.dense_index(check_id(atlas, id)?);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_dense_index_bound(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> usize {
+    atlas.dense_index_bound()
+}
+
+/// Returns false if the id is not valid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_get(
+    atlas: &guillotiere_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    result: &mut guillotiere_rectangle_t,
+) -> bool {
+    if let Some(rect) = atlas.get(id.into()) {
+        *result = (*rect).into();
+        return true;
+    }
+
+    false
+}
+
+/// Returns false if no allocation covers the point.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocation_at(
+    atlas: &guillotiere_atlas_allocator_t,
+    point: guillotiere_point_t,
+    result: &mut guillotiere_alloc_id_t,
+) -> bool {
+    guard(|| {
+        if let Some(id) = atlas.allocation_at(point.into()) {
+            *result = id.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+/// Invoke a callback for each allocation that intersects the rectangle.
+///
+/// `user_data` is passed to the callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocations_in(
+    atlas: &guillotiere_atlas_allocator_t,
+    rect: guillotiere_rectangle_t,
+    callback: guillotiere_alloc_id_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        for id in atlas.allocations_in(rect.into()) {
+            callback(user_data, id.into());
+        }
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_largest_free_rect_size(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> guillotiere_size_t {
+    atlas.largest_free_rect_size().into()
+}
+
+/// Invoke a callback for each rectangle to copy when growing the atlas's texture, see
+/// `AtlasAllocator::grow_copy_plan`.
+///
+/// `user_data` is passed to the callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_grow_copy_plan(
+    atlas: &guillotiere_atlas_allocator_t,
+    old_size: guillotiere_size_t,
+    new_size: guillotiere_size_t,
+    callback: guillotiere_rectangle_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let old_size = check_size(&old_size)?;
+        for rect in atlas.grow_copy_plan(old_size, check_growth(old_size, &new_size)?) {
+            callback(user_data, rect.into());
+        }
+        Ok(())
+    }))
+}
+
+/// Returns false if the atlas is empty.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_allocated_bounds(
+    atlas: &guillotiere_atlas_allocator_t,
+    result: &mut guillotiere_rectangle_t,
+) -> bool {
+    guard(|| {
+        if let Some(rect) = atlas.allocated_bounds() {
+            *result = rect.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_plan_rearrange(
+    atlas: &guillotiere_atlas_allocator_t,
+    change_list: &mut guillotiere_change_list_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = atlas.plan_rearrange().into();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_rearrange_preserving_ids(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    change_list: &mut guillotiere_change_list_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = atlas.rearrange_preserving_ids().into();
+        Ok(())
+    }))
+}
+
+/// Move at most `budget` allocations, see `AtlasAllocator::defragment_step`. The change list
+/// has no failures.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_defragment_step(
+    atlas: &mut guillotiere_atlas_allocator_t,
+    budget: usize,
+    change_list: &mut guillotiere_change_list_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        *change_list = ChangeList {
+            changes: atlas.defragment_step(budget),
+            failures: Vec::new(),
+        }
+        .into();
+        Ok(())
+    }))
+}
+
+/// Returns a zero size if the requested size is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_suggest_grow(
+    atlas: &guillotiere_atlas_allocator_t,
+    requested_size: guillotiere_size_t,
+) -> guillotiere_size_t {
+    guard(|| Ok(atlas.suggest_grow(check_size(&requested_size)?).into()))
+        .unwrap_or(guillotiere_size_t { width: 0, height: 0 })
+}
+
+/// Write a Graphviz DOT representation of the atlas's tree through a callback.
+///
+/// `user_data` is passed to the callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_dump_tree_dot(
+    atlas: &guillotiere_atlas_allocator_t,
+    callback: guillotiere_write_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let mut output = CallbackWriter { callback, user_data };
+        // The callback can't fail.
+        let _ = atlas.dump_tree_dot(&mut output);
+        Ok(())
+    }))
+}
+
+/// Verify the integrity of the atlas, returning `ValidationFailed` if it is corrupted.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_validate(
+    atlas: &guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas
+            .validate()
+            .map_err(|error| Error::new(guillotiere_status_t::ValidationFailed, error.to_string()))
+    }))
+}
+
+/// Serialize the atlas into a compact binary snapshot, preserving the allocation ids.
+///
+/// On success `data` and `len` receive a buffer that must be released with
+/// `guillotiere_buffer_free`. See `AtlasAllocator::to_binary`.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_serialize(
+    atlas: &guillotiere_atlas_allocator_t,
+    data: &mut *mut u8,
+    len: &mut usize,
+) -> guillotiere_status_t {
+    *data = std::ptr::null_mut();
+    *len = 0;
+    status(guard(|| {
+        let bytes = atlas.to_binary().into_boxed_slice();
+        *len = bytes.len();
+        *data = Box::into_raw(bytes) as *mut u8;
+        Ok(())
+    }))
+}
+
+/// Load an atlas from a snapshot written by `guillotiere_atlas_allocator_serialize`.
+///
+/// Returns `InvalidArgument` if the data is malformed and `ValidationFailed` if the atlas it
+/// describes is corrupted, in which case `result` receives null.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_atlas_allocator_deserialize(
+    data: *const u8,
+    len: usize,
+    result: &mut *mut guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    *result = std::ptr::null_mut();
+    status(guard(|| {
+        let atlas = AtlasAllocator::from_binary(check_slice(data, len)?).map_err(|error| {
+            let status = match error {
+                SerializationError::Invalid(_) => guillotiere_status_t::ValidationFailed,
+                _ => guillotiere_status_t::InvalidArgument,
+            };
+            Error::new(status, error.to_string())
+        })?;
+
+        *result = Box::into_raw(Box::new(atlas));
+        Ok(())
+    }))
+}
+
+/// Release a buffer returned by `guillotiere_atlas_allocator_serialize`.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_buffer_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_change_list_new() -> *mut guillotiere_change_list_t {
+    Box::into_raw(Box::new(guillotiere_change_list_t {
+        changes: Vec::new(),
+        failures: Vec::new(),
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_change_list_delete(
+    change_list: *mut guillotiere_change_list_t,
+) {
+    if !change_list.is_null() {
+        drop(Box::from_raw(change_list));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_change_list_changes(
+    change_list: &guillotiere_change_list_t,
+) -> guillotiere_changes_t {
+    guillotiere_changes_t {
+        changes: change_list.changes.as_ptr(),
+        count: change_list.changes.len(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_change_list_failures(
+    change_list: &guillotiere_change_list_t,
+) -> guillotiere_failures_t {
+    guillotiere_failures_t {
+        failures: change_list.failures.as_ptr(),
+        count: change_list.failures.len(),
+    }
+}
+
+/// Returns null if the size is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_new(
+    size: guillotiere_size_t,
+) -> *mut guillotiere_simple_atlas_allocator_t {
+    guard(|| Ok(Box::into_raw(Box::new(SimpleAtlasAllocator::new(check_size(&size)?)))))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Returns null if the size or the options are invalid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_with_options(
+    size: guillotiere_size_t,
+    options: &guillotiere_allocator_options_t,
+) -> *mut guillotiere_simple_atlas_allocator_t {
+    guard(|| {
+        let options = check_options(options)?;
+        Ok(Box::into_raw(Box::new(SimpleAtlasAllocator::with_options(
+            check_size(&size)?,
+            &options,
+        ))))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_delete(
+    atlas: *mut guillotiere_simple_atlas_allocator_t,
+) {
+    if !atlas.is_null() {
+        drop(Box::from_raw(atlas));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_clear(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.clear();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_reset(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+    size: guillotiere_size_t,
+    options: &guillotiere_allocator_options_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        let options = check_options(options)?;
+        atlas.reset(check_size(&size)?, &options);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_is_empty(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+) -> bool {
+    atlas.is_empty()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_size(
+    atlas: &guillotiere_simple_atlas_allocator_t,
+) -> guillotiere_size_t {
+    atlas.size().into()
+}
+
+/// Returns false if the allocation fails or if the size is invalid, in which case
+/// `guillotiere_last_error_message` is not null.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_allocate(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+    size: guillotiere_size_t,
+    result: &mut guillotiere_rectangle_t,
+) -> bool {
+    guard(|| {
+        if let Some(alloc) = atlas.allocate(check_size(&size)?) {
+            *result = alloc.into();
+            return Ok(true);
+        }
+
+        Ok(false)
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_grow(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+    new_size: guillotiere_size_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.grow(check_growth(atlas.size(), &new_size)?);
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_coalesce(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.coalesce();
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_init_from_allocator(
+    atlas: &mut guillotiere_simple_atlas_allocator_t,
+    src: &guillotiere_atlas_allocator_t,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.init_from_allocator(src);
+        Ok(())
+    }))
+}
+
+/// Invoke a callback for each free rectangle of the atlas.
+///
+/// `user_data` is passed to the callback as is.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_simple_atlas_allocator_for_each_free_rectangle(
+    atlas: &guillotiere_simple_atlas_allocator_t,
+    callback: guillotiere_free_rectangle_callback_t,
+    user_data: *mut c_void,
+) -> guillotiere_status_t {
+    status(guard(|| {
+        atlas.for_each_free_rectangle(|rect| {
+            callback(user_data, (*rect).into());
+        });
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_allocator_options_default(
+    options: &mut guillotiere_allocator_options_t,
+) {
+    *options = to_ffi_options(&DEFAULT_OPTIONS);
+}
+
+#[test]
+fn layout() {
+    use std::mem::{align_of, offset_of};
+
+    assert_eq!(align_of::<guillotiere_size_t>(), 4);
+    assert_eq!(offset_of!(guillotiere_size_t, height), 4);
+    assert_eq!(offset_of!(guillotiere_rectangle_t, min_y), 4);
+    assert_eq!(offset_of!(guillotiere_rectangle_t, max_x), 8);
+    assert_eq!(offset_of!(guillotiere_rectangle_t, max_y), 12);
+    assert_eq!(align_of::<guillotiere_alloc_id_t>(), ID_SIZE);
+    assert_eq!(offset_of!(guillotiere_allocation_t, rectangle), ID_SIZE);
+    assert_eq!(offset_of!(guillotiere_allocation_t, content), ID_SIZE + 16);
+    assert_eq!(offset_of!(guillotiere_allocation_t, rotated), ID_SIZE + 32);
+    assert_eq!(offset_of!(guillotiere_change_t, new_alloc), std::mem::size_of::<guillotiere_allocation_t>());
+}
+
+#[test]
+fn conversions() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    let alloc = atlas.allocate(size2(10, 20)).unwrap();
+
+    let ffi_alloc: guillotiere_allocation_t = alloc.into();
+    assert_eq!(
+        ffi_alloc.rectangle,
+        guillotiere_rectangle_t {
+            min_x: alloc.rectangle.min.x,
+            min_y: alloc.rectangle.min.y,
+            max_x: alloc.rectangle.max.x,
+            max_y: alloc.rectangle.max.y,
+        }
+    );
+    assert_eq!(AllocId::from(ffi_alloc.id), alloc.id);
+
+    let size: guillotiere_size_t = atlas.size().into();
+    assert_eq!(size, guillotiere_size_t { width: 100, height: 100 });
+    assert_eq!(Size::from(size), atlas.size());
+}
+
+#[test]
+fn rearrange_into() {
+    unsafe {
+        let atlas = &mut *guillotiere_atlas_allocator_new(guillotiere_size_t { width: 100, height: 100 });
+        let mut alloc = std::mem::MaybeUninit::uninit();
+        for _ in 0..3 {
+            assert!(guillotiere_atlas_allocator_allocate(
+                atlas,
+                guillotiere_size_t { width: 50, height: 50 },
+                &mut *alloc.as_mut_ptr(),
+            ));
+        }
+
+        // The first call sizes the arrays without rearranging.
+        let (mut changes_count, mut failures_count) = (0, 0);
+        let status = guillotiere_atlas_allocator_resize_and_rearrange_into(
+            atlas,
+            guillotiere_size_t { width: 80, height: 80 },
+            std::ptr::null_mut(),
+            0,
+            &mut changes_count,
+            std::ptr::null_mut(),
+            0,
+            &mut failures_count,
+        );
+        assert_eq!(status, guillotiere_status_t::BufferTooSmall);
+        assert_eq!((changes_count, failures_count), (3, 3));
+        assert_eq!(atlas.size(), size2(100, 100));
+
+        let mut changes = Vec::with_capacity(changes_count);
+        let mut failures = Vec::with_capacity(failures_count);
+        let status = guillotiere_atlas_allocator_resize_and_rearrange_into(
+            atlas,
+            guillotiere_size_t { width: 80, height: 80 },
+            changes.as_mut_ptr(),
+            changes.capacity(),
+            &mut changes_count,
+            failures.as_mut_ptr(),
+            failures.capacity(),
+            &mut failures_count,
+        );
+        assert_eq!(status, guillotiere_status_t::Ok);
+        changes.set_len(changes_count);
+        failures.set_len(failures_count);
+        assert_eq!((changes.len(), failures.len()), (1, 2));
+        for change in &changes {
+            let rect = atlas.get(change.new_alloc.id.into()).copied().unwrap();
+            assert_eq!(guillotiere_rectangle_t::from(rect), change.new_alloc.rectangle);
+        }
+
+        // Atlases with pinned allocations can't be shrunk and are left untouched.
+        let pinned = changes[0].new_alloc;
+        assert_eq!(guillotiere_atlas_allocator_set_pinned(atlas, pinned.id, true), guillotiere_status_t::Ok);
+        let status = guillotiere_atlas_allocator_resize_and_rearrange_into(
+            atlas,
+            guillotiere_size_t { width: 60, height: 60 },
+            changes.as_mut_ptr(),
+            changes.capacity(),
+            &mut changes_count,
+            failures.as_mut_ptr(),
+            failures.capacity(),
+            &mut failures_count,
+        );
+        assert_eq!(status, guillotiere_status_t::InvalidSize);
+        assert!(!guillotiere_last_error_message().is_null());
+        assert_eq!(atlas.size(), size2(80, 80));
+        assert!(atlas.is_valid(pinned.id.into()));
+
+        guillotiere_atlas_allocator_delete(atlas);
+    }
+}
+
+#[test]
+fn error_statuses() {
+    unsafe fn last_error() -> String {
+        let message = guillotiere_last_error_message();
+        assert!(!message.is_null());
+        std::ffi::CStr::from_ptr(message).to_str().unwrap().to_string()
+    }
+
+    unsafe {
+        let size = guillotiere_size_t { width: 100, height: 100 };
+        let atlas = &mut *guillotiere_atlas_allocator_new(size);
+        let mut alloc = std::mem::MaybeUninit::uninit();
+        assert!(guillotiere_atlas_allocator_allocate(atlas, size2(10, 10).into(), &mut *alloc.as_mut_ptr()));
+        let alloc = alloc.assume_init();
+        assert!(guillotiere_last_error_message().is_null());
+
+        // Stale ids.
+        assert_eq!(guillotiere_atlas_allocator_deallocate(atlas, alloc.id), guillotiere_status_t::Ok);
+        assert!(guillotiere_last_error_message().is_null());
+        assert_eq!(guillotiere_atlas_allocator_deallocate(atlas, alloc.id), guillotiere_status_t::InvalidId);
+        assert!(last_error().starts_with("invalid allocation id"));
+
+        // Repeated ids are rejected before anything is deallocated.
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let mut alloc = std::mem::MaybeUninit::uninit();
+            assert!(guillotiere_atlas_allocator_allocate(atlas, size2(10, 10).into(), &mut *alloc.as_mut_ptr()));
+            ids.push(alloc.assume_init().id);
+        }
+        ids.push(ids[0]);
+        let status = guillotiere_atlas_allocator_deallocate_many(atlas, ids.as_ptr(), ids.len());
+        assert_eq!(status, guillotiere_status_t::InvalidId);
+        assert!(last_error().starts_with("repeated allocation id"));
+        assert_eq!(atlas.allocation_count(), 2);
+        atlas.validate().unwrap();
+        let status = guillotiere_atlas_allocator_deallocate_many(atlas, ids.as_ptr(), 2);
+        assert_eq!(status, guillotiere_status_t::Ok);
+
+        // Empty and negative sizes.
+        let status = guillotiere_atlas_allocator_clear_with_size(atlas, guillotiere_size_t { width: 0, height: 10 });
+        assert_eq!(status, guillotiere_status_t::InvalidSize);
+        assert_eq!(last_error(), "invalid size 0x10");
+        let status = guillotiere_atlas_allocator_grow(atlas, guillotiere_size_t { width: -5, height: 200 });
+        assert_eq!(status, guillotiere_status_t::InvalidSize);
+        assert_eq!(last_error(), "invalid size -5x200");
+        assert!(guillotiere_atlas_allocator_new(guillotiere_size_t { width: 10, height: -1 }).is_null());
+        assert_eq!(last_error(), "invalid size 10x-1");
+        assert_eq!(atlas.size(), size2(100, 100));
+
+        // Invalid options are rejected before reaching the allocators.
+        let mut options = to_ffi_options(&DEFAULT_OPTIONS);
+        options.width_alignment = 0;
+        assert!(guillotiere_atlas_allocator_with_options(size, &options).is_null());
+        assert_eq!(last_error(), "invalid allocator options");
+        assert!(guillotiere_simple_atlas_allocator_with_options(size, &options).is_null());
+        assert_eq!(last_error(), "invalid allocator options");
+        let mut options = to_ffi_options(&DEFAULT_OPTIONS);
+        options.small_size_threshold = options.large_size_threshold + 1;
+        let status = guillotiere_atlas_allocator_reset(atlas, size, &options);
+        assert_eq!(status, guillotiere_status_t::InvalidArgument);
+        assert_eq!(last_error(), "invalid allocator options");
+        let mut result = std::mem::MaybeUninit::uninit();
+        assert!(!guillotiere_atlas_allocator_allocate_with_padding(atlas, size2(10, 10).into(), -1, &mut *result.as_mut_ptr()));
+        assert_eq!(last_error(), "negative padding -1");
+        assert!(atlas.is_empty());
+        atlas.validate().unwrap();
+
+        guillotiere_atlas_allocator_delete(atlas);
+    }
+
+    // Panics are caught and reported with their message.
+    let result: Result<(), _> = guard(|| panic!("something went wrong"));
+    assert_eq!(result, Err(guillotiere_status_t::Panic));
+    assert_eq!(unsafe { last_error() }, "something went wrong");
+    let result: Result<(), _> = guard(|| panic!("{} went wrong", 2));
+    assert_eq!(result, Err(guillotiere_status_t::Panic));
+    assert_eq!(unsafe { last_error() }, "2 went wrong");
+    assert_eq!(guard(|| Ok(1)), Ok(1));
+    assert!(unsafe { guillotiere_last_error_message() }.is_null());
+}
+
+#[test]
+fn dump_svg_into_buffer() {
+    extern "C" fn append(user_data: *mut c_void, data: *const c_char, len: usize) {
+        unsafe {
+            let output = &mut *(user_data as *mut Vec<u8>);
+            output.extend_from_slice(std::slice::from_raw_parts(data as *const u8, len));
+        }
+    }
+
+    unsafe {
+        let atlas = &mut *guillotiere_atlas_allocator_new(guillotiere_size_t { width: 100, height: 100 });
+        let mut alloc = std::mem::MaybeUninit::uninit();
+        assert!(guillotiere_atlas_allocator_allocate(
+            atlas,
+            guillotiere_size_t { width: 30, height: 40 },
+            &mut *alloc.as_mut_ptr(),
+        ));
+
+        // The first call sizes the buffer.
+        let mut written = 0;
+        let status = guillotiere_atlas_allocator_dump_svg(atlas, std::ptr::null_mut(), 0, &mut written);
+        assert_eq!(status, guillotiere_status_t::BufferTooSmall);
+        assert!(!guillotiere_last_error_message().is_null());
+        assert!(written > 0);
+
+        let mut buffer = vec![0u8; written - 1];
+        let mut written_again = 0;
+        let status = guillotiere_atlas_allocator_dump_svg(
+            atlas,
+            buffer.as_mut_ptr() as *mut c_char,
+            buffer.len(),
+            &mut written_again,
+        );
+        assert_eq!(status, guillotiere_status_t::BufferTooSmall);
+        assert_eq!(written_again, written);
+        assert!(buffer.iter().all(|&byte| byte == 0));
+
+        let mut buffer = vec![0u8; written];
+        let status = guillotiere_atlas_allocator_dump_svg(
+            atlas,
+            buffer.as_mut_ptr() as *mut c_char,
+            buffer.len(),
+            &mut written,
+        );
+        assert_eq!(status, guillotiere_status_t::Ok);
+        assert_eq!(written, buffer.len());
+
+        // The streaming variant produces the same dump.
+        let mut streamed: Vec<u8> = Vec::new();
+        let status = guillotiere_atlas_allocator_dump_svg_with_callback(
+            atlas,
+            append,
+            &mut streamed as *mut Vec<u8> as *mut c_void,
+        );
+        assert_eq!(status, guillotiere_status_t::Ok);
+        assert_eq!(streamed, buffer);
+
+        guillotiere_atlas_allocator_delete(atlas);
+    }
+}
+
+#[test]
+fn for_each_rectangle() {
+    extern "C" fn allocated(user_data: *mut c_void, id: guillotiere_alloc_id_t, rectangle: guillotiere_rectangle_t) {
+        unsafe { (*(user_data as *mut Vec<(guillotiere_alloc_id_t, guillotiere_rectangle_t)>)).push((id, rectangle)) };
+    }
+    extern "C" fn free(user_data: *mut c_void, rectangle: guillotiere_rectangle_t) {
+        unsafe { (*(user_data as *mut Vec<guillotiere_rectangle_t>)).push(rectangle) };
+    }
+
+    unsafe {
+        let atlas = &mut *guillotiere_atlas_allocator_new(guillotiere_size_t { width: 100, height: 100 });
+        let mut allocs = Vec::new();
+        for _ in 0..3 {
+            let mut alloc = std::mem::MaybeUninit::uninit();
+            assert!(guillotiere_atlas_allocator_allocate(
+                atlas,
+                guillotiere_size_t { width: 20, height: 20 },
+                &mut *alloc.as_mut_ptr(),
+            ));
+            allocs.push(alloc.assume_init());
+        }
+
+        let mut allocated_rects: Vec<(guillotiere_alloc_id_t, guillotiere_rectangle_t)> = Vec::new();
+        let status = guillotiere_atlas_allocator_for_each_allocated_rectangle(
+            atlas,
+            allocated,
+            &mut allocated_rects as *mut _ as *mut c_void,
+        );
+        assert_eq!(status, guillotiere_status_t::Ok);
+        assert_eq!(allocated_rects.len(), 3);
+        for alloc in &allocs {
+            assert!(allocated_rects.contains(&(alloc.id, alloc.rectangle)));
+        }
+
+        let mut free_rects: Vec<guillotiere_rectangle_t> = Vec::new();
+        let status = guillotiere_atlas_allocator_for_each_free_rectangle(
+            atlas,
+            free,
+            &mut free_rects as *mut _ as *mut c_void,
+        );
+        assert_eq!(status, guillotiere_status_t::Ok);
+        let free_area: i64 = free_rects.iter().map(|rect| Rectangle::from(*rect).area() as i64).sum();
+        assert_eq!(free_area, 100 * 100 - 3 * 20 * 20);
+
+        guillotiere_atlas_allocator_delete(atlas);
+    }
+}
+
+#[test]
+fn serialization() {
+    unsafe {
+        let atlas = &mut *guillotiere_atlas_allocator_new(guillotiere_size_t { width: 100, height: 100 });
+        let mut alloc = std::mem::MaybeUninit::uninit();
+        assert!(guillotiere_atlas_allocator_allocate(
+            atlas,
+            guillotiere_size_t { width: 30, height: 40 },
+            &mut *alloc.as_mut_ptr(),
+        ));
+        let alloc = alloc.assume_init();
+
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        assert_eq!(guillotiere_atlas_allocator_serialize(atlas, &mut data, &mut len), guillotiere_status_t::Ok);
+        let mut loaded = std::ptr::null_mut();
+        assert_eq!(guillotiere_atlas_allocator_deserialize(data, len, &mut loaded), guillotiere_status_t::Ok);
+        let loaded = &mut *loaded;
+        assert_eq!(loaded.get(alloc.id.into()).copied().map(guillotiere_rectangle_t::from), Some(alloc.rectangle));
+
+        let mut truncated = std::ptr::null_mut();
+        let status = guillotiere_atlas_allocator_deserialize(data, len - 1, &mut truncated);
+        assert_eq!(status, guillotiere_status_t::InvalidArgument);
+        assert!(truncated.is_null());
+        assert!(!guillotiere_last_error_message().is_null());
+
+        // Well-formed data describing a broken tree: the right sibling of the allocation (the
+        // fourth of the 54 bytes long nodes following the 79 bytes long header) links back to it.
+        let mut corrupted = std::slice::from_raw_parts(data, len).to_vec();
+        let next_sibling = 79 + 3 * 54 + 9;
+        corrupted[next_sibling..next_sibling + 4].copy_from_slice(&2u32.to_le_bytes());
+        let mut loaded_corrupted = std::ptr::null_mut();
+        let status = guillotiere_atlas_allocator_deserialize(corrupted.as_ptr(), corrupted.len(), &mut loaded_corrupted);
+        assert_eq!(status, guillotiere_status_t::ValidationFailed);
+        assert!(loaded_corrupted.is_null());
+        assert!(!guillotiere_last_error_message().is_null());
+
+        guillotiere_buffer_free(data, len);
+        guillotiere_atlas_allocator_delete(loaded);
+        guillotiere_atlas_allocator_delete(atlas);
+    }
+}
+
+#[test]
+fn api_coverage() {
+    // Each public method of the allocators, with the C function that wraps it. Naming both
+    // sides makes removing or renaming either of them a compile error. New methods must be
+    // added here or to `EXCLUDED`.
+    macro_rules! wrappers {
+        ($($method:expr => $wrapper:ident,)*) => {
+            [$((stringify!($method), $method as *const (), $wrapper as *const ())),*]
+        };
+    }
+
+    let wrapped = wrappers! {
+        AtlasAllocator::new => guillotiere_atlas_allocator_new,
+        AtlasAllocator::with_options => guillotiere_atlas_allocator_with_options,
+        AtlasAllocator::from_simple => guillotiere_atlas_allocator_from_simple,
+        AtlasAllocator::from_allocations => guillotiere_atlas_allocator_from_allocations,
+        AtlasAllocator::size => guillotiere_atlas_allocator_size,
+        AtlasAllocator::options => guillotiere_atlas_allocator_options,
+        AtlasAllocator::allocate => guillotiere_atlas_allocator_allocate,
+        AtlasAllocator::allocate_best_of => guillotiere_atlas_allocator_allocate_best_of,
+        AtlasAllocator::allocate_with_alignment => guillotiere_atlas_allocator_allocate_with_alignment,
+        AtlasAllocator::allocate_with_padding => guillotiere_atlas_allocator_allocate_with_padding,
+        AtlasAllocator::allocate_tagged => guillotiere_atlas_allocator_allocate_tagged,
+        AtlasAllocator::release_tag => guillotiere_atlas_allocator_release_tag,
+        AtlasAllocator::tag => guillotiere_atlas_allocator_tag,
+        AtlasAllocator::allocate_at => guillotiere_atlas_allocator_allocate_at,
+        AtlasAllocator::allocate_in => guillotiere_atlas_allocator_allocate_in,
+        AtlasAllocator::reserve_region => guillotiere_atlas_allocator_reserve_region,
+        AtlasAllocator::deallocate => guillotiere_atlas_allocator_deallocate,
+        AtlasAllocator::deallocate_deferred => guillotiere_atlas_allocator_deallocate_deferred,
+        AtlasAllocator::flush_deferred => guillotiere_atlas_allocator_flush_deferred,
+        AtlasAllocator::coalesce => guillotiere_atlas_allocator_coalesce,
+        AtlasAllocator::coalesce_aggressively => guillotiere_atlas_allocator_coalesce_aggressively,
+        AtlasAllocator::deallocate_many => guillotiere_atlas_allocator_deallocate_many,
+        AtlasAllocator::merge => guillotiere_atlas_allocator_merge,
+        AtlasAllocator::split => guillotiere_atlas_allocator_split,
+        AtlasAllocator::realloc => guillotiere_atlas_allocator_realloc,
+        AtlasAllocator::set_pinned => guillotiere_atlas_allocator_set_pinned,
+        AtlasAllocator::is_pinned => guillotiere_atlas_allocator_is_pinned,
+        AtlasAllocator::allocation_count => guillotiere_atlas_allocator_allocation_count,
+        AtlasAllocator::free_rect_count => guillotiere_atlas_allocator_free_rect_count,
+        AtlasAllocator::capacity => guillotiere_atlas_allocator_capacity,
+        AtlasAllocator::reserve => guillotiere_atlas_allocator_reserve,
+        AtlasAllocator::shrink_internal_storage => guillotiere_atlas_allocator_shrink_internal_storage,
+        AtlasAllocator::canonicalize => guillotiere_atlas_allocator_canonicalize,
+        AtlasAllocator::allocated_space => guillotiere_atlas_allocator_allocated_space,
+        AtlasAllocator::free_space => guillotiere_atlas_allocator_free_space,
+        AtlasAllocator::occupancy => guillotiere_atlas_allocator_occupancy,
+        AtlasAllocator::stats => guillotiere_atlas_allocator_stats,
+        AtlasAllocator::is_valid => guillotiere_atlas_allocator_is_valid,
+        AtlasAllocator::dense_index => guillotiere_atlas_allocator_dense_index,
+        AtlasAllocator::dense_index_bound => guillotiere_atlas_allocator_dense_index_bound,
+        AtlasAllocator::get => guillotiere_atlas_allocator_get,
+        AtlasAllocator::allocation_at => guillotiere_atlas_allocator_allocation_at,
+        AtlasAllocator::allocations_in => guillotiere_atlas_allocator_allocations_in,
+        AtlasAllocator::largest_free_rect_size => guillotiere_atlas_allocator_largest_free_rect_size,
+        AtlasAllocator::is_empty => guillotiere_atlas_allocator_is_empty,
+        AtlasAllocator::grow_copy_plan => guillotiere_atlas_allocator_grow_copy_plan,
+        AtlasAllocator::allocated_bounds => guillotiere_atlas_allocator_allocated_bounds,
+        AtlasAllocator::clear => guillotiere_atlas_allocator_clear,
+        AtlasAllocator::clear_with_size => guillotiere_atlas_allocator_clear_with_size,
+        AtlasAllocator::reset => guillotiere_atlas_allocator_reset,
+        AtlasAllocator::rearrange => guillotiere_atlas_allocator_rearrange,
+        AtlasAllocator::plan_rearrange => guillotiere_atlas_allocator_plan_rearrange,
+        AtlasAllocator::rearrange_preserving_ids => guillotiere_atlas_allocator_rearrange_preserving_ids,
+        AtlasAllocator::resize_and_rearrange => guillotiere_atlas_allocator_resize_and_rearrange,
+        AtlasAllocator::defragment_step => guillotiere_atlas_allocator_defragment_step,
+        AtlasAllocator::suggest_grow => guillotiere_atlas_allocator_suggest_grow,
+        AtlasAllocator::grow => guillotiere_atlas_allocator_grow,
+        AtlasAllocator::for_each_free_rectangle::<fn(&Rectangle)> => guillotiere_atlas_allocator_for_each_free_rectangle,
+        AtlasAllocator::for_each_allocated_rectangle::<fn(AllocId, &Rectangle)> => guillotiere_atlas_allocator_for_each_allocated_rectangle,
+        AtlasAllocator::dump_tree_dot => guillotiere_atlas_allocator_dump_tree_dot,
+        AtlasAllocator::validate => guillotiere_atlas_allocator_validate,
+        SimpleAtlasAllocator::new => guillotiere_simple_atlas_allocator_new,
+        SimpleAtlasAllocator::with_options => guillotiere_simple_atlas_allocator_with_options,
+        SimpleAtlasAllocator::clear => guillotiere_simple_atlas_allocator_clear,
+        SimpleAtlasAllocator::reset => guillotiere_simple_atlas_allocator_reset,
+        SimpleAtlasAllocator::is_empty => guillotiere_simple_atlas_allocator_is_empty,
+        SimpleAtlasAllocator::size => guillotiere_simple_atlas_allocator_size,
+        SimpleAtlasAllocator::allocate => guillotiere_simple_atlas_allocator_allocate,
+        SimpleAtlasAllocator::grow => guillotiere_simple_atlas_allocator_grow,
+        SimpleAtlasAllocator::coalesce => guillotiere_simple_atlas_allocator_coalesce,
+        SimpleAtlasAllocator::for_each_free_rectangle::<fn(&Rectangle)> => guillotiere_simple_atlas_allocator_for_each_free_rectangle,
+        SimpleAtlasAllocator::init_from_allocator => guillotiere_simple_atlas_allocator_init_from_allocator,
+    };
+
+    // Methods that don't map to C, with the alternative.
+    const EXCLUDED: &[&str] = &[
+        // Takes a closure. Use allocate.
+        "AtlasAllocator::allocate_with_score",
+        // Regions are owned Rust objects. Use allocate_in.
+        "AtlasAllocator::split_off_region",
+        "AtlasAllocator::reclaim",
+        // Depend on serde.
+        "AtlasAllocator::serialize_compact",
+        // Exposed as serialize and deserialize.
+        "AtlasAllocator::to_binary",
+        "AtlasAllocator::from_binary",
+        // Return Rust-specific structures. Use the iteration functions.
+        "AtlasAllocator::freeze",
+        "AtlasAllocator::layout",
+        "AtlasAllocator::memory_usage",
+        "AtlasAllocator::area_by_tag",
+        "AtlasAllocator::visit",
+        // The growth policies are not exposed. Use suggest_grow and grow.
+        "AtlasAllocator::ensure_can_allocate",
+        "AtlasAllocator::grow_and_allocate",
+        // The C functions report errors with guillotiere_status_t.
+        "AtlasAllocator::try_allocate",
+        "AtlasAllocator::try_deallocate",
+        "AtlasAllocator::try_reset",
+        "AtlasAllocator::try_grow",
+        "AtlasAllocator::try_resize_and_rearrange",
+    ];
+
+    let mut methods: Vec<&str> = wrapped.iter().map(|&(method, _, _)| method).collect();
+    let mut functions: Vec<*const ()> = wrapped.iter().map(|&(_, _, wrapper)| wrapper).collect();
+    methods.extend_from_slice(EXCLUDED);
+    methods.sort_unstable();
+    functions.sort_unstable();
+    assert!(methods.windows(2).all(|w| w[0] != w[1]), "methods listed twice");
+    assert!(functions.windows(2).all(|w| w[0] != w[1]), "C functions listed twice");
+}
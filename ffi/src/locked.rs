@@ -0,0 +1,239 @@
+//! An atlas allocator that can be shared between threads.
+//!
+//! The functions lock a mutex around the corresponding `guillotiere_atlas_allocator_*`
+//! functions, which report errors the same way. The error messages are per thread, so
+//! `guillotiere_last_error_message` can be called after a failed call without holding a lock.
+
+use crate::*;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub struct guillotiere_locked_atlas_allocator_t {
+    atlas: Mutex<AtlasAllocator>,
+}
+
+impl guillotiere_locked_atlas_allocator_t {
+    fn new(atlas: *mut guillotiere_atlas_allocator_t) -> *mut Self {
+        if atlas.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        let atlas = unsafe { *Box::from_raw(atlas) };
+        Box::into_raw(Box::new(guillotiere_locked_atlas_allocator_t { atlas: Mutex::new(atlas) }))
+    }
+
+    // The exported functions catch panics before they can poison the mutex, but a panicking
+    // callback of `guillotiere_locked_atlas_allocator_with_lock` could still poison it.
+    fn lock(&self) -> MutexGuard<'_, AtlasAllocator> {
+        self.atlas.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Invoked with exclusive access to the atlas, see `guillotiere_locked_atlas_allocator_with_lock`.
+pub type guillotiere_locked_atlas_callback_t =
+    extern "C" fn(user_data: *mut c_void, atlas: *mut guillotiere_atlas_allocator_t);
+
+/// Returns null if the size is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_new(
+    size: guillotiere_size_t,
+) -> *mut guillotiere_locked_atlas_allocator_t {
+    guillotiere_locked_atlas_allocator_t::new(guillotiere_atlas_allocator_new(size))
+}
+
+/// Returns null if the size or the options are invalid.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_with_options(
+    size: guillotiere_size_t,
+    options: &guillotiere_allocator_options_t,
+) -> *mut guillotiere_locked_atlas_allocator_t {
+    guillotiere_locked_atlas_allocator_t::new(guillotiere_atlas_allocator_with_options(size, options))
+}
+
+/// Take ownership of an atlas to share it between threads.
+///
+/// The atlas must not be used or deleted afterwards. Returns null if the atlas is null.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_from_atlas(
+    atlas: *mut guillotiere_atlas_allocator_t,
+) -> *mut guillotiere_locked_atlas_allocator_t {
+    guillotiere_locked_atlas_allocator_t::new(atlas)
+}
+
+/// The handle must not be used by other threads anymore.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_delete(
+    atlas: *mut guillotiere_locked_atlas_allocator_t,
+) {
+    if !atlas.is_null() {
+        drop(Box::from_raw(atlas));
+    }
+}
+
+/// Run a callback with exclusive access to the atlas, to use the functions that don't have
+/// a locked equivalent or to make several calls atomically.
+///
+/// The callback must not call the locked functions on the same handle, and must not keep
+/// the atlas pointer after it returns.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_with_lock(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    callback: guillotiere_locked_atlas_callback_t,
+    user_data: *mut c_void,
+) {
+    callback(user_data, &mut *atlas.lock());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_clear(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_clear(&mut atlas.lock())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_size(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> guillotiere_size_t {
+    guillotiere_atlas_allocator_size(&atlas.lock())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_is_empty(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> bool {
+    guillotiere_atlas_allocator_is_empty(&mut atlas.lock())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_allocation_count(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> usize {
+    guillotiere_atlas_allocator_allocation_count(&atlas.lock())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_free_space(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> i64 {
+    guillotiere_atlas_allocator_free_space(&atlas.lock())
+}
+
+/// See `guillotiere_atlas_allocator_allocate`.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_allocate(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    size: guillotiere_size_t,
+    result: &mut guillotiere_allocation_t,
+) -> bool {
+    guillotiere_atlas_allocator_allocate(&mut atlas.lock(), size, result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_deallocate(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_deallocate(&mut atlas.lock(), id)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_get(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    id: guillotiere_alloc_id_t,
+    result: &mut guillotiere_rectangle_t,
+) -> bool {
+    guillotiere_atlas_allocator_get(&atlas.lock(), id, result)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_coalesce(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_coalesce(&mut atlas.lock())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_grow(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    new_size: guillotiere_size_t,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_grow(&mut atlas.lock(), new_size)
+}
+
+/// See `guillotiere_atlas_allocator_rearrange_into`.
+///
+/// The allocation count can change between a first call that sizes the arrays and the
+/// actual rearrangement, in which case `BufferTooSmall` is returned again.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_rearrange_into(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    out_changes: *mut guillotiere_change_t,
+    changes_capacity: usize,
+    out_changes_count: &mut usize,
+    out_failures: *mut guillotiere_allocation_t,
+    failures_capacity: usize,
+    out_failures_count: &mut usize,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_rearrange_into(
+        &mut atlas.lock(),
+        out_changes,
+        changes_capacity,
+        out_changes_count,
+        out_failures,
+        failures_capacity,
+        out_failures_count,
+    )
+}
+
+/// See `guillotiere_atlas_allocator_serialize`.
+#[no_mangle]
+pub unsafe extern "C" fn guillotiere_locked_atlas_allocator_serialize(
+    atlas: &guillotiere_locked_atlas_allocator_t,
+    data: &mut *mut u8,
+    len: &mut usize,
+) -> guillotiere_status_t {
+    guillotiere_atlas_allocator_serialize(&atlas.lock(), data, len)
+}
+
+#[test]
+fn shared_between_threads() {
+    struct Handle(*mut guillotiere_locked_atlas_allocator_t);
+    unsafe impl Send for Handle {}
+    unsafe impl Sync for Handle {}
+
+    extern "C" fn count(user_data: *mut c_void, atlas: *mut guillotiere_atlas_allocator_t) {
+        unsafe { *(user_data as *mut usize) = (*atlas).allocation_count() };
+    }
+
+    unsafe {
+        let handle = Handle(guillotiere_locked_atlas_allocator_new(guillotiere_size_t { width: 256, height: 256 }));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let handle = &handle;
+                scope.spawn(move || {
+                    let atlas = &*handle.0;
+                    let mut alloc = std::mem::MaybeUninit::uninit();
+                    for _ in 0..8 {
+                        assert!(guillotiere_locked_atlas_allocator_allocate(
+                            atlas,
+                            guillotiere_size_t { width: 16, height: 16 },
+                            &mut *alloc.as_mut_ptr(),
+                        ));
+                        let alloc = alloc.assume_init();
+                        let mut rect = std::mem::MaybeUninit::uninit();
+                        assert!(guillotiere_locked_atlas_allocator_get(atlas, alloc.id, &mut *rect.as_mut_ptr()));
+                        assert_eq!(rect.assume_init(), alloc.rectangle);
+                    }
+                });
+            }
+        });
+
+        let atlas = &*handle.0;
+        assert_eq!(guillotiere_locked_atlas_allocator_allocation_count(atlas), 32);
+        let mut allocation_count = 0usize;
+        guillotiere_locked_atlas_allocator_with_lock(atlas, count, &mut allocation_count as *mut usize as *mut c_void);
+        assert_eq!(allocation_count, 32);
+
+        guillotiere_locked_atlas_allocator_delete(handle.0);
+    }
+}
@@ -0,0 +1,40 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use guillotiere::*;
+
+// Same as `alloc_dealloc`, with the input decoded with `FuzzEvent::decode`, so that crashes
+// can be converted into recordings with `Recording::from_fuzz_events` and recordings into
+// corpus entries with `FuzzEvent::encode`.
+fuzz_target!(|data: &[u8]| {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let mut allocations = Vec::new();
+
+    for evt in &FuzzEvent::decode(data) {
+        match *evt {
+            FuzzEvent::Allocate(w, h) => {
+                if let Some(alloc) = atlas.allocate(size2(w, h)) {
+                    allocations.push(alloc.id);
+                }
+            }
+            FuzzEvent::Deallocate(idx) => {
+                if idx < allocations.len() {
+                    atlas.deallocate(allocations[idx]);
+                    allocations.swap_remove(idx);
+                }
+            }
+        }
+
+        let mut count = 0;
+        atlas.for_each_allocated_rectangle(&mut |_id: AllocId, _r: &Rectangle| {
+            count += 1;
+        });
+
+        assert_eq!(count, allocations.len());
+    }
+
+    for id in allocations {
+        atlas.deallocate(id);
+    }
+});
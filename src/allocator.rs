@@ -1,13 +1,17 @@
-use crate::{Rectangle, Size};
+use crate::{Point, Rectangle, RegionAllocator, Size};
 use euclid::{vec2, point2, size2};
 
 use std::num::Wrapping;
+use std::sync::Arc;
 
 const LARGE_BUCKET: usize = 2;
 const MEDIUM_BUCKET: usize = 1;
 const SMALL_BUCKET: usize = 0;
 const NUM_BUCKETS: usize = 3;
 
+// The maximum number of nodes added to the tree by a regular allocation.
+const NODES_PER_ALLOCATION: usize = 3;
+
 fn free_list_for_size(small_threshold: i32, large_threshold: i32, size: &Size) -> usize {
     if size.width >= large_threshold || size.height >= large_threshold {
         LARGE_BUCKET
@@ -18,43 +22,119 @@ fn free_list_for_size(small_threshold: i32, large_threshold: i32, size: &Size) -
     }
 }
 
+// Number of size classes with `power_of_two_sizes`: one per pair of width and height
+// exponents.
+const NUM_SIZE_CLASSES: usize = 32 * 32;
+
+// Index in AtlasAllocator::size_classes of the class for the given size, or None if the width
+// or height is not a power of two.
+fn size_class(size: &Size) -> Option<usize> {
+    if size.width <= 0 || size.height <= 0 {
+        return None;
+    }
+
+    let (w, h) = (size.width as u32, size.height as u32);
+    if !w.is_power_of_two() || !h.is_power_of_two() {
+        return None;
+    }
+
+    Some(w.trailing_zeros() as usize * 32 + h.trailing_zeros() as usize)
+}
+
+// Round a dimension up to its size class. Dimensions above 2^30 have no size class and
+// saturate to `i32::MAX` so that they are rejected as too large instead of wrapping around.
+fn size_class_dimension(x: i32) -> i32 {
+    (x as u32)
+        .checked_next_power_of_two()
+        .filter(|&s| s <= i32::MAX as u32)
+        .map_or(i32::MAX, |s| s as i32)
+}
+
+// See `AtlasAllocator::allocate_with_score`.
+type ScoreFn<'l> = dyn Fn(&Rectangle, Size) -> i32 + 'l;
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-struct AllocIndex(u32);
+pub(crate) struct AllocIndex(pub(crate) u32);
 impl AllocIndex {
-    const NONE: AllocIndex = AllocIndex(std::u32::MAX);
+    pub(crate) const NONE: AllocIndex = AllocIndex(u32::MAX);
 
-    fn index(self) -> usize {
+    pub(crate) fn index(self) -> usize {
         self.0 as usize
     }
 
-    fn is_none(self) -> bool {
+    pub(crate) fn is_none(self) -> bool {
         self == AllocIndex::NONE
     }
 
-    fn is_some(self) -> bool {
+    pub(crate) fn is_some(self) -> bool {
         self != AllocIndex::NONE
     }
 }
 
+/// The integer representation of `AllocId`.
+///
+/// Ids pack a generation counter and a node index. By default they are 32 bits wide with an
+/// 8 bits generation, which limits the number of nodes to 2^24. With the `wide_ids` feature,
+/// ids are 64 bits wide with a 32 bits generation and a 32 bits index.
+#[cfg(not(feature = "wide_ids"))]
+pub type AllocIdRepr = u32;
+#[cfg(feature = "wide_ids")]
+pub type AllocIdRepr = u64;
+
+#[cfg(not(feature = "wide_ids"))]
+pub(crate) type Generation = u8;
+#[cfg(feature = "wide_ids")]
+pub(crate) type Generation = u32;
+
+#[cfg(not(feature = "wide_ids"))]
+const GEN_SHIFT: u32 = 24;
+#[cfg(feature = "wide_ids")]
+const GEN_SHIFT: u32 = 32;
+
+pub(crate) const IDX_MASK: AllocIdRepr = (1 << GEN_SHIFT) - 1;
+
 /// ID referring to an allocated rectangle.
 #[repr(C)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
-pub struct AllocId(pub(crate) u32);
+pub struct AllocId(pub(crate) AllocIdRepr);
 
 impl AllocId {
-    pub fn serialize(&self) -> u32 {
+    pub fn serialize(&self) -> AllocIdRepr {
         self.0
     }
 
-    pub fn deserialize(bytes: u32) -> Self {
+    pub fn deserialize(bytes: AllocIdRepr) -> Self {
         AllocId(bytes)
     }
+
+    pub(crate) fn new(index: AllocIndex, generation: Generation) -> Self {
+        debug_assert!(index.0 as AllocIdRepr & IDX_MASK == index.0 as AllocIdRepr);
+        AllocId(index.0 as AllocIdRepr | (generation as AllocIdRepr) << GEN_SHIFT)
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn index(self) -> AllocIndex {
+        AllocIndex((self.0 & IDX_MASK) as u32)
+    }
+
+    /// Returns the generation of the slot at the time this id was created.
+    ///
+    /// Generations wrap around after 2^8 allocations in the same slot, or 2^32 with the
+    /// `wide_ids` feature. Mostly useful to diagnose stale id bugs.
+    #[allow(clippy::unnecessary_cast)]
+    pub fn generation(&self) -> u32 {
+        (self.0 >> GEN_SHIFT) as u32
+    }
+
+    // Whether the id was created for the provided generation of its slot.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn matches_generation(self, generation: Generation) -> bool {
+        self.generation() == generation as u32
+    }
 }
 
-const GEN_MASK: u32 = 0xFF000000;
-const IDX_MASK: u32 = 0x00FFFFFF;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -72,12 +152,24 @@ impl Orientation {
     }
 }
 
+/// An axis along which an allocation can be split, see `AtlasAllocator::split`.
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    /// Split at an x coordinate, producing a left and a right rectangle.
+    X,
+    /// Split at a y coordinate, producing a top and a bottom rectangle.
+    Y,
+}
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum NodeKind {
     Container,
     Alloc,
     Free,
+    Reserved,
     Unused,
 }
 
@@ -90,6 +182,100 @@ struct Node {
     kind: NodeKind,
     orientation: Orientation,
     rect: Rectangle,
+    /// Only meaningful for allocated nodes, see `AtlasAllocator::set_pinned`.
+    pinned: bool,
+    /// Only meaningful for allocated nodes, see `AllocatorOptions::padding`.
+    padding: i32,
+    /// Only meaningful for allocated nodes, see `AllocatorOptions::allow_rotation`.
+    rotated: bool,
+    /// Only meaningful for allocated nodes, see `AtlasAllocator::allocate_tagged`.
+    tag: Option<u32>,
+    /// Only meaningful for allocated nodes, see `AtlasAllocator::deallocate_deferred`.
+    retired_frame: Option<u64>,
+}
+
+impl Node {
+    // A node without siblings, with the default attributes.
+    fn new(kind: NodeKind, rect: Rectangle, orientation: Orientation, parent: AllocIndex) -> Self {
+        Node {
+            parent,
+            next_sibling: AllocIndex::NONE,
+            prev_sibling: AllocIndex::NONE,
+            kind,
+            orientation,
+            rect,
+            pinned: false,
+            padding: 0,
+            rotated: false,
+            tag: None,
+            retired_frame: None,
+        }
+    }
+}
+
+/// Heuristic used to choose among the free rectangles that can hold a requested allocation.
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FitPolicy {
+    /// Worst-fit for large allocations and best-short-side-fit for the others.
+    Adaptive,
+    /// Pick the free rectangle with the largest minimum leftover dimension.
+    WorstFit,
+    /// Pick the free rectangle with the smallest leftover area.
+    BestAreaFit,
+    /// Pick the free rectangle with the smallest minimum leftover dimension.
+    BestShortSideFit,
+    /// Pick the first free rectangle that is large enough.
+    FirstFit,
+}
+
+/// Rule used to decide in which direction the free rectangle is cut after an allocation.
+///
+/// Once an allocation is placed in the corner of a free rectangle, the remaining space is split
+/// into two rectangles: one that spans the entire free rectangle along one axis and one that
+/// is adjacent to the allocation. The names follow the terminology of Jukka Jylänki's survey
+/// "A Thousand Ways to Pack the Bin".
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SplitPolicy {
+    /// Cut along the axis with the shorter leftover length.
+    ShorterLeftoverAxis,
+    /// Cut along the axis with the longer leftover length.
+    LongerLeftoverAxis,
+    /// Produce a single large free rectangle at the expense of a small one.
+    MinimizeArea,
+    /// Produce two free rectangles of similar areas.
+    MaximizeArea,
+}
+
+/// Preference for the position of allocations among equally good free rectangles.
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PlacementBias {
+    /// No preference, the first candidate found is picked.
+    None,
+    /// Pick the candidate closest to the origin of the atlas.
+    TopLeft,
+    /// Pick the candidate closest to the top edge of the atlas.
+    Top,
+    /// Pick the candidate closest to the left edge of the atlas.
+    Left,
+}
+
+impl PlacementBias {
+    // Candidates with lower keys are preferred.
+    fn key(self, position: Point) -> i64 {
+        let (x, y) = (position.x as i64, position.y as i64);
+        match self {
+            PlacementBias::None => 0,
+            PlacementBias::TopLeft => x + y,
+            PlacementBias::Top => (y << 32) + x,
+            PlacementBias::Left => (x << 32) + y,
+        }
+    }
 }
 
 /// Options to tweak the behavior of the atlas allocator.
@@ -119,14 +305,112 @@ pub struct AllocatorOptions {
     ///
     /// Default value: 256,
     pub large_size_threshold: i32,
+
+    /// Number of pixels added on each side of the allocated rectangles.
+    ///
+    /// Padding is typically used to avoid bleeding between neighbor allocations when
+    /// sampling the atlas with bilinear filtering. The padded rectangle is rounded up to
+    /// the alignment.
+    ///
+    /// Default value: 0,
+    pub padding: i32,
+
+    /// Allow the allocator to rotate the requested rectangles by 90 degrees when it
+    /// leads to a better fit.
+    ///
+    /// Rotated allocations are reported via `Allocation::rotated`.
+    ///
+    /// Default value: false,
+    pub allow_rotation: bool,
+
+    /// Heuristic used to select a free rectangle for each allocation.
+    ///
+    /// Default value: FitPolicy::Adaptive,
+    pub fit_policy: FitPolicy,
+
+    /// Rule used to split the free rectangles.
+    ///
+    /// Default value: SplitPolicy::MinimizeArea,
+    pub split_policy: SplitPolicy,
+
+    /// Defer the coalescing of free rectangles.
+    ///
+    /// When enabled, deallocated rectangles are immediately available for new allocations
+    /// but are only merged with their free neighbors when `AtlasAllocator::coalesce` is
+    /// called or when an allocation fails. This lowers the cost of deallocations at the
+    /// expense of more fragmentation in the mean time.
+    ///
+    /// Default value: false,
+    pub deferred_coalescing: bool,
+
+    /// Free rectangles narrower or shorter than this value are not tracked in the free lists.
+    ///
+    /// Such slivers can't be allocated until they are merged with neighboring free space,
+    /// but skipping them keeps the free lists short, which speeds up lookups.
+    ///
+    /// Default value: 0,
+    pub min_free_size: i32,
+
+    /// Try the free rectangles next to the previous allocation before searching the free lists.
+    ///
+    /// This makes sequential allocations of similar sizes (for example when rasterizing
+    /// glyphs) cheap and keeps them close to each other, at the expense of a less careful
+    /// choice of free rectangle. The fit policy is only used when the neighbors of the
+    /// previous allocation can't hold the requested size.
+    ///
+    /// Default value: false,
+    pub next_fit: bool,
+
+    /// Preference for the position of allocations among free rectangles that are equally good
+    /// according to the fit policy.
+    ///
+    /// Biasing allocations toward the origin keeps the occupied region compact, so that the
+    /// atlas texture can be shrunk or partially uploaded. With a bias, perfect fits are
+    /// compared against each other instead of picking the first one, which makes allocations
+    /// a bit slower. The `FirstFit` policy picks the best placed free rectangle that is large
+    /// enough.
+    ///
+    /// Default value: PlacementBias::None,
+    pub placement_bias: PlacementBias,
+
+    /// Round the width and height of the allocations up to powers of two.
+    ///
+    /// Free rectangles with power of two dimensions are also tracked in a list per size
+    /// class, which lets allocations reuse a free rectangle of exactly the same size
+    /// without searching the free lists. This is most useful for workloads where the
+    /// requested sizes are already powers of two, such as mip tiles or virtual texture pages.
+    /// The extra space added by the rounding is part of the allocation's content rectangle.
+    ///
+    /// Default value: false,
+    pub power_of_two_sizes: bool,
 }
 
 pub const DEFAULT_OPTIONS: AllocatorOptions = AllocatorOptions {
     alignment: size2(1,  1),
     large_size_threshold: 256,
     small_size_threshold: 32,
+    padding: 0,
+    allow_rotation: false,
+    fit_policy: FitPolicy::Adaptive,
+    split_policy: SplitPolicy::MinimizeArea,
+    deferred_coalescing: false,
+    min_free_size: 0,
+    next_fit: false,
+    placement_bias: PlacementBias::None,
+    power_of_two_sizes: false,
 };
 
+impl AllocatorOptions {
+    /// Returns false if the options would make the allocator panic.
+    pub fn is_valid(&self) -> bool {
+        self.alignment.width > 0
+            && self.alignment.height > 0
+            && self.large_size_threshold >= self.small_size_threshold
+            && self.padding >= 0
+            && self.min_free_size >= 0
+    }
+}
+
 impl Default for AllocatorOptions {
     fn default() -> Self {
         DEFAULT_OPTIONS
@@ -314,7 +598,8 @@ impl Default for AllocatorOptions {
 ///
 /// This algorithm is, however, not the best solution for very "structured" grid-like
 /// subdivision patterns where the ability to merge across containers would have provided
-/// frequent defragmentation opportunities.
+/// frequent defragmentation opportunities. For these workloads `coalesce_aggressively`
+/// can be called from time to time to restructure the tree and merge such rectangles.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 pub struct AtlasAllocator {
@@ -328,7 +613,7 @@ pub struct AtlasAllocator {
 
     /// We keep a per-node generation counter to reduce the likelihood of ID reuse bugs
     /// going unnoticed.
-    generations: Vec<Wrapping<u8>>,
+    generations: Vec<Wrapping<Generation>>,
 
     /// See `AllocatorOptions`.
     alignment: Size,
@@ -339,11 +624,63 @@ pub struct AtlasAllocator {
     /// See `AllocatorOptions`.
     large_size_threshold: i32,
 
+    /// See `AllocatorOptions`.
+    padding: i32,
+
+    /// See `AllocatorOptions`.
+    allow_rotation: bool,
+
+    /// See `AllocatorOptions`.
+    fit_policy: FitPolicy,
+
+    /// See `AllocatorOptions`.
+    split_policy: SplitPolicy,
+
+    /// See `AllocatorOptions`.
+    deferred_coalescing: bool,
+
+    /// See `AllocatorOptions`.
+    min_free_size: i32,
+
+    /// See `AllocatorOptions`.
+    next_fit: bool,
+
+    /// See `AllocatorOptions`.
+    placement_bias: PlacementBias,
+
+    /// See `AllocatorOptions`.
+    power_of_two_sizes: bool,
+
+    /// Free nodes with power of two dimensions, indexed by `size_class`. Only used with
+    /// `power_of_two_sizes`, and like the free lists, it may contain stale entries.
+    size_classes: Vec<Vec<AllocIndex>>,
+
+    /// The most recent allocation, which is where the search starts with `next_fit`.
+    last_alloc: AllocIndex,
+
+    /// Free nodes that haven't been merged with their neighbors yet.
+    uncoalesced: Vec<AllocIndex>,
+
     /// Total size of the atlas.
     size: Size,
 
     /// Index of one of the top-level nodes in the tree.
     root_node: AllocIndex,
+
+    /// Sum of the areas of the allocated rectangles.
+    allocated_space: i64,
+
+    /// Number of allocated rectangles.
+    allocation_count: usize,
+
+    /// Sum of the areas of the reserved regions.
+    reserved_space: i64,
+
+    /// Largest width and height of the free rectangles, see `largest_free_rect_size`.
+    ///
+    /// It grows when free rectangles are added and is recomputed when one of the largest free
+    /// rectangles is allocated from.
+    largest_free_rect: Size,
 }
 
 // Some notes about the atlas's tree data structure:
@@ -405,6 +742,8 @@ impl AtlasAllocator {
         assert!(size.width > 0);
         assert!(size.height > 0);
         assert!(options.large_size_threshold >= options.small_size_threshold);
+        assert!(options.padding >= 0);
+        assert!(options.min_free_size >= 0);
 
         let mut free_lists = [Vec::new(), Vec::new(), Vec::new()];
         let bucket = free_list_for_size(
@@ -415,1340 +754,8249 @@ impl AtlasAllocator {
         free_lists[bucket].push(AllocIndex(0));
 
         AtlasAllocator {
-            nodes: vec![Node {
-                parent: AllocIndex::NONE,
-                next_sibling: AllocIndex::NONE,
-                prev_sibling: AllocIndex::NONE,
-                rect: size.into(),
-                kind: NodeKind::Free,
-                orientation: Orientation::Vertical,
-            }],
+            nodes: vec![Node::new(
+                NodeKind::Free,
+                size.into(),
+                Orientation::Vertical,
+                AllocIndex::NONE,
+            )],
             free_lists,
             generations: vec![Wrapping(0)],
             unused_nodes: AllocIndex::NONE,
             alignment: options.alignment,
             small_size_threshold: options.small_size_threshold,
             large_size_threshold: options.large_size_threshold,
+            padding: options.padding,
+            allow_rotation: options.allow_rotation,
+            fit_policy: options.fit_policy,
+            split_policy: options.split_policy,
+            deferred_coalescing: options.deferred_coalescing,
+            min_free_size: options.min_free_size,
+            next_fit: options.next_fit,
+            placement_bias: options.placement_bias,
+            power_of_two_sizes: options.power_of_two_sizes,
+            size_classes: Vec::new(),
+            last_alloc: AllocIndex::NONE,
+            uncoalesced: Vec::new(),
             size,
             root_node: AllocIndex(0),
+            allocated_space: 0,
+            allocation_count: 0,
+            reserved_space: 0,
+            largest_free_rect: size,
         }
     }
 
-    /// The total size of the atlas.
-    pub fn size(&self) -> Size {
-        self.size
+    /// Create an atlas allocator from the allocations of a `SimpleAtlasAllocator`.
+    ///
+    /// The simple allocator doesn't keep track of its allocations, so the allocated rectangles
+    /// must be provided. The new atlas has the same size and options as the simple allocator,
+    /// and the returned ids are in the same order as the rectangles.
+    ///
+    /// This makes it possible to pack a lot of rectangles quickly with the simple allocator
+    /// and upgrade it into an atlas that supports deallocation. Returns `None` if the
+    /// rectangles overlap, are out of the atlas's bounds or can't be separated with
+    /// guillotine cuts, which never happens with rectangles allocated by the simple allocator.
+    pub fn from_simple(
+        simple: &SimpleAtlasAllocator,
+        allocated: &[Rectangle],
+    ) -> Option<(AtlasAllocator, Vec<AllocId>)> {
+        let options = AllocatorOptions {
+            alignment: simple.alignment,
+            small_size_threshold: simple.small_size_threshold,
+            large_size_threshold: simple.large_size_threshold,
+            split_policy: simple.split_policy,
+            ..DEFAULT_OPTIONS
+        };
+        let (atlas, indices) = AtlasAllocator::from_rectangles(simple.size, allocated, &options)?;
+
+        let ids = indices.iter().map(|&idx| atlas.alloc_id(idx)).collect();
+
+        Some((atlas, ids))
     }
 
-    /// Allocate a rectangle in the atlas.
-    pub fn allocate(&mut self, mut requested_size: Size) -> Option<Allocation> {
-        if requested_size.is_empty() {
-            return None;
+    /// Create an atlas allocator from a list of allocations and their rectangles, keeping
+    /// their ids.
+    ///
+    /// This restores an atlas that supports further allocations and deallocations from the
+    /// allocations alone, for example after persisting only the ids and rectangles of an
+    /// atlas. The free space is subdivided around the provided rectangles, so the tree may
+    /// differ from the one of the original atlas. The padding and rotation of the allocations
+    /// are not restored: their content is their whole rectangle.
+    ///
+    /// Returns `None` if two allocations have the same index, or if the rectangles overlap,
+    /// are out of the atlas's bounds or can't be separated with guillotine cuts, which never
+    /// happens with the allocations of an `AtlasAllocator` of the same size.
+    pub fn from_allocations(
+        size: Size,
+        allocations: &[(AllocId, Rectangle)],
+        options: &AllocatorOptions,
+    ) -> Option<AtlasAllocator> {
+        let rects: Vec<Rectangle> = allocations.iter().map(|(_, rect)| *rect).collect();
+        let (mut atlas, indices) = AtlasAllocator::from_rectangles(size, &rects, options)?;
+
+        let len = allocations
+            .iter()
+            .map(|(id, _)| id.index().index() + 1)
+            .max()
+            .unwrap_or(0)
+            .max(atlas.nodes.len());
+        while atlas.nodes.len() < len {
+            atlas.nodes.push(Node::new(
+                NodeKind::Unused,
+                Rectangle::zero(),
+                Orientation::Horizontal,
+                AllocIndex::NONE,
+            ));
         }
 
-        adjust_size(self.alignment.width, &mut requested_size.width);
-        adjust_size(self.alignment.height, &mut requested_size.height);
+        // Move each allocation into the slot of its id and distribute the other nodes among
+        // the remaining slots.
+        let mut permutation = vec![AllocIndex::NONE; len];
+        let mut taken = vec![false; len];
+        for ((id, _), src) in allocations.iter().zip(&indices) {
+            let dst = id.index();
+            if taken[dst.index()] {
+                return None;
+            }
+            permutation[src.index()] = dst;
+            taken[dst.index()] = true;
+        }
 
-        // Find a suitable free rect.
-        let chosen_id = self.find_suitable_rect(&requested_size);
+        let mut slot = 0;
+        for dst in &mut permutation {
+            if dst.is_some() {
+                continue;
+            }
+            while taken[slot] {
+                slot += 1;
+            }
+            *dst = AllocIndex(slot as u32);
+            taken[slot] = true;
+        }
 
-        if chosen_id.is_none() {
-            //println!("failed to allocate {:?}", requested_size);
-            //self.print_free_rects();
+        atlas.remap_nodes(&permutation);
 
-            // No suitable free rect!
-            return None;
+        atlas.generations = vec![Wrapping(0); len];
+        for (id, _) in allocations {
+            atlas.generations[id.index().index()] = Wrapping(id.generation() as Generation);
         }
 
-        let chosen_node = self.nodes[chosen_id.index()].clone();
-        let chosen_rect = chosen_node.rect;
-        let allocated_rect = Rectangle {
-            min: chosen_rect.min,
-            max: chosen_rect.min + requested_size.to_vector(),
-        };
-        let current_orientation = chosen_node.orientation;
-        assert_eq!(chosen_node.kind, NodeKind::Free);
-
-        let (split_rect, leftover_rect, orientation) =
-            guillotine_rect(&chosen_node.rect, requested_size, current_orientation);
+        #[cfg(feature = "checks")]
+        atlas.check_tree();
 
-        // Update the tree.
+        Some(atlas)
+    }
 
-        let allocated_id;
-        let split_id;
-        let leftover_id;
+    // Create an atlas with the provided allocated rectangles. Returns the atlas along with
+    // the indices of the allocations, in the same order as the rectangles.
+    fn from_rectangles(
+        size: Size,
+        allocated: &[Rectangle],
+        options: &AllocatorOptions,
+    ) -> Option<(AtlasAllocator, Vec<AllocIndex>)> {
+        let bounds: Rectangle = size.into();
+        if allocated.iter().any(|rect| rect.is_empty() || !bounds.contains_box(rect)) {
+            return None;
+        }
 
-        //println!("{:?} -> {:?}", current_orientation, orientation);
-        if orientation == current_orientation {
-            if !split_rect.is_empty() {
-                let next_sibling = chosen_node.next_sibling;
+        let mut atlas = AtlasAllocator::with_options(size, options);
 
-                split_id = self.new_node();
-                self.nodes[split_id.index()] = Node {
-                    parent: chosen_node.parent,
-                    next_sibling,
-                    prev_sibling: chosen_id,
-                    rect: split_rect,
-                    kind: NodeKind::Free,
-                    orientation: current_orientation,
-                };
+        atlas.nodes.clear();
+        atlas.generations.clear();
+        for list in &mut atlas.free_lists {
+            list.clear();
+        }
+        atlas.largest_free_rect = Size::zero();
+
+        let rects: Vec<(usize, Rectangle)> = allocated.iter().cloned().enumerate().collect();
+        let mut indices = vec![AllocIndex::NONE; allocated.len()];
+        let top_level = atlas.build_sibling_list(
+            bounds,
+            &rects,
+            Orientation::Vertical,
+            AllocIndex::NONE,
+            &mut indices,
+        )?;
+        atlas.root_node = top_level[0];
 
-                self.nodes[chosen_id.index()].next_sibling = split_id;
-                if next_sibling.is_some() {
-                    self.nodes[next_sibling.index()].prev_sibling = split_id;
-                }
-            } else {
-                split_id = AllocIndex::NONE;
+        for idx in 0..atlas.nodes.len() {
+            if atlas.nodes[idx].kind == NodeKind::Free {
+                let size = atlas.nodes[idx].rect.size();
+                atlas.add_free_rect(AllocIndex(idx as u32), &size);
             }
+        }
 
-            if !leftover_rect.is_empty() {
-                self.nodes[chosen_id.index()].kind = NodeKind::Container;
+        atlas.allocated_space = allocated.iter().map(|rect| area(rect.size())).sum();
+        atlas.allocation_count = allocated.len();
 
-                allocated_id = self.new_node();
-                leftover_id = self.new_node();
+        #[cfg(feature = "checks")]
+        atlas.check_tree();
 
-                self.nodes[allocated_id.index()] = Node {
-                    parent: chosen_id,
-                    next_sibling: leftover_id,
-                    prev_sibling: AllocIndex::NONE,
-                    rect: allocated_rect,
-                    kind: NodeKind::Alloc,
-                    orientation: current_orientation.flipped(),
-                };
+        Some((atlas, indices))
+    }
 
-                self.nodes[leftover_id.index()] = Node {
-                    parent: chosen_id,
-                    next_sibling: AllocIndex::NONE,
-                    prev_sibling: allocated_id,
-                    rect: leftover_rect,
-                    kind: NodeKind::Free,
-                    orientation: current_orientation.flipped(),
-                };
-            } else {
-                // No need to split for the leftover area, we can allocate directly in the chosen node.
-                allocated_id = chosen_id;
-                let node = &mut self.nodes[chosen_id.index()];
-                node.kind = NodeKind::Alloc;
-                node.rect = allocated_rect;
+    // Build the nodes of a sibling list covering `region` with the provided orientation by
+    // cutting it between the rectangles. Returns the nodes of the list in order, or None if
+    // the rectangles can't be separated with guillotine cuts.
+    fn build_sibling_list(
+        &mut self,
+        region: Rectangle,
+        rects: &[(usize, Rectangle)],
+        orientation: Orientation,
+        parent: AllocIndex,
+        indices: &mut [AllocIndex],
+    ) -> Option<Vec<AllocIndex>> {
+        let range = |rect: &Rectangle| match orientation {
+            Orientation::Horizontal => (rect.min.x, rect.max.x),
+            Orientation::Vertical => (rect.min.y, rect.max.y),
+        };
 
-                leftover_id = AllocIndex::NONE
+        // Cut wherever no rectangle crosses the line.
+        let (start, end) = range(&region);
+        let mut cuts: Vec<i32> = rects
+            .iter()
+            .flat_map(|(_, rect)| {
+                let (min, max) = range(rect);
+                [min, max]
+            })
+            .filter(|&pos| pos > start && pos < end)
+            .filter(|&pos| rects.iter().all(|(_, rect)| {
+                let (min, max) = range(rect);
+                pos <= min || pos >= max
+            }))
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        cuts.push(end);
+
+        // Split the rectangles into slabs (start, end, rectangles), merging consecutive empty
+        // slabs.
+        let mut slabs: Vec<(i32, i32, Vec<_>)> = Vec::new();
+        let mut slab_start = start;
+        for cut in cuts {
+            let slab_rects: Vec<(usize, Rectangle)> = rects
+                .iter()
+                .filter(|(_, rect)| range(rect).0 >= slab_start && range(rect).1 <= cut)
+                .cloned()
+                .collect();
+
+            match slabs.last_mut() {
+                Some((_, slab_end, prev_rects)) if prev_rects.is_empty() && slab_rects.is_empty() => {
+                    *slab_end = cut;
+                }
+                _ => {
+                    slabs.push((slab_start, cut, slab_rects));
+                }
             }
-        } else {
-            self.nodes[chosen_id.index()].kind = NodeKind::Container;
+            slab_start = cut;
+        }
 
-            if !split_rect.is_empty() {
-                split_id = self.new_node();
-                self.nodes[split_id.index()] = Node {
-                    parent: chosen_id,
-                    next_sibling: AllocIndex::NONE,
-                    prev_sibling: AllocIndex::NONE,
-                    rect: split_rect,
-                    kind: NodeKind::Free,
-                    orientation: current_orientation.flipped(),
-                };
-            } else {
-                split_id = AllocIndex::NONE;
-            }
+        // Containers must have at least two children, otherwise the rectangles can't be
+        // separated.
+        if slabs.len() < 2 && parent.is_some() {
+            return None;
+        }
 
-            if !leftover_rect.is_empty() {
-                let container_id = self.new_node();
-                self.nodes[container_id.index()] = Node {
-                    parent: chosen_id,
-                    next_sibling: split_id,
-                    prev_sibling: AllocIndex::NONE,
-                    rect: Rectangle::zero(),
-                    kind: NodeKind::Container,
-                    orientation: current_orientation.flipped(),
-                };
+        let mut list = Vec::new();
+        let mut prev = AllocIndex::NONE;
+        for (slab_start, slab_end, slab_rects) in slabs {
+            let slab = match orientation {
+                Orientation::Horizontal => Rectangle {
+                    min: point2(slab_start, region.min.y),
+                    max: point2(slab_end, region.max.y),
+                },
+                Orientation::Vertical => Rectangle {
+                    min: point2(region.min.x, slab_start),
+                    max: point2(region.max.x, slab_end),
+                },
+            };
 
-                self.nodes[split_id.index()].prev_sibling = container_id;
+            let node = self.new_node();
+            self.nodes[node.index()] = Node {
+                prev_sibling: prev,
+                ..Node::new(NodeKind::Free, slab, orientation, parent)
+            };
+            if prev.is_some() {
+                self.nodes[prev.index()].next_sibling = node;
+            }
+            prev = node;
+            list.push(node);
+
+            match slab_rects.as_slice() {
+                [] => {}
+                [(rect_index, rect)] if *rect == slab => {
+                    self.nodes[node.index()].kind = NodeKind::Alloc;
+                    indices[*rect_index] = node;
+                }
+                _ => {
+                    self.nodes[node.index()].kind = NodeKind::Container;
+                    self.build_sibling_list(slab, &slab_rects, orientation.flipped(), node, indices)?;
+                }
+            }
+        }
 
-                allocated_id = self.new_node();
-                leftover_id = self.new_node();
+        Some(list)
+    }
 
-                self.nodes[allocated_id.index()] = Node {
-                    parent: container_id,
-                    next_sibling: leftover_id,
-                    prev_sibling: AllocIndex::NONE,
-                    rect: allocated_rect,
-                    kind: NodeKind::Alloc,
-                    orientation: current_orientation,
-                };
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
 
-                self.nodes[leftover_id.index()] = Node {
-                    parent: container_id,
-                    next_sibling: AllocIndex::NONE,
-                    prev_sibling: allocated_id,
-                    rect: leftover_rect,
-                    kind: NodeKind::Free,
-                    orientation: current_orientation,
-                };
-            } else {
-                allocated_id = self.new_node();
-                self.nodes[allocated_id.index()] = Node {
-                    parent: chosen_id,
-                    next_sibling: split_id,
-                    prev_sibling: AllocIndex::NONE,
-                    rect: allocated_rect,
-                    kind: NodeKind::Alloc,
-                    orientation: current_orientation.flipped(),
-                };
+    /// The options the atlas was created with.
+    pub fn options(&self) -> AllocatorOptions {
+        AllocatorOptions {
+            alignment: self.alignment,
+            small_size_threshold: self.small_size_threshold,
+            large_size_threshold: self.large_size_threshold,
+            padding: self.padding,
+            allow_rotation: self.allow_rotation,
+            fit_policy: self.fit_policy,
+            split_policy: self.split_policy,
+            deferred_coalescing: self.deferred_coalescing,
+            min_free_size: self.min_free_size,
+            next_fit: self.next_fit,
+            placement_bias: self.placement_bias,
+            power_of_two_sizes: self.power_of_two_sizes,
+        }
+    }
 
-                self.nodes[split_id.index()].prev_sibling = allocated_id;
+    /// Allocate a rectangle in the atlas, returning the reason of the failure if any.
+    ///
+    /// The error helps deciding how to handle the failure: `TooLarge` and `OutOfSpace` call
+    /// for growing the atlas, while rearranging it may be enough in the case of `Fragmented`.
+    pub fn try_allocate(&mut self, requested_size: Size) -> Result<Allocation, AllocError> {
+        if requested_size.is_empty() {
+            return Err(AllocError::InvalidSize);
+        }
 
-                leftover_id = AllocIndex::NONE;
-            }
+        if let Some(alloc) = self.allocate(requested_size) {
+            return Ok(alloc);
         }
 
-        assert_eq!(self.nodes[allocated_id.index()].kind, NodeKind::Alloc);
+        let (size, _) = self.padded_sizes(requested_size, self.padding, self.alignment);
 
-        if split_id.is_some() {
-            self.add_free_rect(split_id, &split_rect.size());
-        }
+        let fits = size.width <= self.size.width && size.height <= self.size.height;
+        let fits_rotated = self.allow_rotation
+            && size.height <= self.size.width
+            && size.width <= self.size.height;
 
-        if leftover_id.is_some() {
-            self.add_free_rect(leftover_id, &leftover_rect.size());
+        if !fits && !fits_rotated {
+            Err(AllocError::TooLarge)
+        } else if area(size) <= self.free_space() {
+            Err(AllocError::Fragmented)
+        } else {
+            Err(AllocError::OutOfSpace)
         }
+    }
 
-        //println!("allocated {:?}     split: {:?} leftover: {:?}", allocated_rect, split_rect, leftover_rect);
-        //self.print_free_rects();
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        self.allocate_padded(requested_size, self.padding, self.alignment, None)
+    }
 
-        #[cfg(feature = "checks")]
-        self.check_tree();
-
-        Some(Allocation {
-            id: self.alloc_id(allocated_id),
-            rectangle: allocated_rect,
-        })
-    }
-
-    /// Deallocate a rectangle in the atlas.
-    pub fn deallocate(&mut self, node_id: AllocId) {
-        let mut node_id = self.get_index(node_id);
-
-        assert!(node_id.index() < self.nodes.len());
-        assert_eq!(self.nodes[node_id.index()].kind, NodeKind::Alloc);
-
-        self.nodes[node_id.index()].kind = NodeKind::Free;
+    /// Allocate the best fitting of several acceptable sizes.
+    ///
+    /// Every size that fits in a free rectangle is scored by the area that the allocation
+    /// would leave unused in the tightest free rectangle that can hold it, and the size with
+    /// the smallest leftover is allocated in that rectangle. Ties go to the size that comes
+    /// first, so the sizes should be listed in order of preference, for example from the
+    /// highest to the lowest resolution of a texture. Returns the index of the allocated size
+    /// along with the allocation.
+    pub fn allocate_best_of(&mut self, sizes: &[Size]) -> Option<(usize, Allocation)> {
+        let mut best = self.best_of(sizes);
+        if best.is_none() && !self.uncoalesced.is_empty() {
+            // Merging the free rectangles may make room for one of the sizes.
+            self.coalesce();
+            best = self.best_of(sizes);
+        }
 
-        loop {
-            let orientation = self.nodes[node_id.index()].orientation;
+        let idx = best?;
+        let leftover = |rect: &Rectangle, size: Size| {
+            (area(rect.size()) - area(size)).min(i32::MAX as i64) as i32
+        };
+        let alloc = self.allocate_with_score(sizes[idx], &leftover)?;
 
-            let next = self.nodes[node_id.index()].next_sibling;
-            let prev = self.nodes[node_id.index()].prev_sibling;
+        Some((idx, alloc))
+    }
 
-            // Try to merge with the next node.
-            if next.is_some() && self.nodes[next.index()].kind == NodeKind::Free {
-                self.merge_siblings(node_id, next, orientation);
+    // Returns the index of the size that leaves the smallest area unused in the free
+    // rectangle that fits it most tightly, see `allocate_best_of`.
+    fn best_of(&self, sizes: &[Size]) -> Option<usize> {
+        let alignment = self.alignment;
+        let mut best: Option<(usize, i64)> = None;
+        for (idx, &size) in sizes.iter().enumerate() {
+            if size.is_empty() {
+                continue;
             }
 
-            // Try to merge with the previous node.
-            if prev.is_some() && self.nodes[prev.index()].kind == NodeKind::Free {
-                self.merge_siblings(prev, node_id, orientation);
-                node_id = prev;
+            let (padded, rotated) = self.padded_sizes(size, self.padding, alignment);
+            let bound = self.largest_free_rect;
+            let fits = |size: &Size| size.width <= bound.width && size.height <= bound.height;
+            if !fits(&padded) && !rotated.as_ref().is_some_and(fits) {
+                continue;
             }
 
-            // If this node is now a unique child. We collapse it into its parent and try to merge
-            // again at the parent level.
-            let parent = self.nodes[node_id.index()].parent;
-            if self.nodes[node_id.index()].prev_sibling.is_none()
-                && self.nodes[node_id.index()].next_sibling.is_none()
-                && parent.is_some()
-            {
-                debug_assert_eq!(self.nodes[parent.index()].kind, NodeKind::Container);
-
-                self.mark_node_unused(node_id);
+            for node in &self.nodes {
+                if node.kind != NodeKind::Free {
+                    continue;
+                }
 
-                // Replace the parent container with a free node.
-                self.nodes[parent.index()].rect = self.nodes[node_id.index()].rect;
-                self.nodes[parent.index()].kind = NodeKind::Free;
+                // Only the part of the rect after the first aligned position is usable.
+                let position = aligned_position(&alignment, node.rect.min);
+                let usable = node.rect.max - position;
+                for candidate in std::iter::once(&padded).chain(rotated.as_ref()) {
+                    if candidate.width > usable.x || candidate.height > usable.y {
+                        continue;
+                    }
 
-                // Start again at the parent level.
-                node_id = parent;
-            } else {
-                let size = self.nodes[node_id.index()].rect.size();
-                self.add_free_rect(node_id, &size);
-                break;
+                    let leftover = area(node.rect.size()) - area(*candidate);
+                    if best.is_none_or(|(_, best_leftover)| leftover < best_leftover) {
+                        best = Some((idx, leftover));
+                    }
+                }
             }
         }
 
-        #[cfg(feature = "checks")]
-        self.check_tree();
+        best.map(|(idx, _)| idx)
     }
 
-    pub fn is_empty(&self) -> bool {
-        let root = &self.nodes[self.root_node.index()];
-
-        root.kind == NodeKind::Free && root.next_sibling.is_none()
+    /// Allocate a rectangle in the atlas, using a custom function to choose among the free
+    /// rectangles that can hold it instead of the fit policy.
+    ///
+    /// The scoring function receives each candidate free rectangle and the size that would be
+    /// allocated in it (which is the rotated size if the allocation would be rotated). The
+    /// candidate with the lowest score is selected.
+    pub fn allocate_with_score(
+        &mut self,
+        requested_size: Size,
+        score: &dyn Fn(&Rectangle, Size) -> i32,
+    ) -> Option<Allocation> {
+        self.allocate_padded(requested_size, self.padding, self.alignment, Some(score))
     }
 
-    /// Drop all rectangles, clearing the atlas to its initial state.
-    pub fn clear(&mut self) {
-        self.nodes.clear();
-        self.nodes.push(Node {
-            parent: AllocIndex::NONE,
-            next_sibling: AllocIndex::NONE,
-            prev_sibling: AllocIndex::NONE,
-            rect: self.size.into(),
-            kind: NodeKind::Free,
-            orientation: Orientation::Vertical,
-        });
+    /// Allocate a rectangle in the atlas, overriding the alignment specified in the options.
+    ///
+    /// The size of the rectangle is rounded up to a multiple of the alignment and its position
+    /// is a multiple of the alignment as well.
+    pub fn allocate_with_alignment(&mut self, requested_size: Size, alignment: Size) -> Option<Allocation> {
+        assert!(alignment.width > 0);
+        assert!(alignment.height > 0);
 
-        self.root_node = AllocIndex(0);
+        self.allocate_padded(requested_size, self.padding, alignment, None)
+    }
 
-        self.generations.clear();
-        self.generations.push(Wrapping(0));
+    /// Allocate a rectangle in the atlas, overriding the padding specified in the options.
+    pub fn allocate_with_padding(&mut self, requested_size: Size, padding: i32) -> Option<Allocation> {
+        assert!(padding >= 0);
 
-        self.unused_nodes = AllocIndex::NONE;
+        self.allocate_padded(requested_size, padding, self.alignment, None)
+    }
 
-        let bucket = free_list_for_size(
-            self.small_size_threshold,
-            self.large_size_threshold,
-            &self.size,
-        );
-        for i in 0..NUM_BUCKETS {
-            self.free_lists[i].clear();
-        }
-        self.free_lists[bucket].push(AllocIndex(0));
+    /// Allocate a rectangle in the atlas and associate it with a tag.
+    ///
+    /// All allocations sharing a tag can be deallocated at once with `release_tag`, which is
+    /// convenient for transient allocations that only live for a frame.
+    pub fn allocate_tagged(&mut self, requested_size: Size, tag: u32) -> Option<Allocation> {
+        let alloc = self.allocate(requested_size)?;
+        let index = self.get_index(alloc.id);
+        self.nodes[index.index()].tag = Some(tag);
+
+        Some(alloc)
     }
 
-    /// Clear the allocator and reset its size and options.
-    pub fn reset(&mut self, size: Size, options: &AllocatorOptions) {
-        self.alignment = options.alignment;
-        self.small_size_threshold = options.small_size_threshold;
-        self.large_size_threshold = options.large_size_threshold;
-        self.size = size;
+    /// Deallocate all of the rectangles allocated with the provided tag.
+    pub fn release_tag(&mut self, tag: u32) {
+        let ids: Vec<AllocId> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.kind == NodeKind::Alloc && node.tag == Some(tag))
+            .map(|(idx, _)| self.alloc_id(AllocIndex(idx as u32)))
+            .collect();
 
-        self.clear();
+        self.deallocate_many(&ids);
     }
 
-    /// Recompute the allocations in the atlas and returns a list of the changes.
-    ///
-    /// Previous ids and rectangles are not valid anymore after this operation as each id/rectangle
-    /// pair is assigned to new values which are communicated in the returned change list.
-    /// Rearranging the atlas can help reduce fragmentation.
-    pub fn rearrange(&mut self) -> ChangeList {
-        let size = self.size;
-        self.resize_and_rearrange(size)
+    /// Returns the tag of an allocation, if it was allocated with `allocate_tagged`.
+    pub fn tag(&self, id: AllocId) -> Option<u32> {
+        self.nodes[self.get_index(id).index()].tag
     }
 
-    /// Identical to `AtlasAllocator::rearrange`, also allowing to change the size of the atlas.
-    pub fn resize_and_rearrange(&mut self, new_size: Size) -> ChangeList {
-        let mut allocs = Vec::with_capacity(self.nodes.len());
-        for (i, node) in self.nodes.iter().enumerate() {
+    /// Returns the area allocated with each tag, sorted by tag.
+    ///
+    /// This makes it possible to attribute the memory usage of an atlas shared by several
+    /// users, for example to decide which of them should release allocations. Allocations
+    /// without a tag are not counted.
+    pub fn area_by_tag(&self) -> Vec<TagUsage> {
+        let mut usage: Vec<TagUsage> = Vec::new();
+        for node in &self.nodes {
             if node.kind != NodeKind::Alloc {
                 continue;
             }
-            let id = self.alloc_id(AllocIndex(i as u32));
-            allocs.push(Allocation {
-                id,
-                rectangle: node.rect,
-            });
+            let tag = match node.tag {
+                Some(tag) => tag,
+                None => continue,
+            };
+
+            let index = match usage.binary_search_by_key(&tag, |usage| usage.tag) {
+                Ok(index) => index,
+                Err(index) => {
+                    usage.insert(index, TagUsage { tag, allocated_space: 0, allocation_count: 0 });
+                    index
+                }
+            };
+            usage[index].allocated_space += area(node.rect.size());
+            usage[index].allocation_count += 1;
         }
 
-        allocs.sort_by_key(|alloc| safe_area(&alloc.rectangle));
-        allocs.reverse();
+        usage
+    }
 
-        self.size = new_size;
-        self.clear();
+    fn allocate_padded(
+        &mut self,
+        requested_size: Size,
+        padding: i32,
+        alignment: Size,
+        score: Option<&ScoreFn>,
+    ) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
 
-        let mut changes = Vec::new();
-        let mut failures = Vec::new();
+        let (size, rotated_size) = self.padded_sizes(requested_size, padding, alignment);
 
-        for old in allocs {
-            let size = old.rectangle.size();
-            if let Some(new) = self.allocate(size) {
-                changes.push(Change { old, new });
-            } else {
-                failures.push(old);
+        let allocated_id = self.allocate_index(&size, rotated_size.as_ref(), &alignment, score);
+        if allocated_id.is_none() {
+            return None;
+        }
+
+        let node = &mut self.nodes[allocated_id.index()];
+        node.padding = padding;
+        node.rotated = node.rect.size() != size;
+
+        Some(self.allocation(allocated_id))
+    }
+
+    // Returns the size of the slot to allocate for the requested size and, if rotation is
+    // allowed, the size of the rotated slot.
+    pub(crate) fn padded_sizes(&self, requested_size: Size, padding: i32, alignment: Size) -> (Size, Option<Size>) {
+        let padded_size = |mut size: Size| {
+            size.width += padding * 2;
+            size.height += padding * 2;
+            adjust_size(alignment.width, &mut size.width);
+            adjust_size(alignment.height, &mut size.height);
+            if self.power_of_two_sizes {
+                size.width = size_class_dimension(size.width);
+                size.height = size_class_dimension(size.height);
             }
+            size
+        };
+
+        let size = padded_size(requested_size);
+        let mut rotated_size = None;
+        if self.allow_rotation && requested_size.width != requested_size.height {
+            rotated_size = Some(padded_size(size2(requested_size.height, requested_size.width)));
         }
 
-        ChangeList { changes, failures }
+        (size, rotated_size)
     }
 
-    /// Resize the atlas without changing the allocations.
-    ///
-    /// This method is not allowed to shrink the width or height of the atlas.
-    pub fn grow(&mut self, new_size: Size) {
-        assert!(new_size.width >= self.size.width);
-        assert!(new_size.height >= self.size.height);
+    // Allocate a rectangle of exactly the requested (or rotated) size at a position that is a
+    // multiple of the alignment and return the index of the allocated node.
+    fn allocate_index(
+        &mut self,
+        requested_size: &Size,
+        rotated_size: Option<&Size>,
+        alignment: &Size,
+        score: Option<&ScoreFn>,
+    ) -> AllocIndex {
+        // Find a suitable free rect.
+        let size_class_rect = if self.power_of_two_sizes && score.is_none() {
+            self.find_size_class_rect(requested_size, rotated_size, alignment)
+        } else {
+            None
+        };
+        let (chosen_id, chosen_size) = match size_class_rect {
+            Some(found) => found,
+            None => self.find_suitable_rect(requested_size, rotated_size, alignment, score),
+        };
 
-        let old_size = self.size;
-        self.size = new_size;
+        if chosen_id.is_none() {
+            debug!("failed to allocate {:?}", requested_size);
 
-        let dx = new_size.width - old_size.width;
-        let dy = new_size.height - old_size.height;
+            if !self.uncoalesced.is_empty() {
+                // Merging the free rectangles may make room for the allocation.
+                self.coalesce();
+                return self.allocate_index(requested_size, rotated_size, alignment, score);
+            }
 
-        // If there is only one node and it is free, just grow it.
-        let root = &mut self.nodes[self.root_node.index()];
-        if root.kind == NodeKind::Free && root.rect.size() == old_size {
-            root.rect.max = root.rect.min + new_size.to_vector();
-            return;
+            // No suitable free rect!
+            return AllocIndex::NONE;
         }
 
-        let root_orientation = root.orientation;
-        let grows_in_root_orientation = match root_orientation {
-            Orientation::Horizontal => dx > 0,
-            Orientation::Vertical => dy > 0,
-        };
+        let requested_size = chosen_size;
 
-        // If growing along the orientation of the root node, find the right-or-bottom-most sibling
-        // and either grow it (if it is free) or append a free node next.
-        if grows_in_root_orientation {
-            let mut sibling = self.root_node;
-            while self.nodes[sibling.index()].next_sibling != AllocIndex::NONE {
-                sibling = self.nodes[sibling.index()].next_sibling;
-            }
-            let node = &mut self.nodes[sibling.index()];
-            if node.kind == NodeKind::Free {
-                node.rect.max += match root_orientation {
-                    Orientation::Horizontal => vec2(dx, 0),
-                    Orientation::Vertical => vec2(0, dy),
-                };
-            } else {
-                let rect = match root_orientation {
-                    Orientation::Horizontal => {
-                        let min = point2(node.rect.max.x, node.rect.min.y);
-                        let max = min + vec2(dx, node.rect.height());
-                        Rectangle { min, max }
-                    }
-                    Orientation::Vertical => {
-                        let min = point2(node.rect.min.x, node.rect.max.y);
-                        let max = min + vec2(node.rect.width(), dy);
-                        Rectangle { min, max }
-                    }
-                };
+        let position = aligned_position(alignment, self.nodes[chosen_id.index()].rect.min);
+        if position != self.nodes[chosen_id.index()].rect.min {
+            // The free rect is not aligned, carve the allocation out of it at the next
+            // aligned position.
+            let rect = Rectangle {
+                min: position,
+                max: position + requested_size.to_vector(),
+            };
+            let allocated_id = self.carve(chosen_id, &rect);
+            self.last_alloc = allocated_id;
 
-                let next = self.new_node();
-                self.nodes[sibling.index()].next_sibling = next;
-                self.nodes[next.index()] = Node {
-                    kind: NodeKind::Free,
-                    rect,
-                    prev_sibling: sibling,
-                    next_sibling: AllocIndex::NONE,
-                    parent: AllocIndex::NONE,
-                    orientation: root_orientation,
-                };
+            #[cfg(feature = "checks")]
+            self.check_tree();
 
-                self.add_free_rect(next, &rect.size());
-            }
+            return allocated_id;
         }
 
-        let grows_in_opposite_orientation = match root_orientation {
-            Orientation::Horizontal => dy > 0,
-            Orientation::Vertical => dx > 0,
+        let chosen_node = self.nodes[chosen_id.index()].clone();
+        let chosen_rect = chosen_node.rect;
+        let allocated_rect = Rectangle {
+            min: chosen_rect.min,
+            max: chosen_rect.min + requested_size.to_vector(),
         };
+        let current_orientation = chosen_node.orientation;
+        assert_eq!(chosen_node.kind, NodeKind::Free);
 
-        if grows_in_opposite_orientation {
-            let free_node = self.new_node();
-            let new_root = self.new_node();
+        let (split_rect, leftover_rect, orientation) =
+            guillotine_rect(&chosen_node.rect, requested_size, current_orientation, self.split_policy);
 
-            let old_root = self.root_node;
-            self.root_node = new_root;
+        // Update the tree.
 
-            let new_root_orientation = root_orientation.flipped();
+        let allocated_id;
+        let split_id;
+        let leftover_id;
 
-            let min = match new_root_orientation {
-                Orientation::Horizontal => point2(old_size.width, 0),
-                Orientation::Vertical => point2(0, old_size.height),
-            };
-            let max = point2(new_size.width, new_size.height);
-            let rect = Rectangle { min, max };
+        if orientation == current_orientation {
+            if !split_rect.is_empty() {
+                let next_sibling = chosen_node.next_sibling;
 
-            self.nodes[free_node.index()] = Node {
-                parent: AllocIndex::NONE,
-                prev_sibling: new_root,
-                next_sibling: AllocIndex::NONE,
-                kind: NodeKind::Free,
-                rect,
-                orientation: new_root_orientation,
-            };
+                split_id = self.new_node();
+                self.nodes[split_id.index()] = Node {
+                    next_sibling,
+                    prev_sibling: chosen_id,
+                    ..Node::new(NodeKind::Free, split_rect, current_orientation, chosen_node.parent)
+                };
 
-            self.nodes[new_root.index()] = Node {
-                parent: AllocIndex::NONE,
-                prev_sibling: AllocIndex::NONE,
-                next_sibling: free_node,
-                kind: NodeKind::Container,
-                rect: Rectangle::zero(),
-                orientation: new_root_orientation,
-            };
+                self.nodes[chosen_id.index()].next_sibling = split_id;
+                if next_sibling.is_some() {
+                    self.nodes[next_sibling.index()].prev_sibling = split_id;
+                }
+            } else {
+                split_id = AllocIndex::NONE;
+            }
 
-            self.add_free_rect(free_node, &rect.size());
+            if !leftover_rect.is_empty() {
+                self.nodes[chosen_id.index()].kind = NodeKind::Container;
 
-            // Update the nodes that need to be re-parented to the new-root.
+                allocated_id = self.new_node();
+                leftover_id = self.new_node();
 
-            let mut iter = old_root;
-            while iter != AllocIndex::NONE {
-                self.nodes[iter.index()].parent = new_root;
-                iter = self.nodes[iter.index()].next_sibling;
-            }
+                self.nodes[allocated_id.index()] = Node {
+                    next_sibling: leftover_id,
+                    ..Node::new(
+                        NodeKind::Alloc,
+                        allocated_rect,
+                        current_orientation.flipped(),
+                        chosen_id,
+                    )
+                };
 
-            // That second loop might not be necessary, I think that the root is always the first
-            // sibling.
-            let mut iter = self.nodes[old_root.index()].next_sibling;
-            while iter != AllocIndex::NONE {
-                self.nodes[iter.index()].parent = new_root;
-                iter = self.nodes[iter.index()].prev_sibling;
+                self.nodes[leftover_id.index()] = Node {
+                    prev_sibling: allocated_id,
+                    ..Node::new(
+                        NodeKind::Free,
+                        leftover_rect,
+                        current_orientation.flipped(),
+                        chosen_id,
+                    )
+                };
+            } else {
+                // No need to split for the leftover area, we can allocate directly in the chosen node.
+                allocated_id = chosen_id;
+                let node = &mut self.nodes[chosen_id.index()];
+                node.kind = NodeKind::Alloc;
+                node.rect = allocated_rect;
+
+                leftover_id = AllocIndex::NONE
+            }
+        } else {
+            self.nodes[chosen_id.index()].kind = NodeKind::Container;
+
+            if !split_rect.is_empty() {
+                split_id = self.new_node();
+                self.nodes[split_id.index()] = Node::new(
+                    NodeKind::Free,
+                    split_rect,
+                    current_orientation.flipped(),
+                    chosen_id,
+                );
+            } else {
+                split_id = AllocIndex::NONE;
+            }
+
+            if !leftover_rect.is_empty() {
+                let container_id = self.new_node();
+                self.nodes[container_id.index()] = Node {
+                    next_sibling: split_id,
+                    ..Node::new(
+                        NodeKind::Container,
+                        Rectangle::zero(),
+                        current_orientation.flipped(),
+                        chosen_id,
+                    )
+                };
+
+                self.nodes[split_id.index()].prev_sibling = container_id;
+
+                allocated_id = self.new_node();
+                leftover_id = self.new_node();
+
+                self.nodes[allocated_id.index()] = Node {
+                    next_sibling: leftover_id,
+                    ..Node::new(NodeKind::Alloc, allocated_rect, current_orientation, container_id)
+                };
+
+                self.nodes[leftover_id.index()] = Node {
+                    prev_sibling: allocated_id,
+                    ..Node::new(NodeKind::Free, leftover_rect, current_orientation, container_id)
+                };
+            } else {
+                allocated_id = self.new_node();
+                self.nodes[allocated_id.index()] = Node {
+                    next_sibling: split_id,
+                    ..Node::new(
+                        NodeKind::Alloc,
+                        allocated_rect,
+                        current_orientation.flipped(),
+                        chosen_id,
+                    )
+                };
+
+                self.nodes[split_id.index()].prev_sibling = allocated_id;
+
+                leftover_id = AllocIndex::NONE;
             }
         }
 
+        assert_eq!(self.nodes[allocated_id.index()].kind, NodeKind::Alloc);
+
+        if split_id.is_some() {
+            self.add_free_rect(split_id, &split_rect.size());
+        }
+
+        if leftover_id.is_some() {
+            self.add_free_rect(leftover_id, &leftover_rect.size());
+        }
+
+        trace!(
+            "allocated {:?} from #{:?}, split: {:?}, leftover: {:?}",
+            allocated_rect, chosen_id, split_rect, leftover_rect,
+        );
+
+        self.allocated_space += area(allocated_rect.size());
+        self.allocation_count += 1;
+        self.last_alloc = allocated_id;
+        self.remove_free_rect(&chosen_rect.size());
+
         #[cfg(feature = "checks")]
         self.check_tree();
+
+        allocated_id
     }
 
-    /// Invoke a callback for each free rectangle in the atlas.
-    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
-    where
-        F: FnMut(&Rectangle),
-    {
-        for node in &self.nodes {
-            if node.kind == NodeKind::Free {
-                callback(&node.rect);
-            }
+    /// Allocate a rectangle at a specific position in the atlas.
+    ///
+    /// The padded size is rounded up to the alignment but the position is used as is.
+    /// Returns `None` if the requested rectangle is not entirely contained in a single free
+    /// rectangle of the atlas.
+    pub fn allocate_at(&mut self, position: Point, mut requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
         }
-    }
 
-    /// Invoke a callback for each allocated rectangle in the atlas.
-    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
-    where
-        F: FnMut(AllocId, &Rectangle),
-    {
-        for (i, node) in self.nodes.iter().enumerate() {
-            if node.kind != NodeKind::Alloc {
-                continue;
-            }
+        requested_size.width += self.padding * 2;
+        requested_size.height += self.padding * 2;
+        adjust_size(self.alignment.width, &mut requested_size.width);
+        adjust_size(self.alignment.height, &mut requested_size.height);
 
-            let id = self.alloc_id(AllocIndex(i as u32));
+        let rect = Rectangle {
+            min: position,
+            max: position + requested_size.to_vector(),
+        };
 
-            callback(id, &node.rect);
+        let free_id = self.find_free_rect_containing(&rect);
+        if free_id.is_none() {
+            return None;
         }
-    }
 
-    fn find_suitable_rect(&mut self, requested_size: &Size) -> AllocIndex {
-        let ideal_bucket = free_list_for_size(
-            self.small_size_threshold,
-            self.large_size_threshold,
-            requested_size,
-        );
+        let allocated_id = self.carve(free_id, &rect);
+        self.nodes[allocated_id.index()].padding = self.padding;
 
-        let use_worst_fit = ideal_bucket == LARGE_BUCKET;
-        for bucket in ideal_bucket..NUM_BUCKETS {
-            let mut candidate_score = if use_worst_fit { 0 } else { std::i32::MAX };
-            let mut candidate = None;
+        #[cfg(feature = "checks")]
+        self.check_tree();
 
-            let mut freelist_idx = 0;
-            while freelist_idx < self.free_lists[bucket].len() {
-                let id = self.free_lists[bucket][freelist_idx];
+        Some(self.allocation(allocated_id))
+    }
 
-                // During tree simplification we don't remove merged nodes from the free list, so we have
-                // to handle it here.
-                // This is a tad awkward, but lets us avoid having to maintain a doubly linked list for
-                // the free list (which would be needed to remove nodes during tree simplification).
-                if self.nodes[id.index()].kind != NodeKind::Free {
-                    // remove the element from the free list
-                    self.free_lists[bucket].swap_remove(freelist_idx);
+    /// Allocate a rectangle that is entirely contained in a region of the atlas.
+    ///
+    /// This makes it possible to partition an atlas into several zones. Only the free space
+    /// inside of the region is considered, the rest of the atlas is left untouched.
+    pub fn allocate_in(&mut self, region: Rectangle, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() || region.is_empty() {
+            return None;
+        }
+
+        let alignment = self.alignment;
+        let (size, rotated_size) = self.padded_sizes(requested_size, self.padding, alignment);
+
+        let mut best: Option<(i64, AllocIndex, Rectangle)> = None;
+        for list in &self.free_lists {
+            for &id in list {
+                let node = &self.nodes[id.index()];
+                if node.kind != NodeKind::Free {
                     continue;
                 }
 
-                let size = self.nodes[id.index()].rect.size();
-                let dx = size.width - requested_size.width;
-                let dy = size.height - requested_size.height;
-
-                if dx >= 0 && dy >= 0 {
-                    if dx == 0 || dy == 0 {
-                        // Perfect fit!
-                        candidate = Some((id, freelist_idx));
-                        break;
+                let available = match node.rect.intersection(&region) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                let position = aligned_position(&alignment, available.min);
+
+                for candidate_size in std::iter::once(size).chain(rotated_size) {
+                    let rect = Rectangle {
+                        min: position,
+                        max: position + candidate_size.to_vector(),
+                    };
+                    if !available.contains_box(&rect) {
+                        continue;
                     }
 
-                    // Favor the largest minimum dimension, except for small
-                    // allocations.
-                    let score = i32::min(dx, dy);
-                    if (use_worst_fit && score > candidate_score)
-                        || (!use_worst_fit && score < candidate_score)
-                    {
-                        candidate_score = score;
-                        candidate = Some((id, freelist_idx));
+                    // Best area fit within the region.
+                    let score = area(available.size()) - area(candidate_size);
+                    if best.is_none_or(|(best_score, _, _)| score < best_score) {
+                        best = Some((score, id, rect));
                     }
                 }
-
-                freelist_idx += 1;
-            }
-
-            if let Some((id, freelist_idx)) = candidate {
-                self.free_lists[bucket].swap_remove(freelist_idx);
-                return id;
             }
         }
 
-        AllocIndex::NONE
-    }
-
-    fn new_node(&mut self) -> AllocIndex {
-        let idx = self.unused_nodes;
-        if idx.index() < self.nodes.len() {
-            self.unused_nodes = self.nodes[idx.index()].next_sibling;
-            self.generations[idx.index()] += Wrapping(1);
-            debug_assert_eq!(self.nodes[idx.index()].kind, NodeKind::Unused);
-            return idx;
-        }
-
-        self.nodes.push(Node {
-            parent: AllocIndex::NONE,
-            next_sibling: AllocIndex::NONE,
-            prev_sibling: AllocIndex::NONE,
-            rect: Rectangle::zero(),
-            kind: NodeKind::Unused,
-            orientation: Orientation::Horizontal,
-        });
+        let (_, free_id, rect) = best?;
+        let allocated_id = self.carve(free_id, &rect);
+        let node = &mut self.nodes[allocated_id.index()];
+        node.padding = self.padding;
+        node.rotated = rect.size() != size;
 
-        self.generations.push(Wrapping(0));
+        #[cfg(feature = "checks")]
+        self.check_tree();
 
-        AllocIndex(self.nodes.len() as u32 - 1)
+        Some(self.allocation(allocated_id))
     }
 
-    fn mark_node_unused(&mut self, id: AllocIndex) {
-        debug_assert!(self.nodes[id.index()].kind != NodeKind::Unused);
-        self.nodes[id.index()].kind = NodeKind::Unused;
-        self.nodes[id.index()].next_sibling = self.unused_nodes;
-        self.unused_nodes = id;
+    /// Allocate a region of the atlas and return an independent allocator for it.
+    ///
+    /// The region allocator has the same options as the atlas and can be sent to another
+    /// thread, so that jobs can allocate without synchronizing with each other. Its
+    /// rectangles are in the coordinate space of the atlas. The region must be given back with
+    /// `reclaim`.
+    ///
+    /// Until then, the region is reserved: it is preserved by `clear`, `rearrange` and
+    /// `defragment_step` and prevents the atlas from being shrunk.
+    pub fn split_off_region(&mut self, size: Size) -> Option<RegionAllocator> {
+        let alloc = self.allocate_with_padding(size, 0)?;
+
+        // The top-level nodes of the region become the children of the region's node when it
+        // is reclaimed, so they must be laid out in the other direction.
+        let index = self.get_index(alloc.id);
+        let horizontal_root = self.nodes[index.index()].orientation == Orientation::Vertical;
+
+        self.nodes[index.index()].kind = NodeKind::Reserved;
+        self.allocated_space -= area(alloc.rectangle.size());
+        self.allocation_count -= 1;
+        self.reserved_space += area(alloc.rectangle.size());
+
+        Some(RegionAllocator::new(alloc.id, alloc.rectangle, horizontal_root, &self.options()))
     }
 
-    #[allow(dead_code)]
-    fn print_free_rects(&self) {
-        println!("Large:");
-        for &id in &self.free_lists[LARGE_BUCKET] {
-            if self.nodes[id.index()].kind == NodeKind::Free {
-                println!(" - {:?} #{:?}", self.nodes[id.index()].rect, id);
-            }
-        }
-        println!("Medium:");
-        for &id in &self.free_lists[MEDIUM_BUCKET] {
-            if self.nodes[id.index()].kind == NodeKind::Free {
-                println!(" - {:?} #{:?}", self.nodes[id.index()].rect, id);
-            }
-        }
-        println!("Small:");
-        for &id in &self.free_lists[SMALL_BUCKET] {
-            if self.nodes[id.index()].kind == NodeKind::Free {
-                println!(" - {:?} #{:?}", self.nodes[id.index()].rect, id);
+    /// Merge back a region created by `split_off_region`, along with its allocations.
+    ///
+    /// The tree of the region is grafted into the atlas, so the allocations keep their
+    /// rectangles but get new ids. In the returned change list, `old` is the allocation of
+    /// the region and `new` the allocation of the atlas.
+    ///
+    /// If the region is not part of the atlas anymore, for example because the atlas was
+    /// reset in the mean time, the allocations of the region are reported as failures.
+    pub fn reclaim(&mut self, region: RegionAllocator) -> ChangeList {
+        let src = &region.atlas;
+
+        let idx = region.id.index().index();
+        let is_reserved = idx < self.nodes.len()
+            && self.nodes[idx].kind == NodeKind::Reserved
+            && self.nodes[idx].rect == region.rectangle()
+            && region.id.matches_generation(self.generations[idx].0);
+        if !is_reserved {
+            let mut failures = Vec::new();
+            for (i, node) in src.nodes.iter().enumerate() {
+                if node.kind == NodeKind::Alloc {
+                    failures.push(region.to_atlas_space(src.allocation(AllocIndex(i as u32))));
+                }
             }
+            return ChangeList { changes: Vec::new(), failures };
         }
-    }
 
-    #[cfg(feature = "checks")]
-    fn check_siblings(&self, id: AllocIndex, next: AllocIndex, orientation: Orientation) {
-        if next.is_none() {
-            return;
-        }
+        let region_index = region.id.index();
+        self.reserved_space -= area(region.size());
 
-        if self.nodes[next.index()].prev_sibling != id {
-            panic!("error: #{:?}'s next sibling #{:?} has prev sibling #{:?}", id, next, self.nodes[next.index()].prev_sibling);
+        if src.is_empty() {
+            let node = &mut self.nodes[region_index.index()];
+            node.kind = NodeKind::Alloc;
+            self.allocated_space += area(region.size());
+            self.allocation_count += 1;
+            self.deallocate_index(region_index);
+            return ChangeList::empty();
         }
-        assert_eq!(self.nodes[next.index()].prev_sibling, id);
 
-        match self.nodes[id.index()].kind {
-            NodeKind::Container | NodeKind::Unused => {
-                return;
-            }
-            _ => {}
-        }
-        match self.nodes[next.index()].kind {
-            NodeKind::Container | NodeKind::Unused => {
-                return;
-            }
-            _ => {}
-        }
+        let offset = region.rectangle().min.to_vector();
+        let mut changes = Vec::new();
 
-        let r1 = self.nodes[id.index()].rect;
-        let r2 = self.nodes[next.index()].rect;
-        match orientation {
-            Orientation::Horizontal => {
-                assert_eq!(r1.min.y, r2.min.y);
-                assert_eq!(r1.max.y, r2.max.y);
-            }
-            Orientation::Vertical => {
-                assert_eq!(r1.min.x, r2.min.x);
-                assert_eq!(r1.max.x, r2.max.x);
+        let mut remap = vec![AllocIndex::NONE; src.nodes.len()];
+        for (i, node) in src.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unused {
+                remap[i] = self.new_node();
             }
         }
-    }
-
-    #[cfg(feature = "checks")]
-    fn check_tree(&self) {
-        for node_idx in 0..self.nodes.len() {
-            let node = &self.nodes[node_idx];
+        let map = |index: AllocIndex| if index.is_some() { remap[index.index()] } else { AllocIndex::NONE };
 
+        for (i, node) in src.nodes.iter().enumerate() {
             if node.kind == NodeKind::Unused {
-                if node.next_sibling.is_some() {
-                    assert_eq!(self.nodes[node.next_sibling.index()].kind, NodeKind::Unused);
-                }
                 continue;
             }
 
-            let mut iter = node.next_sibling;
-            while iter.is_some() {
-                assert_eq!(self.nodes[iter.index()].orientation, node.orientation);
-                assert_eq!(self.nodes[iter.index()].parent, node.parent);
-                assert!(self.nodes[iter.index()].kind != NodeKind::Unused);
-                let next = self.nodes[iter.index()].next_sibling;
-
-                #[cfg(feature = "checks")]
-                self.check_siblings(iter, next, node.orientation);
-
-                iter = next;
-            }
+            let dst = remap[i];
+            self.nodes[dst.index()] = Node {
+                parent: if node.parent.is_some() { map(node.parent) } else { region_index },
+                next_sibling: map(node.next_sibling),
+                prev_sibling: map(node.prev_sibling),
+                rect: node.rect.translate(offset),
+                ..node.clone()
+            };
 
-            if node.parent.is_some() {
-                if self.nodes[node.parent.index()].kind != NodeKind::Container {
-                    panic!("error: child: {:?} parent: {:?}", node_idx, node.parent);
+            match node.kind {
+                NodeKind::Free => {
+                    self.add_free_rect(dst, &node.rect.size());
                 }
-                assert_eq!(
-                    self.nodes[node.parent.index()].orientation,
-                    node.orientation.flipped()
-                );
-                assert_eq!(self.nodes[node.parent.index()].kind, NodeKind::Container);
+                NodeKind::Alloc => {
+                    self.allocated_space += area(node.rect.size());
+                    self.allocation_count += 1;
+                    changes.push(Change {
+                        old: region.to_atlas_space(src.allocation(AllocIndex(i as u32))),
+                        new: self.allocation(dst),
+                    });
+                }
+                _ => {}
             }
         }
-    }
 
-    fn add_free_rect(&mut self, id: AllocIndex, size: &Size) {
-        debug_assert_eq!(self.nodes[id.index()].kind, NodeKind::Free);
-        let bucket = free_list_for_size(self.small_size_threshold, self.large_size_threshold, size);
-        //println!("add free rect #{:?} size {} bucket {}", id, size, bucket);
-        self.free_lists[bucket].push(id);
+        self.nodes[region_index.index()].kind = NodeKind::Container;
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        ChangeList { changes, failures: Vec::new() }
     }
 
-    // Merge `next` into `node` and append `next` to a list of available `nodes`vector slots.
-    fn merge_siblings(&mut self, node: AllocIndex, next: AllocIndex, orientation: Orientation) {
-        debug_assert_eq!(self.nodes[node.index()].kind, NodeKind::Free);
-        debug_assert_eq!(self.nodes[next.index()].kind, NodeKind::Free);
-        let r1 = self.nodes[node.index()].rect;
-        let r2 = self.nodes[next.index()].rect;
-        //println!("merge {} #{:?} and {} #{:?}       {:?}", r1, node, r2, next, orientation);
-        let merge_size = self.nodes[next.index()].rect.size();
-        match orientation {
-            Orientation::Horizontal => {
-                debug_assert_eq!(r1.min.y, r2.min.y);
-                debug_assert_eq!(r1.max.y, r2.max.y);
-                self.nodes[node.index()].rect.max.x += merge_size.width;
-            }
-            Orientation::Vertical => {
-                debug_assert_eq!(r1.min.x, r2.min.x);
-                debug_assert_eq!(r1.max.x, r2.max.x);
-                self.nodes[node.index()].rect.max.y += merge_size.height;
-            }
+    /// Permanently remove a region from the free space of the atlas.
+    ///
+    /// Reserved regions are never handed out by the allocator and are preserved by `clear`,
+    /// `rearrange` and `grow`. Like pinned allocations, they prevent the atlas from being
+    /// shrunk. `reset` removes them.
+    ///
+    /// Returns false if the region is not entirely contained in a single free rectangle of the
+    /// atlas.
+    pub fn reserve_region(&mut self, region: Rectangle) -> bool {
+        if region.is_empty() {
+            return false;
         }
 
-        // Remove the merged node from the sibling list.
-        let next_next = self.nodes[next.index()].next_sibling;
-        self.nodes[node.index()].next_sibling = next_next;
-        if next_next.is_some() {
-            self.nodes[next_next.index()].prev_sibling = node;
+        let free_id = self.find_free_rect_containing(&region);
+        if free_id.is_none() {
+            return false;
         }
 
-        // Add the merged node to the list of available slots in the nodes vector.
-        self.mark_node_unused(next);
-    }
+        let reserved_id = self.carve(free_id, &region);
+        self.nodes[reserved_id.index()].kind = NodeKind::Reserved;
+        self.allocated_space -= area(region.size());
+        self.allocation_count -= 1;
+        self.reserved_space += area(region.size());
 
-    fn alloc_id(&self, index: AllocIndex) -> AllocId {
-        let generation = self.generations[index.index()].0 as u32;
-        debug_assert!(index.0 & IDX_MASK == index.0);
-        AllocId(index.0 + (generation << 24))
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        true
     }
 
-    fn get_index(&self, id: AllocId) -> AllocIndex {
-        let idx = id.0 & IDX_MASK;
-        let expected_generation = (self.generations[idx as usize].0 as u32) << 24;
-        assert_eq!(id.0 & GEN_MASK, expected_generation);
-        AllocIndex(idx)
+    /// Deallocate a rectangle in the atlas, returning an error instead of panicking if the
+    /// id is not valid.
+    pub fn try_deallocate(&mut self, id: AllocId) -> Result<(), AllocError> {
+        if !self.is_valid(id) {
+            return Err(AllocError::StaleId);
+        }
+
+        self.deallocate(id);
+
+        Ok(())
     }
-}
 
-impl std::ops::Index<AllocId> for AtlasAllocator {
-    type Output = Rectangle;
-    fn index(&self, index: AllocId) -> &Rectangle {
-        let idx = self.get_index(index);
+    /// Deallocate a rectangle in the atlas.
+    pub fn deallocate(&mut self, node_id: AllocId) {
+        let node_id = self.get_index(node_id);
+        if self.deferred_coalescing {
+            self.release_node(node_id);
+            let size = self.nodes[node_id.index()].rect.size();
+            self.add_free_rect(node_id, &size);
+            self.uncoalesced.push(node_id);
+            return;
+        }
 
-        &self.nodes[idx.index()].rect
+        self.deallocate_index(node_id);
     }
-}
 
-/// A simpler atlas allocator implementation that can allocate rectangles but not deallocate them.
-pub struct SimpleAtlasAllocator {
-    free_rects: [Vec<Rectangle>; 3],
-    alignment: Size,
-    small_size_threshold: i32,
-    large_size_threshold: i32,
-    size: Size,
-}
+    /// Schedule the deallocation of a rectangle that may still be in use by the GPU.
+    ///
+    /// The rectangle stays allocated (and its id valid) until `flush_deferred` is called with
+    /// a frame index greater or equal to `frame_index`, which is typically the index of the
+    /// last frame that reads from it.
+    pub fn deallocate_deferred(&mut self, id: AllocId, frame_index: u64) {
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
+
+        self.nodes[index.index()].retired_frame = Some(frame_index);
+    }
 
-impl SimpleAtlasAllocator {
-    /// Create a simple atlas allocator with default options.
-    pub fn new(size: Size) -> Self {
-        Self::with_options(size, &DEFAULT_OPTIONS)
+    /// Deallocate the rectangles scheduled with `deallocate_deferred` for frames up to and
+    /// including `frame_index`.
+    ///
+    /// This is meant to be called once the GPU has finished executing the frame.
+    pub fn flush_deferred(&mut self, frame_index: u64) {
+        let ids: Vec<AllocId> = self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| {
+                node.kind == NodeKind::Alloc
+                    && node.retired_frame.is_some_and(|frame| frame <= frame_index)
+            })
+            .map(|(idx, _)| self.alloc_id(AllocIndex(idx as u32)))
+            .collect();
+
+        self.deallocate_many(&ids);
     }
 
-    /// Create a simple atlas allocator with the provided options.
-    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
-        let bucket = free_list_for_size(
-            options.small_size_threshold,
-            options.large_size_threshold,
-            &size,
-        );
+    /// Merge the free rectangles that were left uncoalesced because of the
+    /// `deferred_coalescing` option.
+    ///
+    /// This is also done automatically when an allocation fails.
+    pub fn coalesce(&mut self) {
+        let mut pending = std::mem::take(&mut self.uncoalesced);
+        self.coalesce_nodes(&mut pending);
+        // Keep the allocation of the vector around.
+        self.uncoalesced = pending;
+    }
 
-        let mut free_rects = [Vec::new(), Vec::new(), Vec::new()];
-        free_rects[bucket].push(size.into());
+    /// Merge free rectangles that are adjacent but not siblings in the tree.
+    ///
+    /// This looks for consecutive sibling containers that are subdivided the same way (see
+    /// the "Limitations" section of the `AtlasAllocator` documentation) and restructures them
+    /// so that their free children become siblings and can be merged. It is much more
+    /// expensive than the regular coalescing, and is meant to be called explicitly from time
+    /// to time by workloads with very regular grid-like allocation patterns.
+    ///
+    /// Allocation ids are preserved.
+    pub fn coalesce_aggressively(&mut self) {
+        self.coalesce();
 
-        SimpleAtlasAllocator {
-            free_rects,
-            alignment: options.alignment,
-            small_size_threshold: options.small_size_threshold,
-            large_size_threshold: options.large_size_threshold,
-            size,
-        }
-    }
+        loop {
+            // Containers don't link to their children, so build that map once per pass.
+            let first_child = self.first_children();
 
-    /// Drop all rectangles, clearing the atlas to its initial state.
-    pub fn clear(&mut self) {
+            let candidate = (0..self.nodes.len() as u32)
+                .map(AllocIndex)
+                .find(|&idx| self.can_transpose(idx, &first_child));
 
-        for i in 0..NUM_BUCKETS {
-            self.free_rects[i].clear();
+            match candidate {
+                Some(idx) => self.transpose(idx, &first_child),
+                None => break,
+            }
         }
 
-        let bucket = free_list_for_size(
-            self.small_size_threshold,
-            self.large_size_threshold,
-            &self.size,
-        );
+        #[cfg(feature = "checks")]
+        self.check_tree();
+    }
 
-        self.free_rects[bucket].push(self.size.into());
+    // Returns the index of the first child of each container, and NONE for other nodes.
+    fn first_children(&self) -> Vec<AllocIndex> {
+        let mut first_child = vec![AllocIndex::NONE; self.nodes.len()];
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unused && node.parent.is_some() && node.prev_sibling.is_none() {
+                first_child[node.parent.index()] = AllocIndex(idx as u32);
+            }
+        }
+
+        first_child
     }
 
-    /// Clear the allocator and reset its size and options.
-    pub fn reset(&mut self, size: Size, options: &AllocatorOptions) {
-        self.alignment = options.alignment;
-        self.small_size_threshold = options.small_size_threshold;
-        self.large_size_threshold = options.large_size_threshold;
-        self.size = size;
+    fn sibling_list(&self, mut first: AllocIndex) -> Vec<AllocIndex> {
+        let mut list = Vec::new();
+        while first.is_some() {
+            list.push(first);
+            first = self.nodes[first.index()].next_sibling;
+        }
 
-        self.clear();
+        list
     }
 
-    pub fn is_empty(&self) -> bool {
-        for b in 0..NUM_BUCKETS {
-            for rect in &self.free_rects[b] {
-                return rect.size() == self.size;
+    // Returns true if `first` and its next sibling are containers subdivided into the same
+    // rows (or columns) and at least one pair of free rectangles would become siblings if the
+    // two levels of the tree were swapped.
+    fn can_transpose(&self, first: AllocIndex, first_child: &[AllocIndex]) -> bool {
+        let second = self.nodes[first.index()].next_sibling;
+        if self.nodes[first.index()].kind != NodeKind::Container
+            || second.is_none()
+            || self.nodes[second.index()].kind != NodeKind::Container
+        {
+            return false;
+        }
+
+        let rows1 = self.sibling_list(first_child[first.index()]);
+        let rows2 = self.sibling_list(first_child[second.index()]);
+        if rows1.len() != rows2.len() {
+            return false;
+        }
+
+        let span = |idx: &AllocIndex| {
+            let rect = &self.nodes[idx.index()].rect;
+            match self.nodes[idx.index()].orientation {
+                Orientation::Horizontal => (rect.min.x, rect.max.x),
+                Orientation::Vertical => (rect.min.y, rect.max.y),
             }
+        };
+
+        if !rows1.iter().map(span).eq(rows2.iter().map(span)) {
+            return false;
         }
 
-        // This should be unreachable.
-        return false;
+        rows1.iter().zip(rows2.iter()).any(|(&row1, &row2)| {
+            let last = match self.nodes[row1.index()].kind {
+                NodeKind::Container => *self.sibling_list(first_child[row1.index()]).last().unwrap(),
+                _ => row1,
+            };
+            let first = match self.nodes[row2.index()].kind {
+                NodeKind::Container => first_child[row2.index()],
+                _ => row2,
+            };
+
+            self.nodes[last.index()].kind == NodeKind::Free
+                && self.nodes[first.index()].kind == NodeKind::Free
+        })
     }
 
-    /// The total size of the atlas.
-    pub fn size(&self) -> Size {
-        self.size
+    // Replace two sibling containers subdivided into the same rows with a single container
+    // holding one container per row, and coalesce the free rectangles that became siblings.
+    //
+    // For two columns of the same rows A, B and C, D:
+    //
+    //         #                     #
+    //         |                     |
+    //     +---+---+             +---+---+
+    //     |       |     ->      |       |
+    //     #       #             #       #
+    //     |       |             |       |
+    //   +-+-+   +-+-+         +-+-+   +-+-+
+    //   A   B   C   D         A   C   B   D
+    //
+    // Rows that are themselves containers have their children spliced into the new rows so
+    // that orientations keep alternating between levels.
+    fn transpose(&mut self, first: AllocIndex, first_child: &[AllocIndex]) {
+        let second = self.nodes[first.index()].next_sibling;
+        let rows1 = self.sibling_list(first_child[first.index()]);
+        let rows2 = self.sibling_list(first_child[second.index()]);
+        let orientation = self.nodes[first.index()].orientation;
+
+        // The first container covers both of them and the second one is removed.
+        let second_rect = self.nodes[second.index()].rect;
+        let next = self.nodes[second.index()].next_sibling;
+        self.nodes[first.index()].rect = self.nodes[first.index()].rect.union(&second_rect);
+        self.nodes[first.index()].next_sibling = next;
+        if next.is_some() {
+            self.nodes[next.index()].prev_sibling = first;
+        }
+        self.mark_node_unused(second);
+
+        let mut pending = Vec::new();
+        let mut prev_row = AllocIndex::NONE;
+        for (&row1, &row2) in rows1.iter().zip(rows2.iter()) {
+            let row = self.new_node();
+            self.nodes[row.index()] = Node {
+                prev_sibling: prev_row,
+                ..Node::new(
+                    NodeKind::Container,
+                    self.nodes[row1.index()].rect.union(&self.nodes[row2.index()].rect),
+                    orientation.flipped(),
+                    first,
+                )
+            };
+            if prev_row.is_some() {
+                self.nodes[prev_row.index()].next_sibling = row;
+            }
+            prev_row = row;
+
+            let mut prev_item = AllocIndex::NONE;
+            for item in [row1, row2] {
+                let items = if self.nodes[item.index()].kind == NodeKind::Container {
+                    let children = self.sibling_list(first_child[item.index()]);
+                    self.mark_node_unused(item);
+                    children
+                } else {
+                    vec![item]
+                };
+
+                for item in items {
+                    let node = &mut self.nodes[item.index()];
+                    node.parent = row;
+                    node.orientation = orientation;
+                    node.prev_sibling = prev_item;
+                    node.next_sibling = AllocIndex::NONE;
+                    if node.kind == NodeKind::Free {
+                        pending.push(item);
+                    }
+                    if prev_item.is_some() {
+                        self.nodes[prev_item.index()].next_sibling = item;
+                    }
+                    prev_item = item;
+                }
+            }
+        }
+
+        self.coalesce_nodes(&mut pending);
     }
 
-    /// Allocate a rectangle in the atlas.
-    pub fn allocate(&mut self, mut requested_size: Size) -> Option<Rectangle> {
-        if requested_size.is_empty() {
-            return None;
+    /// Deallocate several rectangles at once.
+    ///
+    /// This is equivalent to calling `deallocate` for each id, except that free rectangles
+    /// are coalesced once after all of the allocations have been released instead of after
+    /// each of them.
+    ///
+    /// Panics without deallocating anything if one of the ids is not valid or if an id is
+    /// repeated.
+    pub fn deallocate_many(&mut self, ids: &[AllocId]) {
+        let mut pending = Vec::with_capacity(ids.len());
+        for &id in ids {
+            assert!(self.is_valid(id), "invalid allocation id {}", id.serialize());
+            pending.push(id.index());
         }
 
-        adjust_size(self.alignment.width, &mut requested_size.width);
-        adjust_size(self.alignment.height, &mut requested_size.height);
+        let mut sorted: Vec<usize> = pending.iter().map(|index| index.index()).collect();
+        sorted.sort_unstable();
+        assert!(sorted.windows(2).all(|pair| pair[0] != pair[1]), "repeated allocation id");
 
-        let ideal_bucket = free_list_for_size(
-            self.small_size_threshold,
-            self.large_size_threshold,
-            &requested_size,
-        );
+        for &index in &pending {
+            self.release_node(index);
+        }
 
-        let use_worst_fit = ideal_bucket == LARGE_BUCKET;
+        if self.deferred_coalescing {
+            for &index in &pending {
+                let size = self.nodes[index.index()].rect.size();
+                self.add_free_rect(index, &size);
+            }
+            self.uncoalesced.append(&mut pending);
+            return;
+        }
 
-        let mut chosen_rect = None;
-        for bucket in ideal_bucket..NUM_BUCKETS {
-            let mut candidate_score = if use_worst_fit { 0 } else { std::i32::MAX };
-            let mut candidate = None;
+        self.coalesce_nodes(&mut pending);
+    }
 
-            for (index, rect) in self.free_rects[bucket].iter().enumerate() {
-                let dx = rect.width() - requested_size.width;
-                let dy = rect.height() - requested_size.height;
+    // Turn an allocated node into a free node without merging it with its neighbors.
+    fn release_node(&mut self, index: AllocIndex) {
+        assert!(index.index() < self.nodes.len());
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
+
+        let node = &mut self.nodes[index.index()];
+        self.allocated_space -= area(node.rect.size());
+        self.allocation_count -= 1;
+        node.kind = NodeKind::Free;
+        node.pinned = false;
+        node.padding = 0;
+        node.rotated = false;
+        node.tag = None;
+        node.retired_frame = None;
+    }
 
-                if dx >= 0 && dy >= 0 {
-                    if dx == 0 || dy == 0 {
-                        // Perfect fit!
-                        candidate = Some(index);
-                        break;
-                    }
+    // Merge each of the provided free nodes with their free siblings, collapsing them into
+    // their parents when possible. The vector is drained in the process.
+    fn coalesce_nodes(&mut self, pending: &mut Vec<AllocIndex>) {
+        while let Some(mut node_id) = pending.pop() {
+            // The node may have been merged into one of its siblings already.
+            if self.nodes[node_id.index()].kind != NodeKind::Free {
+                continue;
+            }
 
-                    let score = i32::min(dx, dy);
-                    if (use_worst_fit && score > candidate_score)
-                        || (!use_worst_fit && score < candidate_score)
-                    {
-                        candidate_score = score;
-                        candidate = Some(index);
-                    }
+            // Go to the first free node of the run and merge everything after it in one pass.
+            loop {
+                let prev = self.nodes[node_id.index()].prev_sibling;
+                if prev.is_none() || self.nodes[prev.index()].kind != NodeKind::Free {
+                    break;
                 }
+                node_id = prev;
             }
 
-            if let Some(index) = candidate {
-                let rect = self.free_rects[bucket].remove(index);
-                chosen_rect = Some(rect);
-                break;
+            let orientation = self.nodes[node_id.index()].orientation;
+            loop {
+                let next = self.nodes[node_id.index()].next_sibling;
+                if next.is_none() || self.nodes[next.index()].kind != NodeKind::Free {
+                    break;
+                }
+                self.merge_siblings(node_id, next, orientation);
             }
-        }
 
-        if let Some(rect) = chosen_rect {
-            let (split_rect, leftover_rect, _) =
-                guillotine_rect(&rect, requested_size, Orientation::Vertical);
-            self.add_free_rect(&split_rect);
-            self.add_free_rect(&leftover_rect);
+            // If this node is now a unique child, collapse it into its parent and continue
+            // at the parent level.
+            let parent = self.nodes[node_id.index()].parent;
+            if self.nodes[node_id.index()].prev_sibling.is_none()
+                && self.nodes[node_id.index()].next_sibling.is_none()
+                && parent.is_some()
+            {
+                debug_assert_eq!(self.nodes[parent.index()].kind, NodeKind::Container);
 
-            return Some(Rectangle {
-                min: rect.min,
-                max: rect.min + requested_size.to_vector(),
-            });
+                self.mark_node_unused(node_id);
+
+                self.nodes[parent.index()].rect = self.nodes[node_id.index()].rect;
+                self.nodes[parent.index()].kind = NodeKind::Free;
+
+                pending.push(parent);
+            } else {
+                let size = self.nodes[node_id.index()].rect.size();
+                self.add_free_rect(node_id, &size);
+            }
         }
 
-        None
+        #[cfg(feature = "checks")]
+        self.check_tree();
     }
 
-    /// Resize the atlas without changing the allocations.
+    /// Merge two adjacent allocations into a single one covering both rectangles.
     ///
-    /// This method is not allowed to shrink the width or height of the atlas.
-    pub fn grow(&mut self, new_size: Size) {
-        assert!(new_size.width >= self.size.width);
-        assert!(new_size.height >= self.size.height);
+    /// This is only possible if the two allocations are consecutive siblings in the atlas's
+    /// tree, which is the case of allocations that were split from the same free rectangle
+    /// and share a full edge, and if they have the same padding and rotation. The merged
+    /// allocation keeps the other attributes of the first one (pinning, tag, etc.).
+    ///
+    /// The ids of both allocations are invalidated. Returns `None` and leaves the allocations
+    /// untouched if they can't be merged.
+    pub fn merge(&mut self, a: AllocId, b: AllocId) -> Option<Allocation> {
+        let a = self.get_index(a);
+        let b = self.get_index(b);
+        assert_eq!(self.nodes[a.index()].kind, NodeKind::Alloc);
+        assert_eq!(self.nodes[b.index()].kind, NodeKind::Alloc);
+
+        let (first, second) = if self.nodes[a.index()].next_sibling == b {
+            (a, b)
+        } else if self.nodes[b.index()].next_sibling == a {
+            (b, a)
+        } else {
+            return None;
+        };
 
-        let (split_rect, leftover_rect, _) =
-            guillotine_rect(&new_size.into(), self.size, Orientation::Vertical);
+        let n1 = &self.nodes[first.index()];
+        let n2 = &self.nodes[second.index()];
+        if n1.padding != n2.padding || n1.rotated != n2.rotated {
+            return None;
+        }
 
-        self.size = new_size;
+        let orientation = n1.orientation;
+        self.merge_siblings(first, second, orientation);
+        self.allocation_count -= 1;
 
-        self.add_free_rect(&split_rect);
-        self.add_free_rect(&leftover_rect);
-    }
+        let mut merged = first;
 
-    /// Initialize this simple allocator with the content of an atlas allocator.
-    pub fn init_from_allocator(&mut self, src: &AtlasAllocator) {
-        self.size = src.size;
-        self.small_size_threshold = src.small_size_threshold;
-        self.large_size_threshold = src.large_size_threshold;
+        // If the merged node is now a unique child, the allocation takes the place of its
+        // parent, which lets it be merged with the parent's siblings later.
+        let parent = self.nodes[merged.index()].parent;
+        if self.nodes[merged.index()].prev_sibling.is_none()
+            && self.nodes[merged.index()].next_sibling.is_none()
+            && parent.is_some()
+        {
+            let node = self.nodes[merged.index()].clone();
+            self.mark_node_unused(merged);
 
-        for bucket in 0..NUM_BUCKETS {
-            for id in src.free_lists[bucket].iter() {
-                // During tree simplification we don't remove merged nodes from the free list, so we have
-                // to handle it here.
-                // This is a tad awkward, but lets us avoid having to maintain a doubly linked list for
-                // the free list (which would be needed to remove nodes during tree simplification).
-                if src.nodes[id.index()].kind != NodeKind::Free {
-                    continue;
+            self.nodes[parent.index()].rect = node.rect;
+            self.nodes[parent.index()].kind = NodeKind::Alloc;
+            self.copy_alloc_attributes(&node, parent);
+
+            merged = parent;
+        }
+
+        // Make sure that the previous ids are not valid anymore.
+        self.generations[merged.index()] += Wrapping(1);
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        Some(self.allocation(merged))
+    }
+
+    /// Split an allocation into two allocations, without changing the free space.
+    ///
+    /// The rectangle is divided at `offset` pixels from its left (`Axis::X`) or top (`Axis::Y`)
+    /// edge, and the two resulting allocations, in that order, inherit the attributes of the
+    /// original one. This is the inverse of `merge`.
+    ///
+    /// The id of the original allocation is invalidated. Returns `None` if the offset is not
+    /// strictly inside of the rectangle.
+    pub fn split(&mut self, id: AllocId, axis: Axis, offset: i32) -> Option<(Allocation, Allocation)> {
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
+
+        let node = self.nodes[index.index()].clone();
+        let rect = node.rect;
+        let (first_rect, second_rect, split_orientation) = match axis {
+            Axis::X => {
+                if offset <= 0 || offset >= rect.width() {
+                    return None;
+                }
+                let x = rect.min.x + offset;
+                (
+                    Rectangle { min: rect.min, max: point2(x, rect.max.y) },
+                    Rectangle { min: point2(x, rect.min.y), max: rect.max },
+                    Orientation::Horizontal,
+                )
+            }
+            Axis::Y => {
+                if offset <= 0 || offset >= rect.height() {
+                    return None;
                 }
+                let y = rect.min.y + offset;
+                (
+                    Rectangle { min: rect.min, max: point2(rect.max.x, y) },
+                    Rectangle { min: point2(rect.min.x, y), max: rect.max },
+                    Orientation::Vertical,
+                )
+            }
+        };
 
-                self.free_rects[bucket].push(src.nodes[id.index()].rect);
+        let first;
+        let second = self.new_node();
+        if split_orientation == node.orientation {
+            // The second rectangle is inserted as the next sibling.
+            first = index;
+            self.nodes[first.index()].rect = first_rect;
+            self.nodes[second.index()] = Node {
+                rect: second_rect,
+                prev_sibling: first,
+                ..node.clone()
+            };
+            if node.next_sibling.is_some() {
+                self.nodes[node.next_sibling.index()].prev_sibling = second;
             }
+            self.nodes[first.index()].next_sibling = second;
+        } else {
+            // The allocation becomes a container with the two rectangles as children.
+            first = self.new_node();
+            self.nodes[first.index()] = Node {
+                parent: index,
+                next_sibling: second,
+                prev_sibling: AllocIndex::NONE,
+                rect: first_rect,
+                orientation: split_orientation,
+                ..node.clone()
+            };
+            self.nodes[second.index()] = Node {
+                parent: index,
+                next_sibling: AllocIndex::NONE,
+                prev_sibling: first,
+                rect: second_rect,
+                orientation: split_orientation,
+                ..node.clone()
+            };
+            self.nodes[index.index()].kind = NodeKind::Container;
         }
+
+        // Make sure that the previous id is not valid anymore.
+        self.generations[first.index()] += Wrapping(1);
+        self.allocation_count += 1;
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        Some((self.allocation(first), self.allocation(second)))
     }
 
-    fn add_free_rect(&mut self, rect: &Rectangle) {
-        if rect.width() < self.alignment.width || rect.height() < self.alignment.height {
-            return;
+    /// Resize an allocation, preserving its position if possible.
+    ///
+    /// If the new rectangle fits at the same position once the free space around the
+    /// allocation is taken into account, the allocation is resized in place and only
+    /// its id changes. Otherwise a new rectangle is allocated elsewhere and the previous
+    /// one is deallocated, in which case the content has to be copied by the caller.
+    ///
+    /// The allocation keeps its padding.
+    ///
+    /// Returns `None` and leaves the allocation untouched if the request can't be satisfied.
+    pub fn realloc(&mut self, id: AllocId, mut new_size: Size) -> Option<Allocation> {
+        if new_size.is_empty() {
+            return None;
         }
 
-        let bucket = free_list_for_size(
-            self.small_size_threshold,
-            self.large_size_threshold,
-            &rect.size(),
-        );
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
 
-        self.free_rects[bucket].push(*rect);
+        let old_node = self.nodes[index.index()].clone();
+        if old_node.rotated {
+            new_size = size2(new_size.height, new_size.width);
+        }
+        new_size.width += old_node.padding * 2;
+        new_size.height += old_node.padding * 2;
+        adjust_size(self.alignment.width, &mut new_size.width);
+        adjust_size(self.alignment.height, &mut new_size.height);
+
+        let old_rect = old_node.rect;
+        if new_size == old_rect.size() {
+            return Some(self.allocation(index));
+        }
+
+        let in_place = Rectangle {
+            min: old_rect.min,
+            max: old_rect.min + new_size.to_vector(),
+        };
+
+        if self.rect_after_deallocation(index).contains_box(&in_place) {
+            let free_id = self.deallocate_index(index);
+            let allocated_id = self.carve(free_id, &in_place);
+            self.copy_alloc_attributes(&old_node, allocated_id);
+
+            #[cfg(feature = "checks")]
+            self.check_tree();
+
+            return Some(self.allocation(allocated_id));
+        }
+
+        // Allocate before deallocating so that the content can be copied from the old
+        // rectangle to the new one.
+        let alignment = self.alignment;
+        let allocated_id = self.allocate_index(&new_size, None, &alignment, None);
+        if allocated_id.is_none() {
+            return None;
+        }
+        self.copy_alloc_attributes(&old_node, allocated_id);
+        self.deallocate_index(index);
+
+        Some(self.allocation(allocated_id))
     }
-}
 
-fn adjust_size(alignment: i32, size: &mut i32) {
-    let rem = *size % alignment;
-    if rem > 0 {
-        *size += alignment - rem;
+    /// Mark an allocation as pinned or not.
+    ///
+    /// Pinned allocations keep their position and their id when the atlas is rearranged or
+    /// defragmented. Pinned allocations prevent the atlas from being shrunk.
+    pub fn set_pinned(&mut self, id: AllocId, pinned: bool) {
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
+
+        self.nodes[index.index()].pinned = pinned;
     }
-}
 
-/// Compute the area, saturating at i32::MAX instead of overflowing.
-fn safe_area(rect: &Rectangle) -> i32 {
-    rect.width().checked_mul(rect.height()).unwrap_or(std::i32::MAX)
-}
+    /// Returns whether an allocation is pinned.
+    pub fn is_pinned(&self, id: AllocId) -> bool {
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
 
-fn guillotine_rect(
-    chosen_rect: &Rectangle,
-    requested_size: Size,
-    default_orientation: Orientation,
-) -> (Rectangle, Rectangle, Orientation) {
-    // Decide whether to split horizontally or vertically.
-    //
-    // If the chosen free rectangle is bigger than the requested size, we subdivide it
-    // into an allocated rectangle, a split rectangle and a leftover rectangle:
-    //
-    // +-----------+-------------+
-    // |///////////|             |
-    // |/allocated/|             |
-    // |///////////|             |
-    // +-----------+             |
-    // |                         |
-    // |          chosen         |
-    // |                         |
-    // +-------------------------+
-    //
-    // Will be split into either:
+        self.nodes[index.index()].pinned
+    }
+
+    // Returns the index of the free node that contains the deallocated rectangle after coalescing.
+    fn deallocate_index(&mut self, mut node_id: AllocIndex) -> AllocIndex {
+        self.release_node(node_id);
+
+        loop {
+            let orientation = self.nodes[node_id.index()].orientation;
+
+            let next = self.nodes[node_id.index()].next_sibling;
+            let prev = self.nodes[node_id.index()].prev_sibling;
+
+            // Try to merge with the next node.
+            if next.is_some() && self.nodes[next.index()].kind == NodeKind::Free {
+                self.merge_siblings(node_id, next, orientation);
+            }
+
+            // Try to merge with the previous node.
+            if prev.is_some() && self.nodes[prev.index()].kind == NodeKind::Free {
+                self.merge_siblings(prev, node_id, orientation);
+                node_id = prev;
+            }
+
+            // If this node is now a unique child. We collapse it into its parent and try to merge
+            // again at the parent level.
+            let parent = self.nodes[node_id.index()].parent;
+            if self.nodes[node_id.index()].prev_sibling.is_none()
+                && self.nodes[node_id.index()].next_sibling.is_none()
+                && parent.is_some()
+            {
+                debug_assert_eq!(self.nodes[parent.index()].kind, NodeKind::Container);
+
+                self.mark_node_unused(node_id);
+
+                // Replace the parent container with a free node.
+                self.nodes[parent.index()].rect = self.nodes[node_id.index()].rect;
+                self.nodes[parent.index()].kind = NodeKind::Free;
+
+                // Start again at the parent level.
+                node_id = parent;
+            } else {
+                let size = self.nodes[node_id.index()].rect.size();
+                self.add_free_rect(node_id, &size);
+                break;
+            }
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        node_id
+    }
+
+    // Compute the free rectangle that deallocating a node would produce, without modifying
+    // the atlas. This mirrors the coalescing logic in `deallocate_index`.
+    fn rect_after_deallocation(&self, mut node_id: AllocIndex) -> Rectangle {
+        let mut rect = self.nodes[node_id.index()].rect;
+        loop {
+            let mut next = self.nodes[node_id.index()].next_sibling;
+            let mut prev = self.nodes[node_id.index()].prev_sibling;
+
+            if next.is_some() && self.nodes[next.index()].kind == NodeKind::Free {
+                rect = rect.union(&self.nodes[next.index()].rect);
+                next = self.nodes[next.index()].next_sibling;
+            }
+
+            if prev.is_some() && self.nodes[prev.index()].kind == NodeKind::Free {
+                rect = rect.union(&self.nodes[prev.index()].rect);
+                prev = self.nodes[prev.index()].prev_sibling;
+            }
+
+            let parent = self.nodes[node_id.index()].parent;
+            if prev.is_none() && next.is_none() && parent.is_some() {
+                node_id = parent;
+            } else {
+                return rect;
+            }
+        }
+    }
+
+    // Allocate `rect` inside of the free node `free_id` and return the index of the allocated node.
     //
-    // +-----------+-------------+
-    // |///////////|             |
-    // |/allocated/|  leftover   |
-    // |///////////|             |
-    // +-----------+-------------+
-    // |                         |
-    // |          split          |
-    // |                         |
-    // +-------------------------+
+    // The free node is first split along its own orientation into up to three siblings and the
+    // middle one is then split in the other direction if needed:
     //
-    // or:
+    // +--------+-------+-------+
+    // |        | first |       |
+    // |        +-------+       |
+    // | before | alloc | after |
+    // |        +-------+       |
+    // |        | last  |       |
+    // +--------+-------+-------+
     //
-    // +-----------+-------------+
-    // |///////////|             |
-    // |/allocated/|             |
-    // |///////////|    split    |
-    // +-----------+             |
-    // |           |             |
-    // | leftover  |             |
-    // |           |             |
-    // +-----------+-------------+
+    // (Here with a horizontal free node).
+    fn carve(&mut self, free_id: AllocIndex, rect: &Rectangle) -> AllocIndex {
+        let node = self.nodes[free_id.index()].clone();
+        debug_assert_eq!(node.kind, NodeKind::Free);
+        debug_assert!(node.rect.contains_box(rect));
+
+        self.allocated_space += area(rect.size());
+        self.allocation_count += 1;
+
+        let orientation = node.orientation;
+        let r = node.rect;
+        let (before, middle, after, first, last) = match orientation {
+            Orientation::Horizontal => (
+                Rectangle { min: r.min, max: point2(rect.min.x, r.max.y) },
+                Rectangle { min: point2(rect.min.x, r.min.y), max: point2(rect.max.x, r.max.y) },
+                Rectangle { min: point2(rect.max.x, r.min.y), max: r.max },
+                Rectangle { min: point2(rect.min.x, r.min.y), max: point2(rect.max.x, rect.min.y) },
+                Rectangle { min: point2(rect.min.x, rect.max.y), max: point2(rect.max.x, r.max.y) },
+            ),
+            Orientation::Vertical => (
+                Rectangle { min: r.min, max: point2(r.max.x, rect.min.y) },
+                Rectangle { min: point2(r.min.x, rect.min.y), max: point2(r.max.x, rect.max.y) },
+                Rectangle { min: point2(r.min.x, rect.max.y), max: r.max },
+                Rectangle { min: point2(r.min.x, rect.min.y), max: point2(rect.min.x, rect.max.y) },
+                Rectangle { min: point2(rect.max.x, rect.min.y), max: point2(r.max.x, rect.max.y) },
+            ),
+        };
+
+        if !before.is_empty() {
+            let before_id = self.new_node();
+            self.nodes[before_id.index()] = Node {
+                next_sibling: free_id,
+                prev_sibling: node.prev_sibling,
+                ..Node::new(NodeKind::Free, before, orientation, node.parent)
+            };
+
+            if node.prev_sibling.is_some() {
+                self.nodes[node.prev_sibling.index()].next_sibling = before_id;
+            }
+            self.nodes[free_id.index()].prev_sibling = before_id;
+
+            // The root node must remain the first of the top-level siblings.
+            if self.root_node == free_id {
+                self.root_node = before_id;
+            }
+
+            self.add_free_rect(before_id, &before.size());
+        }
+
+        if !after.is_empty() {
+            let after_id = self.new_node();
+            self.nodes[after_id.index()] = Node {
+                next_sibling: node.next_sibling,
+                prev_sibling: free_id,
+                ..Node::new(NodeKind::Free, after, orientation, node.parent)
+            };
+
+            if node.next_sibling.is_some() {
+                self.nodes[node.next_sibling.index()].prev_sibling = after_id;
+            }
+            self.nodes[free_id.index()].next_sibling = after_id;
+
+            self.add_free_rect(after_id, &after.size());
+        }
+
+        if first.is_empty() && last.is_empty() {
+            let node = &mut self.nodes[free_id.index()];
+            node.kind = NodeKind::Alloc;
+            node.rect = *rect;
+            self.remove_free_rect(&r.size());
+
+            return free_id;
+        }
+
+        self.nodes[free_id.index()].kind = NodeKind::Container;
+        self.nodes[free_id.index()].rect = middle;
+
+        let allocated_id = self.new_node();
+        self.nodes[allocated_id.index()] = Node::new(
+            NodeKind::Alloc,
+            *rect,
+            orientation.flipped(),
+            free_id,
+        );
+
+        if !first.is_empty() {
+            let first_id = self.new_node();
+            self.nodes[first_id.index()] = Node {
+                next_sibling: allocated_id,
+                ..Node::new(NodeKind::Free, first, orientation.flipped(), free_id)
+            };
+            self.nodes[allocated_id.index()].prev_sibling = first_id;
+
+            self.add_free_rect(first_id, &first.size());
+        }
+
+        if !last.is_empty() {
+            let last_id = self.new_node();
+            self.nodes[last_id.index()] = Node {
+                prev_sibling: allocated_id,
+                ..Node::new(NodeKind::Free, last, orientation.flipped(), free_id)
+            };
+            self.nodes[allocated_id.index()].next_sibling = last_id;
+
+            self.add_free_rect(last_id, &last.size());
+        }
+
+        self.remove_free_rect(&r.size());
+
+        allocated_id
+    }
+
+    /// Number of allocated rectangles.
+    pub fn allocation_count(&self) -> usize {
+        self.allocation_count
+    }
+
+    /// Number of free rectangles.
+    ///
+    /// This walks the entire node vector.
+    pub fn free_rect_count(&self) -> usize {
+        self.nodes.iter().filter(|node| node.kind == NodeKind::Free).count()
+    }
+
+    /// Number of allocations the atlas can hold without reallocating its internal storage.
+    ///
+    /// This is a conservative estimate, since an allocation can create several nodes in the
+    /// tree.
+    pub fn capacity(&self) -> usize {
+        self.nodes.capacity() / NODES_PER_ALLOCATION
+    }
+
+    /// Reserve internal storage for at least `additional` more allocations.
+    ///
+    /// This avoids reallocations when the number of allocations grows, at the expense of
+    /// memory usage.
+    pub fn reserve(&mut self, additional: usize) {
+        let nodes = additional * NODES_PER_ALLOCATION;
+        self.nodes.reserve(nodes);
+        self.generations.reserve(nodes);
+        for list in &mut self.free_lists {
+            list.reserve(additional);
+        }
+    }
+
+    /// Release the internal storage that isn't needed by the current allocations.
+    ///
+    /// The storage of the tree never shrinks on its own, so this can be used to reclaim memory
+    /// after a spike in the number of allocations. Since allocation ids refer to slots in the
+    /// storage, only the unused slots at the end of it can be released without invalidating
+    /// them; `rearrange` followed by this method releases as much memory as possible.
+    ///
+    /// The generation counters of the released slots are lost, which makes stale ids that
+    /// referred to them more likely to be mistaken for new allocations.
+    pub fn shrink_internal_storage(&mut self) {
+        let len = self.nodes
+            .iter()
+            .rposition(|node| node.kind != NodeKind::Unused)
+            .map_or(1, |idx| idx + 1);
+
+        self.nodes.truncate(len);
+        self.generations.truncate(len);
+        self.nodes.shrink_to_fit();
+        self.generations.shrink_to_fit();
+
+        // Rebuild the list of unused nodes without the released slots.
+        self.unused_nodes = AllocIndex::NONE;
+        for idx in (0..len).rev() {
+            if self.nodes[idx].kind == NodeKind::Unused {
+                self.nodes[idx].next_sibling = self.unused_nodes;
+                self.unused_nodes = AllocIndex(idx as u32);
+            }
+        }
+
+        // Also drop the stale free list entries.
+        let nodes = &self.nodes;
+        let is_free = |idx: &AllocIndex| idx.index() < len && nodes[idx.index()].kind == NodeKind::Free;
+        for list in self.free_lists.iter_mut().chain(&mut self.size_classes) {
+            list.retain(is_free);
+            list.shrink_to_fit();
+        }
+        self.uncoalesced.retain(is_free);
+        self.uncoalesced.shrink_to_fit();
+
+        if self.last_alloc.index() >= len {
+            self.last_alloc = AllocIndex::NONE;
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+    }
+
+    /// Renumber the nodes into a canonical form that only depends on the layout of the atlas,
+    /// not on the sequence of operations that produced it.
+    ///
+    /// Nodes are numbered in depth-first order, unused nodes are released and the free lists
+    /// are sorted. Two atlases with the same tree of rectangles and the same options have the
+    /// same canonical form, which makes their snapshots comparable, see `serialize_compact`.
+    ///
+    /// Rectangles don't move but all allocations get new ids, which are provided by the
+    /// returned change list. The generation counters are reset, which makes the old ids more
+    /// likely to be mistaken for the new ones.
+    pub fn canonicalize(&mut self) -> ChangeList {
+        let first_child = self.first_children();
+
+        let mut first = self.root_node;
+        while self.nodes[first.index()].prev_sibling.is_some() {
+            first = self.nodes[first.index()].prev_sibling;
+        }
+
+        // Depth-first traversal: each node is followed by its children, then by its next sibling.
+        let mut permutation = vec![AllocIndex::NONE; self.nodes.len()];
+        let mut count = 0;
+        let mut stack = vec![first];
+        while let Some(mut idx) = stack.pop() {
+            while idx.is_some() {
+                permutation[idx.index()] = AllocIndex(count as u32);
+                count += 1;
+
+                let next = self.nodes[idx.index()].next_sibling;
+                let child = first_child[idx.index()];
+                if child.is_some() {
+                    if next.is_some() {
+                        stack.push(next);
+                    }
+                    idx = child;
+                } else {
+                    idx = next;
+                }
+            }
+        }
+
+        // Unused nodes go at the end and are released.
+        let mut old_allocs = Vec::new();
+        let mut slot = count;
+        for (i, node) in self.nodes.iter().enumerate() {
+            match node.kind {
+                NodeKind::Unused => {
+                    permutation[i] = AllocIndex(slot as u32);
+                    slot += 1;
+                }
+                NodeKind::Alloc => old_allocs.push(self.allocation(AllocIndex(i as u32))),
+                _ => {}
+            }
+        }
+
+        self.largest_free_rect = Size::zero();
+        self.remap_nodes(&permutation);
+
+        self.nodes.truncate(count);
+        self.generations = vec![Wrapping(0); count];
+        self.unused_nodes = AllocIndex::NONE;
+        self.last_alloc = AllocIndex::NONE;
+        let nodes = &self.nodes;
+        self.uncoalesced.retain(|idx| idx.index() < count && nodes[idx.index()].kind == NodeKind::Free);
+        self.uncoalesced.sort_by_key(|idx| idx.index());
+        self.uncoalesced.dedup();
+
+        let mut changes = ChangeList::empty();
+        for old in old_allocs {
+            let new = self.allocation(permutation[old.id.index().index()]);
+            changes.changes.push(Change { old, new });
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        changes
+    }
+
+    /// Serialize the canonical form of the atlas without modifying it, see `canonicalize`.
+    ///
+    /// The ids of the allocations in the serialized atlas differ from the current ones. It can
+    /// be used with `#[serde(serialize_with = "AtlasAllocator::serialize_compact")]`.
+    #[cfg(feature = "serde")]
+    pub fn serialize_compact<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut atlas = self.clone();
+        atlas.canonicalize();
+
+        serde::Serialize::serialize(&atlas, serializer)
+    }
+
+    /// Serialize the atlas into a compact binary format, see `from_binary`.
+    ///
+    /// Unlike the serde serialization, this doesn't require the `serialization` feature and
+    /// preserves the ids of the allocations. The format is not portable between builds with
+    /// and without the `wide_ids` feature.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = BinaryWriter(Vec::with_capacity(64 + self.nodes.len() * 40));
+        out.0.extend_from_slice(BINARY_MAGIC);
+        out.u32(SERIALIZATION_VERSION);
+
+        let options = self.options();
+        out.i32(options.alignment.width);
+        out.i32(options.alignment.height);
+        out.i32(options.small_size_threshold);
+        out.i32(options.large_size_threshold);
+        out.i32(options.padding);
+        out.bool(options.allow_rotation);
+        out.u8(FIT_POLICIES.iter().position(|p| *p == options.fit_policy).unwrap() as u8);
+        out.u8(SPLIT_POLICIES.iter().position(|p| *p == options.split_policy).unwrap() as u8);
+        out.bool(options.deferred_coalescing);
+        out.i32(options.min_free_size);
+        out.bool(options.next_fit);
+        out.u8(PLACEMENT_BIASES.iter().position(|p| *p == options.placement_bias).unwrap() as u8);
+        out.bool(options.power_of_two_sizes);
+
+        out.i32(self.size.width);
+        out.i32(self.size.height);
+        out.u32(self.root_node.0);
+        out.i64(self.allocated_space);
+        out.u64(self.allocation_count as u64);
+        out.i64(self.reserved_space);
+
+        out.u32(self.nodes.len() as u32);
+        for (node, generation) in self.nodes.iter().zip(&self.generations) {
+            out.u8(NODE_KINDS.iter().position(|k| *k == node.kind).unwrap() as u8);
+            out.generation(generation.0);
+            if node.kind == NodeKind::Unused {
+                continue;
+            }
+            out.u32(node.parent.0);
+            out.u32(node.next_sibling.0);
+            out.u32(node.prev_sibling.0);
+            out.bool(node.orientation == Orientation::Horizontal);
+            out.i32(node.rect.min.x);
+            out.i32(node.rect.min.y);
+            out.i32(node.rect.max.x);
+            out.i32(node.rect.max.y);
+            out.bool(node.pinned);
+            out.i32(node.padding);
+            out.bool(node.rotated);
+            out.bool(node.tag.is_some());
+            out.u32(node.tag.unwrap_or(0));
+            out.bool(node.retired_frame.is_some());
+            out.u64(node.retired_frame.unwrap_or(0));
+        }
+
+        out.u32(self.uncoalesced.len() as u32);
+        for idx in &self.uncoalesced {
+            out.u32(idx.0);
+        }
+
+        out.0
+    }
+
+    /// Load an atlas serialized with `to_binary`.
+    ///
+    /// The atlas is validated, so corrupted data is rejected rather than causing panics
+    /// later.
+    pub fn from_binary(bytes: &[u8]) -> Result<AtlasAllocator, SerializationError> {
+        let mut input = BinaryReader(bytes);
+        if input.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(SerializationError::Malformed);
+        }
+        let version = input.u32()?;
+        if version != SERIALIZATION_VERSION {
+            return Err(SerializationError::UnsupportedVersion(version));
+        }
+
+        let options = AllocatorOptions {
+            alignment: size2(input.i32()?, input.i32()?),
+            small_size_threshold: input.i32()?,
+            large_size_threshold: input.i32()?,
+            padding: input.i32()?,
+            allow_rotation: input.bool()?,
+            fit_policy: input.variant(&FIT_POLICIES)?,
+            split_policy: input.variant(&SPLIT_POLICIES)?,
+            deferred_coalescing: input.bool()?,
+            min_free_size: input.i32()?,
+            next_fit: input.bool()?,
+            placement_bias: input.variant(&PLACEMENT_BIASES)?,
+            power_of_two_sizes: input.bool()?,
+        };
+        let size = size2(input.i32()?, input.i32()?);
+        if !options.is_valid() || size.width <= 0 || size.height <= 0 {
+            return Err(SerializationError::Malformed);
+        }
+
+        let mut atlas = AtlasAllocator::with_options(size, &options);
+        atlas.root_node = AllocIndex(input.u32()?);
+        atlas.allocated_space = input.i64()?;
+        atlas.allocation_count = input.u64()? as usize;
+        atlas.reserved_space = input.i64()?;
+
+        let len = input.u32()? as usize;
+        if len as AllocIdRepr > IDX_MASK || len > bytes.len() {
+            return Err(SerializationError::Malformed);
+        }
+        let index = |idx: u32| {
+            let idx = AllocIndex(idx);
+            if idx.is_some() && idx.index() >= len {
+                return Err(SerializationError::Malformed);
+            }
+            Ok(idx)
+        };
+
+        atlas.nodes.clear();
+        atlas.generations.clear();
+        for _ in 0..len {
+            let kind = input.variant(&NODE_KINDS)?;
+            atlas.generations.push(Wrapping(input.generation()?));
+
+            let mut node = Node::new(
+                kind,
+                Rectangle::zero(),
+                Orientation::Vertical,
+                AllocIndex::NONE,
+            );
+            if kind != NodeKind::Unused {
+                node.parent = index(input.u32()?)?;
+                node.next_sibling = index(input.u32()?)?;
+                node.prev_sibling = index(input.u32()?)?;
+                if input.bool()? {
+                    node.orientation = Orientation::Horizontal;
+                }
+                node.rect = Rectangle {
+                    min: point2(input.i32()?, input.i32()?),
+                    max: point2(input.i32()?, input.i32()?),
+                };
+                node.pinned = input.bool()?;
+                node.padding = input.i32()?;
+                node.rotated = input.bool()?;
+                let has_tag = input.bool()?;
+                let tag = input.u32()?;
+                node.tag = if has_tag { Some(tag) } else { None };
+                let retired = input.bool()?;
+                let frame = input.u64()?;
+                node.retired_frame = if retired { Some(frame) } else { None };
+            }
+            atlas.nodes.push(node);
+        }
+
+        let uncoalesced_len = input.u32()? as usize;
+        if uncoalesced_len > len {
+            return Err(SerializationError::Malformed);
+        }
+        for _ in 0..uncoalesced_len {
+            let idx = index(input.u32()?)?;
+            if idx.is_none() {
+                return Err(SerializationError::Malformed);
+            }
+            atlas.uncoalesced.push(idx);
+        }
+        if !input.0.is_empty() {
+            return Err(SerializationError::Malformed);
+        }
+
+        // The links are in bounds, so the free lists and the list of unused nodes can be
+        // rebuilt before validating the rest of the tree.
+        index(atlas.root_node.0)?;
+        let identity: Vec<AllocIndex> = (0..len as u32).map(AllocIndex).collect();
+        atlas.largest_free_rect = Size::zero();
+        atlas.remap_nodes(&identity);
+        atlas.validate().map_err(SerializationError::Invalid)?;
+
+        Ok(atlas)
+    }
+
+    /// Sum of the areas of the allocated rectangles, including their padding.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocated_space
+    }
+
+    /// Sum of the areas of the free rectangles.
+    pub fn free_space(&self) -> i64 {
+        area(self.size) - self.allocated_space - self.reserved_space
+    }
+
+    /// Ratio of the atlas area that is allocated, between 0.0 and 1.0.
+    pub fn occupancy(&self) -> f32 {
+        (self.allocated_space as f64 / area(self.size) as f64) as f32
+    }
+
+    /// Take a read-only snapshot of the allocations.
+    ///
+    /// The snapshot is not affected by later modifications of the atlas, and can be cheaply
+    /// cloned and shared with other threads.
+    pub fn freeze(&self) -> FrozenAtlas {
+        FrozenAtlas {
+            slots: self
+                .nodes
+                .iter()
+                .zip(self.generations.iter())
+                .map(|(node, generation)| FrozenSlot {
+                    generation: generation.0,
+                    rect: if node.kind == NodeKind::Alloc { Some(node.rect) } else { None },
+                })
+                .collect(),
+            size: self.size,
+            allocated_space: self.allocated_space,
+        }
+    }
+
+    /// Describe the size of the atlas and its allocations, for example to export them to
+    /// external tools, see `LayoutDescription`.
+    pub fn layout(&self) -> LayoutDescription {
+        let mut allocations = Vec::with_capacity(self.allocation_count);
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Alloc {
+                continue;
+            }
+
+            allocations.push(AllocationDescription {
+                id: self.alloc_id(AllocIndex(idx as u32)).serialize(),
+                x: node.rect.min.x,
+                y: node.rect.min.y,
+                width: node.rect.width(),
+                height: node.rect.height(),
+                padding: node.padding,
+                rotated: node.rotated,
+            });
+        }
+        allocations.sort_by_key(|alloc| alloc.id);
+
+        LayoutDescription {
+            width: self.size.width,
+            height: self.size.height,
+            allocations,
+        }
+    }
+
+    /// Report the memory used by the internal storage of the atlas.
+    ///
+    /// This does not include the size of the `AtlasAllocator` structure itself.
+    pub fn memory_usage(&self) -> MemoryReport {
+        MemoryReport {
+            nodes: StorageUsage::of(&self.nodes),
+            generations: StorageUsage::of(&self.generations),
+            free_lists: self.free_lists.iter().map(StorageUsage::of).collect(),
+            size_classes: self.size_classes.iter().fold(StorageUsage::of(&self.size_classes), |total, list| {
+                let list = StorageUsage::of(list);
+                StorageUsage {
+                    len: total.len + list.len,
+                    capacity: total.capacity + list.capacity,
+                    bytes: total.bytes + list.bytes,
+                }
+            }),
+            uncoalesced: StorageUsage::of(&self.uncoalesced),
+        }
+    }
+
+    /// Compute statistics about the free space of the atlas.
+    ///
+    /// This walks the entire tree and sorts the free rectangles, so it is best not to call it
+    /// every frame.
+    pub fn stats(&self) -> AllocatorStats {
+        let mut free_areas = Vec::new();
+        let mut largest_free_rect = Size::zero();
+        for node in &self.nodes {
+            if node.kind != NodeKind::Free {
+                continue;
+            }
+
+            let size = node.rect.size();
+            if area(size) > area(largest_free_rect) {
+                largest_free_rect = size;
+            }
+            free_areas.push(area(size));
+        }
+
+        free_areas.sort_unstable();
+
+        let percentile = |p: usize| {
+            if free_areas.is_empty() {
+                return 0;
+            }
+            free_areas[(free_areas.len() - 1) * p / 100]
+        };
+
+        let free_space = self.free_space();
+        let (mean_free_rect_area, fragmentation) = if free_areas.is_empty() {
+            (0.0, 0.0)
+        } else {
+            (
+                (free_space as f64 / free_areas.len() as f64) as f32,
+                (1.0 - area(largest_free_rect) as f64 / free_space as f64) as f32,
+            )
+        };
+
+        AllocatorStats {
+            free_rect_count: free_areas.len(),
+            largest_free_rect,
+            mean_free_rect_area,
+            median_free_rect_area: percentile(50),
+            p90_free_rect_area: percentile(90),
+            fragmentation,
+        }
+    }
+
+    /// Returns whether the id refers to a live allocation of this atlas.
+    ///
+    /// Unlike indexing the atlas or deallocating, this does not panic with stale ids.
+    /// Generations are stored in 8 bits (32 bits with the `wide_ids` feature), so a stale id
+    /// can occasionally be mistaken for a valid one if its slot was reused many times.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        let idx = id.index().index();
+        idx < self.nodes.len()
+            && self.nodes[idx].kind == NodeKind::Alloc
+            && id.matches_generation(self.generations[idx].0)
+    }
+
+    /// Returns a small integer that is unique among the live allocations of the atlas.
+    ///
+    /// Dense indices are smaller than `dense_index_bound()`, which makes it possible to store
+    /// per-allocation data in a vector rather than a hash map. The index of an allocation
+    /// does not change as long as its id doesn't, and it can only be reused after the
+    /// allocation is deallocated or its id changes (for example when rearranging the atlas).
+    pub fn dense_index(&self, id: AllocId) -> usize {
+        let index = self.get_index(id);
+        assert_eq!(self.nodes[index.index()].kind, NodeKind::Alloc);
+
+        index.index()
+    }
+
+    /// Upper bound (exclusive) of the values returned by `dense_index`.
+    ///
+    /// The bound grows with the number of nodes in the atlas and never shrinks unless the
+    /// atlas is cleared.
+    pub fn dense_index_bound(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.nodes[id.index().index()].rect)
+    }
+
+    /// Returns the allocation that covers the provided point, if any.
+    ///
+    /// This walks the entire node vector, so it is meant for debugging tools rather than
+    /// performance sensitive code.
+    pub fn allocation_at(&self, point: Point) -> Option<AllocId> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Alloc && node.rect.contains(point) {
+                return Some(self.alloc_id(AllocIndex(i as u32)));
+            }
+        }
+
+        None
+    }
+
+    /// Iterate over the allocations that intersect the provided rectangle.
+    ///
+    /// Like `allocation_at`, this walks the entire node vector.
+    pub fn allocations_in(&self, rect: Rectangle) -> impl Iterator<Item = AllocId> + '_ {
+        self.nodes.iter().enumerate().filter_map(move |(i, node)| {
+            if node.kind == NodeKind::Alloc && node.rect.intersects(&rect) {
+                Some(self.alloc_id(AllocIndex(i as u32)))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Largest width and largest height of the free rectangles.
+    ///
+    /// The two dimensions may come from different rectangles. An allocation that is wider or
+    /// taller than the returned size is guaranteed to fail, which makes it possible to early
+    /// out before attempting it. A smaller allocation may still fail, in particular if it
+    /// needs to be padded or aligned. The value is cached and kept up to date as rectangles
+    /// are allocated and deallocated.
+    pub fn largest_free_rect_size(&self) -> Size {
+        self.largest_free_rect
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocation_count == 0
+    }
+
+    /// Compute the rectangles to copy from the texture of the atlas to a new texture when
+    /// growing the atlas from `old_size` to `new_size`.
+    ///
+    /// Since `grow` preserves the position of the allocations, only the parts of the old
+    /// texture that contain allocations need to be copied, at the same position. The
+    /// allocations are grouped per top-level region of the atlas to keep the number of copies
+    /// low while skipping most of the unused space.
+    pub fn grow_copy_plan(&self, old_size: Size, new_size: Size) -> Vec<Rectangle> {
+        assert!(new_size.width >= old_size.width);
+        assert!(new_size.height >= old_size.height);
+
+        let mut bounds: Vec<Option<Rectangle>> = vec![None; self.nodes.len()];
+        for node in &self.nodes {
+            if node.kind != NodeKind::Alloc {
+                continue;
+            }
+
+            // Find the top-level ancestor of the allocation.
+            let mut top = node.parent;
+            if top.is_some() {
+                while self.nodes[top.index()].parent.is_some() {
+                    top = self.nodes[top.index()].parent;
+                }
+            }
+
+            let rect = node.rect;
+            let slot = if top.is_some() {
+                &mut bounds[top.index()]
+            } else {
+                // Top-level allocation.
+                bounds.push(None);
+                bounds.last_mut().unwrap()
+            };
+            *slot = Some(slot.map_or(rect, |b| b.union(&rect)));
+        }
+
+        let old_bounds: Rectangle = old_size.into();
+        bounds
+            .into_iter()
+            .flatten()
+            .filter_map(|rect| rect.intersection(&old_bounds))
+            .collect()
+    }
+
+    /// Bounding box of all of the allocated rectangles, or `None` if the atlas is empty.
+    ///
+    /// This is useful to only copy the used part of the atlas's texture when migrating to
+    /// another one. It walks the entire node vector.
+    pub fn allocated_bounds(&self) -> Option<Rectangle> {
+        self.nodes
+            .iter()
+            .filter(|node| node.kind == NodeKind::Alloc)
+            .map(|node| node.rect)
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Reserved regions are preserved. The capacity of the internal storage is kept, so
+    /// clearing does not allocate memory.
+    pub fn clear(&mut self) {
+        if self.nodes.iter().any(|node| node.kind == NodeKind::Reserved) {
+            for i in 0..self.nodes.len() {
+                if self.nodes[i].kind == NodeKind::Alloc {
+                    self.deallocate_index(AllocIndex(i as u32));
+                }
+            }
+
+            return;
+        }
+
+        self.clear_all();
+    }
+
+    /// Drop all rectangles and change the size of the atlas, keeping its options.
+    ///
+    /// Reserved regions are removed. Like `clear` and `reset`, this keeps the capacity of the
+    /// internal storage, which makes it suitable for transient atlases that are rebuilt
+    /// frequently.
+    pub fn clear_with_size(&mut self, size: Size) {
+        assert!(size.width > 0);
+        assert!(size.height > 0);
+
+        self.size = size;
+        self.clear_all();
+    }
+
+    // Lay the top-level nodes out horizontally instead of vertically, which only works while
+    // the atlas is empty. This is used by `StripAllocator` to keep the tree flat.
+    pub(crate) fn set_horizontal_root(&mut self) {
+        assert!(self.is_empty());
+        assert_eq!(self.nodes.len(), 1);
+        self.nodes[self.root_node.index()].orientation = Orientation::Horizontal;
+    }
+
+    // Drop all rectangles including the reserved regions.
+    fn clear_all(&mut self) {
+        self.nodes.clear();
+        self.nodes.push(Node::new(
+            NodeKind::Free,
+            self.size.into(),
+            Orientation::Vertical,
+            AllocIndex::NONE,
+        ));
+
+        self.root_node = AllocIndex(0);
+        self.allocated_space = 0;
+        self.allocation_count = 0;
+        self.reserved_space = 0;
+        self.largest_free_rect = self.size;
+        self.uncoalesced.clear();
+        self.last_alloc = AllocIndex::NONE;
+
+        self.generations.clear();
+        self.generations.push(Wrapping(0));
+
+        self.unused_nodes = AllocIndex::NONE;
+
+        let bucket = free_list_for_size(
+            self.small_size_threshold,
+            self.large_size_threshold,
+            &self.size,
+        );
+        for list in &mut self.free_lists {
+            list.clear();
+        }
+        self.free_lists[bucket].push(AllocIndex(0));
+        for list in &mut self.size_classes {
+            list.clear();
+        }
+    }
+
+    /// Same as `reset`, returning an error instead of panicking if the size or the options
+    /// are invalid.
+    pub fn try_reset(&mut self, size: Size, options: &AllocatorOptions) -> Result<(), AllocError> {
+        if size.is_empty() {
+            return Err(AllocError::InvalidSize);
+        }
+
+        if !options.is_valid() {
+            return Err(AllocError::InvalidOptions);
+        }
+
+        self.reset(size, options);
+
+        Ok(())
+    }
+
+    /// Clear the allocator and reset its size and options.
+    ///
+    /// Reserved regions are removed. The capacity of the internal storage is kept.
+    pub fn reset(&mut self, size: Size, options: &AllocatorOptions) {
+        self.alignment = options.alignment;
+        self.small_size_threshold = options.small_size_threshold;
+        self.large_size_threshold = options.large_size_threshold;
+        self.padding = options.padding;
+        self.allow_rotation = options.allow_rotation;
+        self.fit_policy = options.fit_policy;
+        self.split_policy = options.split_policy;
+        self.deferred_coalescing = options.deferred_coalescing;
+        self.min_free_size = options.min_free_size;
+        self.next_fit = options.next_fit;
+        self.placement_bias = options.placement_bias;
+        self.power_of_two_sizes = options.power_of_two_sizes;
+        self.size = size;
+
+        self.clear_all();
+    }
+
+    /// Recompute the allocations in the atlas and returns a list of the changes.
+    ///
+    /// Previous ids and rectangles are not valid anymore after this operation as each id/rectangle
+    /// pair is assigned to new values which are communicated in the returned change list.
+    /// Rearranging the atlas can help reduce fragmentation.
+    pub fn rearrange(&mut self) -> ChangeList {
+        let size = self.size;
+        self.resize_and_rearrange(size)
+    }
+
+    /// Compute the change list that `rearrange` would produce without modifying the atlas.
+    ///
+    /// This can be used to estimate the cost of rearranging the atlas (for example the
+    /// number of texture copies) before deciding to do it.
+    pub fn plan_rearrange(&self) -> ChangeList {
+        self.clone().rearrange()
+    }
+
+    /// Identical to `AtlasAllocator::rearrange`, except that allocations keep their ids.
+    ///
+    /// Only the rectangles change. In the returned change list, the `old` and `new` members
+    /// of each change share the same id. The ids of the allocations that could not be placed
+    /// back into the atlas are reported as failures and are not valid anymore.
+    pub fn rearrange_preserving_ids(&mut self) -> ChangeList {
+        let generations = self.generations.clone();
+        let mut changes = self.rearrange();
+
+        let len = self.nodes.len().max(generations.len());
+        while self.nodes.len() < len {
+            self.nodes.push(Node::new(
+                NodeKind::Unused,
+                Rectangle::zero(),
+                Orientation::Horizontal,
+                AllocIndex::NONE,
+            ));
+        }
+
+        // Move each allocation back into the slot it occupied before rearranging and
+        // distribute the other nodes among the remaining slots.
+        let mut permutation = vec![AllocIndex::NONE; len];
+        let mut taken = vec![false; len];
+        for change in &changes.changes {
+            let src = change.new.id.index().index();
+            let dst = change.old.id.index();
+            permutation[src] = dst;
+            taken[dst.index()] = true;
+        }
+
+        // Pinned allocations and reserved regions did not move.
+        for (i, node) in self.nodes.iter().enumerate() {
+            if (node.kind == NodeKind::Alloc && node.pinned) || node.kind == NodeKind::Reserved {
+                permutation[i] = AllocIndex(i as u32);
+                taken[i] = true;
+            }
+        }
+
+        let mut slot = 0;
+        for dst in &mut permutation {
+            if dst.is_some() {
+                continue;
+            }
+            while taken[slot] {
+                slot += 1;
+            }
+            *dst = AllocIndex(slot as u32);
+            taken[slot] = true;
+        }
+
+        self.remap_nodes(&permutation);
+
+        self.generations = generations;
+        self.generations.resize(len, Wrapping(0));
+        // The ids of the allocations that were lost must not be handed out again as is.
+        for failure in &changes.failures {
+            let idx = failure.id.index().index();
+            self.generations[idx] += Wrapping(1);
+        }
+
+        for change in &mut changes.changes {
+            change.new.id = change.old.id;
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        changes
+    }
+
+    /// Same as `resize_and_rearrange`, returning an error and leaving the atlas untouched if it
+    /// can't be shrunk because of pinned allocations or reserved regions.
+    pub fn try_resize_and_rearrange(&mut self, new_size: Size) -> Result<ChangeList, AllocError> {
+        if new_size.is_empty() {
+            return Err(AllocError::InvalidSize);
+        }
+
+        if self.would_shrink(new_size) && self.has_pinned_content() {
+            return Err(AllocError::WouldShrink);
+        }
+
+        Ok(self.resize_and_rearrange(new_size))
+    }
+
+    // Whether the atlas contains pinned allocations or reserved regions, which stay in place
+    // when the atlas is rearranged.
+    fn has_pinned_content(&self) -> bool {
+        self.nodes.iter().any(|node| {
+            node.kind == NodeKind::Reserved || (node.kind == NodeKind::Alloc && node.pinned)
+        })
+    }
+
+    fn would_shrink(&self, new_size: Size) -> bool {
+        new_size.width < self.size.width || new_size.height < self.size.height
+    }
+
+    /// Identical to `AtlasAllocator::rearrange`, also allowing to change the size of the atlas.
+    ///
+    /// The atlas can't be shrunk if it contains pinned allocations or reserved regions. In
+    /// that case the atlas is left untouched and all of the other allocations are reported as
+    /// failures, although they remain valid. `try_resize_and_rearrange` reports this case
+    /// with an error instead.
+    pub fn resize_and_rearrange(&mut self, new_size: Size) -> ChangeList {
+        let mut allocs = Vec::with_capacity(self.nodes.len());
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Alloc || node.pinned {
+                continue;
+            }
+            let index = AllocIndex(i as u32);
+            allocs.push((self.allocation(index), node.clone()));
+        }
+
+        let has_pinned_allocs = self.has_pinned_content();
+        if has_pinned_allocs && self.would_shrink(new_size) {
+            let failures = allocs.into_iter().map(|(alloc, _)| alloc).collect();
+            return ChangeList { changes: Vec::new(), failures };
+        }
+
+        allocs.sort_by_key(|(alloc, _)| area(alloc.rectangle.size()));
+        allocs.reverse();
+
+        if has_pinned_allocs {
+            // Pinned allocations and reserved regions stay where they are, so instead of
+            // starting from an empty atlas, only the other allocations are removed.
+            for (alloc, _) in &allocs {
+                self.deallocate_index(alloc.id.index());
+            }
+            self.grow(new_size);
+        } else {
+            self.size = new_size;
+            self.clear();
+        }
+
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+
+        let alignment = self.alignment;
+        for (old, old_node) in allocs {
+            let size = old.rectangle.size();
+            let new_id = self.allocate_index(&size, None, &alignment, None);
+            if new_id.is_some() {
+                self.copy_alloc_attributes(&old_node, new_id);
+                changes.push(Change { old, new: self.allocation(new_id) });
+            } else {
+                failures.push(old);
+            }
+        }
+
+        debug!(
+            "rearranged into {:?}: {} changes, {} failures",
+            new_size, changes.len(), failures.len(),
+        );
+
+        ChangeList { changes, failures }
+    }
+
+    // The full description of a live allocation, including its content rectangle.
+    pub(crate) fn get_allocation(&self, id: AllocId) -> Allocation {
+        self.allocation(self.get_index(id))
+    }
+
+    // Allocate a rectangle for an allocation moved from another atlas, keeping its padding
+    // and orientation.
+    pub(crate) fn allocate_moved(&mut self, src: &Allocation) -> Option<Allocation> {
+        let alignment = self.alignment;
+        let id = self.allocate_index(&src.rectangle.size(), None, &alignment, None);
+        if id.is_none() {
+            return None;
+        }
+
+        let node = &mut self.nodes[id.index()];
+        node.padding = src.content.min.x - src.rectangle.min.x;
+        node.rotated = src.rotated;
+
+        Some(self.allocation(id))
+    }
+
+    /// Move at most `budget` allocations toward a more compact layout.
+    ///
+    /// Unlike `rearrange`, only the ids and rectangles of the allocations that were moved are
+    /// invalidated, which makes it possible to spread the cost of defragmenting the atlas over
+    /// several frames.
+    ///
+    /// Allocations are moved toward the top-left corner of the atlas, starting with the ones
+    /// that are the furthest away from it. An empty change list means that no allocation can
+    /// be moved anymore.
+    pub fn defragment_step(&mut self, budget: usize) -> Vec<Change> {
+        let mut allocs = Vec::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Alloc {
+                allocs.push(AllocIndex(i as u32));
+            }
+        }
+
+        allocs.sort_by_key(|id| {
+            let min = self.nodes[id.index()].rect.min;
+            (min.y, min.x)
+        });
+
+        let mut changes = Vec::new();
+        for &old_id in allocs.iter().rev() {
+            if changes.len() >= budget {
+                break;
+            }
+
+            if self.nodes[old_id.index()].pinned {
+                continue;
+            }
+
+            let old_rect = self.nodes[old_id.index()].rect;
+            let size = old_rect.size();
+
+            // Look for the top-most, left-most free rectangle that can hold the allocation
+            // at an aligned position closer to the origin than its current position.
+            let mut best_key = (old_rect.min.y, old_rect.min.x);
+            let mut candidate = None;
+            for (i, node) in self.nodes.iter().enumerate() {
+                if node.kind != NodeKind::Free {
+                    continue;
+                }
+
+                let position = aligned_position(&self.alignment, node.rect.min);
+                let available = node.rect.max - position;
+                if available.x < size.width || available.y < size.height {
+                    continue;
+                }
+
+                let key = (position.y, position.x);
+                if key < best_key {
+                    best_key = key;
+                    candidate = Some((AllocIndex(i as u32), position));
+                }
+            }
+
+            let (candidate, min) = match candidate {
+                Some(candidate) => candidate,
+                None => continue,
+            };
+
+            let new_rect = Rectangle {
+                min,
+                max: min + size.to_vector(),
+            };
+
+            let old = self.allocation(old_id);
+            let old_node = self.nodes[old_id.index()].clone();
+
+            let new_id = self.carve(candidate, &new_rect);
+            self.copy_alloc_attributes(&old_node, new_id);
+            let new = self.allocation(new_id);
+
+            self.deallocate_index(old_id);
+
+            changes.push(Change { old, new });
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        changes
+    }
+
+    /// Compute a size to grow the atlas to, so that an allocation of the requested size is
+    /// guaranteed to succeed.
+    ///
+    /// This takes into account the free space at the right or bottom edge of the atlas that
+    /// `grow` would extend, and picks the candidate size with the smallest area. It is meant
+    /// to be used after an allocation failed.
+    pub fn suggest_grow(&self, requested_size: Size) -> Size {
+        let (along, across) = self.grow_candidates(requested_size);
+        match along {
+            Some(along) if area(along) <= area(across) => along,
+            _ => across,
+        }
+    }
+
+    // Compute the sizes that guarantee that an allocation of the requested size succeeds
+    // when growing along the orientation of the root (if any) and across it.
+    fn grow_candidates(&self, requested_size: Size) -> (Option<Size>, Size) {
+        let (size, rotated_size) = self.padded_sizes(requested_size, self.padding, self.alignment);
+        let (mut along, mut across) = self.grow_candidates_for_slot(size);
+        if let Some(rotated_size) = rotated_size {
+            let (rotated_along, rotated_across) = self.grow_candidates_for_slot(rotated_size);
+            let smaller = |a: Size, b: Size| if area(b) < area(a) { b } else { a };
+            along = match (along, rotated_along) {
+                (Some(a), Some(b)) => Some(smaller(a, b)),
+                (a, b) => a.or(b),
+            };
+            across = smaller(across, rotated_across);
+        }
+
+        (along, across)
+    }
+
+    fn grow_candidates_for_slot(&self, requested_size: Size) -> (Option<Size>, Size) {
+        let root = &self.nodes[self.root_node.index()];
+        if root.kind == NodeKind::Free && root.next_sibling.is_none() {
+            return (None, self.size.max(requested_size));
+        }
+
+        let mut last = self.root_node;
+        while self.nodes[last.index()].next_sibling.is_some() {
+            last = self.nodes[last.index()].next_sibling;
+        }
+        let last = &self.nodes[last.index()];
+
+        // Growing along the orientation of the root extends the last top-level node if it is
+        // free, while growing in the other direction adds a new free node that spans the whole
+        // atlas.
+        let (along, across) = match root.orientation {
+            Orientation::Horizontal => {
+                let free_width = if last.kind == NodeKind::Free { last.rect.width() } else { 0 };
+                (
+                    size2(
+                        self.size.width + i32::max(0, requested_size.width - free_width),
+                        self.size.height,
+                    ),
+                    size2(
+                        self.size.width,
+                        self.size.height + requested_size.height,
+                    ).max(size2(requested_size.width, 0)),
+                )
+            }
+            Orientation::Vertical => {
+                let free_height = if last.kind == NodeKind::Free { last.rect.height() } else { 0 };
+                (
+                    size2(
+                        self.size.width,
+                        self.size.height + i32::max(0, requested_size.height - free_height),
+                    ),
+                    size2(
+                        self.size.width + requested_size.width,
+                        self.size.height,
+                    ).max(size2(0, requested_size.height)),
+                )
+            }
+        };
+
+        let along_fits = along.width >= requested_size.width && along.height >= requested_size.height;
+
+        (if along_fits { Some(along) } else { None }, across)
+    }
+
+    /// Grow the atlas if needed so that an allocation of the requested size is guaranteed to
+    /// succeed, following the provided growth policy.
+    ///
+    /// Returns false and leaves the atlas untouched if the allocation can't be satisfied without
+    /// exceeding `GrowthPolicy::max_size`.
+    pub fn ensure_can_allocate(&mut self, requested_size: Size, policy: &GrowthPolicy) -> bool {
+        let (size, rotated_size) = self.padded_sizes(requested_size, self.padding, self.alignment);
+        let alignment = self.alignment;
+        if self.find_suitable_rect(&size, rotated_size.as_ref(), &alignment, None).0.is_some() {
+            return true;
+        }
+
+        let (along, across) = self.grow_candidates(requested_size);
+        let mut best: Option<Size> = None;
+        for candidate in along.into_iter().chain(std::iter::once(across)) {
+            let new_size = match policy.new_size(self.size, candidate) {
+                Some(new_size) => new_size,
+                None => continue,
+            };
+
+            let better = match best {
+                None => true,
+                Some(best) => match policy.strategy {
+                    GrowthStrategy::WidthThenHeight => {
+                        let key = |s: Size| (s.height, area(s));
+                        key(new_size) < key(best)
+                    }
+                    _ => area(new_size) < area(best),
+                },
+            };
+
+            if better {
+                best = Some(new_size);
+            }
+        }
+
+        match best {
+            Some(new_size) => {
+                self.grow(new_size);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Allocate a rectangle, growing and if allowed rearranging the atlas when there is not
+    /// enough space.
+    ///
+    /// The atlas is first grown with `ensure_can_allocate`. If the allocation still fails and `GrowOptions::allow_rearrange` is set, the
+    /// atlas is rearranged and the allocation attempted one last time.
+    ///
+    /// The returned change list is empty unless the atlas was rearranged, and must be applied
+    /// by the caller even if the allocation failed.
+    pub fn grow_and_allocate(&mut self, requested_size: Size, options: &GrowOptions) -> (Option<Allocation>, ChangeList) {
+        let no_changes = || ChangeList { changes: Vec::new(), failures: Vec::new() };
+
+        if let Some(alloc) = self.allocate(requested_size) {
+            return (Some(alloc), no_changes());
+        }
+
+        if self.ensure_can_allocate(requested_size, &options.policy) {
+            if let Some(alloc) = self.allocate(requested_size) {
+                return (Some(alloc), no_changes());
+            }
+        }
+
+        if !options.allow_rearrange {
+            return (None, no_changes());
+        }
+
+        let changes = self.rearrange();
+        let alloc = self.allocate(requested_size);
+
+        (alloc, changes)
+    }
+
+    /// Same as `grow`, returning an error instead of panicking if the new size is smaller than
+    /// the current one.
+    pub fn try_grow(&mut self, new_size: Size) -> Result<Vec<Rectangle>, AllocError> {
+        if new_size.width < self.size.width || new_size.height < self.size.height {
+            return Err(AllocError::WouldShrink);
+        }
+
+        Ok(self.grow(new_size))
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    ///
+    /// Returns the free rectangles that were added or enlarged. Enlarged free rectangles
+    /// are reported with their new bounds, which contain the previous ones.
+    pub fn grow(&mut self, new_size: Size) -> Vec<Rectangle> {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        let mut free_rects = Vec::new();
+
+        let old_size = self.size;
+        self.size = new_size;
+        debug!("grow {:?} -> {:?}", old_size, new_size);
+
+        let dx = new_size.width - old_size.width;
+        let dy = new_size.height - old_size.height;
+
+        // If there is only one node and it is free, just grow it.
+        let root = &mut self.nodes[self.root_node.index()];
+        if root.kind == NodeKind::Free && root.rect.size() == old_size {
+            root.rect.max = root.rect.min + new_size.to_vector();
+            self.largest_free_rect = new_size;
+            free_rects.push(root.rect);
+            return free_rects;
+        }
+
+        let root_orientation = root.orientation;
+        let grows_in_root_orientation = match root_orientation {
+            Orientation::Horizontal => dx > 0,
+            Orientation::Vertical => dy > 0,
+        };
+
+        // If growing along the orientation of the root node, find the right-or-bottom-most sibling
+        // and either grow it (if it is free) or append a free node next.
+        if grows_in_root_orientation {
+            let mut sibling = self.root_node;
+            while self.nodes[sibling.index()].next_sibling != AllocIndex::NONE {
+                sibling = self.nodes[sibling.index()].next_sibling;
+            }
+            let node = &mut self.nodes[sibling.index()];
+            if node.kind == NodeKind::Free {
+                let old_size = node.rect.size();
+                node.rect.max += match root_orientation {
+                    Orientation::Horizontal => vec2(dx, 0),
+                    Orientation::Vertical => vec2(0, dy),
+                };
+                let rect = node.rect;
+                let old_bucket = free_list_for_size(self.small_size_threshold, self.large_size_threshold, &old_size);
+                let new_bucket = free_list_for_size(self.small_size_threshold, self.large_size_threshold, &rect.size());
+                if self.is_sliver(&old_size) || old_bucket != new_bucket {
+                    // The node was not in the free lists, or is in a list for smaller rectangles
+                    // which won't be searched for the sizes it can now hold. The old entry is
+                    // skipped like other duplicates.
+                    self.add_free_rect(sibling, &rect.size());
+                } else {
+                    self.largest_free_rect = self.largest_free_rect.max(rect.size());
+                }
+                free_rects.push(rect);
+            } else {
+                let rect = match root_orientation {
+                    Orientation::Horizontal => {
+                        let min = point2(node.rect.max.x, node.rect.min.y);
+                        let max = min + vec2(dx, node.rect.height());
+                        Rectangle { min, max }
+                    }
+                    Orientation::Vertical => {
+                        let min = point2(node.rect.min.x, node.rect.max.y);
+                        let max = min + vec2(node.rect.width(), dy);
+                        Rectangle { min, max }
+                    }
+                };
+
+                let next = self.new_node();
+                self.nodes[sibling.index()].next_sibling = next;
+                self.nodes[next.index()] = Node {
+                    prev_sibling: sibling,
+                    ..Node::new(NodeKind::Free, rect, root_orientation, AllocIndex::NONE)
+                };
+
+                self.add_free_rect(next, &rect.size());
+                free_rects.push(rect);
+            }
+        }
+
+        let grows_in_opposite_orientation = match root_orientation {
+            Orientation::Horizontal => dy > 0,
+            Orientation::Vertical => dx > 0,
+        };
+
+        if grows_in_opposite_orientation {
+            let free_node = self.new_node();
+            let new_root = self.new_node();
+
+            let old_root = self.root_node;
+            self.root_node = new_root;
+
+            let new_root_orientation = root_orientation.flipped();
+
+            let min = match new_root_orientation {
+                Orientation::Horizontal => point2(old_size.width, 0),
+                Orientation::Vertical => point2(0, old_size.height),
+            };
+            let max = point2(new_size.width, new_size.height);
+            let rect = Rectangle { min, max };
+
+            self.nodes[free_node.index()] = Node {
+                prev_sibling: new_root,
+                ..Node::new(NodeKind::Free, rect, new_root_orientation, AllocIndex::NONE)
+            };
+
+            self.nodes[new_root.index()] = Node {
+                next_sibling: free_node,
+                ..Node::new(
+                    NodeKind::Container,
+                    Rectangle::zero(),
+                    new_root_orientation,
+                    AllocIndex::NONE,
+                )
+            };
+
+            self.add_free_rect(free_node, &rect.size());
+            free_rects.push(rect);
+
+            // Update the nodes that need to be re-parented to the new-root.
+
+            let mut iter = old_root;
+            while iter != AllocIndex::NONE {
+                self.nodes[iter.index()].parent = new_root;
+                iter = self.nodes[iter.index()].next_sibling;
+            }
+
+            // That second loop might not be necessary, I think that the root is always the first
+            // sibling.
+            let mut iter = self.nodes[old_root.index()].next_sibling;
+            while iter != AllocIndex::NONE {
+                self.nodes[iter.index()].parent = new_root;
+                iter = self.nodes[iter.index()].prev_sibling;
+            }
+        }
+
+        #[cfg(feature = "checks")]
+        self.check_tree();
+
+        free_rects
+    }
+
+    /// Invoke a callback for each node of the atlas's tree, in depth-first order.
+    ///
+    /// Unlike `for_each_free_rectangle` and `for_each_allocated_rectangle`, this exposes the
+    /// containers and how the rectangles are nested, which is mostly useful for debugging
+    /// tools. Node indices are only meaningful until the atlas is modified.
+    pub fn visit<F>(&self, mut callback: F)
+    where
+        F: FnMut(&NodeInfo),
+    {
+        let first_child = self.first_children();
+
+        let mut stack = vec![(self.root_node, 0)];
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx.index()];
+
+            // Visit the next sibling after the children of this node.
+            if node.next_sibling.is_some() {
+                stack.push((node.next_sibling, depth));
+            }
+            if first_child[idx.index()].is_some() {
+                stack.push((first_child[idx.index()], depth + 1));
+            }
+
+            callback(&NodeInfo {
+                index: idx.index(),
+                parent: if node.parent.is_some() { Some(node.parent.index()) } else { None },
+                kind: node.kind,
+                rectangle: node.rect,
+                axis: match node.orientation {
+                    Orientation::Horizontal => Axis::X,
+                    Orientation::Vertical => Axis::Y,
+                },
+                depth,
+                id: if node.kind == NodeKind::Alloc { Some(self.alloc_id(idx)) } else { None },
+            });
+        }
+    }
+
+    /// Invoke a callback for each free rectangle in the atlas.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for node in &self.nodes {
+            if node.kind == NodeKind::Free {
+                callback(&node.rect);
+            }
+        }
+    }
+
+    /// Invoke a callback for each allocated rectangle in the atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Alloc {
+                continue;
+            }
+
+            let id = self.alloc_id(AllocIndex(i as u32));
+
+            callback(id, &node.rect);
+        }
+    }
+
+    // Returns the chosen free rect along with the size it was chosen for, which can be either
+    // the requested size or the rotated size.
+    //
+    // Without a custom scoring function, the search stops at the first bucket that contains
+    // a suitable free rect.
+    fn find_suitable_rect(
+        &mut self,
+        requested_size: &Size,
+        rotated_size: Option<&Size>,
+        alignment: &Size,
+        custom_score: Option<&ScoreFn>,
+    ) -> (AllocIndex, Size) {
+        let ideal_bucket = free_list_for_size(
+            self.small_size_threshold,
+            self.large_size_threshold,
+            requested_size,
+        );
+
+        let use_worst_fit = custom_score.is_none() && match self.fit_policy {
+            FitPolicy::Adaptive => ideal_bucket == LARGE_BUCKET,
+            FitPolicy::WorstFit => true,
+            _ => false,
+        };
+
+        if self.next_fit && custom_score.is_none() {
+            if let Some(found) = self.find_next_fit(requested_size, rotated_size, alignment) {
+                return found;
+            }
+        }
+
+        let mut candidate_score = if use_worst_fit { 0 } else { i64::MAX };
+        let mut candidate = None;
+        // Breaks ties between candidates with the same score, see `PlacementBias`.
+        let mut candidate_key = 0;
+        let bias = self.placement_bias;
+
+        for bucket in ideal_bucket..NUM_BUCKETS {
+            let mut freelist_idx = 0;
+            'free_rects: while freelist_idx < self.free_lists[bucket].len() {
+                let id = self.free_lists[bucket][freelist_idx];
+
+                // During tree simplification we don't remove merged nodes from the free list, so we have
+                // to handle it here.
+                // This is a tad awkward, but lets us avoid having to maintain a doubly linked list for
+                // the free list (which would be needed to remove nodes during tree simplification).
+                if self.nodes[id.index()].kind != NodeKind::Free {
+                    // remove the element from the free list
+                    self.free_lists[bucket].swap_remove(freelist_idx);
+                    continue;
+                }
+
+                // Only the part of the rect after the first aligned position is usable.
+                let rect = self.nodes[id.index()].rect;
+                let position = aligned_position(alignment, rect.min);
+                let size = rect.max - position;
+                let key = bias.key(position);
+
+                for candidate_size in std::iter::once(requested_size).chain(rotated_size) {
+                    let dx = size.x - candidate_size.width;
+                    let dy = size.y - candidate_size.height;
+
+                    if dx >= 0 && dy >= 0 {
+                        if let Some(custom_score) = custom_score {
+                            let score = custom_score(&rect, *candidate_size) as i64;
+                            let tie = candidate.is_some() && score == candidate_score && key < candidate_key;
+                            if score < candidate_score || tie {
+                                candidate_score = score;
+                                candidate_key = key;
+                                candidate = Some((id, bucket, freelist_idx, *candidate_size));
+                            }
+                            continue;
+                        }
+
+                        let perfect_fit = dx == 0 || dy == 0 || self.fit_policy == FitPolicy::FirstFit;
+                        if perfect_fit && bias == PlacementBias::None {
+                            candidate = Some((id, bucket, freelist_idx, *candidate_size));
+                            break 'free_rects;
+                        }
+
+                        // With the adaptive policy, favor the largest minimum dimension,
+                        // except for small allocations.
+                        let score = match self.fit_policy {
+                            _ if perfect_fit => {
+                                if use_worst_fit { i64::MAX } else { i64::MIN }
+                            }
+                            FitPolicy::BestAreaFit => {
+                                area(size2(size.x, size.y)) - area(*candidate_size)
+                            }
+                            _ => i32::min(dx, dy) as i64,
+                        };
+                        let tie = candidate.is_some() && score == candidate_score && key < candidate_key;
+                        if (use_worst_fit && score > candidate_score)
+                            || (!use_worst_fit && score < candidate_score)
+                            || tie
+                        {
+                            candidate_score = score;
+                            candidate_key = key;
+                            candidate = Some((id, bucket, freelist_idx, *candidate_size));
+                        }
+                    }
+                }
+
+                freelist_idx += 1;
+            }
+
+            if candidate.is_some() && custom_score.is_none() {
+                break;
+            }
+        }
+
+        if let Some((id, bucket, freelist_idx, size)) = candidate {
+            self.free_lists[bucket].swap_remove(freelist_idx);
+            return (id, size);
+        }
+
+        (AllocIndex::NONE, *requested_size)
+    }
+
+    // Look for a free rectangle that can hold the requested size among the siblings of the
+    // previous allocation and the siblings of its parent, which is where the leftovers of the
+    // free rectangle it was allocated from end up.
+    //
+    // The free list entry of the chosen node is left behind and removed lazily.
+    fn find_next_fit(
+        &self,
+        requested_size: &Size,
+        rotated_size: Option<&Size>,
+        alignment: &Size,
+    ) -> Option<(AllocIndex, Size)> {
+        let last = self.last_alloc;
+        if last.is_none() || last.index() >= self.nodes.len() || self.nodes[last.index()].kind == NodeKind::Unused {
+            return None;
+        }
+
+        let parent = self.nodes[last.index()].parent;
+        for start in [last, parent] {
+            if start.is_none() {
+                continue;
+            }
+
+            let next = std::iter::successors(Some(start), |idx| {
+                Some(self.nodes[idx.index()].next_sibling).filter(|idx| idx.is_some())
+            });
+            let prev = std::iter::successors(Some(start), |idx| {
+                Some(self.nodes[idx.index()].prev_sibling).filter(|idx| idx.is_some())
+            });
+
+            for idx in next.chain(prev.skip(1)) {
+                let node = &self.nodes[idx.index()];
+                if node.kind != NodeKind::Free || self.is_sliver(&node.rect.size()) {
+                    continue;
+                }
+
+                let size = node.rect.max - aligned_position(alignment, node.rect.min);
+                for candidate_size in std::iter::once(requested_size).chain(rotated_size) {
+                    if size.x >= candidate_size.width && size.y >= candidate_size.height {
+                        return Some((idx, *candidate_size));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn find_free_rect_containing(&self, rect: &Rectangle) -> AllocIndex {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Free && node.rect.contains_box(rect) {
+                return AllocIndex(i as u32);
+            }
+        }
+
+        AllocIndex::NONE
+    }
+
+    fn new_node(&mut self) -> AllocIndex {
+        let idx = self.unused_nodes;
+        if idx.index() < self.nodes.len() {
+            self.unused_nodes = self.nodes[idx.index()].next_sibling;
+            self.generations[idx.index()] += Wrapping(1);
+            debug_assert_eq!(self.nodes[idx.index()].kind, NodeKind::Unused);
+            return idx;
+        }
+
+        assert!(
+            (self.nodes.len() as AllocIdRepr) < IDX_MASK,
+            "Too many nodes for the allocation id space, see the wide_ids feature."
+        );
+
+        self.nodes.push(Node::new(
+            NodeKind::Unused,
+            Rectangle::zero(),
+            Orientation::Horizontal,
+            AllocIndex::NONE,
+        ));
+
+        self.generations.push(Wrapping(0));
+
+        AllocIndex(self.nodes.len() as u32 - 1)
+    }
+
+    fn mark_node_unused(&mut self, id: AllocIndex) {
+        debug_assert!(self.nodes[id.index()].kind != NodeKind::Unused);
+        self.nodes[id.index()].kind = NodeKind::Unused;
+        self.nodes[id.index()].next_sibling = self.unused_nodes;
+        self.unused_nodes = id;
+    }
+
+    #[allow(dead_code)]
+    fn print_free_rects(&self) {
+        for (bucket, name) in [(LARGE_BUCKET, "Large"), (MEDIUM_BUCKET, "Medium"), (SMALL_BUCKET, "Small")] {
+            println!("{}:", name);
+            for &id in &self.free_lists[bucket] {
+                if self.nodes[id.index()].kind == NodeKind::Free {
+                    println!(" - {:?} #{:?}", self.nodes[id.index()].rect, id);
+                }
+            }
+        }
+    }
+
+    /// Write the tree of nodes of the atlas in the Graphviz DOT format.
+    ///
+    /// Each node is labeled with its kind and rectangle and linked to its parent. Siblings
+    /// are drawn in order on the same rank, linked by dashed edges. Rendering the output, for
+    /// example with `dot -Tsvg`, helps understanding how the free space is fragmented.
+    pub fn dump_tree_dot(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(output, "digraph atlas {{")?;
+        writeln!(output, "    node [shape=box, style=filled, fontname=monospace];")?;
+        writeln!(output, "    root [label=\"{}x{}\", shape=ellipse, fillcolor=white];", self.size.width, self.size.height)?;
+
+        let first_child = self.first_children();
+
+        let mut first = self.root_node;
+        while self.nodes[first.index()].prev_sibling.is_some() {
+            first = self.nodes[first.index()].prev_sibling;
+        }
+
+        let mut stack = vec![first];
+        while let Some(first) = stack.pop() {
+            let siblings = self.sibling_list(first);
+            for &idx in &siblings {
+                let node = &self.nodes[idx.index()];
+                let (kind, color) = match node.kind {
+                    NodeKind::Container => {
+                        let orientation = self.nodes[first_child[idx.index()].index()].orientation;
+                        (format!("container ({:?})", orientation).to_lowercase(), "white")
+                    }
+                    NodeKind::Alloc => (format!("alloc #{}", self.alloc_id(idx).serialize()), "lightblue"),
+                    NodeKind::Free => ("free".to_string(), "gray80"),
+                    NodeKind::Reserved => ("reserved".to_string(), "salmon"),
+                    NodeKind::Unused => unreachable!(),
+                };
+                writeln!(
+                    output,
+                    "    n{} [label=\"{}\\n{}x{} at ({}, {})\", fillcolor={}];",
+                    idx.index(),
+                    kind,
+                    node.rect.width(),
+                    node.rect.height(),
+                    node.rect.min.x,
+                    node.rect.min.y,
+                    color,
+                )?;
+
+                if node.parent.is_some() {
+                    writeln!(output, "    n{} -> n{};", node.parent.index(), idx.index())?;
+                } else {
+                    writeln!(output, "    root -> n{};", idx.index())?;
+                }
+
+                if first_child[idx.index()].is_some() {
+                    stack.push(first_child[idx.index()]);
+                }
+            }
+
+            if siblings.len() > 1 {
+                let list: Vec<String> = siblings.iter().map(|idx| format!("n{}", idx.index())).collect();
+                writeln!(
+                    output,
+                    "    {{ rank=same; {} [style=dashed, arrowhead=none]; }}",
+                    list.join(" -> "),
+                )?;
+            }
+        }
+
+        writeln!(output, "}}")
+    }
+
+    /// Verify the integrity of the atlas's internal data structure.
+    ///
+    /// This is useful to reject corrupted input after deserializing an atlas. It visits all of
+    /// the nodes and is too expensive to be used in performance sensitive code.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let len = self.nodes.len();
+        let in_bounds = |idx: AllocIndex| idx.is_none() || idx.index() < len;
+
+        if self.generations.len() != len {
+            return Err(ValidationError::InconsistentCounters);
+        }
+
+        if !self.root_node.is_some() || self.root_node.index() >= len {
+            return Err(ValidationError::InvalidRoot);
+        }
+        let root = &self.nodes[self.root_node.index()];
+        if root.kind == NodeKind::Unused || root.parent.is_some() || root.prev_sibling.is_some() {
+            return Err(ValidationError::InvalidRoot);
+        }
+
+        // Walk the list of unused nodes, making sure that it does not loop.
+        let mut unused_count = 0;
+        let mut iter = self.unused_nodes;
+        while iter.is_some() {
+            if iter.index() >= len || self.nodes[iter.index()].kind != NodeKind::Unused || unused_count >= len {
+                return Err(ValidationError::BrokenUnusedList);
+            }
+            unused_count += 1;
+            iter = self.nodes[iter.index()].next_sibling;
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Unused {
+                continue;
+            }
+
+            if !in_bounds(node.parent) || !in_bounds(node.next_sibling) || !in_bounds(node.prev_sibling) {
+                return Err(ValidationError::InvalidLink { node: i });
+            }
+        }
+
+        self.validate_tree()?;
+
+        let mut leaves = Vec::new();
+        let mut leaf_area: i64 = 0;
+        let mut allocation_count = 0;
+        let mut allocated_space = 0;
+        let mut reserved_space = 0;
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Unused {
+                continue;
+            }
+
+            if node.parent.is_some() {
+                let parent = &self.nodes[node.parent.index()];
+                if parent.kind != NodeKind::Container || parent.orientation != node.orientation.flipped() {
+                    return Err(ValidationError::InvalidParent { node: i });
+                }
+            }
+
+            if node.prev_sibling.is_some() && self.nodes[node.prev_sibling.index()].next_sibling.index() != i {
+                return Err(ValidationError::BrokenSiblingLink { node: i });
+            }
+
+            if node.next_sibling.is_some() {
+                let next = &self.nodes[node.next_sibling.index()];
+                if next.kind == NodeKind::Unused
+                    || next.prev_sibling.index() != i
+                    || next.parent != node.parent
+                    || next.orientation != node.orientation
+                {
+                    return Err(ValidationError::BrokenSiblingLink { node: i });
+                }
+
+                // Consecutive leaves share an edge.
+                if node.kind != NodeKind::Container && next.kind != NodeKind::Container {
+                    let (r1, r2) = (node.rect, next.rect);
+                    let aligned = match node.orientation {
+                        Orientation::Horizontal => r1.min.y == r2.min.y && r1.max.y == r2.max.y,
+                        Orientation::Vertical => r1.min.x == r2.min.x && r1.max.x == r2.max.x,
+                    };
+                    if !aligned {
+                        return Err(ValidationError::BrokenSiblingLink { node: i });
+                    }
+                }
+            }
+
+            if node.kind == NodeKind::Container {
+                continue;
+            }
+
+            let bounds: Rectangle = self.size.into();
+            if node.rect.is_empty() || !bounds.contains_box(&node.rect) {
+                return Err(ValidationError::OutOfBounds { node: i });
+            }
+
+            match node.kind {
+                NodeKind::Alloc => {
+                    allocation_count += 1;
+                    allocated_space += area(node.rect.size());
+                }
+                NodeKind::Reserved => {
+                    reserved_space += area(node.rect.size());
+                }
+                _ => {}
+            }
+
+            leaf_area += area(node.rect.size());
+            leaves.push(i);
+        }
+
+        if allocation_count != self.allocation_count
+            || allocated_space != self.allocated_space
+            || reserved_space != self.reserved_space
+            || self.largest_free_rect != self.find_largest_free_rect()
+        {
+            return Err(ValidationError::InconsistentCounters);
+        }
+
+        // Leaves must not overlap. Sort them horizontally so that only the ones that overlap
+        // on the x axis need to be compared.
+        leaves.sort_by_key(|&i| self.nodes[i].rect.min.x);
+        for (n, &a) in leaves.iter().enumerate() {
+            let ra = self.nodes[a].rect;
+            for &b in &leaves[n + 1..] {
+                let rb = self.nodes[b].rect;
+                if rb.min.x >= ra.max.x {
+                    break;
+                }
+                if ra.intersects(&rb) {
+                    return Err(ValidationError::Overlap { a, b });
+                }
+            }
+        }
+
+        // Since the leaves don't overlap, they cover the atlas if their areas add up.
+        if leaf_area != area(self.size) {
+            return Err(ValidationError::IncompleteCoverage);
+        }
+
+        let mut in_free_list = vec![false; len];
+        for list in &self.free_lists {
+            for &idx in list {
+                if idx.index() >= len {
+                    return Err(ValidationError::InvalidFreeListEntry);
+                }
+                in_free_list[idx.index()] = true;
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Free && !in_free_list[i] && !self.is_sliver(&node.rect.size()) {
+                return Err(ValidationError::MissingFreeListEntry { node: i });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Walk the tree from the root, making sure that each node is visited exactly once and that
+    // each sibling list tiles its parent (or the atlas for the top-level list). Expects the
+    // links of the nodes to be in bounds, but not to be consistent with each other.
+    fn validate_tree(&self) -> Result<(), ValidationError> {
+        let len = self.nodes.len();
+
+        let mut first_child = vec![AllocIndex::NONE; len];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unused && node.parent.is_some() && node.prev_sibling.is_none() {
+                first_child[node.parent.index()] = AllocIndex(i as u32);
+            }
+        }
+
+        // Visiting a node twice means that the tree has a cycle, so the walk is bounded by the
+        // number of nodes. Parents are visited before their children.
+        let mut visited = vec![false; len];
+        let mut order = Vec::new();
+        let mut lists = vec![self.root_node];
+        while let Some(mut iter) = lists.pop() {
+            while iter.is_some() {
+                let i = iter.index();
+                if visited[i] {
+                    return Err(ValidationError::Cycle { node: i });
+                }
+                visited[i] = true;
+                order.push(i);
+
+                if self.nodes[i].kind == NodeKind::Container && first_child[i].is_some() {
+                    lists.push(first_child[i]);
+                }
+
+                iter = self.nodes[i].next_sibling;
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.kind != NodeKind::Unused && !visited[i] {
+                return Err(ValidationError::Unreachable { node: i });
+            }
+        }
+
+        // The rectangles of the containers are not kept up to date, so their area is computed
+        // from their children.
+        let mut regions: Vec<Option<Rectangle>> = vec![None; len];
+        for &i in order.iter().rev() {
+            let node = &self.nodes[i];
+            let region = match (node.kind, regions[i]) {
+                (NodeKind::Container, Some(region)) => region,
+                // A container without children.
+                (NodeKind::Container, None) => return Err(ValidationError::InvalidTiling { node: i }),
+                _ => node.rect,
+            };
+            regions[i] = Some(region);
+            if node.parent.is_some() {
+                let parent = &mut regions[node.parent.index()];
+                *parent = Some(parent.map_or(region, |parent| parent.union(&region)));
+            }
+        }
+
+        let mut lists = vec![(self.root_node, self.size.into())];
+        for &i in &order {
+            if self.nodes[i].kind == NodeKind::Container {
+                lists.push((first_child[i], regions[i].unwrap()));
+            }
+        }
+
+        for (first, parent_region) in lists {
+            let parent_region: Rectangle = parent_region;
+            let orientation = self.nodes[first.index()].orientation;
+            let (start, end) = match orientation {
+                Orientation::Horizontal => (parent_region.min.x, parent_region.max.x),
+                Orientation::Vertical => (parent_region.min.y, parent_region.max.y),
+            };
+
+            let mut position = start;
+            let mut last = first;
+            let mut iter = first;
+            while iter.is_some() {
+                let i = iter.index();
+                let region = regions[i].unwrap();
+                let (min, max, spans_parent) = match orientation {
+                    Orientation::Horizontal => (
+                        region.min.x,
+                        region.max.x,
+                        region.min.y == parent_region.min.y && region.max.y == parent_region.max.y,
+                    ),
+                    Orientation::Vertical => (
+                        region.min.y,
+                        region.max.y,
+                        region.min.x == parent_region.min.x && region.max.x == parent_region.max.x,
+                    ),
+                };
+                if min != position {
+                    return Err(ValidationError::NotAdjacent { node: i });
+                }
+                if !spans_parent {
+                    return Err(ValidationError::InvalidTiling { node: i });
+                }
+
+                position = max;
+                last = iter;
+                iter = self.nodes[i].next_sibling;
+            }
+
+            if position != end {
+                return Err(ValidationError::InvalidTiling { node: last.index() });
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "checks")]
+    fn check_tree(&self) {
+        if let Err(error) = self.validate() {
+            panic!("error: {}", error);
+        }
+    }
+
+    // Returns true for free rectangles that are too small to be tracked in the free lists.
+    fn is_sliver(&self, size: &Size) -> bool {
+        size.width < self.min_free_size || size.height < self.min_free_size
+    }
+
+    // Must be called after a free rectangle of the given size is allocated from. The largest
+    // free rectangle only has to be searched for if it may have been the one.
+    fn remove_free_rect(&mut self, size: &Size) {
+        let largest = self.largest_free_rect;
+        if size.width >= largest.width || size.height >= largest.height {
+            self.largest_free_rect = self.find_largest_free_rect();
+        }
+    }
+
+    fn find_largest_free_rect(&self) -> Size {
+        let mut largest = Size::zero();
+        for node in &self.nodes {
+            if node.kind == NodeKind::Free && !self.is_sliver(&node.rect.size()) {
+                largest = largest.max(node.rect.size());
+            }
+        }
+
+        largest
+    }
+
+    fn add_free_rect(&mut self, id: AllocIndex, size: &Size) {
+        debug_assert_eq!(self.nodes[id.index()].kind, NodeKind::Free);
+        if self.is_sliver(size) {
+            return;
+        }
+        self.largest_free_rect = self.largest_free_rect.max(*size);
+        let bucket = free_list_for_size(self.small_size_threshold, self.large_size_threshold, size);
+        trace!("add free rect #{:?} size {:?} bucket {}", id, size, bucket);
+        self.free_lists[bucket].push(id);
+
+        if self.power_of_two_sizes {
+            if let Some(class) = size_class(size) {
+                if self.size_classes.is_empty() {
+                    self.size_classes.resize(NUM_SIZE_CLASSES, Vec::new());
+                }
+                self.size_classes[class].push(id);
+            }
+        }
+    }
+
+    // Pop free nodes of exactly the requested (or rotated) size from the size class lists,
+    // skipping the stale entries, until one is found at an aligned position.
+    fn find_size_class_rect(
+        &mut self,
+        requested_size: &Size,
+        rotated_size: Option<&Size>,
+        alignment: &Size,
+    ) -> Option<(AllocIndex, Size)> {
+        for size in std::iter::once(requested_size).chain(rotated_size) {
+            let class = match size_class(size) {
+                Some(class) if class < self.size_classes.len() => class,
+                _ => continue,
+            };
+
+            while let Some(id) = self.size_classes[class].pop() {
+                let valid = id.index() < self.nodes.len() && {
+                    let node = &self.nodes[id.index()];
+                    node.kind == NodeKind::Free
+                        && node.rect.size() == *size
+                        && aligned_position(alignment, node.rect.min) == node.rect.min
+                };
+                if valid {
+                    return Some((id, *size));
+                }
+            }
+        }
+
+        None
+    }
+
+    // Merge `next` into `node` and append `next` to a list of available `nodes`vector slots.
+    //
+    // Both nodes are typically free, except when merging allocations.
+    fn merge_siblings(&mut self, node: AllocIndex, next: AllocIndex, orientation: Orientation) {
+        debug_assert!(matches!(self.nodes[node.index()].kind, NodeKind::Free | NodeKind::Alloc));
+        debug_assert_eq!(self.nodes[node.index()].kind, self.nodes[next.index()].kind);
+        let r1 = self.nodes[node.index()].rect;
+        let r2 = self.nodes[next.index()].rect;
+        trace!("merge {:?} #{:?} and {:?} #{:?} {:?}", r1, node, r2, next, orientation);
+        let merge_size = self.nodes[next.index()].rect.size();
+        match orientation {
+            Orientation::Horizontal => {
+                debug_assert_eq!(r1.min.y, r2.min.y);
+                debug_assert_eq!(r1.max.y, r2.max.y);
+                self.nodes[node.index()].rect.max.x += merge_size.width;
+            }
+            Orientation::Vertical => {
+                debug_assert_eq!(r1.min.x, r2.min.x);
+                debug_assert_eq!(r1.max.x, r2.max.x);
+                self.nodes[node.index()].rect.max.y += merge_size.height;
+            }
+        }
+
+        // Remove the merged node from the sibling list.
+        let next_next = self.nodes[next.index()].next_sibling;
+        self.nodes[node.index()].next_sibling = next_next;
+        if next_next.is_some() {
+            self.nodes[next_next.index()].prev_sibling = node;
+        }
+
+        // Add the merged node to the list of available slots in the nodes vector.
+        self.mark_node_unused(next);
+    }
+
+    // Move each node `i` to the slot `permutation[i]`, updating all of the links.
+    fn remap_nodes(&mut self, permutation: &[AllocIndex]) {
+        debug_assert_eq!(permutation.len(), self.nodes.len());
+        let remap = |idx: AllocIndex| {
+            if idx.is_none() {
+                idx
+            } else {
+                permutation[idx.index()]
+            }
+        };
+
+        let mut nodes = self.nodes.clone();
+        for (i, node) in self.nodes.iter().enumerate() {
+            nodes[permutation[i].index()] = Node {
+                parent: remap(node.parent),
+                next_sibling: remap(node.next_sibling),
+                prev_sibling: remap(node.prev_sibling),
+                ..node.clone()
+            };
+        }
+        self.nodes = nodes;
+
+        self.root_node = remap(self.root_node);
+        self.last_alloc = remap(self.last_alloc);
+        for idx in &mut self.uncoalesced {
+            *idx = remap(*idx);
+        }
+
+        // Rebuild the free lists and the list of unused nodes.
+        for list in self.free_lists.iter_mut().chain(&mut self.size_classes) {
+            list.clear();
+        }
+        self.unused_nodes = AllocIndex::NONE;
+        for i in (0..self.nodes.len()).rev() {
+            let id = AllocIndex(i as u32);
+            match self.nodes[i].kind {
+                NodeKind::Free => {
+                    let size = self.nodes[i].rect.size();
+                    self.add_free_rect(id, &size);
+                }
+                NodeKind::Unused => {
+                    self.nodes[i].next_sibling = self.unused_nodes;
+                    self.unused_nodes = id;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn allocation(&self, index: AllocIndex) -> Allocation {
+        let node = &self.nodes[index.index()];
+        Allocation {
+            id: self.alloc_id(index),
+            rectangle: node.rect,
+            content: node.rect.inflate(-node.padding, -node.padding),
+            rotated: node.rotated,
+        }
+    }
+
+    // Carry the per-allocation attributes over when an allocation is moved to another node.
+    fn copy_alloc_attributes(&mut self, src: &Node, dst: AllocIndex) {
+        let node = &mut self.nodes[dst.index()];
+        node.pinned = src.pinned;
+        node.padding = src.padding;
+        node.rotated = src.rotated;
+        node.tag = src.tag;
+        node.retired_frame = src.retired_frame;
+    }
+
+    fn alloc_id(&self, index: AllocIndex) -> AllocId {
+        AllocId::new(index, self.generations[index.index()].0)
+    }
+
+    fn get_index(&self, id: AllocId) -> AllocIndex {
+        let idx = id.index();
+        assert!(id.matches_generation(self.generations[idx.index()].0));
+        idx
+    }
+}
+
+impl std::ops::Index<AllocId> for AtlasAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        let idx = self.get_index(index);
+
+        &self.nodes[idx.index()].rect
+    }
+}
+
+/// The layout of an atlas, see `AtlasAllocator::layout`.
+///
+/// Unlike the serialized `AtlasAllocator`, this only describes the allocations with a simple
+/// and stable schema that other tools can consume, for example as JSON with `write_json`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LayoutDescription {
+    pub width: i32,
+    pub height: i32,
+    /// Sorted by id.
+    pub allocations: Vec<AllocationDescription>,
+}
+
+/// An allocation in a `LayoutDescription`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AllocationDescription {
+    /// The id of the allocation, see `AllocId::serialize`.
+    pub id: AllocIdRepr,
+    /// The allocated rectangle, including its padding.
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// The content is inset by the padding on each side.
+    pub padding: i32,
+    pub rotated: bool,
+}
+
+impl LayoutDescription {
+    /// Write the layout in JSON format.
+    ///
+    /// The output has the same structure as serializing the layout with serde, without
+    /// requiring the `serialization` feature.
+    pub fn write_json(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        writeln!(output, "{{")?;
+        writeln!(output, "  \"width\": {},", self.width)?;
+        writeln!(output, "  \"height\": {},", self.height)?;
+        write!(output, "  \"allocations\": [")?;
+        for (i, alloc) in self.allocations.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(
+                output,
+                "{}\n    {{ \"id\": {}, \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"padding\": {}, \"rotated\": {} }}",
+                separator, alloc.id, alloc.x, alloc.y, alloc.width, alloc.height, alloc.padding, alloc.rotated,
+            )?;
+        }
+        if !self.allocations.is_empty() {
+            write!(output, "\n  ")?;
+        }
+        writeln!(output, "]")?;
+        writeln!(output, "}}")
+    }
+}
+
+/// A read-only snapshot of the allocations of an `AtlasAllocator`, see `AtlasAllocator::freeze`.
+///
+/// Cloning a frozen atlas does not copy the allocations.
+#[derive(Clone)]
+pub struct FrozenAtlas {
+    slots: Arc<[FrozenSlot]>,
+    size: Size,
+    allocated_space: i64,
+}
+
+struct FrozenSlot {
+    generation: Generation,
+    /// None for nodes that aren't allocated.
+    rect: Option<Rectangle>,
+}
+
+impl FrozenAtlas {
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Sum of the areas of the allocated rectangles.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocated_space
+    }
+
+    /// Ratio of the atlas area that is allocated, between 0.0 and 1.0.
+    pub fn occupancy(&self) -> f32 {
+        (self.allocated_space as f64 / area(self.size) as f64) as f32
+    }
+
+    /// Returns whether the id referred to a live allocation when the atlas was frozen.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id was not valid when the
+    /// atlas was frozen.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        let slot = self.slots.get(id.index().index())?;
+        if !id.matches_generation(slot.generation) {
+            return None;
+        }
+
+        slot.rect.as_ref()
+    }
+
+    /// Iterate over the allocated rectangles.
+    pub fn iter(&self) -> impl Iterator<Item = (AllocId, &Rectangle)> + '_ {
+        self.slots.iter().enumerate().filter_map(|(idx, slot)| {
+            let rect = slot.rect.as_ref()?;
+            Some((AllocId::new(AllocIndex(idx as u32), slot.generation), rect))
+        })
+    }
+
+    /// Invoke a callback for each allocated rectangle.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (id, rect) in self.iter() {
+            callback(id, rect);
+        }
+    }
+}
+
+impl std::ops::Index<AllocId> for FrozenAtlas {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+/// A simpler atlas allocator implementation that can allocate rectangles but not deallocate them.
+pub struct SimpleAtlasAllocator {
+    free_rects: [Vec<Rectangle>; 3],
+    alignment: Size,
+    small_size_threshold: i32,
+    large_size_threshold: i32,
+    split_policy: SplitPolicy,
+    size: Size,
+}
+
+impl SimpleAtlasAllocator {
+    /// Create a simple atlas allocator with default options.
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a simple atlas allocator with the provided options.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        let bucket = free_list_for_size(
+            options.small_size_threshold,
+            options.large_size_threshold,
+            &size,
+        );
+
+        let mut free_rects = [Vec::new(), Vec::new(), Vec::new()];
+        free_rects[bucket].push(size.into());
+
+        SimpleAtlasAllocator {
+            free_rects,
+            alignment: options.alignment,
+            small_size_threshold: options.small_size_threshold,
+            large_size_threshold: options.large_size_threshold,
+            split_policy: options.split_policy,
+            size,
+        }
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    pub fn clear(&mut self) {
+
+        for i in 0..NUM_BUCKETS {
+            self.free_rects[i].clear();
+        }
+
+        let bucket = free_list_for_size(
+            self.small_size_threshold,
+            self.large_size_threshold,
+            &self.size,
+        );
+
+        self.free_rects[bucket].push(self.size.into());
+    }
+
+    /// Clear the allocator and reset its size and options.
+    pub fn reset(&mut self, size: Size, options: &AllocatorOptions) {
+        self.alignment = options.alignment;
+        self.small_size_threshold = options.small_size_threshold;
+        self.large_size_threshold = options.large_size_threshold;
+        self.split_policy = options.split_policy;
+        self.size = size;
+
+        self.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        for b in 0..NUM_BUCKETS {
+            if let Some(rect) = self.free_rects[b].first() {
+                return rect.size() == self.size;
+            }
+        }
+
+        // This should be unreachable.
+        false
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, mut requested_size: Size) -> Option<Rectangle> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        adjust_size(self.alignment.width, &mut requested_size.width);
+        adjust_size(self.alignment.height, &mut requested_size.height);
+
+        let ideal_bucket = free_list_for_size(
+            self.small_size_threshold,
+            self.large_size_threshold,
+            &requested_size,
+        );
+
+        let use_worst_fit = ideal_bucket == LARGE_BUCKET;
+
+        let mut chosen_rect = None;
+        for bucket in ideal_bucket..NUM_BUCKETS {
+            let mut candidate_score = if use_worst_fit { 0 } else { i32::MAX };
+            let mut candidate = None;
+
+            for (index, rect) in self.free_rects[bucket].iter().enumerate() {
+                let dx = rect.width() - requested_size.width;
+                let dy = rect.height() - requested_size.height;
+
+                if dx >= 0 && dy >= 0 {
+                    if dx == 0 || dy == 0 {
+                        // Perfect fit!
+                        candidate = Some(index);
+                        break;
+                    }
+
+                    let score = i32::min(dx, dy);
+                    if (use_worst_fit && score > candidate_score)
+                        || (!use_worst_fit && score < candidate_score)
+                    {
+                        candidate_score = score;
+                        candidate = Some(index);
+                    }
+                }
+            }
+
+            if let Some(index) = candidate {
+                let rect = self.free_rects[bucket].remove(index);
+                chosen_rect = Some(rect);
+                break;
+            }
+        }
+
+        if let Some(rect) = chosen_rect {
+            let (split_rect, leftover_rect, _) =
+                guillotine_rect(&rect, requested_size, Orientation::Vertical, self.split_policy);
+            self.add_free_rect(&split_rect);
+            self.add_free_rect(&leftover_rect);
+
+            return Some(Rectangle {
+                min: rect.min,
+                max: rect.min + requested_size.to_vector(),
+            });
+        }
+
+        None
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    ///
+    /// The new space is added the same way `AtlasAllocator::grow` does it: a free strip is added
+    /// below the atlas, then a free column on its right. Free rectangles that span the whole
+    /// width or height of the atlas along the grown edge are extended instead, so growing an
+    /// empty atlas (or one with a free band along an edge) doesn't fragment its free space.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        let old_size = self.size;
+        self.size = new_size;
+
+        if new_size.height > old_size.height {
+            let mut rect = Rectangle {
+                min: point2(0, old_size.height),
+                max: point2(old_size.width, new_size.height),
+            };
+            let band = self.take_free_rect(|r| {
+                r.min.x == 0 && r.max.x == old_size.width && r.max.y == old_size.height
+            });
+            match band {
+                Some(band) => rect.min = band.min,
+                None => rect.min = aligned_position(&self.alignment, rect.min),
+            }
+            self.add_free_rect(&rect);
+        }
+
+        if new_size.width > old_size.width {
+            let mut rect = Rectangle {
+                min: point2(old_size.width, 0),
+                max: point2(new_size.width, new_size.height),
+            };
+            let band = self.take_free_rect(|r| {
+                r.min.y == 0 && r.max.y == new_size.height && r.max.x == old_size.width
+            });
+            match band {
+                Some(band) => rect.min = band.min,
+                None => rect.min = aligned_position(&self.alignment, rect.min),
+            }
+            self.add_free_rect(&rect);
+        }
+    }
+
+    // Remove and return the first free rectangle that matches the predicate.
+    fn take_free_rect(&mut self, predicate: impl Fn(&Rectangle) -> bool) -> Option<Rectangle> {
+        for list in &mut self.free_rects {
+            if let Some(index) = list.iter().position(&predicate) {
+                return Some(list.remove(index));
+            }
+        }
+
+        None
+    }
+
+    /// Merge the free rectangles that share a full edge.
+    ///
+    /// This allocator doesn't keep track of the neighbors of its free rectangles, so they are
+    /// never merged on their own, for example after `grow` or `init_from_allocator`. This
+    /// pass sorts all of the free rectangles, which makes it too expensive to run after every
+    /// allocation, but calling it from time to time (or when an allocation fails) reduces the
+    /// fragmentation of long-lived atlases.
+    pub fn coalesce(&mut self) {
+        let mut rects: Vec<Rectangle> = Vec::new();
+        for list in &mut self.free_rects {
+            rects.append(list);
+        }
+
+        loop {
+            let count = rects.len();
+            // Merge horizontal neighbors, then vertical ones.
+            rects.sort_unstable_by_key(|r| (r.min.y, r.max.y, r.min.x));
+            rects.dedup_by(|next, prev| {
+                let mergeable = prev.min.y == next.min.y && prev.max.y == next.max.y && prev.max.x == next.min.x;
+                if mergeable {
+                    prev.max.x = next.max.x;
+                }
+                mergeable
+            });
+            rects.sort_unstable_by_key(|r| (r.min.x, r.max.x, r.min.y));
+            rects.dedup_by(|next, prev| {
+                let mergeable = prev.min.x == next.min.x && prev.max.x == next.max.x && prev.max.y == next.min.y;
+                if mergeable {
+                    prev.max.y = next.max.y;
+                }
+                mergeable
+            });
+
+            if rects.len() == count {
+                break;
+            }
+        }
+
+        for rect in &rects {
+            self.add_free_rect(rect);
+        }
+    }
+
+    /// Invoke a callback for each free rectangle in the atlas.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for rect in self.free_rects.iter().flatten() {
+            callback(rect);
+        }
+    }
+
+    /// Initialize this simple allocator with the content of an atlas allocator.
+    pub fn init_from_allocator(&mut self, src: &AtlasAllocator) {
+        self.size = src.size;
+        self.small_size_threshold = src.small_size_threshold;
+        self.large_size_threshold = src.large_size_threshold;
+
+        for bucket in 0..NUM_BUCKETS {
+            for id in src.free_lists[bucket].iter() {
+                // During tree simplification we don't remove merged nodes from the free list, so we have
+                // to handle it here.
+                // This is a tad awkward, but lets us avoid having to maintain a doubly linked list for
+                // the free list (which would be needed to remove nodes during tree simplification).
+                if src.nodes[id.index()].kind != NodeKind::Free {
+                    continue;
+                }
+
+                self.free_rects[bucket].push(src.nodes[id.index()].rect);
+            }
+        }
+    }
+
+    fn add_free_rect(&mut self, rect: &Rectangle) {
+        if rect.width() < self.alignment.width || rect.height() < self.alignment.height {
+            return;
+        }
+
+        let bucket = free_list_for_size(
+            self.small_size_threshold,
+            self.large_size_threshold,
+            &rect.size(),
+        );
+
+        self.free_rects[bucket].push(*rect);
+    }
+}
+
+pub(crate) fn adjust_size(alignment: i32, size: &mut i32) {
+    let rem = *size % alignment;
+    if rem > 0 {
+        *size += alignment - rem;
+    }
+}
+
+fn aligned_position(alignment: &Size, mut position: Point) -> Point {
+    adjust_size(alignment.width, &mut position.x);
+    adjust_size(alignment.height, &mut position.y);
+
+    position
+}
+
+// Areas can overflow i32 in large atlases, so they are computed with 64 bits integers.
+pub(crate) fn area(size: Size) -> i64 {
+    size.width as i64 * size.height as i64
+}
+
+fn guillotine_rect(
+    chosen_rect: &Rectangle,
+    requested_size: Size,
+    default_orientation: Orientation,
+    split_policy: SplitPolicy,
+) -> (Rectangle, Rectangle, Orientation) {
+    // Decide whether to split horizontally or vertically.
+    //
+    // If the chosen free rectangle is bigger than the requested size, we subdivide it
+    // into an allocated rectangle, a split rectangle and a leftover rectangle:
+    //
+    // +-----------+-------------+
+    // |///////////|             |
+    // |/allocated/|             |
+    // |///////////|             |
+    // +-----------+             |
+    // |                         |
+    // |          chosen         |
+    // |                         |
+    // +-------------------------+
+    //
+    // Will be split into either:
+    //
+    // +-----------+-------------+
+    // |///////////|             |
+    // |/allocated/|  leftover   |
+    // |///////////|             |
+    // +-----------+-------------+
+    // |                         |
+    // |          split          |
+    // |                         |
+    // +-------------------------+
+    //
+    // or:
+    //
+    // +-----------+-------------+
+    // |///////////|             |
+    // |/allocated/|             |
+    // |///////////|    split    |
+    // +-----------+             |
+    // |           |             |
+    // | leftover  |             |
+    // |           |             |
+    // +-----------+-------------+
+
+    let candidate_leftover_rect_to_right = Rectangle {
+        min: chosen_rect.min + vec2(requested_size.width, 0),
+        max: point2(chosen_rect.max.x, chosen_rect.min.y + requested_size.height),
+    };
+    let candidate_leftover_rect_to_bottom = Rectangle {
+        min: chosen_rect.min + vec2(0, requested_size.height),
+        max: point2(chosen_rect.min.x + requested_size.width, chosen_rect.max.y),
+    };
+
+    // Whether the split rectangle is on the right (second case above). If the requested size
+    // matches the chosen rectangle along one axis, the split rectangle must be on the other side
+    // so that the leftover rectangle is empty.
+    let right = &candidate_leftover_rect_to_right;
+    let bottom = &candidate_leftover_rect_to_bottom;
+    let split_to_the_right = if right.is_empty() {
+        false
+    } else if bottom.is_empty() {
+        true
+    } else {
+        match split_policy {
+            SplitPolicy::ShorterLeftoverAxis => right.width() > bottom.height(),
+            SplitPolicy::LongerLeftoverAxis => right.width() <= bottom.height(),
+            SplitPolicy::MinimizeArea => area(right.size()) > area(bottom.size()),
+            SplitPolicy::MaximizeArea => area(right.size()) < area(bottom.size()),
+        }
+    };
+
+    let split_rect;
+    let leftover_rect;
+    let orientation;
+    if requested_size == chosen_rect.size() {
+        // Perfect fit.
+        orientation = default_orientation;
+        split_rect = Rectangle::zero();
+        leftover_rect = Rectangle::zero();
+    } else if split_to_the_right {
+        leftover_rect = candidate_leftover_rect_to_bottom;
+        split_rect = Rectangle {
+            min: candidate_leftover_rect_to_right.min,
+            max: point2(candidate_leftover_rect_to_right.max.x, chosen_rect.max.y),
+        };
+        orientation = Orientation::Horizontal;
+    } else {
+        leftover_rect = candidate_leftover_rect_to_right;
+        split_rect = Rectangle {
+            min: candidate_leftover_rect_to_bottom.min,
+            max: point2(chosen_rect.max.x, candidate_leftover_rect_to_bottom.max.y),
+        };
+        orientation = Orientation::Vertical;
+    }
+
+    (split_rect, leftover_rect, orientation)
+}
+
+/// Information about a node of the atlas's tree, see `AtlasAllocator::visit`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NodeInfo {
+    /// Index of the node, unique among the nodes of the tree.
+    pub index: usize,
+    /// Index of the parent container, `None` for the top-level nodes.
+    pub parent: Option<usize>,
+    pub kind: NodeKind,
+    pub rectangle: Rectangle,
+    /// The axis along which the node and its siblings are laid out.
+    pub axis: Axis,
+    /// Depth of the node in the tree, zero for the top-level nodes.
+    pub depth: usize,
+    /// The id of allocated nodes.
+    pub id: Option<AllocId>,
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Allocation {
+    pub id: AllocId,
+    /// The allocated rectangle, including the padding.
+    pub rectangle: Rectangle,
+    /// The part of the allocated rectangle that is meant to hold the content, excluding the
+    /// padding.
+    pub content: Rectangle,
+    /// Whether the allocation was rotated by 90 degrees, in which case the width and height
+    /// of the rectangles are swapped compared to the requested size.
+    pub rotated: bool,
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Change {
+    pub old: Allocation,
+    pub new: Allocation,
+}
+
+/// The reason why an allocator operation failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AllocError {
+    /// The requested size is larger than the atlas.
+    TooLarge,
+    /// There is not enough free space in the atlas.
+    OutOfSpace,
+    /// There is enough free space in the atlas, but not in a single free rectangle.
+    Fragmented,
+    /// The requested size is empty or negative.
+    InvalidSize,
+    /// The id does not refer to a live allocation.
+    StaleId,
+    /// The operation would shrink the atlas.
+    WouldShrink,
+    /// The allocator options are invalid.
+    InvalidOptions,
+}
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let msg = match self {
+            AllocError::TooLarge => "the requested size is larger than the atlas",
+            AllocError::OutOfSpace => "not enough space in the atlas",
+            AllocError::Fragmented => "the free space of the atlas is too fragmented",
+            AllocError::InvalidSize => "invalid size",
+            AllocError::StaleId => "invalid or stale allocation id",
+            AllocError::WouldShrink => "the atlas can't be shrunk",
+            AllocError::InvalidOptions => "invalid allocator options",
+        };
+
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+/// A problem found by `AtlasAllocator::validate`.
+///
+/// Node indices refer to the internal node storage and are only meaningful for debugging.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ValidationError {
+    /// The root node is missing or is not a top-level node.
+    InvalidRoot,
+    /// A node refers to a node that does not exist.
+    InvalidLink { node: usize },
+    /// A node's parent is not a container with the opposite orientation.
+    InvalidParent { node: usize },
+    /// The sibling links of a node are inconsistent.
+    BrokenSiblingLink { node: usize },
+    /// A list of siblings loops, or a node is the child of several containers.
+    Cycle { node: usize },
+    /// A node can't be reached from the root node.
+    Unreachable { node: usize },
+    /// A node doesn't start where its previous sibling (or its parent) starts.
+    NotAdjacent { node: usize },
+    /// A node doesn't span its parent across its sibling list, or the sibling list of the node
+    /// doesn't cover the parent along its orientation.
+    InvalidTiling { node: usize },
+    /// The list of unused nodes is broken.
+    BrokenUnusedList,
+    /// A rectangle is empty or outside of the atlas.
+    OutOfBounds { node: usize },
+    /// Two rectangles overlap.
+    Overlap { a: usize, b: usize },
+    /// The rectangles do not cover the entire atlas.
+    IncompleteCoverage,
+    /// A free list refers to a node that does not exist.
+    InvalidFreeListEntry,
+    /// A free rectangle is not in any free list.
+    MissingFreeListEntry { node: usize },
+    /// The cached counters do not match the content of the atlas.
+    InconsistentCounters,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidationError::InvalidRoot => write!(f, "invalid root node"),
+            ValidationError::InvalidLink { node } => write!(f, "node #{} has an invalid link", node),
+            ValidationError::InvalidParent { node } => write!(f, "node #{} has an invalid parent", node),
+            ValidationError::BrokenSiblingLink { node } => write!(f, "node #{} has broken sibling links", node),
+            ValidationError::Cycle { node } => write!(f, "node #{} is part of a cycle", node),
+            ValidationError::Unreachable { node } => write!(f, "node #{} is not reachable from the root", node),
+            ValidationError::NotAdjacent { node } => write!(f, "node #{} is not adjacent to its previous sibling", node),
+            ValidationError::InvalidTiling { node } => write!(f, "node #{} does not tile its parent", node),
+            ValidationError::BrokenUnusedList => write!(f, "broken list of unused nodes"),
+            ValidationError::OutOfBounds { node } => write!(f, "node #{} is out of bounds", node),
+            ValidationError::Overlap { a, b } => write!(f, "nodes #{} and #{} overlap", a, b),
+            ValidationError::IncompleteCoverage => write!(f, "the nodes do not cover the atlas"),
+            ValidationError::InvalidFreeListEntry => write!(f, "invalid free list entry"),
+            ValidationError::MissingFreeListEntry { node } => write!(f, "free node #{} is not in a free list", node),
+            ValidationError::InconsistentCounters => write!(f, "inconsistent counters"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The version of the serialization formats of `AtlasAllocator`, see `VersionedAtlas` and
+/// `AtlasAllocator::to_binary`.
+pub const SERIALIZATION_VERSION: u32 = 1;
+
+/// An `AtlasAllocator` along with the version of its serialization format.
+///
+/// Serializing an `AtlasAllocator` directly ties the data to the internal structures of the
+/// version of the crate that wrote it. Serializing a `VersionedAtlas` instead makes it
+/// possible to load data written by older versions of the crate, which is migrated to the
+/// current format, and to report a clear error for data written by newer versions.
+///
+/// Data written by serializing an `AtlasAllocator` before versions were introduced is read
+/// as version 0. Its options that didn't exist yet take their default values. Migrating it
+/// is only possible with self-describing formats such as RON or JSON.
+#[cfg(feature = "serde")]
+pub struct VersionedAtlas {
+    version: u32,
+    // None if the version is not supported.
+    atlas: Option<Box<AtlasAllocator>>,
+}
+
+// The format of the atlas before versions were introduced. Unknown fields are ignored and
+// the free lists are rebuilt.
+#[cfg(feature = "serde")]
+struct LegacyAtlas {
+    nodes: Vec<LegacyNode>,
+    unused_nodes: AllocIndex,
+    generations: Vec<Wrapping<u8>>,
+    alignment: Size,
+    small_size_threshold: i32,
+    large_size_threshold: i32,
+    size: Size,
+    root_node: AllocIndex,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct LegacyNode {
+    parent: AllocIndex,
+    next_sibling: AllocIndex,
+    prev_sibling: AllocIndex,
+    kind: NodeKind,
+    orientation: Orientation,
+    rect: Rectangle,
+}
+
+#[cfg(feature = "serde")]
+impl VersionedAtlas {
+    /// Wrap an atlas to serialize it with the current version.
+    pub fn new(atlas: AtlasAllocator) -> Self {
+        VersionedAtlas {
+            version: SERIALIZATION_VERSION,
+            atlas: Some(Box::new(atlas)),
+        }
+    }
+
+    /// The version of the serialization format the atlas was read from.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Return the atlas, migrated to the current format if needed.
+    ///
+    /// The ids of the allocations are preserved by the migration.
+    pub fn into_atlas(self) -> Result<AtlasAllocator, SerializationError> {
+        match self.atlas {
+            Some(atlas) => Ok(*atlas),
+            None => Err(SerializationError::UnsupportedVersion(self.version)),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for VersionedAtlas {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeStruct};
+
+        // Migrated atlases are written in the current format.
+        let atlas = match &self.atlas {
+            Some(atlas) => atlas,
+            None => return Err(S::Error::custom(SerializationError::UnsupportedVersion(self.version))),
+        };
+
+        let mut state = serializer.serialize_struct("VersionedAtlas", 2)?;
+        state.serialize_field("version", &SERIALIZATION_VERSION)?;
+        state.serialize_field("atlas", atlas)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for VersionedAtlas {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "snake_case")]
+        enum Field {
+            Version,
+            Atlas,
+            Nodes,
+            UnusedNodes,
+            Generations,
+            Alignment,
+            SmallSizeThreshold,
+            LargeSizeThreshold,
+            Size,
+            RootNode,
+            #[serde(other)]
+            Other,
+        }
+
+        struct VersionedAtlasVisitor;
+
+        impl<'de> Visitor<'de> for VersionedAtlasVisitor {
+            type Value = VersionedAtlas;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a versioned atlas")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<VersionedAtlas, A::Error> {
+                // Without field names, the version must come first and match the current one.
+                let version: u32 = seq.next_element()?.ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                if version != SERIALIZATION_VERSION {
+                    return Err(A::Error::custom(SerializationError::UnsupportedVersion(version)));
+                }
+                let atlas = seq.next_element()?.ok_or_else(|| A::Error::invalid_length(1, &self))?;
+
+                Ok(VersionedAtlas { version, atlas: Some(atlas) })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<VersionedAtlas, A::Error> {
+                let mut version = None;
+                let mut atlas = None;
+                let mut nodes = None;
+                let mut unused_nodes = None;
+                let mut generations = None;
+                let mut alignment = None;
+                let mut small_size_threshold = None;
+                let mut large_size_threshold = None;
+                let mut size = None;
+                let mut root_node = None;
+
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        Field::Version => version = Some(map.next_value::<u32>()?),
+                        // The atlas of unsupported versions is skipped so that the version can be
+                        // reported. The version is not known yet if the atlas comes first, in which
+                        // case it is read in the current format and dropped if the version doesn't
+                        // match.
+                        Field::Atlas if version.is_none() || version == Some(SERIALIZATION_VERSION) => {
+                            atlas = Some(map.next_value()?)
+                        }
+                        Field::Nodes => nodes = Some(map.next_value()?),
+                        Field::UnusedNodes => unused_nodes = Some(map.next_value()?),
+                        Field::Generations => generations = Some(map.next_value()?),
+                        Field::Alignment => alignment = Some(map.next_value()?),
+                        Field::SmallSizeThreshold => small_size_threshold = Some(map.next_value()?),
+                        Field::LargeSizeThreshold => large_size_threshold = Some(map.next_value()?),
+                        Field::Size => size = Some(map.next_value()?),
+                        Field::RootNode => root_node = Some(map.next_value()?),
+                        Field::Atlas | Field::Other => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                if let Some(version) = version {
+                    if version != SERIALIZATION_VERSION {
+                        return Ok(VersionedAtlas { version, atlas: None });
+                    }
+                    if atlas.is_none() {
+                        return Err(A::Error::missing_field("atlas"));
+                    }
+                    return Ok(VersionedAtlas { version, atlas });
+                }
+                if atlas.is_some() {
+                    return Err(A::Error::missing_field("version"));
+                }
+
+                let legacy = LegacyAtlas {
+                    nodes: nodes.ok_or_else(|| A::Error::missing_field("nodes"))?,
+                    unused_nodes: unused_nodes.ok_or_else(|| A::Error::missing_field("unused_nodes"))?,
+                    generations: generations.ok_or_else(|| A::Error::missing_field("generations"))?,
+                    alignment: alignment.ok_or_else(|| A::Error::missing_field("alignment"))?,
+                    small_size_threshold: small_size_threshold
+                        .ok_or_else(|| A::Error::missing_field("small_size_threshold"))?,
+                    large_size_threshold: large_size_threshold
+                        .ok_or_else(|| A::Error::missing_field("large_size_threshold"))?,
+                    size: size.ok_or_else(|| A::Error::missing_field("size"))?,
+                    root_node: root_node.ok_or_else(|| A::Error::missing_field("root_node"))?,
+                };
+
+                let atlas = AtlasAllocator::from_legacy(legacy).map_err(A::Error::custom)?;
+
+                Ok(VersionedAtlas {
+                    version: 0,
+                    atlas: Some(Box::new(atlas)),
+                })
+            }
+        }
+
+        deserializer.deserialize_struct(
+            "VersionedAtlas",
+            &["version", "atlas"],
+            VersionedAtlasVisitor,
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl AtlasAllocator {
+    // The migrated atlas is validated since the legacy format has no integrity checks.
+    fn from_legacy(legacy: LegacyAtlas) -> Result<Self, SerializationError> {
+        let options = AllocatorOptions {
+            alignment: legacy.alignment,
+            small_size_threshold: legacy.small_size_threshold,
+            large_size_threshold: legacy.large_size_threshold,
+            ..DEFAULT_OPTIONS
+        };
+        if !options.is_valid() || legacy.size.width <= 0 || legacy.size.height <= 0 {
+            return Err(SerializationError::Malformed);
+        }
+
+        let mut atlas = AtlasAllocator::with_options(legacy.size, &options);
+
+        atlas.nodes = legacy.nodes.iter().map(|node| Node {
+            next_sibling: node.next_sibling,
+            prev_sibling: node.prev_sibling,
+            ..Node::new(node.kind, node.rect, node.orientation, node.parent)
+        }).collect();
+        atlas.generations = legacy.generations.iter().map(|generation| Wrapping(generation.0 as Generation)).collect();
+        atlas.unused_nodes = legacy.unused_nodes;
+        atlas.root_node = legacy.root_node;
+        atlas.last_alloc = AllocIndex::NONE;
+
+        for list in &mut atlas.free_lists {
+            list.clear();
+        }
+        atlas.allocated_space = 0;
+        atlas.allocation_count = 0;
+        for index in 0..atlas.nodes.len() {
+            let rect = atlas.nodes[index].rect;
+            match atlas.nodes[index].kind {
+                NodeKind::Free => atlas.add_free_rect(AllocIndex(index as u32), &rect.size()),
+                NodeKind::Alloc => {
+                    atlas.allocated_space += area(rect.size());
+                    atlas.allocation_count += 1;
+                }
+                _ => {}
+            }
+        }
+
+        atlas.validate().map_err(SerializationError::Invalid)?;
+
+        Ok(atlas)
+    }
+}
+
+/// The reason why a serialized atlas could not be loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SerializationError {
+    /// The atlas was serialized with a version of the format that this version of the crate
+    /// doesn't support, typically by a newer version of the crate.
+    UnsupportedVersion(u32),
+    /// The data is truncated or is not a serialized atlas, see `AtlasAllocator::from_binary`.
+    Malformed,
+    /// The serialized atlas is corrupted, see `AtlasAllocator::from_binary`.
+    Invalid(ValidationError),
+}
+
+impl std::fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SerializationError::UnsupportedVersion(version) => write!(
+                f, "unsupported serialization format version {} (expected at most {})",
+                version, SERIALIZATION_VERSION,
+            ),
+            SerializationError::Malformed => write!(f, "malformed serialized atlas"),
+            SerializationError::Invalid(error) => write!(f, "invalid serialized atlas: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
+const BINARY_MAGIC: &[u8] = b"GLTR";
+
+// The order of the variants in the binary format.
+const FIT_POLICIES: [FitPolicy; 5] = [
+    FitPolicy::Adaptive,
+    FitPolicy::WorstFit,
+    FitPolicy::BestAreaFit,
+    FitPolicy::BestShortSideFit,
+    FitPolicy::FirstFit,
+];
+const SPLIT_POLICIES: [SplitPolicy; 4] = [
+    SplitPolicy::ShorterLeftoverAxis,
+    SplitPolicy::LongerLeftoverAxis,
+    SplitPolicy::MinimizeArea,
+    SplitPolicy::MaximizeArea,
+];
+const PLACEMENT_BIASES: [PlacementBias; 4] = [
+    PlacementBias::None,
+    PlacementBias::TopLeft,
+    PlacementBias::Top,
+    PlacementBias::Left,
+];
+const NODE_KINDS: [NodeKind; 5] = [
+    NodeKind::Container,
+    NodeKind::Alloc,
+    NodeKind::Free,
+    NodeKind::Reserved,
+    NodeKind::Unused,
+];
+
+// Little endian encoding of the binary format, see `AtlasAllocator::to_binary`.
+struct BinaryWriter(Vec<u8>);
+
+impl BinaryWriter {
+    fn u8(&mut self, val: u8) {
+        self.0.push(val);
+    }
+
+    fn bool(&mut self, val: bool) {
+        self.0.push(val as u8);
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn i32(&mut self, val: i32) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    fn i64(&mut self, val: i64) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // Generations are always written as 32 bits integers.
+    #[allow(clippy::unnecessary_cast)]
+    fn generation(&mut self, val: Generation) {
+        self.u32(val as u32);
+    }
+}
+
+struct BinaryReader<'l>(&'l [u8]);
+
+impl<'l> BinaryReader<'l> {
+    fn take(&mut self, len: usize) -> Result<&'l [u8], SerializationError> {
+        if self.0.len() < len {
+            return Err(SerializationError::Malformed);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], SerializationError> {
+        Ok(std::convert::TryInto::try_into(self.take(N)?).unwrap())
+    }
+
+    fn bool(&mut self) -> Result<bool, SerializationError> {
+        match self.bytes::<1>()?[0] {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SerializationError::Malformed),
+        }
+    }
+
+    fn variant<T: Copy>(&mut self, variants: &[T]) -> Result<T, SerializationError> {
+        let idx = self.bytes::<1>()?[0] as usize;
+        variants.get(idx).copied().ok_or(SerializationError::Malformed)
+    }
+
+    fn u32(&mut self) -> Result<u32, SerializationError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, SerializationError> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SerializationError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, SerializationError> {
+        Ok(i64::from_le_bytes(self.bytes()?))
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    fn generation(&mut self) -> Result<Generation, SerializationError> {
+        let val = self.u32()?;
+        if val > Generation::MAX as u32 {
+            return Err(SerializationError::Malformed);
+        }
+
+        Ok(val as Generation)
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeList {
+    pub changes: Vec<Change>,
+    pub failures: Vec<Allocation>,
+}
+
+impl ChangeList {
+    pub fn empty() -> Self {
+        ChangeList {
+            changes: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+/// How the dimensions of the atlas are increased, see `GrowthPolicy`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GrowthStrategy {
+    /// Grow exactly as much as needed.
+    Exact,
+    /// Round the dimensions that grow up to the next power of two.
+    PowerOfTwo,
+    /// Double the dimensions that grow until they are large enough.
+    Doubling,
+    /// Grow the dimensions by multiples of a fixed number of pixels.
+    FixedIncrement(i32),
+    /// Same as `Doubling`, but only grow the height when growing the width is not enough.
+    WidthThenHeight,
+}
+
+/// Decides the size an atlas grows to, see `AtlasAllocator::ensure_can_allocate`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GrowthPolicy {
+    /// Default value: GrowthStrategy::PowerOfTwo,
+    pub strategy: GrowthStrategy,
+
+    /// The atlas is never grown beyond this size, typically the maximum texture size
+    /// supported by the GPU.
+    ///
+    /// Default value: (8192, 8192),
+    pub max_size: Size,
+}
+
+impl GrowthPolicy {
+    /// Compute the size to grow to from the current size, given the minimum size required.
+    ///
+    /// Returns `None` if the required size exceeds the maximum size.
+    pub fn new_size(&self, current: Size, required: Size) -> Option<Size> {
+        if required.width > self.max_size.width || required.height > self.max_size.height {
+            return None;
+        }
+
+        let grow = |current: i32, required: i32| {
+            if required <= current {
+                return current;
+            }
+
+            match self.strategy {
+                GrowthStrategy::Exact => required,
+                GrowthStrategy::PowerOfTwo => (required as u32).next_power_of_two() as i32,
+                GrowthStrategy::Doubling | GrowthStrategy::WidthThenHeight => {
+                    let mut size = current.max(1);
+                    while size < required {
+                        size *= 2;
+                    }
+                    size
+                }
+                GrowthStrategy::FixedIncrement(increment) => {
+                    assert!(increment > 0);
+                    let steps = (required - current + increment - 1) / increment;
+                    current + steps * increment
+                }
+            }
+        };
+
+        let size = size2(
+            grow(current.width, required.width),
+            grow(current.height, required.height),
+        );
+
+        Some(size.min(self.max_size).max(current))
+    }
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        GrowthPolicy {
+            strategy: GrowthStrategy::PowerOfTwo,
+            max_size: size2(8192, 8192),
+        }
+    }
+}
+
+/// Parameters of `AtlasAllocator::grow_and_allocate`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GrowOptions {
+    /// Default value: GrowthPolicy::default(),
+    pub policy: GrowthPolicy,
+
+    /// Rearrange the atlas if growing it is not enough to satisfy the allocation.
+    ///
+    /// Default value: false,
+    pub allow_rearrange: bool,
+}
+
+/// Statistics about the free space of an atlas, see `AtlasAllocator::stats`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AllocatorStats {
+    /// Number of free rectangles.
+    pub free_rect_count: usize,
+    /// Size of the free rectangle with the largest area.
+    pub largest_free_rect: Size,
+    /// Average area of the free rectangles.
+    pub mean_free_rect_area: f32,
+    /// Median area of the free rectangles.
+    pub median_free_rect_area: i64,
+    /// 90th percentile of the area of the free rectangles.
+    pub p90_free_rect_area: i64,
+    /// Ratio of the free space that is not part of the largest free rectangle, between 0.0
+    /// (the free space is contiguous) and 1.0 (the free space is scattered into many small
+    /// rectangles).
+    pub fragmentation: f32,
+}
+
+/// The allocations sharing a tag, see `AtlasAllocator::area_by_tag`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TagUsage {
+    pub tag: u32,
+    /// Sum of the areas of the allocated rectangles, including their padding.
+    pub allocated_space: i64,
+    pub allocation_count: usize,
+}
+
+/// Memory used by the internal storage of an atlas, see `AtlasAllocator::memory_usage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The nodes of the tree.
+    pub nodes: StorageUsage,
+    /// The per-node generation counters.
+    pub generations: StorageUsage,
+    /// The free lists, from the smallest to the largest size bucket.
+    pub free_lists: Vec<StorageUsage>,
+    /// The free lists of each size class, see `AllocatorOptions::power_of_two_sizes`.
+    ///
+    /// The lengths and capacities are the numbers of entries of all of the lists, while the
+    /// bytes also include the storage of the lists themselves.
+    pub size_classes: StorageUsage,
+    /// The free rectangles waiting to be coalesced, see `AllocatorOptions::deferred_coalescing`.
+    pub uncoalesced: StorageUsage,
+}
+
+impl MemoryReport {
+    /// Total number of bytes allocated by the atlas.
+    pub fn total_bytes(&self) -> usize {
+        self.nodes.bytes
+            + self.generations.bytes
+            + self.free_lists.iter().map(|list| list.bytes).sum::<usize>()
+            + self.size_classes.bytes
+            + self.uncoalesced.bytes
+    }
+}
+
+/// Length and capacity of one of the vectors of an atlas.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StorageUsage {
+    /// Number of elements in use.
+    pub len: usize,
+    /// Number of elements that can be stored without reallocating.
+    pub capacity: usize,
+    /// Number of bytes allocated, based on the capacity.
+    pub bytes: usize,
+}
+
+impl StorageUsage {
+    fn of<T>(vector: &Vec<T>) -> Self {
+        StorageUsage {
+            len: vector.len(),
+            capacity: vector.capacity(),
+            bytes: vector.capacity() * std::mem::size_of::<T>(),
+        }
+    }
+}
+
+/// Atlases that can be dumped with `dump_svg`.
+pub trait SvgDump {
+    /// The total size of the atlas.
+    fn dump_size(&self) -> Size;
+
+    /// Invoke a callback for each rectangle to draw, in drawing order.
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind));
+
+    /// Identical to `dump_rectangles`, also providing the ids of the allocated rectangles
+    /// when they are known.
+    fn dump_rectangles_with_ids(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind, Option<AllocId>)) {
+        self.dump_rectangles(&mut |rect, kind| callback(rect, kind, None));
+    }
+}
+
+impl SvgDump for AtlasAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        for node in &self.nodes {
+            callback(&node.rect, node.kind);
+        }
+    }
+
+    fn dump_rectangles_with_ids(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind, Option<AllocId>)) {
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let id = if node.kind == NodeKind::Alloc {
+                Some(self.alloc_id(AllocIndex(idx as u32)))
+            } else {
+                None
+            };
+            callback(&node.rect, node.kind, id);
+        }
+    }
+}
+
+impl SvgDump for SimpleAtlasAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    // The simple allocator doesn't track its allocations, so everything that isn't free is
+    // drawn as allocated.
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        callback(&self.size.into(), NodeKind::Alloc);
+        self.for_each_free_rectangle(|rect| callback(rect, NodeKind::Free));
+    }
+}
+
+/// Common interface of the atlas allocators.
+///
+/// This makes it possible to write code that works with any of the allocators, either with
+/// generics or with `dyn DynamicAtlas`. The methods forward to the inherent methods of the
+/// same name, which may offer more control or information.
+pub trait DynamicAtlas {
+    /// The total size of the atlas.
+    fn size(&self) -> Size;
+
+    /// Allocate a rectangle in the atlas.
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation>;
+
+    /// Whether individual allocations can be deallocated.
+    ///
+    /// Write-once allocators such as `SkylineAllocator` can only be cleared as a whole.
+    fn supports_deallocation(&self) -> bool {
+        true
+    }
+
+    /// Deallocate a rectangle in the atlas.
+    ///
+    /// Panics if the allocator doesn't support deallocations.
+    fn deallocate(&mut self, id: AllocId);
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    fn grow(&mut self, new_size: Size);
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    fn clear(&mut self);
+
+    fn is_empty(&self) -> bool;
+
+    /// Invoke a callback for each free rectangle in the atlas.
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle));
+}
+
+impl DynamicAtlas for AtlasAllocator {
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        AtlasAllocator::allocate(self, requested_size)
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        AtlasAllocator::deallocate(self, id);
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        AtlasAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        AtlasAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        AtlasAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        AtlasAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+// The simple allocator doesn't track its allocations, so the ids it returns can't be used for
+// anything.
+impl DynamicAtlas for SimpleAtlasAllocator {
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        let rectangle = SimpleAtlasAllocator::allocate(self, requested_size)?;
+
+        Some(Allocation {
+            id: AllocId(IDX_MASK),
+            rectangle,
+            content: rectangle,
+            rotated: false,
+        })
+    }
+
+    fn supports_deallocation(&self) -> bool {
+        false
+    }
+
+    fn deallocate(&mut self, _id: AllocId) {
+        panic!("SimpleAtlasAllocator can't deallocate rectangles");
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        SimpleAtlasAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        SimpleAtlasAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        SimpleAtlasAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        SimpleAtlasAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+/// Parameters of the SVG dumps, see `dump_svg_with_options`.
+///
+/// Colors are RGB triplets.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgOptions {
+    pub free_color: (u8, u8, u8),
+    pub alloc_color: (u8, u8, u8),
+    pub reserved_color: (u8, u8, u8),
+    /// Color of the allocations listed in `highlighted`.
+    pub highlight_color: (u8, u8, u8),
+    /// Allocations to draw with the highlight color.
+    pub highlighted: Vec<AllocId>,
+    /// Width of the outline of the rectangles, in pixels of the output.
+    pub stroke_width: f32,
+    /// Ratio between the size of the output and the size of the atlas.
+    pub scale: f32,
+    /// Write the id and the size of each allocation inside of its rectangle.
+    ///
+    /// Ids are only known for the allocators that provide them, see
+    /// `SvgDump::dump_rectangles_with_ids`.
+    pub labels: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            free_color: (50, 50, 50),
+            alloc_color: (70, 70, 180),
+            reserved_color: (120, 50, 50),
+            highlight_color: (200, 150, 40),
+            highlighted: Vec::new(),
+            stroke_width: 1.0,
+            scale: 1.0,
+            labels: false,
+        }
+    }
+}
+
+/// Dump a visual representation of the atlas in SVG format.
+pub fn dump_svg<Atlas: SvgDump + ?Sized>(atlas: &Atlas, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    dump_svg_with_options(atlas, &SvgOptions::default(), output)
+}
+
+/// Dump a visual representation of the atlas in SVG format with the provided options.
+pub fn dump_svg_with_options<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    options: &SvgOptions,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    use svg_fmt::*;
+
+    let size = atlas.dump_size().to_f32() * options.scale;
+    writeln!(
+        output,
+        "{}",
+        BeginSvg {
+            w: size.width,
+            h: size.height
+        }
+    )?;
+
+    write_svg_rectangles(atlas, options, (options.scale, options.scale, 0.0, 0.0), output)?;
+
+    writeln!(output, "{}", EndSvg)
+}
+
+/// Dump a visual representation of the atlas in SVG, omitting the beginning and end of the
+/// SVG document, so that it can be included in a larger document.
+///
+/// If a rectangle is provided, translate and scale the output to fit it.
+pub fn dump_into_svg<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    rect: Option<&Rectangle>,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    dump_into_svg_with_options(atlas, rect, &SvgOptions::default(), output)
+}
+
+/// Identical to `dump_into_svg` with the provided options.
+///
+/// The scale of the options is ignored if a rectangle is provided.
+pub fn dump_into_svg_with_options<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    rect: Option<&Rectangle>,
+    options: &SvgOptions,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    let size = atlas.dump_size();
+    let transform = if let Some(rect) = rect {
+        (
+            rect.width() as f32 / size.width as f32,
+            rect.height() as f32 / size.height as f32,
+            rect.min.x as f32,
+            rect.min.y as f32,
+        )
+    } else {
+        (options.scale, options.scale, 0.0, 0.0)
+    };
+
+    writeln!(output, "  <g>")?;
+    write_svg_rectangles(atlas, options, transform, output)?;
+    writeln!(output, "  </g>")
+}
+
+/// Dump several atlases side by side in a single SVG document, for example the atlases of an
+/// `AtlasSet` or the layers of a `LayeredAtlasAllocator`.
+///
+/// The atlases are drawn from left to right with `spacing` pixels between them, before
+/// applying the scale of the options.
+pub fn dump_svg_row(
+    atlases: &[&dyn SvgDump],
+    spacing: i32,
+    options: &SvgOptions,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    use svg_fmt::{BeginSvg, EndSvg};
+
+    let mut width = 0;
+    let mut height = 0;
+    for atlas in atlases {
+        let size = atlas.dump_size();
+        width += size.width;
+        height = height.max(size.height);
+    }
+    width += spacing * (atlases.len() as i32 - 1).max(0);
+
+    writeln!(
+        output,
+        "{}",
+        BeginSvg {
+            w: width as f32 * options.scale,
+            h: height as f32 * options.scale
+        }
+    )?;
+
+    let mut x = 0;
+    for atlas in atlases {
+        let size = atlas.dump_size();
+        let rect = Rectangle {
+            min: point2(x, 0),
+            max: point2(x, 0) + size.to_vector(),
+        };
+        let rect = rect.to_f32().scale(options.scale, options.scale).round().to_i32();
+        dump_into_svg_with_options(*atlas, Some(&rect), options, output)?;
+        x += size.width + spacing;
+    }
+
+    writeln!(output, "{}", EndSvg)
+}
+
+// Check that the allocated rectangles are inside of the atlas and don't overlap, and that the
+// atlas can be dumped. Shared by the tests of the various allocators.
+#[cfg(test)]
+pub(crate) fn check_allocated_rectangles(atlas: &dyn SvgDump, rects: &[Rectangle]) {
+    let bounds = Rectangle::from_size(atlas.dump_size());
+    for (i, a) in rects.iter().enumerate() {
+        assert!(bounds.contains_box(a), "{:?} is outside of the atlas", a);
+        for b in &rects[i + 1..] {
+            assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+        }
+    }
+
+    let mut svg = Vec::new();
+    dump_svg(atlas, &mut svg).unwrap();
+    assert!(!svg.is_empty());
+}
+
+/// Render the atlas as a coarse grid of characters, for example to log it.
+///
+/// Each character represents a square cell of `cell_size` pixels and shows what is at the
+/// center of the cell: letters for the allocations (cycling through the alphabet in drawing
+/// order), `.` for free space and `#` for reserved regions.
+pub fn dump_ascii<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    output: &mut dyn std::fmt::Write,
+    cell_size: i32,
+) -> std::fmt::Result {
+    assert!(cell_size > 0);
+
+    const LETTERS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    let size = atlas.dump_size();
+    let columns = (size.width + cell_size - 1) / cell_size;
+    let rows = (size.height + cell_size - 1) / cell_size;
+    let mut grid = vec![b' '; (columns * rows) as usize];
+
+    let mut alloc_count = 0;
+    atlas.dump_rectangles(&mut |rect, kind| {
+        let c = match kind {
+            NodeKind::Free => b'.',
+            NodeKind::Reserved => b'#',
+            NodeKind::Alloc => {
+                alloc_count += 1;
+                LETTERS[(alloc_count - 1) % LETTERS.len()]
+            }
+            _ => {
+                return;
+            }
+        };
+
+        // The cells whose center is in the rectangle.
+        let cells = |min: i32, max: i32, count: i32| {
+            let first = (min - cell_size / 2 + cell_size - 1).div_euclid(cell_size);
+            let last = (max - cell_size / 2 - 1).div_euclid(cell_size);
+            first.max(0)..=last.min(count - 1)
+        };
+        for y in cells(rect.min.y, rect.max.y, rows) {
+            for x in cells(rect.min.x, rect.max.x, columns) {
+                grid[(y * columns + x) as usize] = c;
+            }
+        }
+    });
+
+    for row in grid.chunks(columns as usize) {
+        writeln!(output, "{}", std::str::from_utf8(row).unwrap())?;
+    }
+
+    Ok(())
+}
+
+// The transform is (scale x, scale y, translation x, translation y).
+fn write_svg_rectangles<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    options: &SvgOptions,
+    (sx, sy, tx, ty): (f32, f32, f32, f32),
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    use svg_fmt::*;
+
+    let color = |(r, g, b)| rgb(r, g, b);
+
+    let mut result = Ok(());
+    atlas.dump_rectangles_with_ids(&mut |rect, kind, id| {
+        let fill = match kind {
+            NodeKind::Free => color(options.free_color),
+            NodeKind::Alloc if id.is_some_and(|id| options.highlighted.contains(&id)) => {
+                color(options.highlight_color)
+            }
+            NodeKind::Alloc => color(options.alloc_color),
+            NodeKind::Reserved => color(options.reserved_color),
+            _ => {
+                return;
+            }
+        };
+
+        if result.is_err() {
+            return;
+        }
+
+        let (x, y) = rect.min.to_f32().to_tuple();
+        let (w, h) = rect.size().to_f32().to_tuple();
+
+        result = writeln!(
+            output,
+            r#"    {}"#,
+            rectangle(tx + x * sx, ty + y * sy, w * sx, h * sy)
+                .fill(fill)
+                .stroke(Stroke::Color(black(), options.stroke_width))
+        );
+
+        let id = match id {
+            Some(id) if options.labels && result.is_ok() => id,
+            _ => {
+                return;
+            }
+        };
+
+        let label = format!("#{} {}x{}", id.serialize(), rect.width(), rect.height());
+        result = writeln!(
+            output,
+            r#"    {}"#,
+            text(tx + (x + w * 0.5) * sx, ty + (y + h * 0.5) * sy, label)
+                .color(white())
+                .align(Align::Center)
+        );
+    });
+
+    result
+}
+
+#[test]
+fn atlas_basic() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+
+    atlas.deallocate(full);
+
+    let a = atlas.allocate(size2(100, 1000)).unwrap().id;
+    let b = atlas.allocate(size2(900, 200)).unwrap().id;
+    let c = atlas.allocate(size2(300, 200)).unwrap().id;
+    let d = atlas.allocate(size2(200, 300)).unwrap().id;
+    let e = atlas.allocate(size2(100, 300)).unwrap().id;
+    let f = atlas.allocate(size2(100, 300)).unwrap().id;
+    let g = atlas.allocate(size2(100, 300)).unwrap().id;
+
+    atlas.deallocate(b);
+    atlas.deallocate(f);
+    atlas.deallocate(c);
+    atlas.deallocate(e);
+    let h = atlas.allocate(size2(500, 200)).unwrap().id;
+    atlas.deallocate(a);
+    let i = atlas.allocate(size2(500, 200)).unwrap().id;
+    atlas.deallocate(g);
+    atlas.deallocate(h);
+    atlas.deallocate(d);
+    atlas.deallocate(i);
+
+    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    atlas.deallocate(full);
+}
+
+#[test]
+fn atlas_random_test() {
+    let mut atlas = AtlasAllocator::with_options(
+        size2(1000, 1000),
+        &AllocatorOptions {
+            alignment: size2(5, 2),
+            ..DEFAULT_OPTIONS
+        },
+    );
+
+    let a = 1103515245;
+    let c = 12345;
+    let m = usize::pow(2, 31);
+    let mut seed: usize = 37;
+
+    let mut rand = || {
+        seed = (a * seed + c) % m;
+        seed
+    };
+
+    let mut n: usize = 0;
+    let mut misses: usize = 0;
+
+    let mut allocated = Vec::new();
+    for _ in 0..500000 {
+        if rand() % 5 > 2 && !allocated.is_empty() {
+            // deallocate something
+            let nth = rand() % allocated.len();
+            let id = allocated[nth];
+            allocated.remove(nth);
+
+            atlas.deallocate(id);
+        } else {
+            // allocate something
+            let size = size2((rand() % 300) as i32 + 5, (rand() % 300) as i32 + 5);
+
+            if let Some(alloc) = atlas.allocate(size) {
+                allocated.push(alloc.id);
+                n += 1;
+            } else {
+                misses += 1;
+            }
+        }
+    }
+
+    while let Some(id) = allocated.pop() {
+        atlas.deallocate(id);
+    }
+
+    println!("added/removed {} rectangles, {} misses", n, misses);
+    println!(
+        "nodes.cap: {}, free_list.cap: {}/{}/{}",
+        atlas.nodes.capacity(),
+        atlas.free_lists[LARGE_BUCKET].capacity(),
+        atlas.free_lists[MEDIUM_BUCKET].capacity(),
+        atlas.free_lists[SMALL_BUCKET].capacity(),
+    );
+
+    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    atlas.deallocate(full);
+}
+
+#[test]
+fn test_grow() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    atlas.grow(size2(2000, 2000));
+
+    let full = atlas.allocate(size2(2000, 2000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    atlas.deallocate(full);
+
+    let a = atlas.allocate(size2(100, 100)).unwrap().id;
+
+    atlas.grow(size2(3000, 3000));
+
+    let b = atlas.allocate(size2(1000, 2900)).unwrap().id;
+
+    atlas.grow(size2(4000, 4000));
+
+    atlas.deallocate(b);
+    atlas.deallocate(a);
+
+    let full = atlas.allocate(size2(4000, 4000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    atlas.deallocate(full);
+}
+
+#[test]
+fn clear_empty() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    assert!(atlas.is_empty());
+
+    assert!(atlas.allocate(size2(10, 10)).is_some());
+    assert!(!atlas.is_empty());
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+
+    let a = atlas.allocate(size2(10, 10)).unwrap().id;
+    let b = atlas.allocate(size2(20, 20)).unwrap().id;
+    assert!(!atlas.is_empty());
+
+    atlas.deallocate(b);
+    atlas.deallocate(a);
+    assert!(atlas.is_empty());
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn simple_atlas() {
+    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+
+    assert!(atlas.allocate(size2(1, 1001)).is_none());
+    assert!(atlas.allocate(size2(1001, 1)).is_none());
+
+    let rectangles = [
+        atlas.allocate(size2(100, 1000)).unwrap(),
+        atlas.allocate(size2(900, 200)).unwrap(),
+        atlas.allocate(size2(300, 200)).unwrap(),
+        atlas.allocate(size2(200, 300)).unwrap(),
+        atlas.allocate(size2(100, 300)).unwrap(),
+        atlas.allocate(size2(100, 300)).unwrap(),
+        atlas.allocate(size2(100, 300)).unwrap(),
+    ];
+    assert!(atlas.allocate(size2(800, 800)).is_none());
+
+    for i in 0..rectangles.len() {
+        for j in 0..rectangles.len() {
+            if i == j {
+                continue;
+            }
+
+            assert!(!rectangles[i].intersects(&rectangles[j]));
+        }
+    }
+}
+
+#[test]
+fn allocate_zero() {
+    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+
+    assert!(atlas.allocate(size2(0, 0)).is_none());
+}
+
+#[test]
+fn allocate_negative() {
+    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+
+    assert!(atlas.allocate(size2(-1, 1)).is_none());
+    assert!(atlas.allocate(size2(1, -1)).is_none());
+    assert!(atlas.allocate(size2(-1, -1)).is_none());
+
+    assert!(atlas.allocate(size2(-167114179, -718142)).is_none());
+}
+
+#[test]
+fn issue_25() {
+    let mut allocator = AtlasAllocator::new(Size::new(65536, 65536));
+    allocator.allocate(Size::new(2,2));
+    allocator.allocate(Size::new(65500,2));
+    allocator.allocate(Size::new(2, 65500));
+}
+
+#[test]
+fn realloc() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let a = atlas.allocate(size2(100, 100)).unwrap();
+    let b = atlas.allocate(size2(100, 100)).unwrap();
+
+    // Shrinking happens in place.
+    let a = atlas.realloc(a.id, size2(50, 60)).unwrap();
+    assert_eq!(a.rectangle.min, point2(0, 0));
+    assert_eq!(a.rectangle.size(), size2(50, 60));
+    assert_eq!(atlas[a.id], a.rectangle);
+
+    // Growing back into the space that was just released also happens in place.
+    let a = atlas.realloc(a.id, size2(100, 100)).unwrap();
+    assert_eq!(a.rectangle.min, point2(0, 0));
+    assert_eq!(atlas[b.id], b.rectangle);
+
+    // Requests that can't be satisfied leave the allocation untouched.
+    assert!(atlas.realloc(a.id, size2(1001, 10)).is_none());
+    assert_eq!(atlas[a.id], a.rectangle);
+
+    atlas.deallocate(a.id);
+    atlas.deallocate(b.id);
+
+    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    atlas.deallocate(full);
+}
+
+#[test]
+fn plan_rearrange() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let a = atlas.allocate(size2(100, 300)).unwrap().id;
+    let b = atlas.allocate(size2(400, 200)).unwrap().id;
+    let c = atlas.allocate(size2(300, 600)).unwrap();
+    let d = atlas.allocate(size2(50, 50)).unwrap();
+    atlas.deallocate(a);
+    atlas.deallocate(b);
+
+    let plan = atlas.plan_rearrange();
+    assert_eq!(plan.changes.len(), 2);
+
+    // The atlas is left untouched.
+    assert_eq!(atlas[c.id], c.rectangle);
+    assert_eq!(atlas[d.id], d.rectangle);
+
+    assert_eq!(atlas.rearrange(), plan);
+}
+
+#[test]
+fn defragment_step_alignment() {
+    let options = AllocatorOptions { alignment: size2(8, 8), ..DEFAULT_OPTIONS };
+    let mut atlas = AtlasAllocator::with_options(size2(64, 64), &options);
+
+    // An unaligned allocation leaves the free space below it at an unaligned position.
+    atlas.allocate_with_alignment(size2(64, 5), size2(1, 1)).unwrap();
+    let mut ids = Vec::new();
+    for _ in 0..30 {
+        ids.push(atlas.allocate(size2(8, 8)).unwrap().id);
+    }
+    for id in ids.drain(..10) {
+        atlas.deallocate(id);
+    }
+
+    let mut total_moves = 0;
+    loop {
+        let changes = atlas.defragment_step(4);
+        if changes.is_empty() {
+            break;
+        }
+        for change in &changes {
+            let min = change.new.rectangle.min;
+            assert_eq!((min.x % 8, min.y % 8), (0, 0));
+        }
+        total_moves += changes.len();
+    }
+
+    assert!(total_moves > 0);
+    atlas.validate().unwrap();
+}
+
+#[test]
+fn defragment_step() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut ids = Vec::new();
+    for _ in 0..100 {
+        ids.push(atlas.allocate(size2(100, 100)).unwrap().id);
+    }
+
+    // Punch holes into the first half of the allocations.
+    let mut remaining = Vec::new();
+    for (i, id) in ids.drain(..).enumerate() {
+        if i < 50 && i % 2 == 0 {
+            atlas.deallocate(id);
+        } else {
+            remaining.push(id);
+        }
+    }
+
+    let mut total_moves = 0;
+    loop {
+        let changes = atlas.defragment_step(3);
+        assert!(changes.len() <= 3);
+        if changes.is_empty() {
+            break;
+        }
+
+        for change in &changes {
+            let old_min = change.old.rectangle.min;
+            let new_min = change.new.rectangle.min;
+            assert!((new_min.y, new_min.x) < (old_min.y, old_min.x));
+            assert_eq!(atlas[change.new.id], change.new.rectangle);
+
+            let idx = remaining.iter().position(|id| *id == change.old.id).unwrap();
+            remaining[idx] = change.new.id;
+        }
+
+        total_moves += changes.len();
+    }
+
+    assert!(total_moves > 0);
+
+    // The allocations were packed toward the top of the atlas.
+    atlas.for_each_allocated_rectangle(|_, rect| {
+        assert!(rect.max.y <= 800);
+    });
+
+    for id in remaining {
+        atlas.deallocate(id);
+    }
+
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn rearrange_preserving_ids() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut ids = Vec::new();
+    for i in 0..60 {
+        ids.push(atlas.allocate(size2(10 + i * 3, 20 + (i % 7) * 15)).unwrap().id);
+    }
+
+    let mut remaining = Vec::new();
+    for (i, id) in ids.drain(..).enumerate() {
+        if i % 3 == 0 {
+            atlas.deallocate(id);
+        } else {
+            remaining.push(id);
+        }
+    }
+
+    let changes = atlas.rearrange_preserving_ids();
+    assert!(changes.failures.is_empty());
+    assert_eq!(changes.changes.len(), remaining.len());
+
+    for change in &changes.changes {
+        assert_eq!(change.old.id, change.new.id);
+        assert_eq!(change.old.rectangle.size(), change.new.rectangle.size());
+        assert_eq!(atlas[change.new.id], change.new.rectangle);
+    }
+
+    // The atlas is still usable with the preserved ids.
+    for _ in 0..10 {
+        let alloc = atlas.allocate(size2(50, 50)).unwrap();
+        assert!(!remaining.contains(&alloc.id));
+        remaining.push(alloc.id);
+    }
+
+    for id in remaining {
+        atlas.deallocate(id);
+    }
+
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn pinned() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut ids = Vec::new();
+    for _ in 0..40 {
+        ids.push(atlas.allocate(size2(100, 100)).unwrap().id);
+    }
+
+    let pinned = ids[15];
+    let pinned_rect = atlas[pinned];
+    atlas.set_pinned(pinned, true);
+    assert!(atlas.is_pinned(pinned));
+    assert!(!atlas.is_pinned(ids[16]));
+
+    for &id in &ids[0..15] {
+        atlas.deallocate(id);
+    }
+
+    let changes = atlas.rearrange();
+    assert!(changes.failures.is_empty());
+    assert_eq!(changes.changes.len(), 24);
+    for change in &changes.changes {
+        assert!(change.old.id != pinned);
+    }
+    assert_eq!(atlas[pinned], pinned_rect);
+    assert!(atlas.is_pinned(pinned));
+
+    let changes = atlas.rearrange_preserving_ids();
+    assert!(changes.failures.is_empty());
+    assert_eq!(atlas[pinned], pinned_rect);
+
+    assert!(atlas.defragment_step(100).iter().all(|change| change.old.id != pinned));
+    assert_eq!(atlas[pinned], pinned_rect);
+
+    let changes = atlas.resize_and_rearrange(size2(2000, 1000));
+    assert!(changes.failures.is_empty());
+    assert_eq!(atlas[pinned], pinned_rect);
+
+    atlas.set_pinned(pinned, false);
+    assert!(!atlas.is_pinned(pinned));
+    atlas.deallocate(pinned);
+    let id = atlas.allocate(size2(100, 100)).unwrap().id;
+    assert!(!atlas.is_pinned(id));
+}
+
+#[test]
+fn shrink_with_pinned() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let pinned = atlas.allocate(size2(100, 100)).unwrap();
+    let other = atlas.allocate(size2(100, 100)).unwrap();
+    atlas.set_pinned(pinned.id, true);
+
+    assert_eq!(atlas.try_resize_and_rearrange(size2(500, 500)), Err(AllocError::WouldShrink));
+    assert_eq!(atlas.try_resize_and_rearrange(size2(1000, 500)), Err(AllocError::WouldShrink));
+
+    // The refusal is reported, but the allocations are left untouched.
+    let changes = atlas.resize_and_rearrange(size2(500, 500));
+    assert!(changes.changes.is_empty());
+    assert_eq!(changes.failures, vec![other]);
+    assert_eq!(atlas.size(), size2(1000, 1000));
+    assert_eq!(atlas[pinned.id], pinned.rectangle);
+    assert_eq!(atlas[other.id], other.rectangle);
+    assert!(atlas.is_valid(other.id));
+    assert_eq!(atlas.allocation_count(), 2);
+    atlas.validate().unwrap();
+
+    // Growing is still possible.
+    let changes = atlas.try_resize_and_rearrange(size2(2000, 1000)).unwrap();
+    assert_eq!(changes.changes.len(), 1);
+    assert_eq!(atlas[pinned.id], pinned.rectangle);
+}
+
+#[test]
+fn allocate_at() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let white = atlas.allocate_at(point2(0, 0), size2(1, 1)).unwrap();
+    assert_eq!(white.rectangle, Rectangle { min: point2(0, 0), max: point2(1, 1) });
+
+    let a = atlas.allocate_at(point2(500, 200), size2(100, 50)).unwrap();
+    assert_eq!(a.rectangle, Rectangle { min: point2(500, 200), max: point2(600, 250) });
+    assert_eq!(atlas[a.id], a.rectangle);
+
+    // Overlapping or out of bounds requests fail.
+    assert!(atlas.allocate_at(point2(550, 220), size2(10, 10)).is_none());
+    assert!(atlas.allocate_at(point2(990, 990), size2(20, 20)).is_none());
+    assert!(atlas.allocate_at(point2(-1, 500), size2(10, 10)).is_none());
+    assert!(atlas.allocate_at(point2(10, 10), size2(0, 10)).is_none());
+
+    let mut ids = vec![white.id, a.id];
+    while let Some(alloc) = atlas.allocate(size2(100, 100)) {
+        ids.push(alloc.id);
+        atlas.for_each_allocated_rectangle(|id, rect| {
+            if id != alloc.id {
+                assert!(!rect.intersects(&alloc.rectangle));
+            }
+        });
+    }
+
+    for id in ids {
+        atlas.deallocate(id);
+    }
+
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn reserve_region() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let band = Rectangle { min: point2(0, 900), max: point2(1000, 1000) };
+    assert!(atlas.reserve_region(band));
+    assert!(atlas.is_empty());
+
+    // Overlapping regions can't be reserved.
+    assert!(!atlas.reserve_region(Rectangle { min: point2(500, 950), max: point2(600, 960) }));
+
+    let check_allocations = |atlas: &AtlasAllocator| {
+        atlas.for_each_allocated_rectangle(|_, rect| {
+            assert!(!rect.intersects(&band));
+        });
+        atlas.for_each_free_rectangle(|rect| {
+            assert!(!rect.intersects(&band));
+        });
+    };
+
+    let mut ids = Vec::new();
+    while let Some(alloc) = atlas.allocate(size2(64, 64)) {
+        ids.push(alloc.id);
+    }
+    assert!(!atlas.is_empty());
+    check_allocations(&atlas);
+
+    for id in ids.drain(..).step_by(2) {
+        atlas.deallocate(id);
+    }
+
+    atlas.rearrange();
+    check_allocations(&atlas);
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+    assert!(atlas.allocate(size2(1000, 1000)).is_none());
+    assert!(atlas.allocate(size2(1000, 900)).is_some());
+
+    atlas.reset(size2(1000, 1000), &DEFAULT_OPTIONS);
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+}
+
+#[test]
+fn allocate_with_alignment() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut ids = Vec::new();
+    for i in 0..200 {
+        let size = size2(3 + i % 11, 5 + i % 7);
+        let alloc = if i % 3 == 0 {
+            let alloc = atlas.allocate_with_alignment(size, size2(4, 4)).unwrap();
+            assert_eq!(alloc.rectangle.min.x % 4, 0);
+            assert_eq!(alloc.rectangle.min.y % 4, 0);
+            assert_eq!(alloc.rectangle.width() % 4, 0);
+            assert_eq!(alloc.rectangle.height() % 4, 0);
+            alloc
+        } else {
+            let alloc = atlas.allocate(size).unwrap();
+            assert_eq!(alloc.rectangle.size(), size);
+            alloc
+        };
+
+        atlas.for_each_allocated_rectangle(|id, rect| {
+            if id != alloc.id {
+                assert!(!rect.intersects(&alloc.rectangle));
+            }
+        });
+
+        ids.push(alloc.id);
+    }
+
+    for id in ids {
+        atlas.deallocate(id);
+    }
+
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn padding() {
+    let mut atlas = AtlasAllocator::with_options(
+        size2(1000, 1000),
+        &AllocatorOptions {
+            padding: 2,
+            ..DEFAULT_OPTIONS
+        },
+    );
+
+    let a = atlas.allocate(size2(10, 20)).unwrap();
+    assert_eq!(a.rectangle.size(), size2(14, 24));
+    assert_eq!(a.content, Rectangle { min: a.rectangle.min + vec2(2, 2), max: a.rectangle.max - vec2(2, 2) });
+    assert_eq!(atlas[a.id], a.rectangle);
+
+    let b = atlas.allocate_with_padding(size2(10, 20), 0).unwrap();
+    assert_eq!(b.rectangle, b.content);
+    assert_eq!(b.content.size(), size2(10, 20));
+
+    let c = atlas.allocate_with_padding(size2(10, 20), 5).unwrap();
+    assert_eq!(c.rectangle.size(), size2(20, 30));
+    assert_eq!(c.content.size(), size2(10, 20));
+
+    // The padding is preserved when reallocating and rearranging.
+    let c = atlas.realloc(c.id, size2(30, 30)).unwrap();
+    assert_eq!(c.rectangle.size(), size2(40, 40));
+    assert_eq!(c.content.size(), size2(30, 30));
+
+    atlas.deallocate(a.id);
+    let changes = atlas.rearrange();
+    assert_eq!(changes.changes.len(), 2);
+    for change in &changes.changes {
+        assert_eq!(change.old.content.size(), change.new.content.size());
+        assert_eq!(change.old.rectangle.size(), change.new.rectangle.size());
+    }
+}
+
+#[test]
+fn rotation() {
+    let options = AllocatorOptions {
+        allow_rotation: true,
+        padding: 1,
+        ..DEFAULT_OPTIONS
+    };
+
+    let mut atlas = AtlasAllocator::new(size2(20, 200));
+    assert!(atlas.allocate(size2(100, 10)).is_none());
+
+    let mut atlas = AtlasAllocator::with_options(size2(20, 200), &options);
+    let a = atlas.allocate(size2(100, 10)).unwrap();
+    assert!(a.rotated);
+    assert_eq!(a.rectangle.size(), size2(12, 102));
+    assert_eq!(a.content.size(), size2(10, 100));
+
+    let b = atlas.allocate(size2(6, 60)).unwrap();
+    assert!(!b.rotated);
+    assert_eq!(b.content.size(), size2(6, 60));
+
+    // Rotated allocations stay rotated when they are reallocated or moved around.
+    let a = atlas.realloc(a.id, size2(50, 10)).unwrap();
+    assert!(a.rotated);
+    assert_eq!(a.content.size(), size2(10, 50));
+
+    let changes = atlas.rearrange();
+    assert!(changes.failures.is_empty());
+    for change in &changes.changes {
+        assert_eq!(change.old.rotated, change.new.rotated);
+        assert_eq!(change.old.content.size(), change.new.content.size());
+    }
+}
+
+#[test]
+fn fit_policies() {
+    // Three free rectangles of the medium bucket separated by reserved columns, in this order
+    // in the free list:
+    // - 100x200 at x = 0,
+    // - 60x200 at x = 110, the tightest one,
+    // - 200x200 at x = 180, the largest one.
+    let layout = |fit_policy| {
+        let options = AllocatorOptions { fit_policy, ..DEFAULT_OPTIONS };
+        let mut atlas = AtlasAllocator::with_options(size2(1000, 200), &options);
+        for (x, w) in [(100, 10), (170, 10), (380, 620)] {
+            assert!(atlas.reserve_region(Rectangle { min: point2(x, 0), max: point2(x + w, 200) }));
+        }
+        atlas
+    };
+
+    for &(fit_policy, expected_x) in &[
+        (FitPolicy::Adaptive, 110),
+        (FitPolicy::WorstFit, 180),
+        (FitPolicy::BestAreaFit, 110),
+        (FitPolicy::BestShortSideFit, 110),
+        (FitPolicy::FirstFit, 0),
+    ] {
+        let mut atlas = layout(fit_policy);
+        let alloc = atlas.allocate(size2(50, 50)).unwrap();
+        assert_eq!(alloc.rectangle.min, point2(expected_x, 0), "{:?}", fit_policy);
+    }
+
+    for &fit_policy in &[
+        FitPolicy::Adaptive,
+        FitPolicy::WorstFit,
+        FitPolicy::BestAreaFit,
+        FitPolicy::BestShortSideFit,
+        FitPolicy::FirstFit,
+    ] {
+        let mut atlas = AtlasAllocator::with_options(
+            size2(1000, 1000),
+            &AllocatorOptions {
+                fit_policy,
+                ..DEFAULT_OPTIONS
+            },
+        );
+
+        let a = 1103515245;
+        let c = 12345;
+        let m = usize::pow(2, 31);
+        let mut seed: usize = 37;
+
+        let mut rand = || {
+            seed = (a * seed + c) % m;
+            seed
+        };
+
+        let mut allocated = Vec::new();
+        for _ in 0..20000 {
+            if rand() % 5 > 2 && !allocated.is_empty() {
+                let nth = rand() % allocated.len();
+                atlas.deallocate(allocated.swap_remove(nth));
+            } else {
+                let size = size2((rand() % 100) as i32 + 5, (rand() % 100) as i32 + 5);
+                if let Some(alloc) = atlas.allocate(size) {
+                    allocated.push(alloc.id);
+                }
+            }
+        }
+
+        atlas.validate().unwrap();
+
+        while let Some(id) = allocated.pop() {
+            atlas.deallocate(id);
+        }
+
+        assert!(atlas.is_empty());
+    }
+}
+
+#[test]
+fn allocate_with_score() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    // Prefer the free rectangles that are closest to the top of the atlas.
+    let top_first = |rect: &Rectangle, _: Size| rect.min.y;
+
+    let mut ids = Vec::new();
+    for _ in 0..50 {
+        let alloc = atlas.allocate_with_score(size2(100, 100), &top_first).unwrap();
+        assert!(alloc.rectangle.max.y <= 500);
+        ids.push(alloc.id);
+    }
+
+    for id in ids.drain(..).step_by(2) {
+        atlas.deallocate(id);
+    }
+
+    let left_first = |rect: &Rectangle, _: Size| rect.min.x;
+    let alloc = atlas.allocate_with_score(size2(50, 50), &left_first).unwrap();
+    atlas.for_each_free_rectangle(|rect| {
+        assert!(rect.min.x >= alloc.rectangle.min.x || rect.width() < 50 || rect.height() < 50);
+    });
+}
+
+#[test]
+fn split_policies() {
+    use SplitPolicy::*;
+
+    let rect = |x0, y0, x1, y1| Rectangle { min: point2(x0, y0), max: point2(x1, y1) };
+
+    // Allocating 100x300 in a 1000x1000 free rectangle leaves a 900x300 rectangle to the right
+    // and a 100x700 one below: the shorter leftover axis is the vertical one, which also
+    // produces the smaller leftover rectangle.
+    let tall = (rect(0, 0, 1000, 1000), size2(100, 300));
+    let right_split = (rect(100, 0, 1000, 1000), rect(0, 300, 100, 1000), Orientation::Horizontal);
+    let bottom_split = (rect(0, 300, 1000, 1000), rect(100, 0, 1000, 300), Orientation::Vertical);
+
+    // Allocating 100x10 in a 400x210 free rectangle leaves a 300x10 rectangle to the right
+    // and a 100x200 one below: the shorter leftover axis is the vertical one, but the smaller
+    // leftover rectangle is the one to the right.
+    let wide = (rect(0, 0, 400, 210), size2(100, 10));
+    let wide_right_split = (rect(100, 0, 400, 210), rect(0, 10, 100, 210), Orientation::Horizontal);
+    let wide_bottom_split = (rect(0, 10, 400, 210), rect(100, 0, 400, 10), Orientation::Vertical);
+
+    for &(split_policy, tall_split, wide_split) in &[
+        (ShorterLeftoverAxis, right_split, wide_right_split),
+        (LongerLeftoverAxis, bottom_split, wide_bottom_split),
+        (MinimizeArea, right_split, wide_bottom_split),
+        (MaximizeArea, bottom_split, wide_right_split),
+    ] {
+        for &((chosen, size), expected) in &[(tall, tall_split), (wide, wide_split)] {
+            let split = guillotine_rect(&chosen, size, Orientation::Vertical, split_policy);
+            assert_eq!(split, expected, "{:?}", split_policy);
+
+            // The allocator ends up with the same free rectangles.
+            let options = AllocatorOptions { split_policy, ..DEFAULT_OPTIONS };
+            let mut atlas = AtlasAllocator::with_options(chosen.size(), &options);
+            atlas.allocate(size).unwrap();
+            let mut free_rects = Vec::new();
+            atlas.for_each_free_rectangle(|rect| free_rects.push(*rect));
+            free_rects.sort_by_key(|rect| (rect.min.x, rect.min.y));
+            let mut expected_rects = vec![expected.0, expected.1];
+            expected_rects.sort_by_key(|rect| (rect.min.x, rect.min.y));
+            assert_eq!(free_rects, expected_rects, "{:?}", split_policy);
+        }
+    }
+
+    for &split_policy in &[ShorterLeftoverAxis, LongerLeftoverAxis, MinimizeArea, MaximizeArea] {
+        let options = AllocatorOptions {
+            split_policy,
+            ..DEFAULT_OPTIONS
+        };
+        let mut atlas = AtlasAllocator::with_options(size2(1000, 1000), &options);
+
+        let mut ids = Vec::new();
+        for i in 0..200 {
+            let size = size2(10 + (i * 37) % 90, 10 + (i * 53) % 110);
+            if let Some(alloc) = atlas.allocate(size) {
+                atlas.for_each_allocated_rectangle(|id, rect| {
+                    if id != alloc.id {
+                        assert!(!rect.intersects(&alloc.rectangle));
+                    }
+                });
+                ids.push(alloc.id);
+            }
+        }
+
+        for id in ids {
+            atlas.deallocate(id);
+        }
+
+        assert!(atlas.is_empty());
+    }
+}
+
+#[test]
+fn allocated_and_free_space() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert_eq!(atlas.allocated_space(), 0);
+    assert_eq!(atlas.free_space(), 1_000_000);
+    assert_eq!(atlas.occupancy(), 0.0);
+
+    let check = |atlas: &AtlasAllocator| {
+        let mut allocated = 0;
+        atlas.for_each_allocated_rectangle(|_, rect| allocated += area(rect.size()));
+        let mut free = 0;
+        atlas.for_each_free_rectangle(|rect| free += area(rect.size()));
+        assert_eq!(atlas.allocated_space(), allocated);
+        assert_eq!(atlas.free_space(), free);
+    };
+
+    assert!(atlas.reserve_region(Rectangle { min: point2(0, 900), max: point2(1000, 1000) }));
+    check(&atlas);
+
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        let alloc = if i % 10 == 0 {
+            atlas.allocate_at(point2(i * 10, 800), size2(10, 10)).unwrap()
+        } else {
+            atlas.allocate(size2(10 + i % 13, 20 + i % 7)).unwrap()
+        };
+        ids.push(alloc.id);
+    }
+    check(&atlas);
+
+    for id in &mut ids[0..50] {
+        *id = atlas.realloc(*id, size2(30, 5)).unwrap().id;
+    }
+    check(&atlas);
+
+    atlas.defragment_step(10);
+    check(&atlas);
+
+    atlas.rearrange();
+    check(&atlas);
+    assert_eq!(atlas.occupancy(), atlas.allocated_space() as f32 / 1_000_000.0);
+
+    atlas.clear();
+    check(&atlas);
+    assert_eq!(atlas.allocated_space(), 0);
+    assert_eq!(atlas.free_space(), 900_000);
+}
+
+#[test]
+fn stats() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let stats = atlas.stats();
+    assert_eq!(stats.free_rect_count, 1);
+    assert_eq!(stats.largest_free_rect, size2(1000, 1000));
+    assert_eq!(stats.median_free_rect_area, 1_000_000);
+    assert_eq!(stats.fragmentation, 0.0);
+
+    let mut ids = Vec::new();
+    while let Some(alloc) = atlas.allocate(size2(100, 100)) {
+        ids.push(alloc.id);
+    }
+
+    let stats = atlas.stats();
+    assert_eq!(stats.free_rect_count, 0);
+    assert_eq!(stats.largest_free_rect, size2(0, 0));
+    assert_eq!(stats.mean_free_rect_area, 0.0);
+
+    for id in ids.drain(..).step_by(2) {
+        atlas.deallocate(id);
+    }
+
+    let stats = atlas.stats();
+    assert!(stats.free_rect_count > 40);
+    assert_eq!(stats.median_free_rect_area, 10_000);
+    assert_eq!(stats.mean_free_rect_area, 500_000.0 / stats.free_rect_count as f32);
+    assert!(stats.fragmentation > 0.9);
+
+    atlas.rearrange();
+    assert!(atlas.stats().fragmentation < stats.fragmentation);
+}
+
+#[test]
+fn largest_free_rect_size() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert_eq!(atlas.largest_free_rect_size(), size2(1000, 1000));
+
+    let a = 1103515245;
+    let c = 12345;
+    let m = usize::pow(2, 31);
+    let mut seed: usize = 37;
+
+    let mut rand = || {
+        seed = (a * seed + c) % m;
+        seed
+    };
+
+    let mut allocated = Vec::new();
+    for _ in 0..10000 {
+        if rand() % 5 > 2 && !allocated.is_empty() {
+            let nth = rand() % allocated.len();
+            atlas.deallocate(allocated.swap_remove(nth));
+        } else {
+            let size = size2((rand() % 300) as i32 + 5, (rand() % 300) as i32 + 5);
+            let bound = atlas.largest_free_rect_size();
+            if let Some(alloc) = atlas.allocate(size) {
+                assert!(size.width <= bound.width && size.height <= bound.height);
+                allocated.push(alloc.id);
+            }
+        }
+
+        // The cached size is exact after allocations, deallocations and merges.
+        let mut max = Size::zero();
+        atlas.for_each_free_rectangle(|rect| max = max.max(rect.size()));
+        assert_eq!(atlas.largest_free_rect_size(), max);
+    }
+
+    atlas.coalesce_aggressively();
+    let mut max = Size::zero();
+    atlas.for_each_free_rectangle(|rect| max = max.max(rect.size()));
+    assert_eq!(atlas.largest_free_rect_size(), max);
+
+    // Filling the atlas without failing allocations leaves no free rectangle.
+    atlas.clear();
+    for _ in 0..4 {
+        atlas.allocate(size2(500, 500)).unwrap();
+    }
+    assert_eq!(atlas.free_space(), 0);
+    assert_eq!(atlas.largest_free_rect_size(), size2(0, 0));
+    assert_eq!(atlas.stats().largest_free_rect, size2(0, 0));
+
+    atlas.clear();
+    let a = atlas.allocate(size2(1000, 400)).unwrap();
+    atlas.allocate(size2(1000, 600)).unwrap();
+    assert_eq!(atlas.largest_free_rect_size(), size2(0, 0));
+    atlas.deallocate(a.id);
+    assert_eq!(atlas.largest_free_rect_size(), size2(1000, 400));
+}
+
+#[test]
+fn suggest_grow() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    assert_eq!(atlas.suggest_grow(size2(200, 50)), size2(200, 100));
+
+    let a = 1103515245;
+    let c = 12345;
+    let m = usize::pow(2, 31);
+    let mut seed: usize = 37;
+
+    let mut rand = || {
+        seed = (a * seed + c) % m;
+        seed
+    };
+
+    let mut allocated = Vec::new();
+    for _ in 0..2000 {
+        if rand() % 5 > 3 && !allocated.is_empty() {
+            let nth = rand() % allocated.len();
+            atlas.deallocate(allocated.swap_remove(nth));
+        } else {
+            let size = size2((rand() % 100) as i32 + 5, (rand() % 100) as i32 + 5);
+            if let Some(alloc) = atlas.allocate(size) {
+                allocated.push(alloc.id);
+                continue;
+            }
+
+            let new_size = atlas.suggest_grow(size);
+            assert!(new_size.width >= atlas.size().width);
+            assert!(new_size.height >= atlas.size().height);
+            assert!(new_size.area() < (atlas.size() * 2).area());
+
+            atlas.grow(new_size);
+            allocated.push(atlas.allocate(size).unwrap().id);
+        }
+    }
+}
+
+#[test]
+fn suggest_grow_power_of_two() {
+    let options = AllocatorOptions { power_of_two_sizes: true, allow_rotation: true, ..DEFAULT_OPTIONS };
+    let mut atlas = AtlasAllocator::with_options(size2(100, 100), &options);
+    // The slot of a 70x20 allocation is 128x32.
+    assert_eq!(atlas.suggest_grow(size2(70, 20)), size2(128, 100));
+
+    let mut seed: u32 = 37;
+    let mut rand = || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345) % (1 << 31);
+        seed as i32
+    };
+
+    for _ in 0..200 {
+        let size = size2(rand() % 60 + 5, rand() % 60 + 5);
+        if atlas.allocate(size).is_some() {
+            continue;
+        }
+
+        let new_size = atlas.suggest_grow(size);
+        atlas.grow(new_size);
+        assert!(atlas.allocate(size).is_some());
+    }
+
+    let policy = GrowthPolicy { strategy: GrowthStrategy::Exact, max_size: size2(1 << 16, 1 << 16) };
+    while atlas.allocate(size2(33, 9)).is_some() {}
+    assert!(atlas.ensure_can_allocate(size2(33, 9), &policy));
+    assert!(atlas.allocate(size2(33, 9)).is_some());
+}
+
+#[test]
+fn allocation_lookup() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let a = atlas.allocate_at(point2(0, 0), size2(100, 100)).unwrap();
+    let b = atlas.allocate_at(point2(100, 0), size2(50, 50)).unwrap();
+    let c = atlas.allocate_at(point2(500, 500), size2(10, 10)).unwrap();
+
+    assert_eq!(atlas.allocation_at(point2(0, 0)), Some(a.id));
+    assert_eq!(atlas.allocation_at(point2(99, 99)), Some(a.id));
+    assert_eq!(atlas.allocation_at(point2(100, 0)), Some(b.id));
+    assert_eq!(atlas.allocation_at(point2(100, 50)), None);
+    assert_eq!(atlas.allocation_at(point2(505, 505)), Some(c.id));
+
+    let ids: Vec<AllocId> = atlas.allocations_in(Rectangle { min: point2(50, 10), max: point2(120, 20) }).collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&a.id));
+    assert!(ids.contains(&b.id));
+
+    assert_eq!(atlas.allocations_in(Rectangle { min: point2(200, 200), max: point2(400, 400) }).count(), 0);
+    assert_eq!(atlas.allocations_in(Rectangle { min: point2(0, 0), max: point2(1000, 1000) }).count(), 3);
+
+    atlas.deallocate(a.id);
+    assert_eq!(atlas.allocation_at(point2(0, 0)), None);
+}
+
+#[test]
+fn id_validity() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let a = atlas.allocate(size2(100, 100)).unwrap();
+    assert!(atlas.is_valid(a.id));
+    assert_eq!(atlas.get(a.id), Some(&a.rectangle));
+
+    atlas.deallocate(a.id);
+    assert!(!atlas.is_valid(a.id));
+    assert_eq!(atlas.get(a.id), None);
+
+    // The slot is reused with a different generation.
+    let b = atlas.allocate(size2(100, 100)).unwrap();
+    assert!(atlas.is_valid(b.id));
+    assert!(!atlas.is_valid(a.id));
+
+    // Ids that are out of bounds.
+    assert!(!atlas.is_valid(AllocId::deserialize(1000)));
+    assert_eq!(atlas.get(AllocId::deserialize(1000)), None);
+}
+
+#[test]
+fn try_api() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+
+    assert_eq!(atlas.try_allocate(size2(0, 10)), Err(AllocError::InvalidSize));
+    assert_eq!(atlas.try_allocate(size2(-1, 10)), Err(AllocError::InvalidSize));
+    assert_eq!(atlas.try_allocate(size2(200, 10)), Err(AllocError::TooLarge));
+
+    let a = atlas.try_allocate(size2(100, 60)).unwrap();
+    assert_eq!(atlas.try_allocate(size2(100, 60)), Err(AllocError::OutOfSpace));
+
+    assert_eq!(atlas.try_deallocate(a.id), Ok(()));
+    assert_eq!(atlas.try_deallocate(a.id), Err(AllocError::StaleId));
+
+    assert_eq!(atlas.try_grow(size2(50, 200)), Err(AllocError::WouldShrink));
+    assert!(atlas.try_grow(size2(200, 200)).is_ok());
+    assert_eq!(atlas.size(), size2(200, 200));
+
+    let invalid_options = AllocatorOptions {
+        alignment: size2(0, 1),
+        ..DEFAULT_OPTIONS
+    };
+    assert_eq!(atlas.try_reset(size2(100, 100), &invalid_options), Err(AllocError::InvalidOptions));
+    assert_eq!(atlas.try_reset(size2(0, 100), &DEFAULT_OPTIONS), Err(AllocError::InvalidSize));
+    assert_eq!(atlas.try_reset(size2(100, 100), &DEFAULT_OPTIONS), Ok(()));
+    assert_eq!(atlas.size(), size2(100, 100));
+}
+
+#[test]
+fn try_allocate_failure_reasons() {
+    // Enough free space in total, but split between two rectangles.
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    let tiles: Vec<Allocation> = (0..4).map(|_| atlas.allocate(size2(50, 50)).unwrap()).collect();
+    for tile in &tiles {
+        if tile.rectangle.min.x == tile.rectangle.min.y {
+            atlas.deallocate(tile.id);
+        }
+    }
+    assert_eq!(atlas.try_allocate(size2(100, 50)), Err(AllocError::Fragmented));
+    // More than the free space.
+    assert_eq!(atlas.try_allocate(size2(100, 60)), Err(AllocError::OutOfSpace));
+
+    // The padding and the alignment count toward the size of the request.
+    let options = AllocatorOptions { padding: 1, ..DEFAULT_OPTIONS };
+    let mut padded = AtlasAllocator::with_options(size2(100, 100), &options);
+    assert_eq!(padded.try_allocate(size2(100, 10)), Err(AllocError::TooLarge));
+    assert!(padded.try_allocate(size2(98, 10)).is_ok());
+
+    // So does rotation.
+    let options = AllocatorOptions { allow_rotation: true, ..DEFAULT_OPTIONS };
+    let mut rotated = AtlasAllocator::with_options(size2(100, 50), &options);
+    assert_eq!(rotated.try_allocate(size2(100, 100)), Err(AllocError::TooLarge));
+    assert!(rotated.try_allocate(size2(50, 100)).unwrap().rotated);
+}
+
+#[test]
+fn deallocate_many() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut ids = Vec::new();
+    for i in 0..200 {
+        let alloc = atlas.allocate(size2(10 + i % 30, 10 + i % 20)).unwrap();
+        ids.push(alloc.id);
+    }
+
+    let (even, odd): (Vec<_>, Vec<_>) = ids.iter().enumerate().partition(|(i, _)| i % 2 == 0);
+    let even: Vec<AllocId> = even.into_iter().map(|(_, id)| *id).collect();
+    let odd: Vec<AllocId> = odd.into_iter().map(|(_, id)| *id).collect();
+
+    // Repeated and stale ids are rejected before anything is deallocated.
+    for invalid in [vec![even[0], even[1], even[0]], vec![even[0], even[1], AllocId::new(AllocIndex(5000), 0)]] {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| atlas.deallocate_many(&invalid)));
+        assert!(result.is_err());
+        assert_eq!(atlas.allocation_count(), 200);
+        assert!(atlas.is_valid(even[0]) && atlas.is_valid(even[1]));
+        atlas.validate().unwrap();
+    }
+
+    atlas.deallocate_many(&even);
+    for id in &even {
+        assert!(!atlas.is_valid(*id));
+    }
+    for id in &odd {
+        assert!(atlas.is_valid(*id));
+    }
+
+    atlas.deallocate_many(&odd);
+    assert!(atlas.is_empty());
+    assert_eq!(atlas.allocated_space(), 0);
+
+    // Everything was coalesced back into a single free rectangle.
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+}
+
+#[test]
+fn deferred_coalescing() {
+    let mut atlas = AtlasAllocator::with_options(
+        size2(1000, 1000),
+        &AllocatorOptions {
+            deferred_coalescing: true,
+            ..DEFAULT_OPTIONS
+        },
+    );
+
+    let a = atlas.allocate(size2(500, 1000)).unwrap();
+    let b = atlas.allocate(size2(500, 1000)).unwrap();
+
+    atlas.deallocate(a.id);
+    atlas.deallocate(b.id);
+    assert!(atlas.is_empty());
+
+    // The freed rectangles are usable right away.
+    let c = atlas.allocate(size2(400, 400)).unwrap();
+    atlas.deallocate(c.id);
+
+    atlas.coalesce();
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+    atlas.clear();
 
-    let candidate_leftover_rect_to_right = Rectangle {
-        min: chosen_rect.min + vec2(requested_size.width, 0),
-        max: point2(chosen_rect.max.x, chosen_rect.min.y + requested_size.height),
+    // Allocation failures coalesce the free rectangles before giving up.
+    let ids: Vec<AllocId> = (0..4).map(|_| atlas.allocate(size2(250, 1000)).unwrap().id).collect();
+    atlas.deallocate_many(&ids);
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+}
+
+#[test]
+fn tagged_allocations() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let persistent = atlas.allocate(size2(100, 100)).unwrap();
+    let mut frame_0 = Vec::new();
+    let mut frame_1 = Vec::new();
+    for _ in 0..10 {
+        frame_0.push(atlas.allocate_tagged(size2(50, 20), 0).unwrap().id);
+        frame_1.push(atlas.allocate_tagged(size2(20, 50), 1).unwrap().id);
+    }
+
+    assert_eq!(
+        atlas.area_by_tag(),
+        vec![
+            TagUsage { tag: 0, allocated_space: 10_000, allocation_count: 10 },
+            TagUsage { tag: 1, allocated_space: 10_000, allocation_count: 10 },
+        ],
+    );
+    assert_eq!(atlas.tag(frame_1[0]), Some(1));
+    assert_eq!(atlas.tag(persistent.id), None);
+
+    atlas.release_tag(0);
+    assert!(frame_0.iter().all(|id| !atlas.is_valid(*id)));
+    assert!(frame_1.iter().all(|id| atlas.is_valid(*id)));
+    assert!(atlas.is_valid(persistent.id));
+
+    // Tags are not carried over by the nodes that get reused.
+    let untagged = atlas.allocate(size2(50, 20)).unwrap();
+    atlas.release_tag(0);
+    assert!(atlas.is_valid(untagged.id));
+
+    atlas.release_tag(1);
+    assert!(frame_1.iter().all(|id| !atlas.is_valid(*id)));
+    assert!(atlas.area_by_tag().is_empty());
+    assert!(atlas.is_valid(persistent.id));
+
+    atlas.deallocate(persistent.id);
+    atlas.deallocate(untagged.id);
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn deferred_deallocation() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+
+    let a = atlas.allocate(size2(100, 50)).unwrap();
+    let b = atlas.allocate(size2(100, 50)).unwrap();
+
+    atlas.deallocate_deferred(a.id, 1);
+    atlas.deallocate_deferred(b.id, 2);
+
+    // The rectangles are not reusable until the frames are flushed.
+    assert!(atlas.allocate(size2(100, 50)).is_none());
+
+    atlas.flush_deferred(0);
+    assert!(atlas.is_valid(a.id));
+    assert!(atlas.is_valid(b.id));
+
+    atlas.flush_deferred(1);
+    assert!(!atlas.is_valid(a.id));
+    assert!(atlas.is_valid(b.id));
+
+    atlas.flush_deferred(5);
+    assert!(!atlas.is_valid(b.id));
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn grow_reports_free_rects() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+
+    // A single free node is grown in place.
+    let full = Rectangle { min: point2(0, 0), max: point2(100, 200) };
+    assert_eq!(atlas.grow(size2(100, 200)), vec![full]);
+
+    atlas.allocate(size2(100, 200)).unwrap();
+
+    let added = atlas.grow(size2(300, 300));
+    assert_eq!(added.len(), 2);
+
+    let mut free_area = 0;
+    atlas.for_each_free_rectangle(|r| free_area += r.area());
+    assert_eq!(free_area, 300 * 300 - 100 * 200);
+    assert_eq!(added.iter().map(|r| r.area()).sum::<i32>(), free_area);
+}
+
+#[test]
+fn grow_and_allocate() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    let options = GrowOptions::default();
+
+    let (alloc, changes) = atlas.grow_and_allocate(size2(256, 256), &options);
+    assert!(alloc.is_some());
+    assert!(changes.changes.is_empty());
+    assert_eq!(atlas.size(), size2(256, 256));
+
+    let (alloc, changes) = atlas.grow_and_allocate(size2(100, 100), &options);
+    assert!(alloc.is_some());
+    assert!(changes.changes.is_empty());
+    assert!((atlas.size().width as u32).is_power_of_two());
+    assert!((atlas.size().height as u32).is_power_of_two());
+
+    // Growing past the maximum size is not allowed.
+    let capped = GrowOptions {
+        policy: GrowthPolicy { max_size: atlas.size(), ..options.policy },
+        allow_rearrange: false,
     };
-    let candidate_leftover_rect_to_bottom = Rectangle {
-        min: chosen_rect.min + vec2(0, requested_size.height),
-        max: point2(chosen_rect.min.x + requested_size.width, chosen_rect.max.y),
+    let size = atlas.size();
+    let (alloc, _) = atlas.grow_and_allocate(size, &capped);
+    assert!(alloc.is_none());
+    assert_eq!(atlas.size(), size);
+
+    // Fragment the atlas so that only rearranging can make room.
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    let ids: Vec<AllocId> = (0..10).map(|_| atlas.allocate(size2(10, 100)).unwrap().id).collect();
+    for id in ids.iter().step_by(2) {
+        atlas.deallocate(*id);
+    }
+    let capped = GrowOptions {
+        policy: GrowthPolicy { max_size: size2(100, 100), ..options.policy },
+        allow_rearrange: true,
     };
+    let (alloc, changes) = atlas.grow_and_allocate(size2(50, 100), &capped);
+    assert!(alloc.is_some());
+    assert_eq!(changes.changes.len(), 5);
+    assert!(changes.failures.is_empty());
+}
 
-    let split_rect;
-    let leftover_rect;
-    let orientation;
-    if requested_size == chosen_rect.size() {
-        // Perfect fit.
-        orientation = default_orientation;
-        split_rect = Rectangle::zero();
-        leftover_rect = Rectangle::zero();
-    } else if safe_area(&candidate_leftover_rect_to_right) > safe_area(&candidate_leftover_rect_to_bottom) {
-        leftover_rect = candidate_leftover_rect_to_bottom;
-        split_rect = Rectangle {
-            min: candidate_leftover_rect_to_right.min,
-            max: point2(candidate_leftover_rect_to_right.max.x, chosen_rect.max.y),
-        };
-        orientation = Orientation::Horizontal;
-    } else {
-        leftover_rect = candidate_leftover_rect_to_right;
-        split_rect = Rectangle {
-            min: candidate_leftover_rect_to_bottom.min,
-            max: point2(chosen_rect.max.x, candidate_leftover_rect_to_bottom.max.y),
-        };
-        orientation = Orientation::Vertical;
+#[test]
+fn growth_policy() {
+    let policy = |strategy| GrowthPolicy { strategy, max_size: size2(16384, 16384) };
+
+    let current = size2(1000, 1000);
+    let required = size2(1100, 1000);
+    assert_eq!(policy(GrowthStrategy::Exact).new_size(current, required), Some(size2(1100, 1000)));
+    assert_eq!(policy(GrowthStrategy::PowerOfTwo).new_size(current, required), Some(size2(2048, 1000)));
+    assert_eq!(policy(GrowthStrategy::Doubling).new_size(current, required), Some(size2(2000, 1000)));
+    assert_eq!(policy(GrowthStrategy::FixedIncrement(256)).new_size(current, required), Some(size2(1256, 1000)));
+
+    // Never exceed the maximum size.
+    assert_eq!(policy(GrowthStrategy::Doubling).new_size(size2(10000, 10), size2(10001, 10)), Some(size2(16384, 10)));
+    assert_eq!(policy(GrowthStrategy::Exact).new_size(current, size2(16385, 10)), None);
+
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    atlas.allocate(size2(256, 256)).unwrap();
+
+    assert!(atlas.ensure_can_allocate(size2(100, 100), &policy(GrowthStrategy::WidthThenHeight)));
+    assert_eq!(atlas.size(), size2(512, 256));
+    assert!(atlas.allocate(size2(100, 100)).is_some());
+
+    // Nothing to do if there is enough space already.
+    assert!(atlas.ensure_can_allocate(size2(100, 100), &policy(GrowthStrategy::WidthThenHeight)));
+    assert_eq!(atlas.size(), size2(512, 256));
+
+    let capped = GrowthPolicy { strategy: GrowthStrategy::Doubling, max_size: size2(512, 256) };
+    assert!(!atlas.ensure_can_allocate(size2(300, 300), &capped));
+    assert_eq!(atlas.size(), size2(512, 256));
+}
+
+#[test]
+fn dense_index() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+
+    let mut data = Vec::new();
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        let id = atlas.allocate(size2(10 + i % 7, 10 + i % 11)).unwrap().id;
+        let idx = atlas.dense_index(id);
+        assert!(idx < atlas.dense_index_bound());
+        data.resize(atlas.dense_index_bound(), None);
+        assert!(data[idx].is_none());
+        data[idx] = Some(i);
+        ids.push(id);
     }
 
-    (split_rect, leftover_rect, orientation)
+    for (i, id) in ids.iter().enumerate() {
+        assert_eq!(data[atlas.dense_index(*id)], Some(i as i32));
+    }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct Allocation {
-    pub id: AllocId,
-    pub rectangle: Rectangle,
+#[test]
+fn allocation_count_and_capacity() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert_eq!(atlas.allocation_count(), 0);
+    assert_eq!(atlas.free_rect_count(), 1);
+
+    atlas.reserve(1000);
+    assert!(atlas.capacity() >= 1000);
+
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        ids.push(atlas.allocate(size2(10 + i % 5, 10 + i % 3)).unwrap().id);
+        assert_eq!(atlas.allocation_count(), ids.len());
+    }
+
+    let mut free_rects = 0;
+    atlas.for_each_free_rectangle(|_| free_rects += 1);
+    assert_eq!(atlas.free_rect_count(), free_rects);
+
+    atlas.deallocate_many(&ids[0..50]);
+    assert_eq!(atlas.allocation_count(), 50);
+
+    atlas.reserve_region(Rectangle { min: point2(900, 900), max: point2(1000, 1000) });
+    assert_eq!(atlas.allocation_count(), 50);
+
+    atlas.clear();
+    assert_eq!(atlas.allocation_count(), 0);
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct Change {
-    pub old: Allocation,
-    pub new: Allocation,
+#[test]
+fn validate() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert_eq!(atlas.validate(), Ok(()));
+
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        ids.push(atlas.allocate(size2(10 + i % 30, 10 + i % 20)).unwrap().id);
+    }
+    for id in ids.iter().step_by(3) {
+        atlas.deallocate(*id);
+    }
+    atlas.grow(size2(1200, 1100));
+    assert_eq!(atlas.validate(), Ok(()));
+
+    let mut corrupted = atlas.clone();
+    let idx = corrupted.get_index(ids[1]).index();
+    corrupted.nodes[idx].rect.max.x += 1;
+    assert!(corrupted.validate().is_err());
+
+    let mut corrupted = atlas.clone();
+    let idx = corrupted.get_index(ids[1]).index();
+    corrupted.nodes[idx].next_sibling = AllocIndex(1_000_000);
+    assert_eq!(corrupted.validate(), Err(ValidationError::InvalidLink { node: idx }));
+
+    let mut corrupted = atlas.clone();
+    corrupted.allocated_space += 1;
+    assert_eq!(corrupted.validate(), Err(ValidationError::InconsistentCounters));
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct ChangeList {
-    pub changes: Vec<Change>,
-    pub failures: Vec<Allocation>,
+// Load a 100x100 atlas with a single 50x50 allocation after overwriting a 32 bits field of
+// a node in its binary form. The field offsets are relative to the start of the node.
+#[cfg(test)]
+fn load_corrupted_binary(node: usize, offset: usize, value: u32) -> Result<AtlasAllocator, SerializationError> {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    atlas.allocate(size2(50, 50)).unwrap();
+    // The root container and the free space below it, then the allocation and the free space
+    // on its right in the container.
+    let kinds: Vec<NodeKind> = atlas.nodes.iter().map(|node| node.kind).collect();
+    assert_eq!(kinds, [NodeKind::Container, NodeKind::Free, NodeKind::Alloc, NodeKind::Free]);
+
+    // The nodes follow the header and are 54 bytes long.
+    let mut bytes = atlas.to_binary();
+    let start = 79 + node * 54 + offset;
+    bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+
+    AtlasAllocator::from_binary(&bytes)
 }
 
-impl ChangeList {
-    pub fn empty() -> Self {
-        ChangeList {
-            changes: Vec::new(),
-            failures: Vec::new(),
+#[cfg(test)]
+const NEXT_SIBLING_OFFSET: usize = 9;
+#[cfg(test)]
+const PREV_SIBLING_OFFSET: usize = 13;
+#[cfg(test)]
+const MIN_X_OFFSET: usize = 18;
+#[cfg(test)]
+const MAX_Y_OFFSET: usize = 30;
+
+#[test]
+fn validate_cycle() {
+    // Writing the current value doesn't corrupt anything.
+    assert!(load_corrupted_binary(3, NEXT_SIBLING_OFFSET, AllocIndex::NONE.0).is_ok());
+
+    // The last child of the container links back to the first one.
+    assert_eq!(
+        load_corrupted_binary(3, NEXT_SIBLING_OFFSET, 2).err(),
+        Some(SerializationError::Invalid(ValidationError::Cycle { node: 2 })),
+    );
+}
+
+#[test]
+fn validate_unreachable() {
+    // The children of the container form a closed loop without a first child.
+    assert_eq!(
+        load_corrupted_binary(2, PREV_SIBLING_OFFSET, 3).err(),
+        Some(SerializationError::Invalid(ValidationError::Unreachable { node: 2 })),
+    );
+}
+
+#[test]
+fn validate_not_adjacent() {
+    // A gap between the allocation and the free rectangle on its right.
+    assert_eq!(
+        load_corrupted_binary(3, MIN_X_OFFSET, 60).err(),
+        Some(SerializationError::Invalid(ValidationError::NotAdjacent { node: 3 })),
+    );
+}
+
+#[test]
+fn validate_invalid_tiling() {
+    // The free rectangle on the right of the allocation doesn't span the container.
+    assert_eq!(
+        load_corrupted_binary(3, MAX_Y_OFFSET, 40).err(),
+        Some(SerializationError::Invalid(ValidationError::InvalidTiling { node: 3 })),
+    );
+}
+
+#[test]
+fn allocate_in() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+
+    let top = Rectangle { min: point2(0, 0), max: point2(256, 128) };
+    let bottom = Rectangle { min: point2(0, 128), max: point2(256, 256) };
+
+    let mut count = 0;
+    while let Some(alloc) = atlas.allocate_in(bottom, size2(30, 20)) {
+        assert!(bottom.contains_box(&alloc.rectangle));
+        count += 1;
+    }
+    assert!(count > 0);
+
+    while let Some(alloc) = atlas.allocate_in(top, size2(20, 30)) {
+        assert!(top.contains_box(&alloc.rectangle));
+    }
+
+    assert!(atlas.allocate_in(top, size2(10, 200)).is_none());
+}
+
+#[test]
+fn merge() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+
+    // Fill the atlas with 2x2 tiles.
+    let tiles: Vec<Allocation> = (0..4).map(|_| atlas.allocate(size2(128, 128)).unwrap()).collect();
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+
+    let find = |x: i32, y: i32| tiles.iter().find(|t| t.rectangle.min == point2(x, y)).unwrap().id;
+    let (tl, tr, bl, br) = (find(0, 0), find(128, 0), find(0, 128), find(128, 128));
+
+    // Diagonal tiles can't be merged.
+    assert!(atlas.merge(tl, br).is_none());
+    assert!(atlas.is_valid(tl));
+
+    let mut merged = Vec::new();
+    for (a, b) in [(tl, tr), (bl, br), (tl, bl), (tr, br)].iter() {
+        if atlas.is_valid(*a) && atlas.is_valid(*b) {
+            if let Some(alloc) = atlas.merge(*a, *b) {
+                assert!(!atlas.is_valid(*a));
+                assert!(!atlas.is_valid(*b));
+                merged.push(alloc);
+            }
+        }
+    }
+    assert_eq!(merged.len(), 2);
+    assert_eq!(atlas.allocation_count(), 2);
+
+    // The two halves can be merged into a single allocation covering the whole atlas.
+    let full = atlas.merge(merged[0].id, merged[1].id).unwrap();
+    assert_eq!(full.rectangle, Rectangle { min: point2(0, 0), max: point2(256, 256) });
+    assert_eq!(atlas.allocation_count(), 1);
+
+    atlas.deallocate(full.id);
+    assert!(atlas.is_empty());
+    assert!(atlas.allocate(size2(256, 256)).is_some());
+}
+
+#[test]
+fn split() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+
+    let alloc = atlas.allocate(size2(128, 128)).unwrap();
+    let free_space = atlas.free_space();
+
+    assert!(atlas.split(alloc.id, Axis::X, 0).is_none());
+    assert!(atlas.split(alloc.id, Axis::Y, 128).is_none());
+
+    let (left, right) = atlas.split(alloc.id, Axis::X, 64).unwrap();
+    assert!(!atlas.is_valid(alloc.id));
+    assert_eq!(left.rectangle, Rectangle { min: point2(0, 0), max: point2(64, 128) });
+    assert_eq!(right.rectangle, Rectangle { min: point2(64, 0), max: point2(128, 128) });
+
+    let (top, bottom) = atlas.split(right.id, Axis::Y, 32).unwrap();
+    assert_eq!(top.rectangle, Rectangle { min: point2(64, 0), max: point2(128, 32) });
+    assert_eq!(bottom.rectangle, Rectangle { min: point2(64, 32), max: point2(128, 128) });
+
+    assert_eq!(atlas.allocation_count(), 3);
+    assert_eq!(atlas.free_space(), free_space);
+
+    // Splitting and merging are symmetrical.
+    let right = atlas.merge(top.id, bottom.id).unwrap();
+    let merged = atlas.merge(left.id, right.id).unwrap();
+    assert_eq!(merged.rectangle, alloc.rectangle);
+
+    atlas.deallocate(merged.id);
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn min_free_size() {
+    let mut atlas = AtlasAllocator::with_options(
+        size2(100, 100),
+        &AllocatorOptions {
+            min_free_size: 4,
+            ..DEFAULT_OPTIONS
+        },
+    );
+
+    // Leaves a 2px wide sliver on the right.
+    let a = atlas.allocate(size2(98, 100)).unwrap();
+    assert!(atlas.allocate(size2(2, 2)).is_none());
+    assert_eq!(atlas.validate(), Ok(()));
+
+    // The sliver is usable again once merged with neighboring free space.
+    atlas.deallocate(a.id);
+    assert!(atlas.allocate(size2(100, 100)).is_some());
+
+    // Growing a sliver makes it available.
+    let mut atlas = AtlasAllocator::with_options(
+        size2(100, 100),
+        &AllocatorOptions {
+            min_free_size: 4,
+            ..DEFAULT_OPTIONS
+        },
+    );
+    atlas.allocate(size2(100, 98)).unwrap();
+    atlas.grow(size2(100, 200));
+    assert!(atlas.allocate(size2(100, 102)).is_some());
+    assert_eq!(atlas.validate(), Ok(()));
+}
+
+#[test]
+fn allocated_bounds() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert_eq!(atlas.allocated_bounds(), None);
+
+    let a = atlas.allocate(size2(100, 200)).unwrap();
+    let b = atlas.allocate(size2(300, 50)).unwrap();
+    assert_eq!(atlas.allocated_bounds(), Some(a.rectangle.union(&b.rectangle)));
+
+    atlas.deallocate(a.id);
+    assert_eq!(atlas.allocated_bounds(), Some(b.rectangle));
+    assert!(!atlas.is_empty());
+
+    atlas.deallocate(b.id);
+    assert_eq!(atlas.allocated_bounds(), None);
+    assert!(atlas.is_empty());
+}
+
+#[test]
+fn grow_copy_plan() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    assert!(atlas.grow_copy_plan(size2(1000, 1000), size2(2000, 2000)).is_empty());
+
+    let mut allocs = Vec::new();
+    for _ in 0..6 {
+        allocs.push(atlas.allocate(size2(100, 100)).unwrap());
+    }
+
+    let plan = atlas.grow_copy_plan(size2(1000, 1000), size2(2000, 2000));
+    assert!(!plan.is_empty());
+    assert!(plan.len() <= allocs.len());
+
+    // Every allocation is covered by the plan.
+    for alloc in &allocs {
+        assert!(plan.iter().any(|r| r.contains_box(&alloc.rectangle)));
+    }
+
+    // The plan only covers a fraction of the atlas.
+    let copied: i32 = plan.iter().map(|r| r.area()).sum();
+    assert!(copied < 1000 * 1000 / 2);
+}
+
+#[test]
+fn maximum_size_atlas() {
+    // The area of the atlas does not fit in 32 bits.
+    let mut atlas = AtlasAllocator::with_options(
+        size2(65536, 65536),
+        &AllocatorOptions {
+            fit_policy: FitPolicy::BestAreaFit,
+            ..DEFAULT_OPTIONS
+        },
+    );
+    assert_eq!(atlas.free_space(), 65536 * 65536);
+
+    let a = atlas.allocate(size2(2, 2)).unwrap();
+    let b = atlas.allocate(size2(65500, 2)).unwrap();
+    let c = atlas.allocate(size2(2, 65500)).unwrap();
+    let d = atlas.allocate(size2(40000, 40000)).unwrap();
+
+    let allocated = 4 + 65500 * 2 * 2 + 40000 * 40000;
+    assert_eq!(atlas.allocated_space(), allocated);
+    assert_eq!(atlas.free_space(), 65536 * 65536 - allocated);
+    assert!(atlas.occupancy() > 0.37 && atlas.occupancy() < 0.38);
+
+    let stats = atlas.stats();
+    assert!(stats.fragmentation >= 0.0 && stats.fragmentation <= 1.0);
+    assert!(stats.median_free_rect_area > 0);
+
+    // The best fit for a small rectangle is the smallest free rectangle, not the largest one.
+    let e = atlas.allocate(size2(30, 2)).unwrap();
+    assert!(e.rectangle.max.x <= 65536);
+    assert_eq!(atlas.try_allocate(size2(65536, 65536)), Err(AllocError::OutOfSpace));
+
+    let grown = atlas.suggest_grow(size2(65536, 65536));
+    assert!(area(grown) >= 2 * area(atlas.size()));
+
+    for id in [a.id, b.id, c.id, d.id, e.id] {
+        atlas.deallocate(id);
+    }
+    assert!(atlas.is_empty());
+    assert_eq!(atlas.validate(), Ok(()));
+    assert!(atlas.allocate(size2(65536, 65536)).is_some());
+}
+
+#[test]
+fn alloc_id_layout() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+
+    let a = atlas.allocate(size2(32, 32)).unwrap();
+    let index = a.id.index();
+    assert_eq!(AllocId::deserialize(a.id.serialize()), a.id);
+    assert_eq!(a.id.generation(), 0);
+
+    // Reuse the same slot until the generation wraps around.
+    let mut id = a.id;
+    let mut generation: u32 = 0;
+    for _ in 0..300 {
+        atlas.deallocate(id);
+        id = atlas.allocate(size2(32, 32)).unwrap().id;
+        assert_eq!(id.index(), index);
+        generation += 1;
+        assert!(id.matches_generation(generation as Generation));
+        assert!(atlas.is_valid(id));
+    }
+
+    #[cfg(not(feature = "wide_ids"))]
+    {
+        assert_eq!(std::mem::size_of::<AllocId>(), 4);
+        // The generation wrapped around.
+        assert!(generation > Generation::MAX as u32);
+    }
+
+    #[cfg(feature = "wide_ids")]
+    {
+        assert_eq!(std::mem::size_of::<AllocId>(), 8);
+        assert_eq!(AllocId::new(AllocIndex(0x00FF_FFFF + 1), 1).index(), AllocIndex(0x0100_0000));
+    }
+}
+
+#[test]
+fn stale_id_generations() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+
+    let stale = atlas.allocate(size2(32, 32)).unwrap().id;
+    let mut id = stale;
+    for _ in 0..256 {
+        atlas.deallocate(id);
+        id = atlas.allocate(size2(32, 32)).unwrap().id;
+    }
+
+    // With 8 bits generations the stale id aliases the live allocation after 256 reuses.
+    let aliased = cfg!(not(feature = "wide_ids"));
+    assert_eq!(stale == id, aliased);
+    assert_eq!(atlas.is_valid(stale), aliased);
+    assert_eq!(id.generation(), if aliased { 0 } else { 256 });
+}
+
+#[test]
+fn coalesce_aggressively() {
+    let tiles = [
+        (0, 1), // A, B
+        (2, 3), // C, D
+        (0, 2), // A, C
+        (1, 3), // B, D
+    ];
+
+    let mut missed = 0;
+    for &(a, b) in &tiles {
+        let mut atlas = AtlasAllocator::new(size2(100, 100));
+        let ids: Vec<AllocId> = (0..4).map(|_| atlas.allocate(size2(50, 50)).unwrap().id).collect();
+        let rects: Vec<Rectangle> = ids.iter().map(|&id| atlas[id]).collect();
+
+        // Deallocate two tiles that share an edge.
+        let (a, b) = if rects[a].min.y == rects[b].min.y || rects[a].min.x == rects[b].min.x {
+            (a, b)
+        } else {
+            continue;
+        };
+        atlas.deallocate(ids[a]);
+        atlas.deallocate(ids[b]);
+
+        if atlas.free_rect_count() > 1 {
+            missed += 1;
+        }
+
+        atlas.coalesce_aggressively();
+        atlas.validate().unwrap();
+
+        let mut free_rects = Vec::new();
+        atlas.for_each_free_rectangle(|rect| free_rects.push(*rect));
+        assert_eq!(free_rects, vec![rects[a].union(&rects[b])]);
+
+        // The remaining allocations are untouched.
+        for i in 0..4 {
+            if i != a && i != b {
+                assert!(atlas.is_valid(ids[i]));
+                assert_eq!(atlas[ids[i]], rects[i]);
+            }
+        }
+
+        assert!(atlas.allocate(free_rects[0].size()).is_some());
+    }
+
+    // Regular coalescing misses at least one of the pairs.
+    assert!(missed > 0);
+}
+
+#[test]
+fn visit() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    let a = atlas.allocate(size2(50, 50)).unwrap();
+    let b = atlas.allocate(size2(50, 50)).unwrap();
+
+    let mut nodes = Vec::new();
+    atlas.visit(|node| nodes.push(*node));
+
+    // Parents are visited before their children.
+    assert_eq!(nodes[0].parent, None);
+    assert_eq!(nodes[0].depth, 0);
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(parent) = node.parent {
+            let parent = nodes[..i].iter().find(|n| n.index == parent).unwrap();
+            assert_eq!(parent.kind, NodeKind::Container);
+            assert_eq!(parent.depth + 1, node.depth);
+            assert_ne!(parent.axis, node.axis);
+            assert!(parent.rectangle.contains_box(&node.rectangle));
+        }
+    }
+
+    let allocated: Vec<AllocId> = nodes.iter().filter_map(|node| node.id).collect();
+    assert_eq!(allocated.len(), 2);
+    assert!(allocated.contains(&a.id));
+    assert!(allocated.contains(&b.id));
+
+    let leaf_area: i64 = nodes
+        .iter()
+        .filter(|node| node.kind != NodeKind::Container)
+        .map(|node| area(node.rectangle.size()))
+        .sum();
+    assert_eq!(leaf_area, 10000);
+}
+
+#[test]
+fn next_fit() {
+    fn adjacent_allocations(next_fit: bool) -> usize {
+        let mut atlas = AtlasAllocator::with_options(
+            size2(512, 512),
+            &AllocatorOptions {
+                next_fit,
+                ..DEFAULT_OPTIONS
+            },
+        );
+
+        let mut adjacent = 0;
+        let mut prev: Option<Rectangle> = None;
+        for i in 0..200 {
+            let alloc = atlas.allocate(size2(10 + i % 7, 12 + i % 5)).unwrap();
+            if let Some(prev) = prev {
+                let r = alloc.rectangle;
+                if r.min.x == prev.max.x || r.min.y == prev.max.y || r.max.x == prev.min.x || r.max.y == prev.min.y {
+                    adjacent += 1;
+                }
+            }
+            prev = Some(alloc.rectangle);
         }
+
+        atlas.validate().unwrap();
+
+        adjacent
     }
+
+    let without = adjacent_allocations(false);
+    let with = adjacent_allocations(true);
+    assert!(with > without);
+
+    // Falls back to the free lists when the neighbors of the previous allocation are full.
+    let mut atlas = AtlasAllocator::with_options(
+        size2(100, 100),
+        &AllocatorOptions {
+            next_fit: true,
+            ..DEFAULT_OPTIONS
+        },
+    );
+    assert!(atlas.allocate(size2(100, 50)).is_some());
+    assert!(atlas.allocate(size2(100, 50)).is_some());
+    assert!(atlas.allocate(size2(1, 1)).is_none());
 }
 
-/// Dump a visual representation of the atlas in SVG format.
-pub fn dump_svg(atlas: &AtlasAllocator, output: &mut dyn std::io::Write) -> std::io::Result<()> {
-    use svg_fmt::*;
+#[test]
+fn frozen_atlas() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    let a = atlas.allocate(size2(32, 32)).unwrap();
+    let b = atlas.allocate(size2(64, 16)).unwrap();
+
+    let frozen = atlas.freeze();
+
+    // Modifications of the atlas don't affect the snapshot.
+    atlas.deallocate(a.id);
+    let c = atlas.allocate(size2(32, 32)).unwrap();
+
+    let shared = frozen.clone();
+    let thread = std::thread::spawn(move || {
+        assert_eq!(shared[a.id], a.rectangle);
+        assert_eq!(shared.get(b.id), Some(&b.rectangle));
+        assert!(!shared.is_valid(c.id));
+        shared.iter().count()
+    });
+    assert_eq!(thread.join().unwrap(), 2);
+
+    assert_eq!(frozen.size(), size2(256, 256));
+    assert_eq!(frozen.allocated_space(), 32 * 32 + 64 * 16);
+    assert_eq!(frozen.occupancy(), (32 * 32 + 64 * 16) as f32 / (256 * 256) as f32);
+
+    let mut ids = Vec::new();
+    frozen.for_each_allocated_rectangle(|id, _| ids.push(id));
+    assert!(ids.contains(&a.id));
+    assert!(ids.contains(&b.id));
+
+    let frozen = atlas.freeze();
+    assert!(!frozen.is_valid(a.id) || c.id == a.id);
+    assert_eq!(frozen[c.id], c.rectangle);
+}
 
-    writeln!(
-        output,
-        "{}",
-        BeginSvg {
-            w: atlas.size.width as f32,
-            h: atlas.size.height as f32
-        }
-    )?;
+#[test]
+fn clear_with_size() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    for _ in 0..50 {
+        atlas.allocate(size2(10, 10)).unwrap();
+    }
+    let capacity = atlas.capacity();
 
-    dump_into_svg(atlas, None, output)?;
+    atlas.clear_with_size(size2(512, 128));
+    assert!(atlas.is_empty());
+    assert_eq!(atlas.size(), size2(512, 128));
+    assert_eq!(atlas.capacity(), capacity);
+    assert!(atlas.allocate(size2(500, 100)).is_some());
+    atlas.validate().unwrap();
 
-    writeln!(output, "{}", EndSvg)
+    atlas.reset(size2(64, 64), &DEFAULT_OPTIONS);
+    assert_eq!(atlas.capacity(), capacity);
+
+    atlas.clear();
+    assert_eq!(atlas.capacity(), capacity);
 }
 
-/// Dump a visual representation of the atlas in SVG, omitting the beginning and end of the
-/// SVG document, so that it can be included in a larger document.
-///
-/// If a rectangle is provided, translate and scale the output to fit it.
-pub fn dump_into_svg(atlas: &AtlasAllocator, rect: Option<&Rectangle>, output: &mut dyn std::io::Write) -> std::io::Result<()> {
-    use svg_fmt::*;
+#[test]
+fn shrink_internal_storage() {
+    let mut atlas = AtlasAllocator::new(size2(1024, 1024));
 
-    let (sx, sy, tx, ty) = if let Some(rect) = rect {
-        (
-            rect.width() as f32 / atlas.size.width as f32,
-            rect.height() as f32 / atlas.size.height as f32,
-            rect.min.x as f32,
-            rect.min.y as f32,
-        )
-    } else {
-        (1.0, 1.0, 0.0, 0.0)
-    };
+    let mut ids = Vec::new();
+    for _ in 0..1000 {
+        ids.push(atlas.allocate(size2(16, 16)).unwrap());
+    }
+    let peak = atlas.capacity();
 
-    for node in &atlas.nodes {
-        let color = match node.kind {
-            NodeKind::Free => rgb(50, 50, 50),
-            NodeKind::Alloc => rgb(70, 70, 180),
-            _ => {
-                continue;
-            }
-        };
+    let kept = &ids[..10];
+    atlas.deallocate_many(&ids[10..].iter().map(|alloc| alloc.id).collect::<Vec<_>>());
 
-        let (x, y) = node.rect.min.to_f32().to_tuple();
-        let (w, h) = node.rect.size().to_f32().to_tuple();
+    atlas.shrink_internal_storage();
+    atlas.validate().unwrap();
+    assert!(atlas.capacity() < peak / 10);
 
-        writeln!(
-            output,
-            r#"    {}"#,
-            rectangle(tx + x * sx, ty + y * sy, w * sx, h * sy)
-                .fill(color)
-                .stroke(Stroke::Color(black(), 1.0))
-        )?;
+    for alloc in kept {
+        assert_eq!(atlas[alloc.id], alloc.rectangle);
     }
 
-    Ok(())
+    for _ in 0..1000 {
+        assert!(atlas.allocate(size2(16, 16)).is_some());
+    }
+    atlas.validate().unwrap();
 }
 
 #[test]
-fn atlas_basic() {
-    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+fn memory_usage() {
+    let mut atlas = AtlasAllocator::new(size2(1024, 1024));
+    let empty = atlas.memory_usage();
+    assert_eq!(empty.nodes.len, 1);
 
-    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
-    assert!(atlas.allocate(size2(1, 1)).is_none());
+    for _ in 0..100 {
+        atlas.allocate(size2(16, 16)).unwrap();
+    }
 
-    atlas.deallocate(full);
+    let report = atlas.memory_usage();
+    assert!(report.nodes.len > 100);
+    assert!(report.nodes.capacity >= report.nodes.len);
+    assert_eq!(report.nodes.bytes, report.nodes.capacity * std::mem::size_of::<Node>());
+    assert_eq!(report.generations.len, report.nodes.len);
+    assert_eq!(report.free_lists.len(), NUM_BUCKETS);
+    assert!(report.free_lists.iter().map(|list| list.len).sum::<usize>() >= atlas.free_rect_count());
+    assert!(report.total_bytes() > empty.total_bytes());
+    assert_eq!(report.size_classes, StorageUsage::default());
+
+    let options = AllocatorOptions { power_of_two_sizes: true, ..DEFAULT_OPTIONS };
+    let mut atlas = AtlasAllocator::with_options(size2(1024, 1024), &options);
+    for _ in 0..100 {
+        atlas.allocate(size2(16, 16)).unwrap();
+    }
+    let report = atlas.memory_usage();
+    assert!(report.size_classes.len > 0);
+    assert!(report.size_classes.bytes >= NUM_SIZE_CLASSES * std::mem::size_of::<Vec<AllocIndex>>());
+    assert!(report.total_bytes() > report.nodes.bytes + report.size_classes.bytes);
+}
 
-    let a = atlas.allocate(size2(100, 1000)).unwrap().id;
-    let b = atlas.allocate(size2(900, 200)).unwrap().id;
-    let c = atlas.allocate(size2(300, 200)).unwrap().id;
-    let d = atlas.allocate(size2(200, 300)).unwrap().id;
-    let e = atlas.allocate(size2(100, 300)).unwrap().id;
-    let f = atlas.allocate(size2(100, 300)).unwrap().id;
-    let g = atlas.allocate(size2(100, 300)).unwrap().id;
+#[test]
+fn placement_bias() {
+    fn allocated_bounds(bias: PlacementBias) -> Rectangle {
+        let mut atlas = AtlasAllocator::with_options(
+            size2(1024, 1024),
+            &AllocatorOptions {
+                placement_bias: bias,
+                fit_policy: FitPolicy::FirstFit,
+                ..DEFAULT_OPTIONS
+            },
+        );
 
-    atlas.deallocate(b);
-    atlas.deallocate(f);
-    atlas.deallocate(c);
-    atlas.deallocate(e);
-    let h = atlas.allocate(size2(500, 200)).unwrap().id;
-    atlas.deallocate(a);
-    let i = atlas.allocate(size2(500, 200)).unwrap().id;
-    atlas.deallocate(g);
-    atlas.deallocate(h);
-    atlas.deallocate(d);
-    atlas.deallocate(i);
+        let mut ids = Vec::new();
+        for i in 0..400 {
+            if let Some(alloc) = atlas.allocate(size2(8 + (i * 7) % 40, 8 + (i * 13) % 40)) {
+                ids.push(alloc.id);
+            }
+            if i % 3 == 0 {
+                let idx = (i as usize * 17) % ids.len();
+                atlas.deallocate(ids.swap_remove(idx));
+            }
+        }
 
-    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
-    assert!(atlas.allocate(size2(1, 1)).is_none());
-    atlas.deallocate(full);
+        atlas.validate().unwrap();
+
+        atlas.allocated_bounds().unwrap()
+    }
+
+    let none = allocated_bounds(PlacementBias::None);
+    let top_left = allocated_bounds(PlacementBias::TopLeft);
+    let top = allocated_bounds(PlacementBias::Top);
+    let left = allocated_bounds(PlacementBias::Left);
+
+    // Each bias keeps the allocations closest to its edges: the top bias yields the shortest
+    // bounds and the left bias the narrowest, with the top-left bias in between.
+    assert!(area(top_left.size()) < area(none.size()));
+    assert!(top.max.y < top_left.max.y);
+    assert!(top_left.max.y < none.max.y);
+    assert!(left.max.x < top_left.max.x);
+    assert!(top_left.max.x < none.max.x);
+    assert!(top.max.x > top_left.max.x);
+    assert!(left.max.y > top_left.max.y);
 }
 
 #[test]
-fn atlas_random_test() {
-    let mut atlas = AtlasAllocator::with_options(
-        size2(1000, 1000),
-        &AllocatorOptions {
-            alignment: size2(5, 2),
-            ..DEFAULT_OPTIONS
-        },
-    );
+fn allocate_best_of() {
+    let mut atlas = AtlasAllocator::new(size2(512, 512));
+    let mips = [size2(512, 512), size2(256, 256), size2(128, 128), size2(64, 64)];
+
+    let (idx, a) = atlas.allocate_best_of(&mips).unwrap();
+    assert_eq!(idx, 0);
+    assert_eq!(a.rectangle.size(), size2(512, 512));
+    atlas.deallocate(a.id);
+
+    atlas.allocate(size2(300, 300)).unwrap();
+    let (idx, b) = atlas.allocate_best_of(&mips).unwrap();
+    assert_eq!(idx, 2);
+    assert_eq!(b.rectangle.size(), size2(128, 128));
+
+    assert!(atlas.allocate_best_of(&[size2(600, 10), size2(10, 600)]).is_none());
+    assert!(atlas.allocate_best_of(&[]).is_none());
+
+    // The size that fills a free rectangle wins over the ones listed before it.
+    let mut atlas = AtlasAllocator::new(size2(512, 512));
+    atlas.allocate(size2(512, 256)).unwrap();
+    let (idx, c) = atlas.allocate_best_of(&[size2(100, 100), size2(512, 256)]).unwrap();
+    assert_eq!(idx, 1);
+    assert_eq!(c.rectangle.size(), size2(512, 256));
+    assert_eq!(atlas.free_space(), 0);
+}
 
-    let a = 1103515245;
-    let c = 12345;
-    let m = usize::pow(2, 31);
-    let mut seed: usize = 37;
+#[test]
+fn simple_atlas_coalesce() {
+    fn split_atlas() -> SimpleAtlasAllocator {
+        let mut atlas = SimpleAtlasAllocator::new(size2(256, 256));
+        atlas.allocate(size2(64, 64)).unwrap();
 
-    let mut rand = || {
-        seed = (a * seed + c) % m;
-        seed
-    };
+        // Leaves two free rectangles on the right of the allocations, one above the other.
+        atlas.allocate(size2(64, 192)).unwrap();
 
-    let mut n: usize = 0;
-    let mut misses: usize = 0;
+        atlas
+    }
 
-    let mut allocated = Vec::new();
-    for _ in 0..500000 {
-        if rand() % 5 > 2 && !allocated.is_empty() {
-            // deallocate something
-            let nth = rand() % allocated.len();
-            let id = allocated[nth];
-            allocated.remove(nth);
+    assert!(split_atlas().allocate(size2(192, 256)).is_none());
 
-            atlas.deallocate(id);
-        } else {
-            // allocate something
-            let size = size2((rand() % 300) as i32 + 5, (rand() % 300) as i32 + 5);
+    let mut atlas = split_atlas();
+    atlas.coalesce();
+    assert_eq!(atlas.free_rects.iter().flatten().count(), 1);
+    assert_eq!(atlas.allocate(size2(192, 256)), Some(Rectangle { min: point2(64, 0), max: point2(256, 256) }));
 
-            if let Some(alloc) = atlas.allocate(size) {
-                allocated.push(alloc.id);
-                n += 1;
-            } else {
-                misses += 1;
-            }
+    let mut empty = SimpleAtlasAllocator::new(size2(256, 256));
+    empty.coalesce();
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn simple_atlas_svg() {
+    let mut atlas = SimpleAtlasAllocator::new(size2(256, 256));
+    atlas.allocate(size2(100, 50)).unwrap();
+    atlas.allocate(size2(30, 200)).unwrap();
+
+    let mut free_area = 0;
+    atlas.for_each_free_rectangle(|rect| free_area += area(rect.size()));
+    assert_eq!(free_area, 256 * 256 - 100 * 50 - 30 * 200);
+
+    let mut svg = Vec::new();
+    dump_svg(&atlas, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    let mut free_rects = 0;
+    atlas.for_each_free_rectangle(|_| free_rects += 1);
+    // The background and the free rectangles.
+    assert_eq!(svg.matches("<rect").count(), free_rects + 1);
+}
+
+#[test]
+fn from_simple() {
+    let mut simple = SimpleAtlasAllocator::new(size2(512, 512));
+    let mut rects = Vec::new();
+    for i in 0..200 {
+        if let Some(rect) = simple.allocate(size2(5 + (i * 7) % 50, 5 + (i * 11) % 40)) {
+            rects.push(rect);
         }
     }
 
-    while let Some(id) = allocated.pop() {
-        atlas.deallocate(id);
+    let (mut atlas, ids) = AtlasAllocator::from_simple(&simple, &rects).unwrap();
+    atlas.validate().unwrap();
+    assert_eq!(atlas.allocation_count(), rects.len());
+    for (id, rect) in ids.iter().zip(rects.iter()) {
+        assert_eq!(atlas[*id], *rect);
     }
 
-    println!("added/removed {} rectangles, {} misses", n, misses);
-    println!(
-        "nodes.cap: {}, free_list.cap: {}/{}/{}",
-        atlas.nodes.capacity(),
-        atlas.free_lists[LARGE_BUCKET].capacity(),
-        atlas.free_lists[MEDIUM_BUCKET].capacity(),
-        atlas.free_lists[SMALL_BUCKET].capacity(),
-    );
+    // The free space is usable.
+    let mut free_space = 0;
+    simple.for_each_free_rectangle(|rect| free_space += area(rect.size()));
+    assert_eq!(atlas.free_space(), 512 * 512 - rects.iter().map(|r| area(r.size())).sum::<i64>());
+    assert!(free_space <= atlas.free_space());
+    let extra = atlas.allocate(size2(10, 10)).unwrap();
 
-    let full = atlas.allocate(size2(1000, 1000)).unwrap().id;
-    assert!(atlas.allocate(size2(1, 1)).is_none());
-    atlas.deallocate(full);
+    // Deallocating everything brings the atlas back to a single free rectangle.
+    for id in ids {
+        atlas.deallocate(id);
+    }
+    atlas.deallocate(extra.id);
+    assert!(atlas.is_empty());
+    assert!(atlas.allocate(size2(512, 512)).is_some());
+
+    // Overlapping rectangles are rejected.
+    let overlapping = [
+        Rectangle { min: point2(0, 0), max: point2(20, 20) },
+        Rectangle { min: point2(10, 10), max: point2(30, 30) },
+    ];
+    assert!(AtlasAllocator::from_simple(&simple, &overlapping).is_none());
 }
 
 #[test]
-fn test_grow() {
-    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+fn simple_atlas_grow() {
+    fn free_area(atlas: &SimpleAtlasAllocator) -> i64 {
+        let mut free_area = 0;
+        atlas.for_each_free_rectangle(|rect| free_area += area(rect.size()));
+        free_area
+    }
 
-    atlas.grow(size2(2000, 2000));
+    // Growing adds the same free space to both allocators.
+    for &(first, new_size) in &[
+        (None, size2(512, 512)),
+        (Some(size2(256, 100)), size2(256, 400)),
+        (Some(size2(100, 256)), size2(300, 512)),
+        (Some(size2(30, 40)), size2(512, 300)),
+    ] {
+        let mut simple = SimpleAtlasAllocator::new(size2(256, 256));
+        let mut atlas = AtlasAllocator::new(size2(256, 256));
+        if let Some(size) = first {
+            simple.allocate(size).unwrap();
+            atlas.allocate(size).unwrap();
+        }
 
-    let full = atlas.allocate(size2(2000, 2000)).unwrap().id;
-    assert!(atlas.allocate(size2(1, 1)).is_none());
-    atlas.deallocate(full);
+        simple.grow(new_size);
+        atlas.grow(new_size);
 
-    let a = atlas.allocate(size2(100, 100)).unwrap().id;
+        assert_eq!(simple.size(), new_size);
+        assert_eq!(free_area(&simple), atlas.free_space());
 
-    atlas.grow(size2(3000, 3000));
+        // The free space along the grown edges is not fragmented more than in the full
+        // allocator.
+        let mut largest = Size::zero();
+        atlas.for_each_free_rectangle(|rect| {
+            if area(rect.size()) > area(largest) {
+                largest = rect.size();
+            }
+        });
+        assert!(simple.allocate(largest).is_some());
+    }
 
-    let b = atlas.allocate(size2(1000, 2900)).unwrap().id;
+    // The added free rectangles start at aligned positions.
+    let mut atlas = SimpleAtlasAllocator::with_options(
+        size2(100, 100),
+        &AllocatorOptions { alignment: size2(8, 8), ..DEFAULT_OPTIONS },
+    );
+    atlas.allocate(size2(96, 96)).unwrap();
+    atlas.grow(size2(130, 100));
+    let rect = atlas.allocate(size2(24, 24)).unwrap();
+    assert_eq!(rect.min, point2(104, 0));
+}
 
-    atlas.grow(size2(4000, 4000));
+#[test]
+fn dynamic_atlas() {
+    fn exercise(atlas: &mut dyn DynamicAtlas) {
+        let size = atlas.size();
+        assert!(atlas.is_empty());
+
+        let mut allocations = Vec::new();
+        for _ in 0..10 {
+            allocations.push(atlas.allocate(size2(16, 16)).unwrap());
+        }
+        assert!(!atlas.is_empty());
+        for (i, a) in allocations.iter().enumerate() {
+            for b in &allocations[i + 1..] {
+                assert!(!a.rectangle.intersects(&b.rectangle));
+            }
+        }
 
-    atlas.deallocate(b);
-    atlas.deallocate(a);
+        atlas.grow(size2(size.width * 2, size.height * 2));
+        assert_eq!(atlas.size(), size2(size.width * 2, size.height * 2));
+        let mut free_rects = 0;
+        atlas.for_each_free_rectangle(&mut |rect| {
+            assert!(allocations.iter().all(|alloc| !alloc.rectangle.intersects(rect)));
+            free_rects += 1;
+        });
+        assert!(free_rects > 0);
 
-    let full = atlas.allocate(size2(4000, 4000)).unwrap().id;
-    assert!(atlas.allocate(size2(1, 1)).is_none());
-    atlas.deallocate(full);
+        if atlas.supports_deallocation() {
+            for alloc in &allocations {
+                atlas.deallocate(alloc.id);
+            }
+        } else {
+            atlas.clear();
+        }
+        assert!(atlas.is_empty());
+    }
+
+    let atlases: Vec<Box<dyn DynamicAtlas>> = vec![
+        Box::new(AtlasAllocator::new(size2(64, 64))),
+        Box::new(SimpleAtlasAllocator::new(size2(64, 64))),
+        Box::new(crate::ShelfAllocator::new(size2(64, 64))),
+        Box::new(crate::SkylineAllocator::new(size2(64, 64))),
+        Box::new(crate::MaxRectsAllocator::new(size2(64, 64))),
+        Box::new(crate::SlabAllocator::new(size2(64, 64), size2(16, 16))),
+        Box::new(crate::TieredAtlasAllocator::new(size2(64, 64))),
+    ];
+    for mut atlas in atlases {
+        exercise(&mut *atlas);
+    }
 }
 
 #[test]
-fn clear_empty() {
-    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
-
-    assert!(atlas.is_empty());
+fn power_of_two_sizes() {
+    let options = AllocatorOptions {
+        power_of_two_sizes: true,
+        ..DEFAULT_OPTIONS
+    };
+    let mut atlas = AtlasAllocator::with_options(size2(1024, 1024), &options);
 
-    assert!(atlas.allocate(size2(10, 10)).is_some());
-    assert!(!atlas.is_empty());
+    let a = atlas.allocate(size2(100, 60)).unwrap();
+    assert_eq!(a.rectangle.size(), size2(128, 64));
+    assert_eq!(a.content, a.rectangle);
 
-    atlas.clear();
-    assert!(atlas.is_empty());
+    let mut tiles = Vec::new();
+    for _ in 0..20 {
+        tiles.push(atlas.allocate(size2(64, 64)).unwrap());
+    }
 
-    let a = atlas.allocate(size2(10, 10)).unwrap().id;
-    let b = atlas.allocate(size2(20, 20)).unwrap().id;
-    assert!(!atlas.is_empty());
+    // Deallocated tiles are reused as is.
+    let tile = tiles.remove(5);
+    atlas.deallocate(tile.id);
+    let reused = atlas.allocate(size2(50, 64)).unwrap();
+    assert_eq!(reused.rectangle, tile.rectangle);
+    tiles.push(reused);
 
-    atlas.deallocate(b);
-    atlas.deallocate(a);
-    assert!(atlas.is_empty());
+    for tile in &tiles {
+        assert!(size_class(&tile.rectangle.size()).is_some());
+    }
 
-    atlas.clear();
+    for tile in tiles {
+        atlas.deallocate(tile.id);
+    }
+    atlas.deallocate(a.id);
     assert!(atlas.is_empty());
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+}
 
-    atlas.clear();
-    assert!(atlas.is_empty());
+#[test]
+fn power_of_two_sizes_too_large() {
+    let options = AllocatorOptions {
+        power_of_two_sizes: true,
+        allow_rotation: true,
+        ..DEFAULT_OPTIONS
+    };
+    let mut atlas = AtlasAllocator::with_options(size2(1024, 1024), &options);
+
+    // Rounding up to the next power of two would overflow.
+    let huge = 1 << 30 | 1;
+    assert_eq!(atlas.try_allocate(size2(huge, 10)), Err(AllocError::TooLarge));
+    assert_eq!(atlas.try_allocate(size2(10, huge)), Err(AllocError::TooLarge));
+    assert_eq!(atlas.try_allocate(size2(huge, huge)), Err(AllocError::TooLarge));
+    let (idx, _) = atlas.allocate_best_of(&[size2(huge, 10), size2(10, 10)]).unwrap();
+    assert_eq!(idx, 1);
 }
 
 #[test]
-fn simple_atlas() {
-    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+fn canonicalize() {
+    let mut a = AtlasAllocator::new(size2(1000, 1000));
+    let mut ids = Vec::new();
+    for i in 0..10 {
+        ids.push(a.allocate(size2(50 + i * 10, 30)).unwrap().id);
+    }
+    a.deallocate(ids[3]);
 
-    assert!(atlas.allocate(size2(1, 1001)).is_none());
-    assert!(atlas.allocate(size2(1001, 1)).is_none());
+    // Same layout, but the temporary allocations leave unused nodes behind.
+    let mut b = a.clone();
+    let tmp: Vec<AllocId> = (0..20).map(|_| b.allocate(size2(20, 20)).unwrap().id).collect();
+    for id in tmp.into_iter().rev() {
+        b.deallocate(id);
+    }
+    assert_ne!(a.nodes.len(), b.nodes.len());
+
+    let changes = a.canonicalize();
+    b.canonicalize();
+    assert_eq!(changes.changes.len(), 9);
+    for change in &changes.changes {
+        assert_eq!(change.old.rectangle, change.new.rectangle);
+        assert_eq!(a[change.new.id], change.new.rectangle);
+    }
 
-    let mut rectangles = Vec::new();
-    rectangles.push(atlas.allocate(size2(100, 1000)).unwrap());
-    rectangles.push(atlas.allocate(size2(900, 200)).unwrap());
-    rectangles.push(atlas.allocate(size2(300, 200)).unwrap());
-    rectangles.push(atlas.allocate(size2(200, 300)).unwrap());
-    rectangles.push(atlas.allocate(size2(100, 300)).unwrap());
-    rectangles.push(atlas.allocate(size2(100, 300)).unwrap());
-    rectangles.push(atlas.allocate(size2(100, 300)).unwrap());
-    assert!(atlas.allocate(size2(800, 800)).is_none());
+    assert_eq!(format!("{:?}", a.nodes), format!("{:?}", b.nodes));
+    assert_eq!(a.free_lists, b.free_lists);
+    assert!(a.nodes.iter().all(|node| node.kind != NodeKind::Unused));
+    a.validate().unwrap();
 
-    for i in 0..rectangles.len() {
-        for j in 0..rectangles.len() {
-            if i == j {
-                continue;
-            }
+    // Canonicalizing again doesn't change anything.
+    let nodes = format!("{:?}", a.nodes);
+    a.canonicalize();
+    assert_eq!(format!("{:?}", a.nodes), nodes);
 
-            assert!(!rectangles[i].intersects(&rectangles[j]));
-        }
+    assert!(a.allocate(size2(80, 30)).is_some());
+}
+
+#[test]
+fn from_allocations() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        ids.push(atlas.allocate(size2(10 + (i * 7) % 50, 10 + (i * 11) % 40)).unwrap().id);
+    }
+    for id in ids.drain(..).step_by(3).collect::<Vec<_>>() {
+        atlas.deallocate(id);
     }
+
+    let mut allocations = Vec::new();
+    atlas.for_each_allocated_rectangle(|id, rect| allocations.push((id, *rect)));
+
+    let mut restored = AtlasAllocator::from_allocations(atlas.size(), &allocations, &DEFAULT_OPTIONS).unwrap();
+    restored.validate().unwrap();
+    assert_eq!(restored.allocation_count(), allocations.len());
+    assert_eq!(restored.free_space(), atlas.free_space());
+    for (id, rect) in &allocations {
+        assert_eq!(restored[*id], *rect);
+    }
+
+    // The restored atlas is fully functional.
+    let extra = restored.allocate(size2(100, 100)).unwrap();
+    for (id, _) in &allocations {
+        restored.deallocate(*id);
+    }
+    restored.deallocate(extra.id);
+    assert!(restored.is_empty());
+    assert!(restored.allocate(size2(1000, 1000)).is_some());
+
+    // Allocations sharing an index are rejected.
+    let (id, rect) = allocations[0];
+    let other = Rectangle { min: point2(990, 990), max: point2(1000, 1000) };
+    assert!(AtlasAllocator::from_allocations(atlas.size(), &[(id, rect), (id, other)], &DEFAULT_OPTIONS).is_none());
 }
 
 #[test]
-fn allocate_zero() {
-    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+fn svg_options() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    let a = atlas.allocate(size2(100, 50)).unwrap();
+    let b = atlas.allocate(size2(30, 200)).unwrap();
+
+    let options = SvgOptions {
+        labels: true,
+        scale: 2.0,
+        stroke_width: 3.0,
+        highlighted: vec![b.id],
+        ..SvgOptions::default()
+    };
+    let mut svg = Vec::new();
+    dump_svg_with_options(&atlas, &options, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert!(svg.contains(r#"viewBox="0 0 512 512""#));
+    assert_eq!(svg.matches("<text").count(), 2);
+    assert!(svg.contains(&format!("#{} 100x50", a.id.serialize())));
+    assert!(svg.contains("rgb(200,150,40)"));
+    assert!(svg.contains("stroke:rgb(0,0,0);stroke-width:3"));
+}
 
-    assert!(atlas.allocate(size2(0, 0)).is_none());
+#[test]
+fn dump_tree_dot() {
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let a = atlas.allocate(size2(100, 100)).unwrap();
+    atlas.allocate(size2(200, 100)).unwrap();
+    atlas.allocate(size2(300, 400)).unwrap();
+    atlas.deallocate(a.id);
+
+    let mut dot = Vec::new();
+    atlas.dump_tree_dot(&mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+
+    let live_nodes = atlas.nodes.iter().filter(|node| node.kind != NodeKind::Unused).count();
+    assert!(dot.starts_with("digraph atlas {"));
+    assert_eq!(dot.matches("[label=").count(), live_nodes + 1);
+    assert_eq!(dot.matches("alloc #").count(), 2);
+    // Each node is linked to its parent.
+    let parent_edges = dot.lines().filter(|line| line.contains(" -> ") && !line.contains("rank=same")).count();
+    assert_eq!(parent_edges, live_nodes);
+    assert!(dot.contains("rank=same"));
 }
 
 #[test]
-fn allocate_negative() {
-    let mut atlas = SimpleAtlasAllocator::new(size2(1000, 1000));
+fn ascii_dump() {
+    let mut atlas = AtlasAllocator::new(size2(80, 40));
+    atlas.allocate(size2(40, 40)).unwrap();
+    atlas.allocate(size2(20, 20)).unwrap();
+    atlas.reserve_region(Rectangle { min: point2(70, 30), max: point2(80, 40) });
+
+    let mut ascii = String::new();
+    dump_ascii(&atlas, &mut ascii, 10).unwrap();
+    assert_eq!(
+        ascii,
+        "AAAABB..\n\
+         AAAABB..\n\
+         AAAA....\n\
+         AAAA...#\n"
+    );
+}
 
-    assert!(atlas.allocate(size2(-1, 1)).is_none());
-    assert!(atlas.allocate(size2(1, -1)).is_none());
-    assert!(atlas.allocate(size2(-1, -1)).is_none());
+#[test]
+fn layout_description() {
+    let mut atlas = AtlasAllocator::new(size2(100, 100));
+    assert!(atlas.layout().allocations.is_empty());
+
+    let a = atlas.allocate(size2(40, 20)).unwrap();
+    let b = atlas.allocate_with_padding(size2(10, 10), 2).unwrap();
+
+    let layout = atlas.layout();
+    assert_eq!((layout.width, layout.height), (100, 100));
+    assert_eq!(layout.allocations.len(), 2);
+    let desc = layout.allocations.iter().find(|alloc| alloc.id == b.id.serialize()).unwrap();
+    assert_eq!(
+        Rectangle { min: point2(desc.x, desc.y), max: point2(desc.x + desc.width, desc.y + desc.height) },
+        b.rectangle,
+    );
+    assert_eq!(desc.padding, 2);
+
+    let mut json = Vec::new();
+    layout.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert_eq!(
+        json,
+        format!(
+            "{{\n  \"width\": 100,\n  \"height\": 100,\n  \"allocations\": [\n    \
+             {{ \"id\": {}, \"x\": 0, \"y\": 0, \"width\": 40, \"height\": 20, \"padding\": 0, \"rotated\": false }},\n    \
+             {{ \"id\": {}, \"x\": {}, \"y\": {}, \"width\": 14, \"height\": 14, \"padding\": 2, \"rotated\": false }}\n  ]\n}}\n",
+            a.id.serialize(), b.id.serialize(), desc.x, desc.y,
+        )
+    );
+}
 
-    assert!(atlas.allocate(size2(-167114179, -718142)).is_none());
+#[test]
+fn binary_serialization() {
+    let options = AllocatorOptions { padding: 1, deferred_coalescing: true, ..DEFAULT_OPTIONS };
+    let mut atlas = AtlasAllocator::with_options(size2(200, 200), &options);
+    let a = atlas.allocate(size2(50, 30)).unwrap();
+    let b = atlas.allocate_tagged(size2(20, 60), 3).unwrap();
+    let c = atlas.allocate(size2(70, 70)).unwrap();
+    atlas.set_pinned(b.id, true);
+    atlas.deallocate(a.id);
+
+    let bytes = atlas.to_binary();
+    let mut loaded = AtlasAllocator::from_binary(&bytes).unwrap();
+    assert_eq!(loaded.options(), atlas.options());
+    assert_eq!(loaded.allocated_space(), atlas.allocated_space());
+    assert_eq!(loaded.get(b.id), Some(&b.rectangle));
+    assert_eq!(loaded.get(c.id), Some(&c.rectangle));
+    assert!(loaded.is_pinned(b.id));
+    assert!(!loaded.is_valid(a.id));
+    assert_eq!(loaded.to_binary(), bytes);
+
+    // The loaded atlas keeps working.
+    loaded.deallocate(c.id);
+    loaded.coalesce();
+    assert!(loaded.allocate(size2(100, 100)).is_some());
+
+    assert_eq!(AtlasAllocator::from_binary(&bytes[..bytes.len() - 1]).err(), Some(SerializationError::Malformed));
+    assert_eq!(AtlasAllocator::from_binary(b"nope").err(), Some(SerializationError::Malformed));
+
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(AtlasAllocator::from_binary(&newer).err(), Some(SerializationError::UnsupportedVersion(2)));
+
+    // Well-formed but inconsistent data: the allocated space follows the header, the
+    // options, the size and the root node.
+    let mut corrupted = bytes;
+    corrupted[51..59].copy_from_slice(&1000i64.to_le_bytes());
+    assert!(matches!(AtlasAllocator::from_binary(&corrupted), Err(SerializationError::Invalid(_))));
 }
 
+#[cfg(feature = "ron_tests")]
 #[test]
-fn issue_25() {
-    let mut allocator = AtlasAllocator::new(Size::new(65536, 65536));
-    allocator.allocate(Size::new(2,2));
-    allocator.allocate(Size::new(65500,2));
-    allocator.allocate(Size::new(2, 65500));
+fn versioned_atlas_legacy() {
+    // An atlas with a single allocation, as serialized before versions were introduced.
+    let legacy = "(
+        nodes: [
+            (
+                parent: (4294967295), next_sibling: (4294967295), prev_sibling: (4294967295),
+                kind: Container, orientation: Vertical, rect: (min: (0, 0), max: (64, 64)),
+            ),
+            (
+                parent: (0), next_sibling: (4294967295), prev_sibling: (2),
+                kind: Free, orientation: Horizontal, rect: (min: (32, 0), max: (64, 64)),
+            ),
+            (
+                parent: (0), next_sibling: (1), prev_sibling: (4294967295),
+                kind: Alloc, orientation: Horizontal, rect: (min: (0, 0), max: (32, 64)),
+            ),
+        ],
+        free_lists: ([], [(1)], []),
+        unused_nodes: (4294967295),
+        generations: [0, 0, 0],
+        alignment: (1, 1),
+        small_size_threshold: 32,
+        large_size_threshold: 256,
+        size: (64, 64),
+        root_node: (0),
+    )";
+
+    let versioned: VersionedAtlas = ron::de::from_str(legacy).unwrap();
+    assert_eq!(versioned.version(), 0);
+    let mut atlas = versioned.into_atlas().unwrap();
+    assert_eq!(atlas.allocation_count(), 1);
+    assert_eq!(atlas.free_space(), 32 * 64);
+    let mut allocations = Vec::new();
+    atlas.for_each_allocated_rectangle(|id, rect| allocations.push((id, *rect)));
+    let (id, rect) = allocations[0];
+    assert_eq!(rect, Rectangle { min: point2(0, 0), max: point2(32, 64) });
+
+    // The migrated atlas is written in the current format.
+    let data = ron::ser::to_string(&VersionedAtlas::new(atlas.clone())).unwrap();
+    let versioned: VersionedAtlas = ron::de::from_str(&data).unwrap();
+    assert_eq!(versioned.version(), SERIALIZATION_VERSION);
+    let mut loaded = versioned.into_atlas().unwrap();
+    loaded.validate().unwrap();
+    assert_eq!(loaded.get(id), Some(&rect));
+    assert_eq!(loaded.allocate(size2(32, 64)), atlas.allocate(size2(32, 64)));
+
+    // Migrated data is validated.
+    let corrupted = legacy.replace("max: (32, 64)", "max: (40, 64)");
+    assert!(ron::de::from_str::<VersionedAtlas>(&corrupted).is_err());
+}
+
+#[cfg(feature = "ron_tests")]
+#[test]
+fn versioned_atlas_field_order() {
+    let mut atlas = AtlasAllocator::new(size2(256, 256));
+    let a = atlas.allocate(size2(20, 30)).unwrap();
+    let atlas_data = ron::ser::to_string(&atlas).unwrap();
+
+    for data in &[
+        format!("(version: {}, atlas: {})", SERIALIZATION_VERSION, atlas_data),
+        format!("(atlas: {}, version: {})", atlas_data, SERIALIZATION_VERSION),
+    ] {
+        let versioned: VersionedAtlas = ron::de::from_str(data).unwrap();
+        assert_eq!(versioned.version(), SERIALIZATION_VERSION);
+        assert_eq!(versioned.into_atlas().unwrap().get(a.id), Some(&a.rectangle));
+    }
+
+    assert!(ron::de::from_str::<VersionedAtlas>(&format!("(atlas: {})", atlas_data)).is_err());
+}
+
+#[cfg(feature = "ron_tests")]
+#[test]
+fn versioned_atlas_unsupported_version() {
+    let atlas = AtlasAllocator::new(size2(256, 256));
+    let atlas_data = ron::ser::to_string(&atlas).unwrap();
+    let newer = SERIALIZATION_VERSION + 1;
+
+    for data in &[
+        // The atlas of a newer version is skipped without being parsed.
+        format!("(version: {}, atlas: (future_field: [1, 2, 3]))", newer),
+        format!("(atlas: {}, version: {})", atlas_data, newer),
+    ] {
+        let versioned: VersionedAtlas = ron::de::from_str(data).unwrap();
+        assert_eq!(versioned.version(), newer);
+        assert_eq!(versioned.into_atlas().err(), Some(SerializationError::UnsupportedVersion(newer)));
+    }
 }
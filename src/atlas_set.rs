@@ -0,0 +1,336 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+
+/// Index of an atlas in an `AtlasSet`.
+pub type AtlasIndex = usize;
+
+/// An allocation that was moved to another atlas by `AtlasSet::compact`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasMove {
+    pub old_atlas: AtlasIndex,
+    pub old: Allocation,
+    pub new_atlas: AtlasIndex,
+    pub new: Allocation,
+}
+
+/// The result of `AtlasSet::compact`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasCompaction {
+    /// The allocations to copy from their old atlas to the new one.
+    pub moves: Vec<AtlasMove>,
+    /// The atlases that were emptied and can be removed.
+    pub emptied_atlases: Vec<AtlasIndex>,
+}
+
+/// A set of atlas allocators, typically one per texture.
+///
+/// Allocations are attempted in each atlas in order, and a new atlas is created when all of
+/// them are full, up to a maximum number of atlases. The index of an atlas doesn't change
+/// while it exists, and the index of a removed atlas may be reused by the next atlas.
+pub struct AtlasSet {
+    atlases: Vec<Option<AtlasAllocator>>,
+    initial_size: Size,
+    max_atlases: usize,
+    options: AllocatorOptions,
+}
+
+impl AtlasSet {
+    /// Create an empty set of atlases with default options.
+    ///
+    /// Atlases are created with the provided size, unless an allocation requires a larger one.
+    pub fn new(initial_size: Size, max_atlases: usize) -> Self {
+        AtlasSet::with_options(initial_size, max_atlases, &DEFAULT_OPTIONS)
+    }
+
+    /// Create an empty set of atlases with the provided options.
+    pub fn with_options(initial_size: Size, max_atlases: usize, options: &AllocatorOptions) -> Self {
+        assert!(initial_size.width > 0);
+        assert!(initial_size.height > 0);
+        assert!(options.is_valid());
+
+        AtlasSet {
+            atlases: Vec::new(),
+            initial_size,
+            max_atlases,
+            options: *options,
+        }
+    }
+
+    /// Number of atlases in the set.
+    pub fn atlas_count(&self) -> usize {
+        self.atlases.iter().flatten().count()
+    }
+
+    /// Returns the atlas at the provided index, if it exists.
+    pub fn atlas(&self, index: AtlasIndex) -> Option<&AtlasAllocator> {
+        self.atlases.get(index)?.as_ref()
+    }
+
+    /// Dump the atlases of the set side by side in SVG format, see `dump_svg_row`.
+    pub fn dump_svg(&self, options: &SvgOptions, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let atlases: Vec<&dyn SvgDump> = self.atlases.iter().flatten().map(|atlas| atlas as &dyn SvgDump).collect();
+
+        dump_svg_row(&atlases, 10, options, output)
+    }
+
+    /// Invoke a callback for each atlas in the set.
+    pub fn for_each_atlas<F>(&self, mut callback: F)
+    where
+        F: FnMut(AtlasIndex, &AtlasAllocator),
+    {
+        for (index, atlas) in self.atlases.iter().enumerate() {
+            if let Some(atlas) = atlas {
+                callback(index, atlas);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atlases.iter().flatten().all(|atlas| atlas.is_empty())
+    }
+
+    /// Allocate a rectangle in the first atlas that can hold it.
+    ///
+    /// If none of the atlases can, a new one is created unless the maximum number of atlases
+    /// is reached. Returns the index of the atlas along with the allocation.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<(AtlasIndex, Allocation)> {
+        for (index, atlas) in self.atlases.iter_mut().enumerate() {
+            if let Some(alloc) = atlas.as_mut().and_then(|atlas| atlas.allocate(requested_size)) {
+                return Some((index, alloc));
+            }
+        }
+
+        self.add_atlas(requested_size)
+    }
+
+    /// Deallocate a rectangle in the atlas at the provided index.
+    pub fn deallocate(&mut self, index: AtlasIndex, id: AllocId) {
+        self.atlases[index].as_mut().expect("Invalid atlas index").deallocate(id);
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the atlas index or the id is not
+    /// valid.
+    pub fn get(&self, index: AtlasIndex, id: AllocId) -> Option<&Rectangle> {
+        self.atlas(index)?.get(id)
+    }
+
+    /// Remove an empty atlas from the set, for example to destroy its texture.
+    ///
+    /// Panics if the atlas contains allocations.
+    pub fn remove_atlas(&mut self, index: AtlasIndex) -> AtlasAllocator {
+        let atlas = self.atlases[index].take().expect("Invalid atlas index");
+        assert!(atlas.is_empty());
+
+        atlas
+    }
+
+    /// Drop all rectangles, keeping the atlases.
+    pub fn clear(&mut self) {
+        for atlas in self.atlases.iter_mut().flatten() {
+            atlas.clear();
+        }
+    }
+
+    /// Migrate the allocations of sparsely used atlases into the other atlases.
+    ///
+    /// Atlases are emptied starting from the least occupied one. The allocations of an atlas
+    /// are only moved if all of them fit in the other atlases, since moving some of them
+    /// would not reclaim any memory. The ids and rectangles of the moved allocations are not
+    /// valid anymore.
+    ///
+    /// The emptied atlases are kept in the set, so that their textures can be destroyed after
+    /// the copies are done, see `remove_atlas`.
+    pub fn compact(&mut self) -> AtlasCompaction {
+        let mut candidates: Vec<(AtlasIndex, f32)> = Vec::new();
+        self.for_each_atlas(|index, atlas| {
+            if !atlas.is_empty() {
+                candidates.push((index, atlas.occupancy()));
+            }
+        });
+        candidates.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        let mut moves = Vec::new();
+        let mut emptied_atlases = Vec::new();
+        for (i, &(src, _)) in candidates.iter().enumerate() {
+            // Only move into the atlases that are more occupied and were not emptied, most
+            // occupied first.
+            let targets: Vec<AtlasIndex> = candidates[i + 1..]
+                .iter()
+                .rev()
+                .map(|&(index, _)| index)
+                .collect();
+            if let Some(atlas_moves) = self.migrate(src, &targets) {
+                moves.extend(atlas_moves);
+                emptied_atlases.push(src);
+            }
+        }
+
+        AtlasCompaction { moves, emptied_atlases }
+    }
+
+    // Move all of the allocations of an atlas into the target atlases, or none of them if
+    // they don't all fit.
+    fn migrate(&mut self, src: AtlasIndex, targets: &[AtlasIndex]) -> Option<Vec<AtlasMove>> {
+        let src_atlas = self.atlases[src].as_ref().unwrap();
+        let mut allocs = Vec::new();
+        src_atlas.for_each_allocated_rectangle(|id, _| allocs.push(src_atlas.get_allocation(id)));
+        allocs.sort_by_key(|alloc| std::cmp::Reverse(area(alloc.rectangle.size())));
+
+        let mut moves = Vec::with_capacity(allocs.len());
+        for old in allocs {
+            let new = targets.iter().find_map(|&dst| {
+                let alloc = self.atlases[dst].as_mut().unwrap().allocate_moved(&old)?;
+                Some((dst, alloc))
+            });
+
+            match new {
+                Some((new_atlas, new)) => {
+                    moves.push(AtlasMove { old_atlas: src, old, new_atlas, new });
+                }
+                None => {
+                    for m in &moves {
+                        self.atlases[m.new_atlas].as_mut().unwrap().deallocate(m.new.id);
+                    }
+                    return None;
+                }
+            }
+        }
+
+        self.atlases[src].as_mut().unwrap().clear();
+
+        Some(moves)
+    }
+
+    // Create an atlas large enough for the requested size and allocate it, reusing the index
+    // of a removed atlas if possible. The atlas is only kept if the allocation succeeds.
+    fn add_atlas(&mut self, requested_size: Size) -> Option<(AtlasIndex, Allocation)> {
+        if self.atlas_count() >= self.max_atlases {
+            return None;
+        }
+
+        let mut atlas = AtlasAllocator::with_options(self.initial_size, &self.options);
+        // The slot includes the padding and the alignment of the allocation.
+        let (slot_size, _) = atlas.padded_sizes(requested_size, self.options.padding, self.options.alignment);
+        let size = self.initial_size.max(slot_size);
+        if size != self.initial_size {
+            atlas = AtlasAllocator::with_options(size, &self.options);
+        }
+        let alloc = atlas.allocate(requested_size)?;
+
+        let index = match self.atlases.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.atlases.push(None);
+                self.atlases.len() - 1
+            }
+        };
+        self.atlases[index] = Some(atlas);
+
+        Some((index, alloc))
+    }
+}
+
+#[test]
+fn atlas_set() {
+    use euclid::size2;
+
+    let mut set = AtlasSet::new(size2(256, 256), 3);
+    assert_eq!(set.atlas_count(), 0);
+
+    let (a_index, _) = set.allocate(size2(200, 200)).unwrap();
+    let (b_index, b) = set.allocate(size2(200, 200)).unwrap();
+    assert_eq!((a_index, b_index), (0, 1));
+    assert_eq!(set.get(b_index, b.id), Some(&b.rectangle));
+
+    // Requests larger than the initial size get a larger atlas.
+    let (c_index, _) = set.allocate(size2(300, 100)).unwrap();
+    assert_eq!(c_index, 2);
+    assert_eq!(set.atlas(c_index).unwrap().size(), size2(300, 256));
+
+    // The maximum number of atlases is reached.
+    assert!(set.allocate(size2(200, 200)).is_none());
+    // Small allocations still fit in the existing atlases.
+    assert_eq!(set.allocate(size2(50, 50)).unwrap().0, 0);
+
+    set.deallocate(b_index, b.id);
+    set.remove_atlas(b_index);
+    assert_eq!(set.atlas_count(), 2);
+    assert!(set.atlas(b_index).is_none());
+
+    // The index of the removed atlas is reused.
+    assert_eq!(set.allocate(size2(200, 200)).unwrap().0, b_index);
+
+    set.clear();
+    assert!(set.is_empty());
+    assert_eq!(set.atlas_count(), 3);
+}
+
+#[test]
+fn atlas_set_padding() {
+    use euclid::size2;
+
+    let options = AllocatorOptions { padding: 2, ..DEFAULT_OPTIONS };
+    let mut set = AtlasSet::with_options(size2(64, 64), 2, &options);
+
+    // New atlases are large enough for the padded allocation.
+    let (a_index, a) = set.allocate(size2(64, 64)).unwrap();
+    let (b_index, b) = set.allocate(size2(64, 64)).unwrap();
+    assert_eq!((a_index, b_index), (0, 1));
+    assert_eq!(set.atlas(a_index).unwrap().size(), size2(68, 68));
+    assert_eq!(a.content.size(), size2(64, 64));
+    assert_eq!(b.content.size(), size2(64, 64));
+    assert_eq!(set.atlas_count(), 2);
+}
+
+#[test]
+fn atlas_set_compact() {
+    use euclid::size2;
+
+    let mut set = AtlasSet::new(size2(256, 256), 3);
+    let (a_index, a) = set.allocate(size2(200, 200)).unwrap();
+    let (b_index, _) = set.allocate(size2(200, 200)).unwrap();
+
+    // Nothing can move when the atlases are full.
+    let compaction = set.compact();
+    assert!(compaction.moves.is_empty());
+    assert!(compaction.emptied_atlases.is_empty());
+
+    set.deallocate(a_index, a.id);
+    let (c_index, c) = set.allocate(size2(30, 30)).unwrap();
+    let (d_index, d) = set.allocate(size2(20, 50)).unwrap();
+    assert_eq!((c_index, d_index), (0, 0));
+
+    let compaction = set.compact();
+    assert_eq!(compaction.emptied_atlases, vec![0]);
+    assert_eq!(compaction.moves.len(), 2);
+    for m in &compaction.moves {
+        assert_eq!((m.old_atlas, m.new_atlas), (0, b_index));
+        assert_eq!(set.get(m.new_atlas, m.new.id), Some(&m.new.rectangle));
+        assert_eq!(m.new.content.size(), m.old.content.size());
+    }
+    assert!(!set.atlas(0).unwrap().is_valid(c.id));
+    assert!(!set.atlas(0).unwrap().is_valid(d.id));
+
+    set.remove_atlas(0);
+    assert_eq!(set.atlas_count(), 1);
+    assert_eq!(set.atlas(b_index).unwrap().allocation_count(), 3);
+}
+
+#[test]
+fn atlas_set_svg() {
+    use euclid::size2;
+
+    let mut set = AtlasSet::new(size2(256, 256), 3);
+    set.allocate(size2(200, 200)).unwrap();
+    set.allocate(size2(200, 200)).unwrap();
+    set.allocate(size2(300, 100)).unwrap();
+
+    let mut svg = Vec::new();
+    set.dump_svg(&SvgOptions::default(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    // The atlases are separated by 10 pixels.
+    assert!(svg.contains(r#"viewBox="0 0 832 256""#));
+    assert_eq!(svg.matches("<g>").count(), 3);
+    assert!(svg.contains(r#"x="266" y="0" width="200" height="200""#));
+}
@@ -0,0 +1,201 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+
+/// An atlas allocator that evicts the least recently used allocations to make room for new
+/// ones, for example for glyph or tile caches.
+///
+/// Each allocation records the timestamp at which it was last used. Timestamps are provided
+/// by the caller with `set_timestamp` (typically a frame number) and are recorded when
+/// allocating and when calling `touch`.
+pub struct CachedAtlasAllocator {
+    atlas: AtlasAllocator,
+    // Indexed by the dense index of the allocations.
+    last_used: Vec<u64>,
+    timestamp: u64,
+}
+
+impl CachedAtlasAllocator {
+    /// Create a cached atlas allocator with default options.
+    pub fn new(size: Size) -> Self {
+        CachedAtlasAllocator::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a cached atlas allocator with the provided options.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        CachedAtlasAllocator {
+            atlas: AtlasAllocator::with_options(size, options),
+            last_used: Vec::new(),
+            timestamp: 0,
+        }
+    }
+
+    /// The underlying atlas allocator.
+    pub fn atlas(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.atlas.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atlas.is_empty()
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.atlas.allocation_count()
+    }
+
+    /// Set the timestamp recorded by the next allocations and calls to `touch`.
+    ///
+    /// Timestamps are expected to increase over time.
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
+
+    /// The current timestamp.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Mark an allocation as used at the current timestamp.
+    pub fn touch(&mut self, id: AllocId) {
+        let index = self.atlas.dense_index(id);
+        self.last_used[index] = self.timestamp;
+    }
+
+    /// The timestamp at which an allocation was last used, or `None` if the id is not valid.
+    pub fn last_used(&self, id: AllocId) -> Option<u64> {
+        if !self.atlas.is_valid(id) {
+            return None;
+        }
+
+        Some(self.last_used[self.atlas.dense_index(id)])
+    }
+
+    /// Allocate a rectangle without evicting anything.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        let alloc = self.atlas.allocate(requested_size)?;
+        self.last_used.resize(self.atlas.dense_index_bound(), 0);
+        self.touch(alloc.id);
+
+        Some(alloc)
+    }
+
+    /// Allocate a rectangle, evicting the least recently used allocations until it fits.
+    ///
+    /// The filter is called with the id and the last use timestamp of the eviction candidates,
+    /// from the least recently used, and returns whether the allocation can be evicted. For
+    /// example allocations used in the current frame usually can't be evicted.
+    ///
+    /// Returns the new allocation, if any, along with the evicted allocations, whose ids are
+    /// not valid anymore. Allocations may be evicted even if the request still doesn't fit in
+    /// the end, for example because of fragmentation.
+    pub fn allocate_with_eviction<F>(&mut self, requested_size: Size, mut filter: F) -> (Option<Allocation>, Vec<Allocation>)
+    where
+        F: FnMut(AllocId, u64) -> bool,
+    {
+        let mut evicted = Vec::new();
+        if let Some(alloc) = self.allocate(requested_size) {
+            return (Some(alloc), evicted);
+        }
+
+        let mut candidates = Vec::new();
+        self.atlas.for_each_allocated_rectangle(|id, _| candidates.push(id));
+        candidates.sort_by_key(|&id| self.last_used[self.atlas.dense_index(id)]);
+
+        let requested_area = area(requested_size);
+        for id in candidates {
+            if !filter(id, self.last_used[self.atlas.dense_index(id)]) {
+                continue;
+            }
+
+            evicted.push(self.atlas.get_allocation(id));
+            self.atlas.deallocate(id);
+
+            if self.atlas.free_space() < requested_area {
+                continue;
+            }
+
+            if let Some(alloc) = self.allocate(requested_size) {
+                return (Some(alloc), evicted);
+            }
+        }
+
+        (None, evicted)
+    }
+
+    /// Deallocate a rectangle.
+    pub fn deallocate(&mut self, id: AllocId) {
+        self.atlas.deallocate(id);
+    }
+
+    /// Returns true if the id refers to a live allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        self.atlas.is_valid(id)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        self.atlas.get(id)
+    }
+
+    /// Resize the atlas without changing the allocations, see `AtlasAllocator::grow`.
+    pub fn grow(&mut self, new_size: Size) {
+        self.atlas.grow(new_size);
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.last_used.clear();
+    }
+}
+
+impl std::ops::Index<AllocId> for CachedAtlasAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        &self.atlas[index]
+    }
+}
+
+#[test]
+fn cached_atlas_allocator() {
+    use euclid::size2;
+
+    let mut atlas = CachedAtlasAllocator::new(size2(256, 256));
+
+    let mut ids = Vec::new();
+    for frame in 0..4 {
+        atlas.set_timestamp(frame);
+        ids.push(atlas.allocate(size2(128, 128)).unwrap().id);
+    }
+    assert!(atlas.allocate(size2(128, 128)).is_none());
+
+    // The first allocation is used again, so the second one is the least recently used.
+    atlas.set_timestamp(4);
+    atlas.touch(ids[0]);
+    assert_eq!(atlas.last_used(ids[0]), Some(4));
+    assert_eq!(atlas.last_used(ids[1]), Some(1));
+
+    let (alloc, evicted) = atlas.allocate_with_eviction(size2(100, 100), |_, _| true);
+    let alloc = alloc.unwrap();
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].id, ids[1]);
+    assert!(!atlas.is_valid(ids[1]));
+    assert_eq!(atlas.last_used(alloc.id), Some(4));
+
+    // Allocations rejected by the filter are not evicted.
+    let (alloc, evicted) = atlas.allocate_with_eviction(size2(256, 256), |_, last_used| last_used < 3);
+    assert!(alloc.is_none());
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].id, ids[2]);
+    assert_eq!(atlas.allocation_count(), 3);
+
+    // Evicting everything makes room for a full-size allocation.
+    let (alloc, evicted) = atlas.allocate_with_eviction(size2(256, 256), |_, _| true);
+    assert!(alloc.is_some());
+    assert_eq!(evicted.len(), 3);
+}
@@ -0,0 +1,204 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+use euclid::{point2, size2};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+/// Index of a shard in a `ConcurrentAtlasAllocator`.
+pub type ShardIndex = usize;
+
+/// An atlas allocator that can be used from several threads at the same time.
+///
+/// The atlas is divided into horizontal bands, the shards, each with its own `AtlasAllocator`
+/// behind a lock. Threads start allocating in different shards and skip the shards that are
+/// locked by other threads, so that allocating concurrently rarely has to wait.
+///
+/// The price is that a rectangle can't span several shards: requests that are taller than a
+/// shard fail, and free space is not shared between shards.
+pub struct ConcurrentAtlasAllocator {
+    shards: Vec<Shard>,
+    size: Size,
+    next_shard: AtomicUsize,
+}
+
+struct Shard {
+    atlas: Mutex<AtlasAllocator>,
+    y: i32,
+}
+
+impl ConcurrentAtlasAllocator {
+    /// Create a concurrent atlas allocator with default options.
+    pub fn new(size: Size, shard_count: usize) -> Self {
+        ConcurrentAtlasAllocator::with_options(size, shard_count, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a concurrent atlas allocator with the provided options.
+    ///
+    /// The height of the atlas is divided evenly between the shards, the last shard taking
+    /// the remainder.
+    pub fn with_options(size: Size, shard_count: usize, options: &AllocatorOptions) -> Self {
+        assert!(shard_count > 0);
+        let shard_height = size.height / shard_count as i32;
+        assert!(shard_height > 0);
+
+        let mut shards = Vec::with_capacity(shard_count);
+        for i in 0..shard_count as i32 {
+            let y = i * shard_height;
+            let height = if i == shard_count as i32 - 1 { size.height - y } else { shard_height };
+            shards.push(Shard {
+                atlas: Mutex::new(AtlasAllocator::with_options(size2(size.width, height), options)),
+                y,
+            });
+        }
+
+        ConcurrentAtlasAllocator {
+            shards,
+            size,
+            next_shard: AtomicUsize::new(0),
+        }
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns true if no shard has allocations.
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|shard| self.lock(shard).is_empty())
+    }
+
+    /// Number of live allocations in all of the shards.
+    pub fn allocation_count(&self) -> usize {
+        (0..self.shards.len()).map(|shard| self.lock(shard).allocation_count()).sum()
+    }
+
+    /// Allocate a rectangle in one of the shards.
+    ///
+    /// Returns the shard index along with the allocation, whose rectangles are in the
+    /// coordinate space of the whole atlas.
+    pub fn allocate(&self, requested_size: Size) -> Option<(ShardIndex, Allocation)> {
+        let count = self.shards.len();
+        let start = self.next_shard.fetch_add(1, Ordering::Relaxed) % count;
+
+        // First try the shards that are not locked by other threads, then wait for the others.
+        let mut busy = Vec::new();
+        for i in 0..count {
+            let shard = (start + i) % count;
+            match self.shards[shard].atlas.try_lock() {
+                Ok(mut atlas) => {
+                    if let Some(alloc) = atlas.allocate(requested_size) {
+                        return Some((shard, self.to_atlas_space(shard, alloc)));
+                    }
+                }
+                Err(TryLockError::WouldBlock) => busy.push(shard),
+                Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+            }
+        }
+
+        for shard in busy {
+            if let Some(alloc) = self.lock(shard).allocate(requested_size) {
+                return Some((shard, self.to_atlas_space(shard, alloc)));
+            }
+        }
+
+        None
+    }
+
+    /// Deallocate a rectangle in the provided shard.
+    pub fn deallocate(&self, shard: ShardIndex, id: AllocId) {
+        self.lock(shard).deallocate(id);
+    }
+
+    /// Returns the rectangle of an allocation in the coordinate space of the atlas, or
+    /// `None` if the shard index or the id is not valid.
+    pub fn get(&self, shard: ShardIndex, id: AllocId) -> Option<Rectangle> {
+        if shard >= self.shards.len() {
+            return None;
+        }
+
+        let rect = *self.lock(shard).get(id)?;
+
+        Some(rect.translate(point2(0, self.shards[shard].y).to_vector()))
+    }
+
+    /// Invoke a callback for each allocated rectangle of each shard.
+    ///
+    /// Each shard is locked while its rectangles are visited.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(ShardIndex, AllocId, &Rectangle),
+    {
+        for shard in 0..self.shards.len() {
+            let offset = point2(0, self.shards[shard].y).to_vector();
+            self.lock(shard).for_each_allocated_rectangle(|id, rect| {
+                callback(shard, id, &rect.translate(offset));
+            });
+        }
+    }
+
+    /// Drop all rectangles in all of the shards.
+    pub fn clear(&self) {
+        for shard in 0..self.shards.len() {
+            self.lock(shard).clear();
+        }
+    }
+
+    fn lock(&self, shard: ShardIndex) -> MutexGuard<'_, AtlasAllocator> {
+        self.shards[shard].atlas.lock().unwrap()
+    }
+
+    fn to_atlas_space(&self, shard: ShardIndex, mut alloc: Allocation) -> Allocation {
+        let offset = point2(0, self.shards[shard].y).to_vector();
+        alloc.rectangle = alloc.rectangle.translate(offset);
+        alloc.content = alloc.content.translate(offset);
+
+        alloc
+    }
+}
+
+#[test]
+fn concurrent_atlas_allocator() {
+    let atlas = ConcurrentAtlasAllocator::new(size2(512, 512), 4);
+    assert_eq!(atlas.shard_count(), 4);
+
+    let allocs: Vec<Vec<(ShardIndex, Allocation)>> = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut allocs = Vec::new();
+                    for i in 0..100 {
+                        allocs.push(atlas.allocate(size2(10 + i % 7, 12 + i % 5)).unwrap());
+                    }
+                    allocs
+                })
+            })
+            .collect();
+
+        threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+    });
+    let allocs: Vec<(ShardIndex, Allocation)> = allocs.into_iter().flatten().collect();
+    assert_eq!(atlas.allocation_count(), 400);
+
+    for (i, (shard, a)) in allocs.iter().enumerate() {
+        assert_eq!(atlas.get(*shard, a.id), Some(a.rectangle));
+        assert!(Rectangle::from_size(atlas.size()).contains_box(&a.rectangle));
+        for (_, b) in &allocs[i + 1..] {
+            assert!(!a.rectangle.intersects(&b.rectangle));
+        }
+    }
+
+    // Requests taller than a shard don't fit.
+    assert!(atlas.allocate(size2(10, 200)).is_none());
+
+    for (shard, alloc) in &allocs {
+        atlas.deallocate(*shard, alloc.id);
+    }
+    assert!(atlas.is_empty());
+}
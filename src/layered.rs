@@ -0,0 +1,207 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+
+/// Index of a layer in a `LayeredAtlasAllocator`.
+pub type LayerIndex = usize;
+
+/// An atlas allocator for texture arrays, where every layer has the same size.
+///
+/// Each layer is managed by an `AtlasAllocator`. Allocations are attempted in each layer in
+/// order. Since the size of the layers of a texture array is fixed, the allocator grows by
+/// adding layers rather than by enlarging them.
+pub struct LayeredAtlasAllocator {
+    layers: Vec<AtlasAllocator>,
+    layer_size: Size,
+    options: AllocatorOptions,
+}
+
+/// An allocation that was moved by `LayeredAtlasAllocator::rearrange`, possibly to another
+/// layer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LayeredChange {
+    pub old_layer: LayerIndex,
+    pub old: Allocation,
+    pub new_layer: LayerIndex,
+    pub new: Allocation,
+}
+
+/// The changes made by `LayeredAtlasAllocator::rearrange`, see `ChangeList`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayeredChangeList {
+    pub changes: Vec<LayeredChange>,
+    pub failures: Vec<(LayerIndex, Allocation)>,
+}
+
+impl LayeredAtlasAllocator {
+    /// Create an allocator for a texture array with default options.
+    pub fn new(layer_size: Size, layer_count: usize) -> Self {
+        LayeredAtlasAllocator::with_options(layer_size, layer_count, &DEFAULT_OPTIONS)
+    }
+
+    /// Create an allocator for a texture array with the provided options.
+    pub fn with_options(layer_size: Size, layer_count: usize, options: &AllocatorOptions) -> Self {
+        let mut layers = Vec::with_capacity(layer_count);
+        for _ in 0..layer_count {
+            layers.push(AtlasAllocator::with_options(layer_size, options));
+        }
+
+        LayeredAtlasAllocator {
+            layers,
+            layer_size,
+            options: *options,
+        }
+    }
+
+    /// The size of each layer.
+    pub fn layer_size(&self) -> Size {
+        self.layer_size
+    }
+
+    /// Number of layers of the texture array.
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// The allocator of a layer.
+    pub fn layer(&self, index: LayerIndex) -> &AtlasAllocator {
+        &self.layers[index]
+    }
+
+    /// Dump the layers side by side in SVG format, see `dump_svg_row`.
+    pub fn dump_svg(&self, options: &SvgOptions, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let layers: Vec<&dyn SvgDump> = self.layers.iter().map(|layer| layer as &dyn SvgDump).collect();
+
+        dump_svg_row(&layers, 10, options, output)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.iter().all(|layer| layer.is_empty())
+    }
+
+    /// Number of live allocations in all of the layers.
+    pub fn allocation_count(&self) -> usize {
+        self.layers.iter().map(|layer| layer.allocation_count()).sum()
+    }
+
+    /// Allocate a rectangle in the first layer that can hold it.
+    ///
+    /// Returns the layer index along with the allocation.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<(LayerIndex, Allocation)> {
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if let Some(alloc) = layer.allocate(requested_size) {
+                return Some((index, alloc));
+            }
+        }
+
+        None
+    }
+
+    /// Deallocate a rectangle in the provided layer.
+    pub fn deallocate(&mut self, layer: LayerIndex, id: AllocId) {
+        self.layers[layer].deallocate(id);
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the layer index or the id is not
+    /// valid.
+    pub fn get(&self, layer: LayerIndex, id: AllocId) -> Option<&Rectangle> {
+        self.layers.get(layer)?.get(id)
+    }
+
+    /// Add layers at the end of the texture array without changing the allocations.
+    ///
+    /// This method is not allowed to remove layers.
+    pub fn grow(&mut self, new_layer_count: usize) {
+        assert!(new_layer_count >= self.layers.len());
+
+        while self.layers.len() < new_layer_count {
+            self.layers.push(AtlasAllocator::with_options(self.layer_size, &self.options));
+        }
+    }
+
+    /// Drop all rectangles in all of the layers.
+    pub fn clear(&mut self) {
+        for layer in &mut self.layers {
+            layer.clear();
+        }
+    }
+
+    /// Invoke a callback for each allocated rectangle of each layer.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(LayerIndex, AllocId, &Rectangle),
+    {
+        for (index, layer) in self.layers.iter().enumerate() {
+            layer.for_each_allocated_rectangle(|id, rect| callback(index, id, rect));
+        }
+    }
+
+    /// Recompute the allocations of all of the layers and return the list of the changes.
+    ///
+    /// Allocations are placed back into the first layers, largest first, so they may move
+    /// to another layer. As with `AtlasAllocator::rearrange`, previous ids and rectangles are
+    /// not valid anymore after this operation.
+    pub fn rearrange(&mut self) -> LayeredChangeList {
+        let mut allocs = Vec::new();
+        for (index, layer) in self.layers.iter().enumerate() {
+            layer.for_each_allocated_rectangle(|id, _| allocs.push((index, layer.get_allocation(id))));
+        }
+        allocs.sort_by_key(|(_, alloc)| std::cmp::Reverse(area(alloc.rectangle.size())));
+
+        self.clear();
+
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+        'allocs: for (old_layer, old) in allocs {
+            for (new_layer, layer) in self.layers.iter_mut().enumerate() {
+                if let Some(new) = layer.allocate_moved(&old) {
+                    changes.push(LayeredChange { old_layer, old, new_layer, new });
+                    continue 'allocs;
+                }
+            }
+            failures.push((old_layer, old));
+        }
+
+        LayeredChangeList { changes, failures }
+    }
+}
+
+#[test]
+fn layered_atlas_allocator() {
+    use euclid::size2;
+
+    let mut atlas = LayeredAtlasAllocator::new(size2(256, 256), 2);
+
+    let a = atlas.allocate(size2(200, 200)).unwrap();
+    let b = atlas.allocate(size2(200, 200)).unwrap();
+    assert_eq!((a.0, b.0), (0, 1));
+    assert_eq!(atlas.get(b.0, b.1.id), Some(&b.1.rectangle));
+    assert!(atlas.allocate(size2(200, 200)).is_none());
+
+    // Growing adds layers of the same size.
+    atlas.grow(3);
+    assert_eq!(atlas.layer_count(), 3);
+    assert_eq!(atlas.layer(2).size(), size2(256, 256));
+    let c = atlas.allocate(size2(100, 100)).unwrap();
+    assert_eq!(c.0, 2);
+    assert_eq!(atlas.allocation_count(), 3);
+
+    // Rearranging moves the allocation of the last layer into the layer that was emptied.
+    atlas.deallocate(b.0, b.1.id);
+    let changes = atlas.rearrange();
+    assert!(changes.failures.is_empty());
+    assert_eq!(changes.changes.len(), 2);
+    let moved = changes.changes.iter().find(|change| change.old == c.1).unwrap();
+    assert_eq!(moved.old_layer, 2);
+    assert_eq!(moved.new_layer, 1);
+    assert!(atlas.layer(2).is_empty());
+
+    let mut count = 0;
+    atlas.for_each_allocated_rectangle(|layer, id, rect| {
+        assert_eq!(atlas.get(layer, id), Some(rect));
+        count += 1;
+    });
+    assert_eq!(count, 2);
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+}
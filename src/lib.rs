@@ -3,10 +3,60 @@
 pub extern crate serde;
 pub extern crate euclid;
 
+// Logging macros that compile to nothing unless the `log` feature is enabled.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
 mod allocator;
-//pub mod recording;
+mod atlas_set;
+mod cached;
+mod concurrent;
+mod layered;
+mod maxrects;
+mod observer;
+mod partition;
+#[cfg(feature = "png")]
+mod raster;
+mod recording;
+mod region;
+mod shared;
+mod shelf;
+mod skyline;
+mod slab;
+mod strip;
+mod tiered;
+mod volume;
 
 pub use crate::allocator::*;
+pub use crate::atlas_set::*;
+pub use crate::cached::*;
+pub use crate::concurrent::*;
+pub use crate::layered::*;
+pub use crate::maxrects::*;
+pub use crate::observer::*;
+pub use crate::partition::*;
+#[cfg(feature = "png")]
+pub use crate::raster::*;
+pub use crate::recording::*;
+pub use crate::region::*;
+pub use crate::shared::*;
+pub use crate::shelf::*;
+pub use crate::skyline::*;
+pub use crate::slab::*;
+pub use crate::strip::*;
+pub use crate::tiered::*;
+pub use crate::volume::*;
 pub use euclid::{point2, size2};
 
 pub type Point = euclid::default::Point2D<i32>;
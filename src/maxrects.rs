@@ -0,0 +1,358 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+use euclid::{point2, size2};
+
+use std::num::Wrapping;
+
+/// A texture atlas allocator using the MaxRects algorithm with the best area fit heuristic.
+///
+/// The allocator keeps track of all of the maximal free rectangles, which may overlap each
+/// other. Each allocation is placed in the free rectangle that leaves the least free area,
+/// and the free rectangles that intersect it are split and pruned.
+///
+/// This produces tightly packed atlases but the cost of each allocation grows quickly with the
+/// number of free rectangles, so this allocator is meant for offline packing (for example when
+/// baking assets) rather than for dynamic atlases. Rectangles can't be deallocated
+/// individually.
+///
+/// The `alignment`, `padding` and `allow_rotation` options are used, the other options are
+/// specific to the guillotine allocator.
+pub struct MaxRectsAllocator {
+    free_rects: Vec<Rectangle>,
+    allocations: Vec<Rectangle>,
+    // Incremented when clearing the atlas, to invalidate the previous ids.
+    generation: Wrapping<Generation>,
+    size: Size,
+    alignment: Size,
+    padding: i32,
+    allow_rotation: bool,
+    allocated_space: i64,
+}
+
+impl MaxRectsAllocator {
+    /// Create a MaxRects allocator with default options.
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a MaxRects allocator with the provided options.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        assert!(options.is_valid());
+        assert!(size.width > 0);
+        assert!(size.height > 0);
+
+        MaxRectsAllocator {
+            free_rects: vec![size.into()],
+            allocations: Vec::new(),
+            generation: Wrapping(0),
+            size,
+            alignment: options.alignment,
+            padding: options.padding,
+            allow_rotation: options.allow_rotation,
+            allocated_space: 0,
+        }
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    /// Number of allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    /// Sum of the areas of the allocated rectangles.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocated_space
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Ids that were allocated before clearing are invalidated.
+    pub fn clear(&mut self) {
+        self.free_rects.clear();
+        self.free_rects.push(self.size.into());
+        self.allocations.clear();
+        self.generation += Wrapping(1);
+        self.allocated_space = 0;
+    }
+
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        let padded_size = |size: Size| {
+            let mut size = size2(size.width + self.padding * 2, size.height + self.padding * 2);
+            adjust_size(self.alignment.width, &mut size.width);
+            adjust_size(self.alignment.height, &mut size.height);
+            size
+        };
+
+        let size = padded_size(requested_size);
+        let rotated_size = if self.allow_rotation && requested_size.width != requested_size.height {
+            Some(padded_size(size2(requested_size.height, requested_size.width)))
+        } else {
+            None
+        };
+
+        // Pick the free rectangle that leaves the least area, then the smallest leftover
+        // along the shortest side.
+        let mut best = None;
+        let mut best_score = (i64::MAX, i32::MAX);
+        for rect in &self.free_rects {
+            for (candidate_size, rotated) in std::iter::once((size, false)).chain(rotated_size.map(|s| (s, true))) {
+                let dx = rect.width() - candidate_size.width;
+                let dy = rect.height() - candidate_size.height;
+                if dx < 0 || dy < 0 {
+                    continue;
+                }
+
+                let score = (area(rect.size()) - area(candidate_size), i32::min(dx, dy));
+                if score < best_score {
+                    best_score = score;
+                    best = Some((rect.min, candidate_size, rotated));
+                }
+            }
+        }
+
+        let (min, size, rotated) = best?;
+        let rect = Rectangle {
+            min,
+            max: min + size.to_vector(),
+        };
+
+        self.split_free_rects(&rect);
+
+        self.allocations.push(rect);
+        self.allocated_space += area(size);
+
+        Some(Allocation {
+            id: AllocId::new(AllocIndex(self.allocations.len() as u32 - 1), self.generation.0),
+            rectangle: rect,
+            content: rect.inflate(-self.padding, -self.padding),
+            rotated,
+        })
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        let old_size = self.size;
+        self.size = new_size;
+
+        // The free rectangles that touch the grown edges are still maximal once extended to
+        // the new edges.
+        for rect in &mut self.free_rects {
+            if rect.max.x == old_size.width {
+                rect.max.x = new_size.width;
+            }
+            if rect.max.y == old_size.height {
+                rect.max.y = new_size.height;
+            }
+        }
+
+        let mut new_rects = Vec::new();
+        if new_size.width > old_size.width {
+            new_rects.push(Rectangle {
+                min: point2(old_size.width, 0),
+                max: new_size.to_vector().to_point(),
+            });
+        }
+        if new_size.height > old_size.height {
+            new_rects.push(Rectangle {
+                min: point2(0, old_size.height),
+                max: new_size.to_vector().to_point(),
+            });
+        }
+
+        self.add_free_rects(new_rects);
+    }
+
+    /// Returns true if the id refers to an allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        id.index().index() < self.allocations.len() && id.matches_generation(self.generation.0)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.allocations[id.index().index()])
+    }
+
+    /// Invoke a callback for each allocated rectangle in the atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (index, rect) in self.allocations.iter().enumerate() {
+            callback(AllocId::new(AllocIndex(index as u32), self.generation.0), rect);
+        }
+    }
+
+    /// Invoke a callback for each maximal free rectangle in the atlas.
+    ///
+    /// Unlike with the other allocators, the free rectangles may overlap.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for rect in &self.free_rects {
+            callback(rect);
+        }
+    }
+
+    // Replace the free rectangles that intersect the allocated rectangle with the maximal
+    // rectangles around it, and remove the free rectangles contained in others.
+    fn split_free_rects(&mut self, allocated: &Rectangle) {
+        let mut new_rects = Vec::new();
+        self.free_rects.retain(|free| {
+            if !free.intersects(allocated) {
+                return true;
+            }
+
+            if allocated.min.x > free.min.x {
+                let mut rect = *free;
+                rect.max.x = allocated.min.x;
+                new_rects.push(rect);
+            }
+            if allocated.max.x < free.max.x {
+                let mut rect = *free;
+                rect.min.x = allocated.max.x;
+                new_rects.push(rect);
+            }
+            if allocated.min.y > free.min.y {
+                let mut rect = *free;
+                rect.max.y = allocated.min.y;
+                new_rects.push(rect);
+            }
+            if allocated.max.y < free.max.y {
+                let mut rect = *free;
+                rect.min.y = allocated.max.y;
+                new_rects.push(rect);
+            }
+
+            false
+        });
+
+        self.add_free_rects(new_rects);
+    }
+
+    // Add free rectangles, skipping the ones that are contained in other free rectangles and
+    // removing the ones they contain.
+    fn add_free_rects(&mut self, new_rects: Vec<Rectangle>) {
+        for rect in new_rects {
+            let redundant = self.free_rects.iter().any(|other| other.contains_box(&rect));
+            if !redundant {
+                self.free_rects.retain(|other| !rect.contains_box(other));
+                self.free_rects.push(rect);
+            }
+        }
+    }
+}
+
+impl std::ops::Index<AllocId> for MaxRectsAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+impl DynamicAtlas for MaxRectsAllocator {
+    fn size(&self) -> Size {
+        MaxRectsAllocator::size(self)
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        MaxRectsAllocator::allocate(self, requested_size)
+    }
+
+    fn supports_deallocation(&self) -> bool {
+        false
+    }
+
+    fn deallocate(&mut self, _id: AllocId) {
+        panic!("MaxRectsAllocator can't deallocate rectangles");
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        MaxRectsAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        MaxRectsAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        MaxRectsAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        MaxRectsAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+impl SvgDump for MaxRectsAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        self.for_each_allocated_rectangle(|_, rect| callback(rect, NodeKind::Alloc));
+        self.for_each_free_rectangle(|rect| callback(rect, NodeKind::Free));
+    }
+}
+
+#[test]
+fn maxrects_allocator() {
+    let mut atlas = MaxRectsAllocator::new(size2(256, 256));
+
+    let a = atlas.allocate(size2(200, 100)).unwrap();
+    // The remaining space is described by two overlapping maximal rectangles.
+    let mut free_rects = Vec::new();
+    atlas.for_each_free_rectangle(|rect| free_rects.push(*rect));
+    assert_eq!(free_rects.len(), 2);
+
+    // Fits exactly in the free space on the right of the first allocation.
+    let b = atlas.allocate(size2(56, 100)).unwrap();
+    assert_eq!(b.rectangle.min, euclid::point2(200, 0));
+    assert_eq!(atlas[a.id], a.rectangle);
+
+    for i in 0..100 {
+        atlas.allocate(size2(3 + (i * 7) % 19, 3 + (i * 11) % 13)).unwrap();
+    }
+
+    let mut rects = Vec::new();
+    atlas.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+    check_allocated_rectangles(&atlas, &rects);
+    atlas.for_each_free_rectangle(|free| {
+        assert!(rects.iter().all(|rect| !rect.intersects(free)));
+    });
+
+    // Rotation lets a tall rectangle use a wide free span.
+    let mut atlas = MaxRectsAllocator::with_options(
+        size2(100, 100),
+        &AllocatorOptions { allow_rotation: true, ..DEFAULT_OPTIONS },
+    );
+    atlas.allocate(size2(100, 80)).unwrap();
+    let alloc = atlas.allocate(size2(20, 100)).unwrap();
+    assert!(alloc.rotated);
+    assert_eq!(alloc.rectangle.size(), size2(100, 20));
+
+    atlas.clear();
+    assert!(!atlas.is_valid(alloc.id));
+}
@@ -0,0 +1,209 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+
+/// Callbacks invoked by `ObservedAtlas` when its allocations change.
+///
+/// All methods have empty default implementations so that observers only need to implement
+/// the events they are interested in.
+pub trait AtlasObserver {
+    /// A rectangle was allocated.
+    fn on_allocate(&mut self, _allocation: &Allocation) {}
+
+    /// A rectangle was deallocated. The rectangle is the one that was allocated.
+    fn on_deallocate(&mut self, _id: AllocId, _rectangle: &Rectangle) {}
+
+    /// The atlas was grown.
+    fn on_grow(&mut self, _old_size: Size, _new_size: Size) {}
+
+    /// The allocations were moved, see `AtlasAllocator::rearrange`.
+    fn on_rearrange(&mut self, _changes: &ChangeList) {}
+
+    /// All allocations were removed.
+    fn on_clear(&mut self) {}
+}
+
+/// An atlas allocator that notifies an `AtlasObserver` of the changes to its allocations.
+///
+/// Read-only methods of the `AtlasAllocator` are available through `Deref`, while the methods
+/// that modify the allocations are wrapped to invoke the observer. Atlases that don't need an
+/// observer don't pay for it.
+pub struct ObservedAtlas<Observer: AtlasObserver> {
+    atlas: AtlasAllocator,
+    observer: Observer,
+}
+
+impl<Observer: AtlasObserver> ObservedAtlas<Observer> {
+    pub fn new(atlas: AtlasAllocator, observer: Observer) -> Self {
+        ObservedAtlas { atlas, observer }
+    }
+
+    pub fn observer(&self) -> &Observer {
+        &self.observer
+    }
+
+    pub fn observer_mut(&mut self) -> &mut Observer {
+        &mut self.observer
+    }
+
+    /// Return the atlas and the observer.
+    pub fn into_inner(self) -> (AtlasAllocator, Observer) {
+        (self.atlas, self.observer)
+    }
+
+    /// See `AtlasAllocator::allocate`.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        let allocation = self.atlas.allocate(requested_size)?;
+        self.observer.on_allocate(&allocation);
+
+        Some(allocation)
+    }
+
+    /// See `AtlasAllocator::allocate_with_padding`.
+    pub fn allocate_with_padding(&mut self, requested_size: Size, padding: i32) -> Option<Allocation> {
+        let allocation = self.atlas.allocate_with_padding(requested_size, padding)?;
+        self.observer.on_allocate(&allocation);
+
+        Some(allocation)
+    }
+
+    /// See `AtlasAllocator::deallocate`.
+    pub fn deallocate(&mut self, id: AllocId) {
+        let rectangle = self.atlas[id];
+        self.atlas.deallocate(id);
+        self.observer.on_deallocate(id, &rectangle);
+    }
+
+    /// See `AtlasAllocator::deallocate_many`.
+    pub fn deallocate_many(&mut self, ids: &[AllocId]) {
+        let rectangles: Vec<Rectangle> = ids.iter().map(|&id| self.atlas[id]).collect();
+        self.atlas.deallocate_many(ids);
+        for (&id, rectangle) in ids.iter().zip(rectangles.iter()) {
+            self.observer.on_deallocate(id, rectangle);
+        }
+    }
+
+    /// See `AtlasAllocator::grow`.
+    pub fn grow(&mut self, new_size: Size) -> Vec<Rectangle> {
+        let old_size = self.atlas.size();
+        let free_rects = self.atlas.grow(new_size);
+        self.observer.on_grow(old_size, new_size);
+
+        free_rects
+    }
+
+    /// See `AtlasAllocator::rearrange`.
+    pub fn rearrange(&mut self) -> ChangeList {
+        let changes = self.atlas.rearrange();
+        self.observer.on_rearrange(&changes);
+
+        changes
+    }
+
+    /// See `AtlasAllocator::resize_and_rearrange`.
+    pub fn resize_and_rearrange(&mut self, new_size: Size) -> ChangeList {
+        let old_size = self.atlas.size();
+        let changes = self.atlas.resize_and_rearrange(new_size);
+        // The atlas keeps its size when it refuses to shrink, and shrinking is not growing.
+        let new_size = self.atlas.size();
+        if new_size != old_size && new_size.width >= old_size.width && new_size.height >= old_size.height {
+            self.observer.on_grow(old_size, new_size);
+        }
+        self.observer.on_rearrange(&changes);
+
+        changes
+    }
+
+    /// See `AtlasAllocator::clear`.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.observer.on_clear();
+    }
+}
+
+impl<Observer: AtlasObserver> std::ops::Deref for ObservedAtlas<Observer> {
+    type Target = AtlasAllocator;
+    fn deref(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+}
+
+#[test]
+fn observed_atlas() {
+    use euclid::size2;
+
+    #[derive(Default)]
+    struct Counters {
+        allocated: i64,
+        deallocated: i64,
+        grown: usize,
+        rearranged: usize,
+    }
+
+    impl AtlasObserver for Counters {
+        fn on_allocate(&mut self, allocation: &Allocation) {
+            self.allocated += allocation.rectangle.area() as i64;
+        }
+        fn on_deallocate(&mut self, _id: AllocId, rectangle: &Rectangle) {
+            self.deallocated += rectangle.area() as i64;
+        }
+        fn on_grow(&mut self, _old_size: Size, _new_size: Size) {
+            self.grown += 1;
+        }
+        fn on_rearrange(&mut self, _changes: &ChangeList) {
+            self.rearranged += 1;
+        }
+    }
+
+    let mut atlas = ObservedAtlas::new(AtlasAllocator::new(size2(256, 256)), Counters::default());
+
+    let a = atlas.allocate(size2(32, 32)).unwrap();
+    atlas.allocate(size2(64, 64)).unwrap();
+    atlas.deallocate(a.id);
+    atlas.grow(size2(512, 512));
+    atlas.rearrange();
+
+    let counters = atlas.observer();
+    assert_eq!(counters.allocated, 32 * 32 + 64 * 64);
+    assert_eq!(counters.deallocated, 32 * 32);
+    assert_eq!(counters.grown, 1);
+    assert_eq!(counters.rearranged, 1);
+
+    // Read-only methods are forwarded to the atlas.
+    assert_eq!(atlas.size(), size2(512, 512));
+    assert_eq!(atlas.allocated_space(), 64 * 64);
+    assert_eq!(atlas.allocation_count(), 1);
+}
+
+#[test]
+fn observed_resize() {
+    use euclid::size2;
+
+    #[derive(Default)]
+    struct Grown(Vec<(Size, Size)>);
+
+    impl AtlasObserver for Grown {
+        fn on_grow(&mut self, old_size: Size, new_size: Size) {
+            self.0.push((old_size, new_size));
+        }
+    }
+
+    let mut pinned = AtlasAllocator::new(size2(256, 256));
+    let a = pinned.allocate(size2(64, 64)).unwrap();
+    pinned.set_pinned(a.id, true);
+
+    // Shrinking with pinned content is refused.
+    let mut atlas = ObservedAtlas::new(pinned, Grown::default());
+    atlas.resize_and_rearrange(size2(128, 128));
+    assert_eq!(atlas.size(), size2(256, 256));
+    assert!(atlas.observer().0.is_empty());
+
+    // Shrinking isn't growing.
+    let mut atlas = ObservedAtlas::new(AtlasAllocator::new(size2(256, 256)), Grown::default());
+    atlas.allocate(size2(64, 64)).unwrap();
+    atlas.resize_and_rearrange(size2(128, 128));
+    assert_eq!(atlas.size(), size2(128, 128));
+    assert!(atlas.observer().0.is_empty());
+
+    atlas.resize_and_rearrange(size2(512, 512));
+    assert_eq!(atlas.observer().0, vec![(size2(128, 128), size2(512, 512))]);
+}
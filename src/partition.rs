@@ -0,0 +1,243 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+
+/// Index of a partition in a `PartitionedAtlasAllocator`.
+pub type PartitionId = u32;
+
+/// An atlas allocator shared by several users, each with a quota of the atlas area.
+///
+/// This prevents a greedy user of the atlas from starving the others when they share a
+/// texture. Partitions don't correspond to regions of the atlas: allocations of all of the
+/// partitions are mixed, and the quotas only limit the area allocated by each partition.
+///
+/// Allocations are tagged with the id of their partition, see `AtlasAllocator::allocate_tagged`.
+pub struct PartitionedAtlasAllocator {
+    atlas: AtlasAllocator,
+    partitions: Vec<Partition>,
+}
+
+struct Partition {
+    name: String,
+    quota: f32,
+    allocated_space: i64,
+    allocation_count: usize,
+}
+
+/// The occupancy of a partition, see `PartitionedAtlasAllocator::partition_stats`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PartitionStats {
+    /// Sum of the areas of the allocated rectangles of the partition, including their padding.
+    pub allocated_space: i64,
+    /// The area the partition is allowed to allocate.
+    pub quota_space: i64,
+    pub allocation_count: usize,
+}
+
+impl PartitionStats {
+    /// Ratio of the quota that is allocated, between 0.0 and 1.0.
+    pub fn occupancy(&self) -> f32 {
+        (self.allocated_space as f64 / self.quota_space.max(1) as f64) as f32
+    }
+}
+
+impl PartitionedAtlasAllocator {
+    /// Create a partitioned atlas allocator with default options and no partition.
+    pub fn new(size: Size) -> Self {
+        PartitionedAtlasAllocator::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a partitioned atlas allocator with the provided options and no partition.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        PartitionedAtlasAllocator {
+            atlas: AtlasAllocator::with_options(size, options),
+            partitions: Vec::new(),
+        }
+    }
+
+    /// Add a partition that may allocate up to the provided ratio of the atlas area.
+    ///
+    /// The sum of the quotas of the partitions can't exceed 1.0.
+    pub fn add_partition(&mut self, name: &str, quota: f32) -> PartitionId {
+        let total: f32 = self.partitions.iter().map(|partition| partition.quota).sum();
+        assert!(quota > 0.0 && total + quota <= 1.0 + f32::EPSILON);
+
+        self.partitions.push(Partition {
+            name: name.to_string(),
+            quota,
+            allocated_space: 0,
+            allocation_count: 0,
+        });
+
+        (self.partitions.len() - 1) as PartitionId
+    }
+
+    /// Returns the id of the partition with the provided name.
+    pub fn partition_by_name(&self, name: &str) -> Option<PartitionId> {
+        self.partitions
+            .iter()
+            .position(|partition| partition.name == name)
+            .map(|index| index as PartitionId)
+    }
+
+    /// Number of partitions.
+    pub fn partition_count(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// The occupancy of a partition.
+    ///
+    /// The quota is relative to the current size of the atlas, so it increases when the
+    /// atlas grows.
+    pub fn partition_stats(&self, partition: PartitionId) -> PartitionStats {
+        let partition = &self.partitions[partition as usize];
+        PartitionStats {
+            allocated_space: partition.allocated_space,
+            quota_space: self.quota_space(partition),
+            allocation_count: partition.allocation_count,
+        }
+    }
+
+    /// The underlying atlas allocator.
+    pub fn atlas(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.atlas.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atlas.is_empty()
+    }
+
+    /// Allocate a rectangle on behalf of a partition.
+    ///
+    /// Returns `None` if the allocation would exceed the quota of the partition, even if
+    /// the atlas has enough free space.
+    pub fn allocate(&mut self, partition: PartitionId, requested_size: Size) -> Option<Allocation> {
+        let quota_space = self.quota_space(&self.partitions[partition as usize]);
+        let allocated_space = self.partitions[partition as usize].allocated_space;
+        if allocated_space + area(requested_size) > quota_space {
+            return None;
+        }
+
+        let alloc = self.atlas.allocate_tagged(requested_size, partition)?;
+
+        // The allocated rectangle can be larger than the requested size because of the padding
+        // and alignment.
+        let alloc_space = area(alloc.rectangle.size());
+        if allocated_space + alloc_space > quota_space {
+            self.atlas.deallocate(alloc.id);
+            return None;
+        }
+
+        let partition = &mut self.partitions[partition as usize];
+        partition.allocated_space += alloc_space;
+        partition.allocation_count += 1;
+
+        Some(alloc)
+    }
+
+    /// Deallocate a rectangle.
+    pub fn deallocate(&mut self, id: AllocId) {
+        let partition = self.atlas.tag(id).unwrap();
+        let alloc_space = area(self.atlas[id].size());
+        self.atlas.deallocate(id);
+
+        let partition = &mut self.partitions[partition as usize];
+        partition.allocated_space -= alloc_space;
+        partition.allocation_count -= 1;
+    }
+
+    /// Returns the partition of an allocation.
+    pub fn partition_of(&self, id: AllocId) -> PartitionId {
+        self.atlas.tag(id).unwrap()
+    }
+
+    /// Returns true if the id refers to a live allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        self.atlas.is_valid(id)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        self.atlas.get(id)
+    }
+
+    /// Resize the atlas without changing the allocations, see `AtlasAllocator::grow`.
+    pub fn grow(&mut self, new_size: Size) {
+        self.atlas.grow(new_size);
+    }
+
+    /// Deallocate all of the rectangles of a partition.
+    pub fn clear_partition(&mut self, partition: PartitionId) {
+        self.atlas.release_tag(partition);
+
+        let partition = &mut self.partitions[partition as usize];
+        partition.allocated_space = 0;
+        partition.allocation_count = 0;
+    }
+
+    /// Drop all rectangles, keeping the partitions.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        for partition in &mut self.partitions {
+            partition.allocated_space = 0;
+            partition.allocation_count = 0;
+        }
+    }
+
+    fn quota_space(&self, partition: &Partition) -> i64 {
+        (area(self.atlas.size()) as f64 * partition.quota as f64) as i64
+    }
+}
+
+impl std::ops::Index<AllocId> for PartitionedAtlasAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        &self.atlas[index]
+    }
+}
+
+#[test]
+fn partitioned_atlas_allocator() {
+    use euclid::size2;
+
+    let mut atlas = PartitionedAtlasAllocator::new(size2(100, 100));
+    let glyphs = atlas.add_partition("glyphs", 0.6);
+    let icons = atlas.add_partition("icons", 0.4);
+    assert_eq!(atlas.partition_by_name("icons"), Some(icons));
+    assert_eq!(atlas.partition_by_name("decals"), None);
+
+    let icon = atlas.allocate(icons, size2(40, 40)).unwrap();
+    assert_eq!(atlas.partition_of(icon.id), icons);
+    assert!(atlas.allocate(icons, size2(40, 40)).is_some());
+    assert!(atlas.allocate(icons, size2(30, 30)).is_none());
+    assert_eq!(atlas.partition_stats(icons).allocation_count, 2);
+
+    // The glyphs can't take more than 60% of the atlas, even though there is free space.
+    let mut glyph_ids = Vec::new();
+    while let Some(alloc) = atlas.allocate(glyphs, size2(10, 10)) {
+        glyph_ids.push(alloc.id);
+    }
+    assert_eq!(glyph_ids.len(), 60);
+    assert!(atlas.atlas().free_space() > 0);
+    let stats = atlas.partition_stats(glyphs);
+    assert_eq!(stats.allocated_space, 6000);
+    assert_eq!(stats.quota_space, 6000);
+    assert_eq!(stats.occupancy(), 1.0);
+
+    atlas.deallocate(glyph_ids.pop().unwrap());
+    assert_eq!(atlas.partition_stats(glyphs).allocated_space, 5900);
+    assert!(atlas.allocate(glyphs, size2(10, 10)).is_some());
+
+    atlas.clear_partition(glyphs);
+    assert_eq!(atlas.partition_stats(glyphs).allocation_count, 0);
+    assert_eq!(atlas.atlas().allocation_count(), 2);
+
+    // The quotas scale with the size of the atlas.
+    atlas.grow(size2(200, 100));
+    assert_eq!(atlas.partition_stats(icons).quota_space, 8000);
+    assert!(atlas.allocate(icons, size2(30, 30)).is_some());
+}
@@ -0,0 +1,188 @@
+use crate::allocator::*;
+
+/// Parameters of the PNG dumps, see `dump_png`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PngOptions {
+    /// Ratio between the size of the image and the size of the atlas.
+    pub scale: f32,
+    /// Derive the color of each allocation from its id rather than from its drawing order,
+    /// so that an allocation keeps its color across dumps.
+    ///
+    /// Ids are only known for the allocators that provide them, see
+    /// `SvgDump::dump_rectangles_with_ids`.
+    pub color_by_id: bool,
+    /// Draw the outline of the rectangles.
+    pub outlines: bool,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        PngOptions {
+            scale: 1.0,
+            color_by_id: false,
+            outlines: true,
+        }
+    }
+}
+
+const FREE_COLOR: [u8; 3] = [50, 50, 50];
+const RESERVED_COLOR: [u8; 3] = [120, 50, 50];
+const OUTLINE_COLOR: [u8; 3] = [0, 0, 0];
+
+/// Rasterize the atlas into a PNG image, giving a distinct color to each allocation.
+///
+/// This is an alternative to `dump_svg` for places where SVG files are not practical. The
+/// image is not compressed.
+pub fn dump_png<Atlas: SvgDump + ?Sized>(
+    atlas: &Atlas,
+    options: &PngOptions,
+    output: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    let size = atlas.dump_size().to_f32() * options.scale;
+    let width = (size.width.ceil() as usize).max(1);
+    let height = (size.height.ceil() as usize).max(1);
+    let mut pixels = vec![0u8; width * height * 3];
+
+    let mut alloc_count = 0;
+    atlas.dump_rectangles_with_ids(&mut |rect, kind, id| {
+        let color = match (kind, id) {
+            (NodeKind::Free, _) => FREE_COLOR,
+            (NodeKind::Reserved, _) => RESERVED_COLOR,
+            // The ids are already 64 bits wide with the `wide_ids` feature.
+            #[allow(clippy::unnecessary_cast)]
+            (NodeKind::Alloc, Some(id)) if options.color_by_id => alloc_color(id.serialize() as u64),
+            (NodeKind::Alloc, _) => {
+                alloc_count += 1;
+                alloc_color(alloc_count)
+            }
+            _ => {
+                return;
+            }
+        };
+
+        let rect = rect.to_f32().scale(options.scale, options.scale);
+        let x0 = (rect.min.x.round() as usize).min(width);
+        let y0 = (rect.min.y.round() as usize).min(height);
+        let x1 = (rect.max.x.round() as usize).min(width);
+        let y1 = (rect.max.y.round() as usize).min(height);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let outline = options.outlines && (x == x0 || y == y0 || x + 1 == x1 || y + 1 == y1);
+                let offset = (y * width + x) * 3;
+                pixels[offset..offset + 3].copy_from_slice(if outline { &OUTLINE_COLOR } else { &color });
+            }
+        }
+    });
+
+    write_png(width as u32, height as u32, &pixels, output)
+}
+
+// Spread the colors of consecutive allocations around the hue circle.
+fn alloc_color(seed: u64) -> [u8; 3] {
+    let hash = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let hue = (hash >> 40) as f32 / (1u64 << 24) as f32 * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let channel = |c: f32| (80.0 + c * 150.0) as u8;
+
+    [channel(r), channel(g), channel(b)]
+}
+
+// Encode 8 bits RGB pixels into a PNG image, using stored (uncompressed) deflate blocks.
+fn write_png(width: u32, height: u32, pixels: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    output.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // Bit depth, color type (RGB), compression, filter and interlace methods.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(b"IHDR", &header, output)?;
+
+    // Each scanline starts with its filter type.
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+    write_chunk(b"IDAT", &zlib, output)?;
+
+    write_chunk(b"IEND", &[], output)
+}
+
+fn write_chunk(kind: &[u8; 4], data: &[u8], output: &mut dyn std::io::Write) -> std::io::Result<()> {
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+
+    let crc = !crc32(crc32(!0, kind), data);
+    output.write_all(&crc.to_be_bytes())
+}
+
+fn crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[test]
+fn png_dump() {
+    use euclid::size2;
+
+    let mut atlas = AtlasAllocator::new(size2(100, 50));
+    atlas.allocate(size2(40, 40)).unwrap();
+    atlas.allocate(size2(20, 20)).unwrap();
+
+    let options = PngOptions {
+        scale: 0.5,
+        ..PngOptions::default()
+    };
+    let mut png = Vec::new();
+    dump_png(&atlas, &options, &mut png).unwrap();
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 50, 0, 0, 0, 25]);
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    // The CRC of the IEND chunk.
+    assert_eq!(&png[png.len() - 4..], &[0xAE, 0x42, 0x60, 0x82]);
+
+    // Raw scanlines and stored deflate blocks.
+    let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+    assert_eq!(idat_len, 2 + 5 + 25 * (50 * 3 + 1) + 4);
+}
@@ -6,22 +6,20 @@ pub struct RecordingAllocator {
     recorder: Recorder,
     // Assign unique ids to recorded events. This simplifies a few things, later on.
     id_map: HashMap<AllocId, AllocId>,
+    // The recorded id of each allocation of the atlas, so that change lists can be remapped
+    // without scanning `id_map`.
+    reverse_id_map: HashMap<AllocId, AllocId>,
     next_id: u32,
+    event_limit: Option<usize>,
+    // The state of the atlas after the first `event_limit` events, which becomes the start of
+    // the recording when older events are dropped.
+    pending_snapshot: Option<(Size, Vec<(AllocId, Rectangle)>)>,
 }
 
 impl RecordingAllocator {
     /// Create an atlas allocator.
     pub fn new(size: Size) -> Self {
-        RecordingAllocator {
-            allocator: AtlasAllocator::new(size),
-            recorder: Recorder {
-                events: Vec::new(),
-                initial_size: size,
-                options: DEFAULT_OPTIONS,
-            },
-            id_map: HashMap::new(),
-            next_id: 0,
-        }
+        RecordingAllocator::with_options(size, &DEFAULT_OPTIONS)
     }
 
     /// Create an atlas allocator with the provided options.
@@ -31,13 +29,31 @@ impl RecordingAllocator {
             recorder: Recorder {
                 events: Vec::new(),
                 initial_size: size,
+                initial_allocations: Vec::new(),
                 options: *options,
             },
             id_map: HashMap::new(),
+            reverse_id_map: HashMap::new(),
             next_id: 0,
+            event_limit: None,
+            pending_snapshot: None,
         }
     }
 
+    /// Create an atlas allocator that only keeps the most recent events, so that it can
+    /// record for an unbounded amount of time.
+    ///
+    /// Every `event_limit` events, the allocations are copied into a snapshot. When older
+    /// events are dropped, the recording starts from the last snapshot that precedes the
+    /// remaining events, so between `event_limit` and twice as many events can be replayed.
+    pub fn with_event_limit(size: Size, options: &AllocatorOptions, event_limit: usize) -> Self {
+        assert!(event_limit > 0);
+        let mut atlas = RecordingAllocator::with_options(size, options);
+        atlas.event_limit = Some(event_limit);
+
+        atlas
+    }
+
     /// The total size of the atlas.
     pub fn size(&self) -> Size {
         self.allocator.size()
@@ -46,27 +62,26 @@ impl RecordingAllocator {
     /// Allocate a rectangle in the atlas.
     pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
         let res = self.allocator.allocate(requested_size).map(|res| {
-            let id = AllocId(self.next_id);
+            let id = AllocId(self.next_id as AllocIdRepr);
             self.next_id += 1;
 
             self.id_map.insert(id, res.id);
-
-            println!(" alloc {:?} (was {:?})", id, res.id);
+            self.reverse_id_map.insert(res.id, id);
 
             Allocation { id, ..res }
         });
 
-        self.recorder.record(Event::Allocate(requested_size, res.map(|r| r.id)));
+        self.record(Event::Allocate(requested_size, res.map(|r| r.id)));
 
         res
     }
 
     /// Deallocate a rectangle in the atlas.
     pub fn deallocate(&mut self, node_id: AllocId) {
-        if let Some(actual_id) = self.id_map.get(&node_id) {
-            println!(" dealloc {:?} (was {:?})", node_id, actual_id);
-            self.allocator.deallocate(*actual_id);
-            self.recorder.record(Event::Deallocate(node_id));
+        if let Some(actual_id) = self.id_map.remove(&node_id) {
+            self.reverse_id_map.remove(&actual_id);
+            self.allocator.deallocate(actual_id);
+            self.record(Event::Deallocate(node_id));
         }
     }
 
@@ -79,7 +94,7 @@ impl RecordingAllocator {
         let changes = self.allocator.rearrange();
         let remapped = self.remap_changelist(&changes);
 
-        self.recorder.record(Event::Rearrange(remapped.clone()));
+        self.record(Event::Rearrange(remapped.clone()));
 
         remapped
     }
@@ -89,33 +104,27 @@ impl RecordingAllocator {
         let changes = self.allocator.resize_and_rearrange(new_size);
         let remapped = self.remap_changelist(&changes);
 
-        self.recorder.record(Event::ResizeAndRearrange(new_size, remapped.clone()));
+        self.record(Event::ResizeAndRearrange(new_size, remapped.clone()));
 
         remapped
     }
 
     fn remap_changelist(&mut self, changes: &ChangeList) -> ChangeList {
         let mut remapped = ChangeList {
-            changes: Vec::new(),
-            failures: Vec::new(),
+            changes: Vec::with_capacity(changes.changes.len()),
+            failures: Vec::with_capacity(changes.failures.len()),
         };
 
-        let prev_id_map = std::mem::replace(&mut self.id_map, HashMap::new());
+        // The new ids may collide with the old ids of other allocations, so the maps are
+        // rebuilt rather than updated in place.
+        let prev_reverse_id_map = std::mem::take(&mut self.reverse_id_map);
         self.id_map.clear();
 
         for change in &changes.changes {
-            let mut id = None;
-            for (k, v) in &prev_id_map {
-                if *v == change.old.id {
-                    id = Some(*k);
-                    break;
-                }
-            }
-
-
-            let id = id.unwrap();
+            let id = prev_reverse_id_map[&change.old.id];
 
             self.id_map.insert(id, change.new.id);
+            self.reverse_id_map.insert(change.new.id, id);
             remapped.changes.push(Change {
                 old: Allocation { id, ..change.old },
                 new: Allocation { id, ..change.new },
@@ -123,16 +132,9 @@ impl RecordingAllocator {
         }
 
         for failure in &changes.failures {
-            let mut id = None;
-            for (k, v) in &prev_id_map {
-                if *v == failure.id {
-                    id = Some(*k);
-                    break;
-                }
-            }
             remapped.failures.push(Allocation {
-                id : id.unwrap(),
-                rectangle: failure.rectangle,
+                id: prev_reverse_id_map[&failure.id],
+                ..*failure
             });
         }
 
@@ -140,8 +142,38 @@ impl RecordingAllocator {
     }
 
     pub fn grow(&mut self, new_size: Size) {
-        self.recorder.record(Event::Grow(new_size));
         self.allocator.grow(new_size);
+        self.record(Event::Grow(new_size));
+    }
+
+    // Record an event after it is applied to the atlas.
+    fn record(&mut self, event: Event) {
+        self.recorder.record(event);
+
+        let limit = match self.event_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let len = self.recorder.events.len();
+        if !len.is_multiple_of(limit) {
+            return;
+        }
+
+        if len >= limit * 2 {
+            let (size, allocations) = self.pending_snapshot.take().unwrap();
+            self.recorder.events.drain(..limit);
+            self.recorder.initial_size = size;
+            self.recorder.initial_allocations = allocations;
+        }
+
+        let mut allocations: Vec<(AllocId, Rectangle)> = self
+            .id_map
+            .iter()
+            .map(|(id, actual_id)| (*id, self.allocator[*actual_id]))
+            .collect();
+        allocations.sort_by_key(|(id, _)| id.serialize());
+        self.pending_snapshot = Some((self.allocator.size(), allocations));
     }
 
     pub fn for_each_free_rectangle<F>(&self, callback: F)
@@ -157,8 +189,19 @@ impl RecordingAllocator {
     {
         self.allocator.for_each_allocated_rectangle(callback);
     }
+
+    /// Return a copy of the events recorded since the allocator was created.
+    pub fn recording(&self) -> Recording {
+        Recording {
+            events: self.recorder.events.clone(),
+            options: self.recorder.options,
+            initial_size: self.recorder.initial_size,
+            initial_allocations: self.recorder.initial_allocations.clone(),
+        }
+    }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Debug)]
 pub enum Event {
     Allocate(Size, Option<AllocId>),
@@ -171,6 +214,7 @@ pub enum Event {
 pub struct Recorder {
     events: Vec<Event>,
     initial_size: Size,
+    initial_allocations: Vec<(AllocId, Rectangle)>,
     options: AllocatorOptions,
 }
 
@@ -181,83 +225,187 @@ impl Recorder {
 
     pub fn finish(&mut self) -> Recording {
         Recording {
-            events: std::mem::replace(&mut self.events, Vec::new()),
+            events: std::mem::take(&mut self.events),
             options: self.options,
             initial_size: self.initial_size,
+            initial_allocations: std::mem::take(&mut self.initial_allocations),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Statistics about the replay of a recording, see `Recording::replay_with_options`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ReplayStats {
-    allocations: u32,
-    deallocations: u32,
-    failed_allocations: u32,
+    pub allocations: u32,
+    pub deallocations: u32,
+    pub failed_allocations: u32,
+    /// Number of allocations that were lost when rearranging the atlas.
+    pub failed_rearrangements: u32,
+    /// The highest ratio of the atlas area that was allocated, between 0.0 and 1.0.
+    pub peak_occupancy: f32,
+    /// The fragmentation of the free space at the end of the recording, see
+    /// `AllocatorStats::fragmentation`.
+    pub final_fragmentation: f32,
 }
 
+/// The state of the atlas after an event of a recording, see `Recording::occupancy_over_time`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OccupancySample {
+    /// Index of the event in the recording.
+    pub event: usize,
+    /// Sum of the areas of the allocated rectangles, including their padding.
+    pub allocated_space: i64,
+    /// Number of free rectangles.
+    pub free_rect_count: usize,
+    /// Size of the free rectangle with the largest area.
+    pub largest_free_rect: Size,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone)]
 pub struct Recording {
     initial_size: Size,
+    // The allocations the recording starts from, with their recorded ids. This is only
+    // used by the recordings of `RecordingAllocator::with_event_limit`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    initial_allocations: Vec<(AllocId, Rectangle)>,
     events: Vec<Event>,
     options: AllocatorOptions,
 }
 
 impl Recording {
+    /// Replay the recording with the options it was recorded with, catching panics.
     pub fn replay(&self) -> std::thread::Result<ReplayStats> {
-        //println!("--------------- replay");
-        std::panic::catch_unwind(|| {
-            let mut stats = ReplayStats {
-                allocations: 0,
-                deallocations: 0,
-                failed_allocations: 0,
-            };
+        std::panic::catch_unwind(|| self.replay_with_options(&self.options))
+    }
 
-            let mut allocator = AtlasAllocator::with_options(self.initial_size, &self.options);
-            let mut id_remap: HashMap<AllocId, Option<AllocId>> = HashMap::default();
-            for evt in &self.events {
-                match *evt {
-                    Event::Allocate(size, recorded_id) => {
-                        let alloc = allocator.allocate(size);
-
-                        match alloc {
-                            Some(_) => {
-                                stats.allocations += 1;
-                            }
-                            None => {
-                                stats.failed_allocations += 1;
-                            }
-                        }
+    /// Replay the recorded requests with other options.
+    ///
+    /// This makes it possible to compare the options on a real workload. The allocations
+    /// that succeeded in the recording may fail with other options; the deallocation events
+    /// of the failed allocations are then skipped.
+    pub fn replay_with_options(&self, options: &AllocatorOptions) -> ReplayStats {
+        self.replay_impl(options, &mut |_, _| {})
+    }
 
-                        if let Some(recorded_id) = recorded_id {
-                            id_remap.insert(recorded_id, alloc.map(|alloc| alloc.id));
-                        }
+    /// Replay the recording with its options and return the state of the atlas after each
+    /// event.
+    ///
+    /// This makes it possible to plot the fragmentation over a session, for example to decide
+    /// when to rearrange the atlas.
+    pub fn occupancy_over_time(&self) -> Vec<OccupancySample> {
+        let mut samples = Vec::with_capacity(self.events.len());
+        self.for_each_occupancy_sample(&self.options, |sample| samples.push(*sample));
 
-                        //println!("+ alloc {:?} ({:?})", recorded_id, alloc.map(|alloc| alloc.id));
-                    }
-                    Event::Deallocate(recorded_id) => {
-                        if let Some(Some(id)) = id_remap.remove(&recorded_id) {
-                            //println!("- dealloc {:?} ({:?})", recorded_id, id);
-                            allocator.deallocate(id);
-                            stats.deallocations += 1;
+        samples
+    }
+
+    /// Replay the recording with the provided options and invoke a callback with the state
+    /// of the atlas after each event.
+    ///
+    /// Each sample walks the entire tree, so this is much slower than `replay_with_options`.
+    pub fn for_each_occupancy_sample<F>(&self, options: &AllocatorOptions, mut callback: F) -> ReplayStats
+    where
+        F: FnMut(&OccupancySample),
+    {
+        self.replay_impl(options, &mut |event, allocator| {
+            let stats = allocator.stats();
+            callback(&OccupancySample {
+                event,
+                allocated_space: allocator.allocated_space(),
+                free_rect_count: stats.free_rect_count,
+                largest_free_rect: stats.largest_free_rect,
+            });
+        })
+    }
+
+    fn replay_impl(
+        &self,
+        options: &AllocatorOptions,
+        on_event: &mut dyn FnMut(usize, &AtlasAllocator),
+    ) -> ReplayStats {
+        let mut stats = ReplayStats {
+            allocations: 0,
+            deallocations: 0,
+            failed_allocations: 0,
+            failed_rearrangements: 0,
+            peak_occupancy: 0.0,
+            final_fragmentation: 0.0,
+        };
+
+        let (mut allocator, mut id_remap) = self.initial_state(options);
+        for (event_index, evt) in self.events.iter().enumerate() {
+            match *evt {
+                Event::Allocate(size, recorded_id) => {
+                    let alloc = allocator.allocate(size);
+
+                    match alloc {
+                        Some(_) => {
+                            stats.allocations += 1;
+                        }
+                        None => {
+                            stats.failed_allocations += 1;
                         }
                     }
-                    Event::Grow(size) => {
-                        allocator.grow(size);
-                    }
-                    Event::Rearrange(ref recorded_changes) => {
-                        //println!(" *** rearrange");
-                        let changes = allocator.rearrange();
-                        Recording::apply_changelists(&mut id_remap, &recorded_changes, &changes);
+
+                    if let Some(recorded_id) = recorded_id {
+                        id_remap.insert(recorded_id, alloc.map(|alloc| alloc.id));
                     }
-                    Event::ResizeAndRearrange(new_size, ref recorded_changes) => {
-                        let changes = allocator.resize_and_rearrange(new_size);
-                        Recording::apply_changelists(&mut id_remap, recorded_changes, &changes);
+                }
+                Event::Deallocate(recorded_id) => {
+                    if let Some(Some(id)) = id_remap.remove(&recorded_id) {
+                        allocator.deallocate(id);
+                        stats.deallocations += 1;
                     }
                 }
+                Event::Grow(size) => {
+                    allocator.grow(size);
+                }
+                Event::Rearrange(ref recorded_changes) => {
+                    let changes = allocator.rearrange();
+                    stats.failed_rearrangements += changes.failures.len() as u32;
+                    Recording::apply_changelists(&mut id_remap, recorded_changes, &changes);
+                }
+                Event::ResizeAndRearrange(new_size, ref recorded_changes) => {
+                    let changes = allocator.resize_and_rearrange(new_size);
+                    stats.failed_rearrangements += changes.failures.len() as u32;
+                    Recording::apply_changelists(&mut id_remap, recorded_changes, &changes);
+                }
             }
 
-            stats
-        })
+            stats.peak_occupancy = stats.peak_occupancy.max(allocator.occupancy());
+            on_event(event_index, &allocator);
+        }
+
+        stats.final_fragmentation = allocator.stats().fragmentation;
+
+        stats
+    }
+
+    // Create the atlas the recording starts from, along with the ids of its allocations.
+    fn initial_state(&self, options: &AllocatorOptions) -> (AtlasAllocator, HashMap<AllocId, Option<AllocId>>) {
+        if self.initial_allocations.is_empty() {
+            return (AtlasAllocator::with_options(self.initial_size, options), HashMap::default());
+        }
+
+        let allocations: Vec<(AllocId, Rectangle)> = self
+            .initial_allocations
+            .iter()
+            .enumerate()
+            .map(|(i, (_, rect))| (AllocId::new(AllocIndex(i as u32), 0), *rect))
+            .collect();
+        let allocator = AtlasAllocator::from_allocations(self.initial_size, &allocations, options)
+            .expect("Invalid initial allocations");
+        let id_remap = self
+            .initial_allocations
+            .iter()
+            .zip(&allocations)
+            .map(|((recorded_id, _), (id, _))| (*recorded_id, Some(*id)))
+            .collect();
+
+        (allocator, id_remap)
     }
 
     fn apply_changelists(
@@ -291,6 +439,100 @@ impl Recording {
         }
     }
 
+    /// Write a self-contained HTML page showing the atlas after each event of the recording,
+    /// with a slider to scrub through the events.
+    ///
+    /// The recording is replayed to compute the rectangles, and the page replays it again in
+    /// the browser up to the selected event. The allocation or deallocation of the selected
+    /// event is highlighted.
+    pub fn write_html_timeline(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let rect_json = |id: AllocId, rect: &Rectangle| {
+            format!("{},{},{},{},{}", id.serialize(), rect.min.x, rect.min.y, rect.width(), rect.height())
+        };
+        let snapshot = |allocator: &AtlasAllocator| {
+            let mut rects = Vec::new();
+            allocator.for_each_allocated_rectangle(|id, rect| rects.push(format!("[{}]", rect_json(id, rect))));
+            let size = allocator.size();
+            format!("[\"r\",{},{},[{}]]", size.width, size.height, rects.join(","))
+        };
+
+        let (mut allocator, mut id_remap) = self.initial_state(&self.options);
+        let mut initial_rects = Vec::new();
+        allocator.for_each_allocated_rectangle(|id, rect| initial_rects.push(format!("[{}]", rect_json(id, rect))));
+
+        // One operation per event: ["a", id, x, y, w, h] for allocations, ["f"] for failed
+        // allocations, ["d", id] for deallocations, ["r", w, h, [[id, x, y, w, h], ...]] to
+        // reset the whole atlas after it is resized or rearranged, and ["n"] for events that
+        // didn't change anything.
+        let mut ops = Vec::with_capacity(self.events.len());
+        for event in &self.events {
+            let op = match *event {
+                Event::Allocate(size, recorded_id) => {
+                    let alloc = allocator.allocate(size);
+                    if let Some(recorded_id) = recorded_id {
+                        id_remap.insert(recorded_id, alloc.map(|alloc| alloc.id));
+                    }
+
+                    match alloc {
+                        Some(alloc) => format!("[\"a\",{}]", rect_json(alloc.id, &alloc.rectangle)),
+                        None => "[\"f\"]".to_string(),
+                    }
+                }
+                Event::Deallocate(recorded_id) => match id_remap.remove(&recorded_id) {
+                    Some(Some(id)) => {
+                        allocator.deallocate(id);
+                        format!("[\"d\",{}]", id.serialize())
+                    }
+                    _ => "[\"n\"]".to_string(),
+                },
+                Event::Grow(size) => {
+                    allocator.grow(size);
+                    snapshot(&allocator)
+                }
+                Event::Rearrange(ref recorded_changes) => {
+                    let changes = allocator.rearrange();
+                    Recording::apply_changelists(&mut id_remap, recorded_changes, &changes);
+                    snapshot(&allocator)
+                }
+                Event::ResizeAndRearrange(new_size, ref recorded_changes) => {
+                    let changes = allocator.resize_and_rearrange(new_size);
+                    Recording::apply_changelists(&mut id_remap, recorded_changes, &changes);
+                    snapshot(&allocator)
+                }
+            };
+            ops.push(op);
+        }
+
+        writeln!(output, "<!DOCTYPE html>")?;
+        writeln!(output, "<html>")?;
+        writeln!(output, "<head><meta charset=\"utf-8\"><title>Atlas timeline</title></head>")?;
+        writeln!(output, "<body style=\"font-family:monospace\">")?;
+        writeln!(
+            output,
+            "<input id=\"slider\" type=\"range\" min=\"0\" max=\"{}\" value=\"{}\" style=\"width:100%\">",
+            ops.len(),
+            ops.len(),
+        )?;
+        writeln!(output, "<p id=\"label\"></p>")?;
+        writeln!(output, "<svg id=\"atlas\" xmlns=\"http://www.w3.org/2000/svg\"></svg>")?;
+        writeln!(output, "<script>")?;
+        writeln!(
+            output,
+            "const initialSize = [{}, {}];",
+            self.initial_size.width, self.initial_size.height
+        )?;
+        writeln!(output, "const initialRects = [{}];", initial_rects.join(","))?;
+        writeln!(output, "const ops = [")?;
+        for op in &ops {
+            writeln!(output, "{},", op)?;
+        }
+        writeln!(output, "];")?;
+        output.write_all(TIMELINE_SCRIPT.as_bytes())?;
+        writeln!(output, "</script>")?;
+        writeln!(output, "</body>")?;
+        writeln!(output, "</html>")
+    }
+
     pub fn remove_event(&mut self, index: usize) {
         //println!("remove {:?}", self.events[index]);
         self.events.remove(index);
@@ -309,7 +551,7 @@ impl Recording {
             let mut reduced = recording.clone();
             reduced.events.remove(i);
 
-            if !reduced.replay().is_ok() {
+            if reduced.replay().is_err() {
                 recording = reduced;
             } else {
                 i += 1;
@@ -321,7 +563,11 @@ impl Recording {
         writeln!(output, "#[test]")?;
         writeln!(output, "fn reduced_testcase() {{")?;
         writeln!(output, "    let options = AllocatorOptions {{")?;
-        writeln!(output, "         snap_size: {},", self.options.snap_size)?;
+        writeln!(
+            output,
+            "         alignment: size2({}, {}),",
+            self.options.alignment.width, self.options.alignment.height
+        )?;
         writeln!(
             output,
             "         small_size_threshold: {},",
@@ -332,21 +578,45 @@ impl Recording {
             "         large_size_threshold: {},",
             self.options.large_size_threshold
         )?;
+        writeln!(output, "         ..DEFAULT_OPTIONS")?;
         writeln!(output, "    }};")?;
         writeln!(
             output,
             "    let size = size2({}, {});",
             self.initial_size.width, self.initial_size.height
         )?;
-        writeln!(
-            output,
-            "    let mut allocator = AtlasAllocator::with_options(size, options);"
-        )?;
-        let mut next_identifier = self.events.len() as u32;
+        if self.initial_allocations.is_empty() {
+            writeln!(
+                output,
+                "    let mut allocator = AtlasAllocator::with_options(size, &options);"
+            )?;
+        } else {
+            writeln!(output, "    let initial_allocations = [")?;
+            for (i, (_, rect)) in self.initial_allocations.iter().enumerate() {
+                writeln!(
+                    output,
+                    "        (AllocId::deserialize({}), Rectangle {{ min: point2({}, {}), max: point2({}, {}) }}),",
+                    i, rect.min.x, rect.min.y, rect.max.x, rect.max.y
+                )?;
+            }
+            writeln!(output, "    ];")?;
+            writeln!(
+                output,
+                "    let mut allocator = AtlasAllocator::from_allocations(size, &initial_allocations, &options).unwrap();"
+            )?;
+            for (i, (id, _)) in self.initial_allocations.iter().enumerate() {
+                writeln!(
+                    output,
+                    "    let r{} = Some(Allocation {{ id: initial_allocations[{}].0, rectangle: initial_allocations[{}].1, content: initial_allocations[{}].1, rotated: false }});",
+                    id.serialize(), i, i, i
+                )?;
+            }
+        }
+        let mut next_identifier = self.events.len() as AllocIdRepr;
         for event in &self.events {
             match *event {
                 Event::Allocate(size, res) => {
-                    let identifier = res.map(|id| id.to_u32()).unwrap_or_else(|| {
+                    let identifier = res.map(|id| id.serialize()).unwrap_or_else(|| {
                         next_identifier += 1;
                         next_identifier
                     });
@@ -360,7 +630,7 @@ impl Recording {
                     writeln!(
                         output,
                         "    allocator.deallocate(r{}.unwrap().id);",
-                        id.to_u32()
+                        id.serialize()
                     )?;
                 }
                 Event::Grow(size) => {
@@ -387,9 +657,261 @@ impl Recording {
         Ok(())
     }
 
+    /// Record the events of the fuzz target, see `FuzzEvent`.
+    ///
+    /// The fuzz target uses an atlas of 1000x1000 pixels with the default options.
+    pub fn from_fuzz_events(size: Size, options: &AllocatorOptions, events: &[FuzzEvent]) -> Recording {
+        let mut atlas = RecordingAllocator::with_options(size, options);
+        let mut allocations = Vec::new();
+        for event in events {
+            match *event {
+                FuzzEvent::Allocate(w, h) => {
+                    if let Some(alloc) = atlas.allocate(size2(w, h)) {
+                        allocations.push(alloc.id);
+                    }
+                }
+                FuzzEvent::Deallocate(idx) => {
+                    if idx < allocations.len() {
+                        atlas.deallocate(allocations.swap_remove(idx));
+                    }
+                }
+            }
+        }
+
+        atlas.recording()
+    }
+
+    /// Convert the recording into events of the fuzz target, for example to seed the fuzzer
+    /// with a real workload.
+    ///
+    /// The fuzz target can't grow or rearrange the atlas, so these events are dropped and
+    /// the allocations may fail or be placed differently when the fuzz target runs them.
+    pub fn to_fuzz_events(&self) -> Vec<FuzzEvent> {
+        let mut events = Vec::with_capacity(self.events.len());
+        let mut allocations = Vec::new();
+        for event in &self.events {
+            match *event {
+                Event::Allocate(size, recorded_id) => {
+                    events.push(FuzzEvent::Allocate(size.width, size.height));
+                    if let Some(id) = recorded_id {
+                        allocations.push(id);
+                    }
+                }
+                Event::Deallocate(id) => {
+                    if let Some(idx) = allocations.iter().position(|alloc| *alloc == id) {
+                        allocations.swap_remove(idx);
+                        events.push(FuzzEvent::Deallocate(idx));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    /// Save the recording to a file, see `Recording::write`.
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut file)?;
+
+        std::io::Write::flush(&mut file)
+    }
+
+    /// Load a recording from a file, see `Recording::read`.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Recording> {
+        Recording::read(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Write the recording in a simple text format, with one line per option, per initial
+    /// allocation and per event.
+    ///
+    /// This doesn't require the `serialization` feature, and the output can be attached to a
+    /// bug report, loaded with `Recording::read`, replayed and reduced. Only the ids of the
+    /// change lists of rearrange events are saved since replaying doesn't need their
+    /// rectangles.
+    pub fn write(&self, output: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let options = &self.options;
+        writeln!(output, "# guillotiere recording")?;
+        writeln!(output, "size {} {}", self.initial_size.width, self.initial_size.height)?;
+        writeln!(output, "alignment {} {}", options.alignment.width, options.alignment.height)?;
+        writeln!(output, "small_size_threshold {}", options.small_size_threshold)?;
+        writeln!(output, "large_size_threshold {}", options.large_size_threshold)?;
+        writeln!(output, "padding {}", options.padding)?;
+        writeln!(output, "allow_rotation {}", options.allow_rotation)?;
+        writeln!(output, "fit_policy {:?}", options.fit_policy)?;
+        writeln!(output, "split_policy {:?}", options.split_policy)?;
+        writeln!(output, "deferred_coalescing {}", options.deferred_coalescing)?;
+        writeln!(output, "min_free_size {}", options.min_free_size)?;
+        writeln!(output, "next_fit {}", options.next_fit)?;
+        writeln!(output, "placement_bias {:?}", options.placement_bias)?;
+        writeln!(output, "power_of_two_sizes {}", options.power_of_two_sizes)?;
+
+        let write_changes = |output: &mut dyn std::io::Write, changes: &ChangeList| -> std::io::Result<()> {
+            for change in &changes.changes {
+                write!(output, " {}:{}", change.old.id.serialize(), change.new.id.serialize())?;
+            }
+            for failure in &changes.failures {
+                write!(output, " !{}", failure.id.serialize())?;
+            }
+            writeln!(output)
+        };
+
+        for (id, rect) in &self.initial_allocations {
+            writeln!(
+                output,
+                "initial_allocation {} {} {} {} {}",
+                id.serialize(),
+                rect.min.x,
+                rect.min.y,
+                rect.width(),
+                rect.height()
+            )?;
+        }
+
+        for event in &self.events {
+            match event {
+                Event::Allocate(size, Some(id)) => {
+                    writeln!(output, "allocate {} {} {}", size.width, size.height, id.serialize())?
+                }
+                Event::Allocate(size, None) => writeln!(output, "allocate {} {} none", size.width, size.height)?,
+                Event::Deallocate(id) => writeln!(output, "deallocate {}", id.serialize())?,
+                Event::Grow(size) => writeln!(output, "grow {} {}", size.width, size.height)?,
+                Event::Rearrange(changes) => {
+                    write!(output, "rearrange")?;
+                    write_changes(output, changes)?;
+                }
+                Event::ResizeAndRearrange(size, changes) => {
+                    write!(output, "resize_and_rearrange {} {}", size.width, size.height)?;
+                    write_changes(output, changes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a recording written by `Recording::write`.
+    pub fn read(input: &mut dyn std::io::BufRead) -> std::io::Result<Recording> {
+        let mut recording = Recording {
+            initial_size: size2(0, 0),
+            initial_allocations: Vec::new(),
+            events: Vec::new(),
+            options: DEFAULT_OPTIONS,
+        };
+
+        for (line_index, line) in std::io::BufRead::lines(input).enumerate() {
+            let line = line?;
+            let invalid = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid recording at line {}: {:?}", line_index + 1, line),
+                )
+            };
+
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+            let args: Vec<&str> = words.collect();
+            let int = |i: usize| -> std::io::Result<i32> {
+                args.get(i).and_then(|arg| arg.parse().ok()).ok_or_else(invalid)
+            };
+            let flag = || -> std::io::Result<bool> {
+                args.first().and_then(|arg| arg.parse().ok()).ok_or_else(invalid)
+            };
+            let id = |arg: &str| -> std::io::Result<AllocId> {
+                arg.parse().map(AllocId::deserialize).map_err(|_| invalid())
+            };
+            let changes = |args: &[&str]| -> std::io::Result<ChangeList> {
+                let alloc = |id| Allocation {
+                    id,
+                    rectangle: Rectangle::zero(),
+                    content: Rectangle::zero(),
+                    rotated: false,
+                };
+                let mut changes = ChangeList::empty();
+                for arg in args {
+                    if let Some(failure) = arg.strip_prefix('!') {
+                        changes.failures.push(alloc(id(failure)?));
+                    } else {
+                        let (old, new) = arg.split_once(':').ok_or_else(invalid)?;
+                        changes.changes.push(Change {
+                            old: alloc(id(old)?),
+                            new: alloc(id(new)?),
+                        });
+                    }
+                }
+                Ok(changes)
+            };
+
+            let options = &mut recording.options;
+            match keyword {
+                "size" => recording.initial_size = size2(int(0)?, int(1)?),
+                "alignment" => options.alignment = size2(int(0)?, int(1)?),
+                "small_size_threshold" => options.small_size_threshold = int(0)?,
+                "large_size_threshold" => options.large_size_threshold = int(0)?,
+                "padding" => options.padding = int(0)?,
+                "allow_rotation" => options.allow_rotation = flag()?,
+                "fit_policy" => options.fit_policy = parse_variant(&args, &[
+                    FitPolicy::Adaptive,
+                    FitPolicy::WorstFit,
+                    FitPolicy::BestAreaFit,
+                    FitPolicy::BestShortSideFit,
+                    FitPolicy::FirstFit,
+                ]).ok_or_else(invalid)?,
+                "split_policy" => options.split_policy = parse_variant(&args, &[
+                    SplitPolicy::ShorterLeftoverAxis,
+                    SplitPolicy::LongerLeftoverAxis,
+                    SplitPolicy::MinimizeArea,
+                    SplitPolicy::MaximizeArea,
+                ]).ok_or_else(invalid)?,
+                "deferred_coalescing" => options.deferred_coalescing = flag()?,
+                "min_free_size" => options.min_free_size = int(0)?,
+                "next_fit" => options.next_fit = flag()?,
+                "placement_bias" => options.placement_bias = parse_variant(&args, &[
+                    PlacementBias::None,
+                    PlacementBias::TopLeft,
+                    PlacementBias::Top,
+                    PlacementBias::Left,
+                ]).ok_or_else(invalid)?,
+                "power_of_two_sizes" => options.power_of_two_sizes = flag()?,
+                "initial_allocation" => {
+                    let recorded_id = id(args.first().ok_or_else(invalid)?)?;
+                    let rect = Rectangle {
+                        min: point2(int(1)?, int(2)?),
+                        max: point2(int(1)? + int(3)?, int(2)? + int(4)?),
+                    };
+                    recording.initial_allocations.push((recorded_id, rect));
+                }
+                "allocate" => {
+                    let recorded_id = match args.get(2) {
+                        Some(&"none") => None,
+                        Some(arg) => Some(id(arg)?),
+                        None => return Err(invalid()),
+                    };
+                    recording.events.push(Event::Allocate(size2(int(0)?, int(1)?), recorded_id));
+                }
+                "deallocate" => recording.events.push(Event::Deallocate(id(args.first().ok_or_else(invalid)?)?)),
+                "grow" => recording.events.push(Event::Grow(size2(int(0)?, int(1)?))),
+                "rearrange" => recording.events.push(Event::Rearrange(changes(&args)?)),
+                "resize_and_rearrange" => {
+                    let size = size2(int(0)?, int(1)?);
+                    recording.events.push(Event::ResizeAndRearrange(size, changes(&args[2..])?));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(recording)
+    }
+
     fn remap_ids(&mut self) {
-        let mut allocator = AtlasAllocator::with_options(self.initial_size, &self.options);
-        let mut id_remap: HashMap<AllocId, Option<AllocId>> = HashMap::default();
+        let (mut allocator, mut id_remap) = self.initial_state(&self.options);
+        for (recorded_id, _) in &mut self.initial_allocations {
+            *recorded_id = id_remap[recorded_id].unwrap();
+        }
         let mut idx = 0;
         while idx < self.events.len() {
             match self.events[idx] {
@@ -436,12 +958,132 @@ impl Recording {
     }
 }
 
+/// An event of the `fuzz_events` fuzz target.
+///
+/// The fuzz target decodes its input with `FuzzEvent::decode`, so that crashes found by
+/// fuzzing can be converted into recordings with `Recording::from_fuzz_events`, reduced and
+/// turned into tests, and that recordings can be converted into corpus entries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FuzzEvent {
+    /// Allocate a rectangle of the provided size.
+    Allocate(i32, i32),
+    /// Deallocate the n-th live allocation. The last allocation takes the place of the
+    /// deallocated one, and indices that are out of bounds are ignored.
+    Deallocate(usize),
+}
+
+impl FuzzEvent {
+    /// Encode the events into bytes that `decode` reads back.
+    ///
+    /// Allocations take 5 bytes: a zero followed by the width and height as little endian
+    /// 16 bits integers. Deallocations take 5 bytes: a one followed by the index as a little
+    /// endian 32 bits integer. Sizes and indices that don't fit are clamped.
+    pub fn encode(events: &[FuzzEvent]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(events.len() * 5);
+        for event in events {
+            match *event {
+                FuzzEvent::Allocate(w, h) => {
+                    bytes.push(0);
+                    bytes.extend_from_slice(&(w.clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+                    bytes.extend_from_slice(&(h.clamp(0, u16::MAX as i32) as u16).to_le_bytes());
+                }
+                FuzzEvent::Deallocate(idx) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&(idx.min(u32::MAX as usize) as u32).to_le_bytes());
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode events from arbitrary bytes, for example a fuzzer input.
+    ///
+    /// Only the lowest bit of the first byte of each event is used, and an incomplete event
+    /// at the end is ignored.
+    pub fn decode(bytes: &[u8]) -> Vec<FuzzEvent> {
+        bytes
+            .chunks_exact(5)
+            .map(|chunk| {
+                if chunk[0] & 1 == 0 {
+                    FuzzEvent::Allocate(
+                        u16::from_le_bytes([chunk[1], chunk[2]]) as i32,
+                        u16::from_le_bytes([chunk[3], chunk[4]]) as i32,
+                    )
+                } else {
+                    FuzzEvent::Deallocate(u32::from_le_bytes([chunk[1], chunk[2], chunk[3], chunk[4]]) as usize)
+                }
+            })
+            .collect()
+    }
+}
+
+// Find the variant whose name is the first argument.
+fn parse_variant<T: Copy + std::fmt::Debug>(args: &[&str], variants: &[T]) -> Option<T> {
+    let name = args.first()?;
+    variants.iter().find(|variant| format!("{:?}", variant) == *name).copied()
+}
+
+// Replays the operations written by `Recording::write_html_timeline` up to the event selected
+// with the slider and draws the atlas.
+const TIMELINE_SCRIPT: &str = r#"
+const slider = document.getElementById("slider");
+const label = document.getElementById("label");
+const svg = document.getElementById("atlas");
+
+function render() {
+    const count = Number(slider.value);
+    let size = initialSize;
+    let rects = new Map(initialRects.map(rect => [rect[0], rect.slice(1)]));
+    let failures = 0;
+    let removed = null;
+    for (let i = 0; i < count; i++) {
+        const op = ops[i];
+        removed = null;
+        if (op[0] === "a") {
+            rects.set(op[1], op.slice(2));
+        } else if (op[0] === "f") {
+            failures += 1;
+        } else if (op[0] === "d") {
+            removed = rects.get(op[1]);
+            rects.delete(op[1]);
+        } else if (op[0] === "r") {
+            size = [op[1], op[2]];
+            rects = new Map(op[3].map(rect => [rect[0], rect.slice(1)]));
+        }
+    }
+
+    const last = count > 0 ? ops[count - 1] : ["n"];
+    const rect = (r, fill) =>
+        `<rect x="${r[0]}" y="${r[1]}" width="${r[2]}" height="${r[3]}" style="fill:${fill};stroke:black"/>`;
+    let content = rect([0, 0, size[0], size[1]], "rgb(50,50,50)");
+    for (const [id, r] of rects) {
+        const selected = last[0] === "a" && last[1] === id;
+        content += rect(r, selected ? "rgb(200,150,40)" : "rgb(70,70,180)");
+    }
+    if (removed) {
+        content += rect(removed, "rgb(180,60,60)");
+    }
+
+    svg.setAttribute("width", size[0]);
+    svg.setAttribute("height", size[1]);
+    svg.innerHTML = content;
+
+    const names = { a: "allocate", f: "failed allocation", d: "deallocate", r: "resize/rearrange", n: "-" };
+    label.textContent = `event ${count}/${ops.length} (${names[last[0]]}): ` +
+        `${rects.size} allocations, ${failures} failed allocations`;
+}
+
+slider.addEventListener("input", render);
+render();
+"#;
+
 #[test]
 fn recording_random_test() {
     let mut atlas = RecordingAllocator::with_options(
         size2(1000, 1000),
         &AllocatorOptions {
-            snap_size: 5,
+            alignment: size2(5, 5),
             ..DEFAULT_OPTIONS
         },
     );
@@ -508,3 +1150,211 @@ fn recording_random_test() {
 
     recording.replay().unwrap();
 }
+
+#[test]
+fn html_timeline() {
+    let mut atlas = RecordingAllocator::new(size2(100, 100));
+    let a = atlas.allocate(size2(20, 20)).unwrap();
+    atlas.allocate(size2(30, 30)).unwrap();
+    assert!(atlas.allocate(size2(200, 200)).is_none());
+    atlas.deallocate(a.id);
+    atlas.grow(size2(200, 100));
+
+    let mut html = Vec::new();
+    atlas.recording().write_html_timeline(&mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains(r#"max="5""#));
+    assert!(html.contains("const initialSize = [100, 100];"));
+    assert_eq!(html.matches("\n[\"a\",").count(), 2);
+    assert_eq!(html.matches("\n[\"f\"]").count(), 1);
+    assert_eq!(html.matches("\n[\"d\",").count(), 1);
+    assert!(html.contains("\n[\"r\",200,100,[["));
+}
+
+#[test]
+fn save_and_load() {
+    let options = AllocatorOptions {
+        alignment: size2(4, 2),
+        fit_policy: FitPolicy::BestAreaFit,
+        placement_bias: PlacementBias::TopLeft,
+        ..DEFAULT_OPTIONS
+    };
+    let mut atlas = RecordingAllocator::with_options(size2(200, 200), &options);
+    let a = atlas.allocate(size2(20, 20)).unwrap();
+    atlas.allocate(size2(30, 30)).unwrap();
+    assert!(atlas.allocate(size2(300, 300)).is_none());
+    atlas.deallocate(a.id);
+    atlas.rearrange();
+    atlas.resize_and_rearrange(size2(300, 200));
+    atlas.grow(size2(400, 200));
+    let recording = atlas.recording();
+
+    let mut text = Vec::new();
+    recording.write(&mut text).unwrap();
+    let loaded = Recording::read(&mut &text[..]).unwrap();
+
+    assert_eq!(loaded.options, options);
+    assert_eq!(loaded.initial_size, size2(200, 200));
+    assert_eq!(loaded.events.len(), recording.events.len());
+    assert_eq!(loaded.replay().unwrap(), recording.replay().unwrap());
+
+    let mut text2 = Vec::new();
+    loaded.write(&mut text2).unwrap();
+    assert_eq!(text, text2);
+
+    let path = std::env::temp_dir().join(format!("guillotiere-recording-{}.txt", std::process::id()));
+    recording.save(&path).unwrap();
+    let loaded = Recording::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.events.len(), recording.events.len());
+
+    assert!(Recording::read(&mut &b"allocate 10 x 3"[..]).is_err());
+}
+
+#[test]
+fn replay_with_options() {
+    let mut atlas = RecordingAllocator::new(size2(100, 100));
+    let mut ids = Vec::new();
+    for _ in 0..10 {
+        ids.push(atlas.allocate(size2(25, 25)).unwrap().id);
+    }
+    for id in ids.drain(..5) {
+        atlas.deallocate(id);
+    }
+    let recording = atlas.recording();
+
+    let stats = recording.replay().unwrap();
+    assert_eq!((stats.allocations, stats.deallocations, stats.failed_allocations), (10, 5, 0));
+    assert_eq!(stats.peak_occupancy, 0.625);
+    assert!(stats.final_fragmentation > 0.0);
+
+    // With a larger alignment, only 9 rectangles fit and the deallocation of the failed
+    // allocation, if any, is skipped.
+    let options = AllocatorOptions {
+        alignment: size2(32, 32),
+        ..DEFAULT_OPTIONS
+    };
+    let stats = recording.replay_with_options(&options);
+    assert_eq!(stats.failed_allocations, 1);
+    assert_eq!(stats.allocations + stats.failed_allocations, 10);
+    assert!(stats.deallocations <= 5);
+}
+
+#[test]
+fn fuzz_events() {
+    let events = vec![
+        FuzzEvent::Allocate(100, 200),
+        FuzzEvent::Allocate(300, 50),
+        FuzzEvent::Allocate(2000, 10),
+        FuzzEvent::Allocate(10, 10),
+        FuzzEvent::Deallocate(0),
+        FuzzEvent::Deallocate(5),
+        FuzzEvent::Allocate(20, 20),
+        FuzzEvent::Deallocate(1),
+    ];
+
+    let bytes = FuzzEvent::encode(&events);
+    assert_eq!(bytes.len(), events.len() * 5);
+    assert_eq!(FuzzEvent::decode(&bytes), events);
+    // Incomplete events are ignored.
+    assert_eq!(FuzzEvent::decode(&bytes[..7]), &events[..1]);
+
+    let recording = Recording::from_fuzz_events(size2(1000, 1000), &DEFAULT_OPTIONS, &events);
+    let stats = recording.replay().unwrap();
+    assert_eq!((stats.allocations, stats.failed_allocations, stats.deallocations), (4, 1, 2));
+
+    // The deallocations that were ignored are dropped.
+    let mut expected = events.clone();
+    expected.remove(5);
+    assert_eq!(recording.to_fuzz_events(), expected);
+}
+
+#[test]
+fn occupancy_over_time() {
+    let mut atlas = RecordingAllocator::new(size2(100, 100));
+    let a = atlas.allocate(size2(50, 100)).unwrap();
+    let b = atlas.allocate(size2(50, 50)).unwrap();
+    atlas.deallocate(a.id);
+    atlas.deallocate(b.id);
+
+    let samples = atlas.recording().occupancy_over_time();
+    let summary: Vec<_> = samples
+        .iter()
+        .map(|sample| (sample.event, sample.allocated_space, sample.free_rect_count, sample.largest_free_rect))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (0, 5000, 1, size2(50, 100)),
+            (1, 7500, 1, size2(50, 50)),
+            (2, 2500, 2, size2(50, 100)),
+            (3, 0, 1, size2(100, 100)),
+        ]
+    );
+}
+
+#[test]
+fn event_limit() {
+    let mut atlas = RecordingAllocator::with_event_limit(size2(1000, 1000), &DEFAULT_OPTIONS, 10);
+    let mut full = RecordingAllocator::new(size2(1000, 1000));
+
+    let mut allocations = Vec::new();
+    for i in 0..95 {
+        if i % 3 == 2 {
+            let (id, full_id) = allocations.swap_remove(i as usize % allocations.len());
+            atlas.deallocate(id);
+            full.deallocate(full_id);
+        } else {
+            let size = size2(10 + i % 17, 20 + i % 13);
+            allocations.push((atlas.allocate(size).unwrap().id, full.allocate(size).unwrap().id));
+        }
+        if i == 50 {
+            atlas.rearrange();
+            full.rearrange();
+        }
+    }
+
+    // Only the events since the snapshot of the 80th event are kept.
+    let recording = atlas.recording();
+    assert_eq!(recording.events.len(), 16);
+    assert!(!recording.initial_allocations.is_empty());
+
+    // Replaying the tail from the snapshot ends with the same allocations as replaying
+    // everything. The free space is subdivided differently.
+    let stats = recording.replay().unwrap();
+    assert_eq!(stats.failed_allocations, 0);
+    let occupancy = |recording: &Recording| recording.occupancy_over_time().last().unwrap().allocated_space;
+    assert_eq!(occupancy(&recording), occupancy(&full.recording()));
+
+    let mut text = Vec::new();
+    recording.write(&mut text).unwrap();
+    let loaded = Recording::read(&mut &text[..]).unwrap();
+    assert_eq!(loaded.initial_allocations, recording.initial_allocations);
+    assert_eq!(loaded.replay().unwrap(), stats);
+
+    let mut testcase = Vec::new();
+    recording.find_reduced_testcase().write_testcase(&mut testcase).unwrap();
+}
+
+#[test]
+fn rearrange_many() {
+    let mut atlas = RecordingAllocator::new(size2(2048, 2048));
+    let mut ids = Vec::new();
+    for i in 0..2000 {
+        ids.push(atlas.allocate(size2(10 + i % 23, 10 + i % 19)).unwrap().id);
+    }
+    for id in ids.drain(..).step_by(2) {
+        atlas.deallocate(id);
+    }
+
+    let changes = atlas.resize_and_rearrange(size2(1024, 1024));
+    assert_eq!(changes.changes.len() + changes.failures.len(), 1000);
+    for change in &changes.changes {
+        assert_eq!(change.old.id, change.new.id);
+        atlas.deallocate(change.new.id);
+    }
+    assert!(atlas.allocator.is_empty());
+    assert!(atlas.reverse_id_map.is_empty());
+}
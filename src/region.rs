@@ -0,0 +1,222 @@
+use crate::allocator::*;
+use crate::{Point, Rectangle, Size};
+
+/// An allocator for a region of an `AtlasAllocator`, see `AtlasAllocator::split_off_region`.
+///
+/// The region allocator doesn't borrow the atlas, so it can be moved to another thread. Its
+/// allocations are only valid in the region allocator until it is reclaimed by the atlas.
+pub struct RegionAllocator {
+    // The allocation of the region in the parent atlas.
+    pub(crate) id: AllocId,
+    pub(crate) atlas: AtlasAllocator,
+    origin: Point,
+    horizontal_root: bool,
+}
+
+impl RegionAllocator {
+    pub(crate) fn new(
+        id: AllocId,
+        rectangle: Rectangle,
+        horizontal_root: bool,
+        options: &AllocatorOptions,
+    ) -> Self {
+        let mut region = RegionAllocator {
+            id,
+            atlas: AtlasAllocator::with_options(rectangle.size(), options),
+            origin: rectangle.min,
+            horizontal_root,
+        };
+        region.clear();
+
+        region
+    }
+
+    /// The region in the coordinate space of the parent atlas.
+    pub fn rectangle(&self) -> Rectangle {
+        Rectangle {
+            min: self.origin,
+            max: self.origin + self.atlas.size().to_vector(),
+        }
+    }
+
+    /// The size of the region.
+    pub fn size(&self) -> Size {
+        self.atlas.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atlas.is_empty()
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.atlas.allocation_count()
+    }
+
+    /// Allocate a rectangle in the region.
+    ///
+    /// The rectangles of the allocation are in the coordinate space of the parent atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        let alloc = self.atlas.allocate(requested_size)?;
+
+        Some(self.to_atlas_space(alloc))
+    }
+
+    /// Deallocate a rectangle.
+    pub fn deallocate(&mut self, id: AllocId) {
+        self.atlas.deallocate(id);
+    }
+
+    /// Returns true if the id refers to a live allocation of this region.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        self.atlas.is_valid(id)
+    }
+
+    /// Returns the rectangle of an allocation in the coordinate space of the parent atlas, or
+    /// `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<Rectangle> {
+        let rect = self.atlas.get(id)?;
+
+        Some(rect.translate(self.origin.to_vector()))
+    }
+
+    /// Invoke a callback for each allocated rectangle, in the coordinate space of the parent
+    /// atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        let offset = self.origin.to_vector();
+        self.atlas.for_each_allocated_rectangle(|id, rect| callback(id, &rect.translate(offset)));
+    }
+
+    /// Drop all rectangles of the region.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        if self.horizontal_root {
+            self.atlas.set_horizontal_root();
+        }
+    }
+
+    pub(crate) fn to_atlas_space(&self, mut alloc: Allocation) -> Allocation {
+        let offset = self.origin.to_vector();
+        alloc.rectangle = alloc.rectangle.translate(offset);
+        alloc.content = alloc.content.translate(offset);
+
+        alloc
+    }
+}
+
+#[test]
+fn region_allocator() {
+    use euclid::size2;
+
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let before = atlas.allocate(size2(100, 100)).unwrap();
+
+    let mut regions = vec![
+        atlas.split_off_region(size2(500, 500)).unwrap(),
+        atlas.split_off_region(size2(300, 300)).unwrap(),
+    ];
+    assert!(!regions[0].rectangle().intersects(&regions[1].rectangle()));
+
+    // Allocate in the regions from other threads.
+    std::thread::scope(|scope| {
+        for region in &mut regions {
+            scope.spawn(move || {
+                for i in 0..20 {
+                    let alloc = region.allocate(size2(10 + i, 20)).unwrap();
+                    assert!(region.rectangle().contains_box(&alloc.rectangle));
+                    assert_eq!(region.get(alloc.id), Some(alloc.rectangle));
+                }
+            });
+        }
+    });
+
+    let mut rects = Vec::new();
+    for region in regions {
+        region.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+        let changes = atlas.reclaim(region);
+        assert_eq!(changes.changes.len(), 20);
+        for change in &changes.changes {
+            assert_eq!(change.new.rectangle, change.old.rectangle);
+            assert_eq!(atlas[change.new.id], change.new.rectangle);
+        }
+    }
+
+    assert_eq!(atlas.allocation_count(), 41);
+    let mut reclaimed = Vec::new();
+    atlas.for_each_allocated_rectangle(|id, rect| {
+        if id != before.id {
+            reclaimed.push(*rect);
+        }
+    });
+    reclaimed.sort_by_key(|rect| (rect.min.x, rect.min.y));
+    rects.sort_by_key(|rect| (rect.min.x, rect.min.y));
+    assert_eq!(reclaimed, rects);
+    atlas.validate().unwrap();
+
+    // The grafted nodes merge back with the rest of the atlas.
+    let mut ids = Vec::new();
+    atlas.for_each_allocated_rectangle(|id, _| ids.push(id));
+    for id in ids {
+        atlas.deallocate(id);
+    }
+    assert!(atlas.allocate(size2(1000, 1000)).is_some());
+
+    // Empty regions and regions filled by a single allocation.
+    atlas.clear();
+    let empty = atlas.split_off_region(size2(200, 200)).unwrap();
+    assert!(atlas.reclaim(empty).changes.is_empty());
+    let mut full = atlas.split_off_region(size2(200, 200)).unwrap();
+    let alloc = full.allocate(size2(200, 200)).unwrap();
+    let changes = atlas.reclaim(full);
+    assert_eq!(changes.changes[0].new.rectangle, alloc.rectangle);
+    assert_eq!(atlas.allocation_count(), 1);
+}
+
+#[test]
+fn region_survives_parent_operations() {
+    use euclid::size2;
+
+    let mut atlas = AtlasAllocator::new(size2(1000, 1000));
+    let mut ids = Vec::new();
+    for _ in 0..10 {
+        ids.push(atlas.allocate(size2(100, 100)).unwrap().id);
+    }
+
+    let mut region = atlas.split_off_region(size2(300, 300)).unwrap();
+    let allocs: Vec<Allocation> = (0..5).map(|_| region.allocate(size2(50, 50)).unwrap()).collect();
+
+    // The region can't be deallocated, moved or dropped by the parent atlas.
+    assert_eq!(atlas.try_deallocate(region.id), Err(AllocError::StaleId));
+    for id in ids.iter().step_by(2) {
+        atlas.deallocate(*id);
+    }
+    atlas.rearrange();
+    atlas.rearrange_preserving_ids();
+    atlas.defragment_step(100);
+    assert!(atlas.resize_and_rearrange(size2(500, 500)).changes.is_empty());
+    assert_eq!(atlas.size(), size2(1000, 1000));
+    atlas.clear();
+    assert!(atlas.allocate(size2(1000, 1000)).is_none());
+
+    let changes = atlas.reclaim(region);
+    assert_eq!(changes.changes.len(), 5);
+    assert!(changes.failures.is_empty());
+    for (change, alloc) in changes.changes.iter().zip(&allocs) {
+        assert_eq!(change.old.rectangle, alloc.rectangle);
+        assert_eq!(atlas[change.new.id], alloc.rectangle);
+    }
+    atlas.validate().unwrap();
+
+    // Reclaiming a region after the atlas was reset fails gracefully.
+    let mut region = atlas.split_off_region(size2(300, 300)).unwrap();
+    let alloc = region.allocate(size2(50, 50)).unwrap();
+    atlas.reset(size2(1000, 1000), &DEFAULT_OPTIONS);
+    let changes = atlas.reclaim(region);
+    assert!(changes.changes.is_empty());
+    assert_eq!(changes.failures, vec![alloc]);
+    assert!(atlas.is_empty());
+    atlas.validate().unwrap();
+}
@@ -0,0 +1,237 @@
+use crate::allocator::*;
+use crate::Rectangle;
+
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// An atlas allocator whose allocations can be looked up from other threads while it is
+/// being modified.
+///
+/// The allocator is owned and modified by a single thread. Other threads read the
+/// allocations through `AtlasReader`s, which see the snapshot of the atlas made by the last
+/// call to `publish`. Publishing increments an epoch, so that readers can cheaply tell when
+/// their snapshot is out of date.
+///
+/// Readers never take a lock: the snapshots are published into two slots that are used in
+/// turn, and a reader only retries if a snapshot was published while it was reading.
+pub struct SharedAtlasAllocator {
+    atlas: AtlasAllocator,
+    published: Arc<Published>,
+}
+
+/// A handle to read the published allocations of a `SharedAtlasAllocator` from any thread.
+#[derive(Clone)]
+pub struct AtlasReader {
+    published: Arc<Published>,
+}
+
+// The snapshot of an epoch is stored in the slot `epoch % 2`. Readers register in the slot
+// of the current epoch before reading it, and the writer waits for the readers of the other
+// slot to leave before overwriting it with the next snapshot. Since the writer only waits
+// for readers that are cloning a snapshot, which doesn't copy the allocations, readers
+// never wait for the writer.
+struct Published {
+    slots: [UnsafeCell<FrozenAtlas>; 2],
+    readers: [AtomicUsize; 2],
+    epoch: AtomicU64,
+}
+
+// The slots are only written by the owner of the `SharedAtlasAllocator` while no reader
+// is registered in them.
+unsafe impl Sync for Published {}
+
+impl Published {
+    fn snapshot(&self) -> FrozenAtlas {
+        loop {
+            // The sequentially consistent orderings make sure that either the writer sees
+            // the reader registered in the slot, or the reader sees the new epoch.
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let slot = (epoch % 2) as usize;
+            self.readers[slot].fetch_add(1, Ordering::SeqCst);
+            let snapshot = if self.epoch.load(Ordering::SeqCst) == epoch {
+                // Safety: the writer doesn't modify the slot of the current epoch, and
+                // waits for this reader to leave before modifying it in the next one.
+                Some(unsafe { (*self.slots[slot].get()).clone() })
+            } else {
+                None
+            };
+            self.readers[slot].fetch_sub(1, Ordering::SeqCst);
+
+            if let Some(snapshot) = snapshot {
+                return snapshot;
+            }
+        }
+    }
+
+    // Must only be called by the owner of the `SharedAtlasAllocator`.
+    fn publish(&self, snapshot: FrozenAtlas) -> u64 {
+        let epoch = self.epoch.load(Ordering::SeqCst) + 1;
+        let slot = (epoch % 2) as usize;
+        while self.readers[slot].load(Ordering::SeqCst) != 0 {
+            std::hint::spin_loop();
+        }
+
+        // Safety: new readers of this slot give up until the epoch is incremented below,
+        // and the ones that registered earlier have left.
+        let previous = unsafe { std::mem::replace(&mut *self.slots[slot].get(), snapshot) };
+        self.epoch.store(epoch, Ordering::SeqCst);
+
+        // Dropping the previous snapshot may deallocate it, which is done outside of the
+        // critical section.
+        drop(previous);
+
+        epoch
+    }
+}
+
+impl SharedAtlasAllocator {
+    /// Wrap an atlas allocator, publishing its current allocations.
+    pub fn new(atlas: AtlasAllocator) -> Self {
+        let snapshot = atlas.freeze();
+        let published = Arc::new(Published {
+            slots: [UnsafeCell::new(snapshot.clone()), UnsafeCell::new(snapshot)],
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            epoch: AtomicU64::new(0),
+        });
+
+        SharedAtlasAllocator { atlas, published }
+    }
+
+    /// Create a handle to read the published allocations.
+    pub fn reader(&self) -> AtlasReader {
+        AtlasReader {
+            published: Arc::clone(&self.published),
+        }
+    }
+
+    /// Make the current allocations visible to the readers and return the new epoch.
+    ///
+    /// This copies the allocations, so it is typically done once per frame rather than after
+    /// each modification.
+    pub fn publish(&mut self) -> u64 {
+        let snapshot = self.atlas.freeze();
+
+        self.published.publish(snapshot)
+    }
+
+    /// The epoch of the last published snapshot.
+    pub fn epoch(&self) -> u64 {
+        self.published.epoch.load(Ordering::Acquire)
+    }
+
+    /// Return the atlas allocator.
+    ///
+    /// The readers keep the last published snapshot.
+    pub fn into_inner(self) -> AtlasAllocator {
+        self.atlas
+    }
+}
+
+impl Deref for SharedAtlasAllocator {
+    type Target = AtlasAllocator;
+    fn deref(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+}
+
+impl DerefMut for SharedAtlasAllocator {
+    fn deref_mut(&mut self) -> &mut AtlasAllocator {
+        &mut self.atlas
+    }
+}
+
+impl AtlasReader {
+    /// The last published snapshot of the allocations.
+    ///
+    /// The snapshot is not affected by later modifications, and cloning it is cheap.
+    pub fn snapshot(&self) -> FrozenAtlas {
+        self.published.snapshot()
+    }
+
+    /// The epoch of the last published snapshot.
+    pub fn epoch(&self) -> u64 {
+        self.published.epoch.load(Ordering::Acquire)
+    }
+
+    /// Returns the rectangle of an allocation in the last published snapshot, or `None` if
+    /// the id was not valid when it was published.
+    ///
+    /// To look up many allocations, it is cheaper to take a snapshot first.
+    pub fn get(&self, id: AllocId) -> Option<Rectangle> {
+        self.published.snapshot().get(id).copied()
+    }
+}
+
+#[test]
+fn shared_atlas_allocator() {
+    use euclid::size2;
+
+    let mut atlas = SharedAtlasAllocator::new(AtlasAllocator::new(size2(1000, 1000)));
+    let reader = atlas.reader();
+
+    let a = atlas.allocate(size2(100, 100)).unwrap();
+    // The allocation is not visible until it is published.
+    assert_eq!(reader.get(a.id), None);
+    assert_eq!(atlas.publish(), 1);
+    assert_eq!(reader.epoch(), 1);
+    assert_eq!(reader.get(a.id), Some(a.rectangle));
+
+    std::thread::scope(|scope| {
+        let reader = reader.clone();
+        let thread = scope.spawn(move || {
+            let snapshot = reader.snapshot();
+            for _ in 0..100 {
+                assert_eq!(snapshot[a.id], a.rectangle);
+                assert_eq!(reader.get(a.id), Some(a.rectangle));
+            }
+        });
+
+        for _ in 0..100 {
+            let b = atlas.allocate(size2(10, 10)).unwrap();
+            atlas.publish();
+            atlas.deallocate(b.id);
+        }
+
+        thread.join().unwrap();
+    });
+
+    atlas.deallocate(a.id);
+    let snapshot = reader.snapshot();
+    assert!(snapshot.is_valid(a.id));
+    atlas.publish();
+    assert!(!reader.snapshot().is_valid(a.id));
+    assert!(snapshot.is_valid(a.id));
+    assert_eq!(reader.epoch(), atlas.epoch());
+}
+
+#[test]
+fn concurrent_publish() {
+    use euclid::size2;
+
+    let mut atlas = SharedAtlasAllocator::new(AtlasAllocator::new(size2(1000, 1000)));
+
+    // Each publish adds a 10x10 allocation, so the snapshots of an epoch have as many.
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            let reader = atlas.reader();
+            scope.spawn(move || loop {
+                let before = reader.epoch();
+                let snapshot = reader.snapshot();
+                let after = reader.epoch();
+                let count = snapshot.allocated_space() as u64 / 100;
+                assert!(before <= count && count <= after);
+                assert_eq!(snapshot.iter().count() as u64, count);
+                if count == 500 {
+                    break;
+                }
+            });
+        }
+
+        for _ in 0..500 {
+            atlas.allocate(size2(10, 10)).unwrap();
+            atlas.publish();
+        }
+    });
+}
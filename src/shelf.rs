@@ -0,0 +1,453 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+use euclid::{point2, size2};
+
+use std::num::Wrapping;
+
+/// A texture atlas allocator using shelf packing.
+///
+/// The atlas is divided into horizontal shelves stacked from the top, and each shelf is divided
+/// into slots from left to right. Allocations go into the shortest shelf that can hold them,
+/// and a new shelf is added below the last one when no existing shelf is suitable.
+///
+/// Shelf packing is cheaper than the guillotine algorithm and works well when the allocated
+/// rectangles have similar heights, like the glyphs of a font. It wastes more space when the
+/// heights vary a lot. Free slots are merged with their free neighbors in the same shelf, and
+/// empty shelves are removed when they are at the bottom of the atlas.
+///
+/// Only the `alignment` and `padding` options are used, the other options are specific to the
+/// guillotine allocator.
+pub struct ShelfAllocator {
+    shelves: Vec<Shelf>,
+    items: Vec<Item>,
+    generations: Vec<Wrapping<Generation>>,
+    free_items: Vec<AllocIndex>,
+    size: Size,
+    alignment: Size,
+    padding: i32,
+    allocated_space: i64,
+    allocation_count: usize,
+}
+
+struct Shelf {
+    y: i32,
+    height: i32,
+    // Ordered from left to right, covering the width of the atlas.
+    slots: Vec<Slot>,
+}
+
+#[derive(Copy, Clone)]
+struct Slot {
+    x: i32,
+    width: i32,
+    // The allocated item, or NONE if the slot is free.
+    item: AllocIndex,
+}
+
+struct Item {
+    rect: Rectangle,
+    shelf: usize,
+    allocated: bool,
+}
+
+impl ShelfAllocator {
+    /// Create a shelf allocator with default options.
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a shelf allocator with the provided options.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        assert!(options.is_valid());
+        assert!(size.width > 0);
+        assert!(size.height > 0);
+
+        ShelfAllocator {
+            shelves: Vec::new(),
+            items: Vec::new(),
+            generations: Vec::new(),
+            free_items: Vec::new(),
+            size,
+            alignment: options.alignment,
+            padding: options.padding,
+            allocated_space: 0,
+            allocation_count: 0,
+        }
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocation_count == 0
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.allocation_count
+    }
+
+    /// Sum of the areas of the allocated rectangles.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocated_space
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Ids that were allocated before clearing are invalidated.
+    pub fn clear(&mut self) {
+        for (index, item) in self.items.iter_mut().enumerate() {
+            if item.allocated {
+                item.allocated = false;
+                self.generations[index] += Wrapping(1);
+                self.free_items.push(AllocIndex(index as u32));
+            }
+        }
+
+        self.shelves.clear();
+        self.allocated_space = 0;
+        self.allocation_count = 0;
+    }
+
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        let mut size = size2(
+            requested_size.width + self.padding * 2,
+            requested_size.height + self.padding * 2,
+        );
+        adjust_size(self.alignment.width, &mut size.width);
+        adjust_size(self.alignment.height, &mut size.height);
+
+        if size.width > self.size.width || size.height > self.size.height {
+            return None;
+        }
+
+        // Prefer shelves that don't waste more than a third of their height, then a new shelf,
+        // then any shelf that is tall enough.
+        let (shelf_index, slot_index) = match self.find_slot(size, size.height + size.height / 2) {
+            Some(slot) => slot,
+            None => match self.add_shelf(size.height) {
+                Some(shelf_index) => (shelf_index, 0),
+                None => self.find_slot(size, i32::MAX)?,
+            },
+        };
+
+        let index = match self.free_items.pop() {
+            Some(index) => index,
+            None => {
+                self.items.push(Item {
+                    rect: Rectangle::zero(),
+                    shelf: 0,
+                    allocated: false,
+                });
+                self.generations.push(Wrapping(0));
+                AllocIndex(self.items.len() as u32 - 1)
+            }
+        };
+
+        let shelf = &mut self.shelves[shelf_index];
+        let slot = shelf.slots[slot_index];
+        if slot.width > size.width {
+            shelf.slots.insert(slot_index + 1, Slot {
+                x: slot.x + size.width,
+                width: slot.width - size.width,
+                item: AllocIndex::NONE,
+            });
+        }
+        shelf.slots[slot_index] = Slot {
+            x: slot.x,
+            width: size.width,
+            item: index,
+        };
+
+        let min = point2(slot.x, shelf.y);
+        let rect = Rectangle {
+            min,
+            max: min + size.to_vector(),
+        };
+        self.items[index.index()] = Item {
+            rect,
+            shelf: shelf_index,
+            allocated: true,
+        };
+
+        self.allocated_space += area(size);
+        self.allocation_count += 1;
+
+        Some(Allocation {
+            id: AllocId::new(index, self.generations[index.index()].0),
+            rectangle: rect,
+            content: rect.inflate(-self.padding, -self.padding),
+            rotated: false,
+        })
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        let old_width = self.size.width;
+        self.size = new_size;
+        if new_size.width == old_width {
+            return;
+        }
+
+        // Extend the shelves to the new width.
+        for shelf in &mut self.shelves {
+            let last = shelf.slots.last_mut().unwrap();
+            if last.item.is_none() {
+                last.width += new_size.width - old_width;
+            } else {
+                shelf.slots.push(Slot {
+                    x: old_width,
+                    width: new_size.width - old_width,
+                    item: AllocIndex::NONE,
+                });
+            }
+        }
+    }
+
+    /// Deallocate a rectangle in the atlas.
+    pub fn deallocate(&mut self, id: AllocId) {
+        assert!(self.is_valid(id));
+        let index = id.index();
+
+        let item = &mut self.items[index.index()];
+        item.allocated = false;
+        let size = item.rect.size();
+
+        let shelf = &mut self.shelves[item.shelf];
+        let slot_index = shelf.slots.iter().position(|slot| slot.item == index).unwrap();
+        shelf.slots[slot_index].item = AllocIndex::NONE;
+
+        // Merge the slot with its free neighbors.
+        if slot_index + 1 < shelf.slots.len() && shelf.slots[slot_index + 1].item.is_none() {
+            shelf.slots[slot_index].width += shelf.slots[slot_index + 1].width;
+            shelf.slots.remove(slot_index + 1);
+        }
+        if slot_index > 0 && shelf.slots[slot_index - 1].item.is_none() {
+            shelf.slots[slot_index - 1].width += shelf.slots[slot_index].width;
+            shelf.slots.remove(slot_index);
+        }
+
+        // Remove the empty shelves at the bottom of the atlas so that their space can be reused
+        // by shelves of a different height.
+        while let Some(shelf) = self.shelves.last() {
+            if shelf.slots.len() > 1 || shelf.slots[0].item.is_some() {
+                break;
+            }
+            self.shelves.pop();
+        }
+
+        self.generations[index.index()] += Wrapping(1);
+        self.free_items.push(index);
+        self.allocated_space -= area(size);
+        self.allocation_count -= 1;
+    }
+
+    /// Returns true if the id refers to a live allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        let index = id.index().index();
+        index < self.items.len()
+            && self.items[index].allocated
+            && id.matches_generation(self.generations[index].0)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.items[id.index().index()].rect)
+    }
+
+    /// Invoke a callback for each allocated rectangle in the atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (index, item) in self.items.iter().enumerate() {
+            if item.allocated {
+                let index = AllocIndex(index as u32);
+                callback(AllocId::new(index, self.generations[index.index()].0), &item.rect);
+            }
+        }
+    }
+
+    /// Invoke a callback for each free rectangle in the atlas.
+    ///
+    /// This includes the free slots of each shelf and the space below the last shelf.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for shelf in &self.shelves {
+            for slot in &shelf.slots {
+                if slot.item.is_none() {
+                    callback(&Rectangle {
+                        min: point2(slot.x, shelf.y),
+                        max: point2(slot.x + slot.width, shelf.y + shelf.height),
+                    });
+                }
+            }
+        }
+
+        let y = self.shelves_end();
+        if y < self.size.height {
+            callback(&Rectangle {
+                min: point2(0, y),
+                max: point2(self.size.width, self.size.height),
+            });
+        }
+    }
+
+    // Find the shortest shelf that is at least as tall as the requested size and no taller than
+    // `max_height`, with a free slot that is wide enough. Returns the shelf and slot indices.
+    fn find_slot(&self, size: Size, max_height: i32) -> Option<(usize, usize)> {
+        let mut best = None;
+        let mut best_height = i32::MAX;
+        for (shelf_index, shelf) in self.shelves.iter().enumerate() {
+            if shelf.height < size.height || shelf.height > max_height || shelf.height >= best_height {
+                continue;
+            }
+
+            let slot = shelf.slots.iter().position(|slot| slot.item.is_none() && slot.width >= size.width);
+            if let Some(slot_index) = slot {
+                best = Some((shelf_index, slot_index));
+                best_height = shelf.height;
+            }
+        }
+
+        best
+    }
+
+    // Add an empty shelf below the last one, if there is enough room.
+    fn add_shelf(&mut self, height: i32) -> Option<usize> {
+        let y = self.shelves_end();
+        if y + height > self.size.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y,
+            height,
+            slots: vec![Slot {
+                x: 0,
+                width: self.size.width,
+                item: AllocIndex::NONE,
+            }],
+        });
+
+        Some(self.shelves.len() - 1)
+    }
+
+    fn shelves_end(&self) -> i32 {
+        self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height)
+    }
+}
+
+impl std::ops::Index<AllocId> for ShelfAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+impl DynamicAtlas for ShelfAllocator {
+    fn size(&self) -> Size {
+        ShelfAllocator::size(self)
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        ShelfAllocator::allocate(self, requested_size)
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        ShelfAllocator::deallocate(self, id);
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        ShelfAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        ShelfAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        ShelfAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        ShelfAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+impl SvgDump for ShelfAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        self.for_each_allocated_rectangle(|_, rect| callback(rect, NodeKind::Alloc));
+        self.for_each_free_rectangle(|rect| callback(rect, NodeKind::Free));
+    }
+}
+
+#[test]
+fn shelf_allocator() {
+    let mut atlas = ShelfAllocator::new(size2(256, 256));
+
+    // Glyph-like allocations of similar heights share shelves.
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        let alloc = atlas.allocate(size2(10 + i % 7, 12 + i % 3)).unwrap();
+        ids.push(alloc.id);
+    }
+    assert_eq!(atlas.allocation_count(), 100);
+
+    let mut rects = Vec::new();
+    atlas.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+    check_allocated_rectangles(&atlas, &rects);
+    // The allocations are laid out in a few rows, stacked without gaps.
+    let mut shelves: Vec<i32> = rects.iter().map(|rect| rect.min.y).collect();
+    shelves.sort_unstable();
+    shelves.dedup();
+    assert_eq!(shelves.len(), 7);
+    assert_eq!(shelves[0], 0);
+    assert!(shelves.windows(2).all(|pair| pair[1] - pair[0] <= 14));
+
+    // Allocations that are shorter than their shelf waste some space.
+    let mut space = atlas.allocated_space();
+    atlas.for_each_free_rectangle(|rect| space += area(rect.size()));
+    assert!(space <= area(atlas.size()));
+
+    // Freed slots are reused.
+    let removed = ids.remove(10);
+    let rect = atlas[removed];
+    atlas.deallocate(removed);
+    assert!(!atlas.is_valid(removed));
+    let alloc = atlas.allocate(rect.size()).unwrap();
+    assert_eq!(alloc.rectangle, rect);
+    ids.push(alloc.id);
+
+    for id in ids {
+        atlas.deallocate(id);
+    }
+    assert!(atlas.is_empty());
+
+    // Deallocating everything removes the shelves.
+    assert_eq!(atlas.allocate(size2(256, 256)).unwrap().rectangle, Rectangle::from_size(size2(256, 256)));
+    atlas.clear();
+    assert!(atlas.allocate(size2(300, 10)).is_none());
+}
@@ -0,0 +1,345 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+use euclid::{point2, size2};
+
+use std::num::Wrapping;
+
+/// A texture atlas allocator using the skyline bottom-left heuristic.
+///
+/// The allocator keeps track of the skyline formed by the bottom edges of the allocations,
+/// and places each rectangle where its bottom edge is the highest (closest to the origin),
+/// favoring the left-most position among equally good ones.
+///
+/// Rectangles can't be deallocated individually, which makes this allocator suitable for
+/// write-once atlases that are packed in one go and cleared as a whole. For such jobs it
+/// typically achieves a better occupancy than the guillotine allocator.
+///
+/// Only the `alignment` and `padding` options are used, the other options are specific to the
+/// guillotine allocator.
+pub struct SkylineAllocator {
+    // Ordered from left to right, covering the width of the atlas.
+    skyline: Vec<Segment>,
+    allocations: Vec<Rectangle>,
+    // Incremented when clearing the atlas, to invalidate the previous ids.
+    generation: Wrapping<Generation>,
+    size: Size,
+    alignment: Size,
+    padding: i32,
+    allocated_space: i64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Segment {
+    x: i32,
+    y: i32,
+    width: i32,
+}
+
+impl SkylineAllocator {
+    /// Create a skyline allocator with default options.
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a skyline allocator with the provided options.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        assert!(options.is_valid());
+        assert!(size.width > 0);
+        assert!(size.height > 0);
+
+        SkylineAllocator {
+            skyline: vec![Segment { x: 0, y: 0, width: size.width }],
+            allocations: Vec::new(),
+            generation: Wrapping(0),
+            size,
+            alignment: options.alignment,
+            padding: options.padding,
+            allocated_space: 0,
+        }
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    /// Number of allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    /// Sum of the areas of the allocated rectangles.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocated_space
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Ids that were allocated before clearing are invalidated.
+    pub fn clear(&mut self) {
+        self.skyline.clear();
+        self.skyline.push(Segment { x: 0, y: 0, width: self.size.width });
+        self.allocations.clear();
+        self.generation += Wrapping(1);
+        self.allocated_space = 0;
+    }
+
+    /// Allocate a rectangle in the atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        let mut size = size2(
+            requested_size.width + self.padding * 2,
+            requested_size.height + self.padding * 2,
+        );
+        adjust_size(self.alignment.width, &mut size.width);
+        adjust_size(self.alignment.height, &mut size.height);
+
+        // Find the position that minimizes the bottom edge of the allocation, then its x
+        // coordinate.
+        let mut best: Option<(usize, i32)> = None;
+        let mut best_bottom = i32::MAX;
+        for index in 0..self.skyline.len() {
+            if let Some(y) = self.fit(index, size) {
+                if y + size.height < best_bottom {
+                    best = Some((index, y));
+                    best_bottom = y + size.height;
+                }
+            }
+        }
+
+        let (index, y) = best?;
+        let x = self.skyline[index].x;
+        self.add_segment(index, Segment { x, y: y + size.height, width: size.width });
+
+        let min = point2(x, y);
+        let rect = Rectangle {
+            min,
+            max: min + size.to_vector(),
+        };
+        self.allocations.push(rect);
+        self.allocated_space += area(size);
+
+        Some(Allocation {
+            id: AllocId::new(AllocIndex(self.allocations.len() as u32 - 1), self.generation.0),
+            rectangle: rect,
+            content: rect.inflate(-self.padding, -self.padding),
+            rotated: false,
+        })
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        let old_width = self.size.width;
+        self.size = new_size;
+        if new_size.width == old_width {
+            return;
+        }
+
+        let last = self.skyline.last_mut().unwrap();
+        if last.y == 0 {
+            last.width += new_size.width - old_width;
+        } else {
+            self.skyline.push(Segment { x: old_width, y: 0, width: new_size.width - old_width });
+        }
+    }
+
+    /// Returns true if the id refers to an allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        id.index().index() < self.allocations.len() && id.matches_generation(self.generation.0)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.allocations[id.index().index()])
+    }
+
+    /// Invoke a callback for each allocated rectangle in the atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (index, rect) in self.allocations.iter().enumerate() {
+            callback(AllocId::new(AllocIndex(index as u32), self.generation.0), rect);
+        }
+    }
+
+    /// Invoke a callback for each free rectangle below the skyline.
+    ///
+    /// The space that is left above the skyline, between the allocations, can't be allocated
+    /// anymore and is not included.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for segment in &self.skyline {
+            if segment.y < self.size.height {
+                callback(&Rectangle {
+                    min: point2(segment.x, segment.y),
+                    max: point2(segment.x + segment.width, self.size.height),
+                });
+            }
+        }
+    }
+
+    // Returns the y coordinate at which a rectangle of the provided size can be placed at the
+    // left edge of the skyline segment, or None if it doesn't fit.
+    fn fit(&self, index: usize, size: Size) -> Option<i32> {
+        let x = self.skyline[index].x;
+        if x + size.width > self.size.width {
+            return None;
+        }
+
+        let mut y = 0;
+        for segment in &self.skyline[index..] {
+            if segment.x >= x + size.width {
+                break;
+            }
+            y = i32::max(y, segment.y);
+        }
+
+        if y + size.height > self.size.height {
+            return None;
+        }
+
+        Some(y)
+    }
+
+    // Insert a segment at the provided index, shrinking or removing the segments it covers.
+    fn add_segment(&mut self, index: usize, segment: Segment) {
+        self.skyline.insert(index, segment);
+
+        let end = segment.x + segment.width;
+        while index + 1 < self.skyline.len() {
+            let next = &mut self.skyline[index + 1];
+            if next.x >= end {
+                break;
+            }
+
+            let next_end = next.x + next.width;
+            if next_end <= end {
+                self.skyline.remove(index + 1);
+            } else {
+                next.width = next_end - end;
+                next.x = end;
+                break;
+            }
+        }
+
+        // Merge neighbor segments at the same height.
+        let mut i = index.saturating_sub(1);
+        while i + 1 < self.skyline.len() && i <= index + 1 {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl std::ops::Index<AllocId> for SkylineAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+impl DynamicAtlas for SkylineAllocator {
+    fn size(&self) -> Size {
+        SkylineAllocator::size(self)
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        SkylineAllocator::allocate(self, requested_size)
+    }
+
+    fn supports_deallocation(&self) -> bool {
+        false
+    }
+
+    fn deallocate(&mut self, _id: AllocId) {
+        panic!("SkylineAllocator can't deallocate rectangles");
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        SkylineAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        SkylineAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        SkylineAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        SkylineAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+impl SvgDump for SkylineAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        self.for_each_allocated_rectangle(|_, rect| callback(rect, NodeKind::Alloc));
+        self.for_each_free_rectangle(|rect| callback(rect, NodeKind::Free));
+    }
+}
+
+#[test]
+fn skyline_allocator() {
+    let mut atlas = SkylineAllocator::new(size2(256, 256));
+
+    // Identical squares pack without any waste.
+    for _ in 0..64 {
+        atlas.allocate(size2(32, 32)).unwrap();
+    }
+    assert_eq!(atlas.allocated_space(), 256 * 256);
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+
+    atlas.clear();
+    let first = atlas.allocate(size2(100, 30)).unwrap();
+    // The next allocation goes next to the first one rather than below it.
+    let second = atlas.allocate(size2(100, 50)).unwrap();
+    assert_eq!(second.rectangle.min, point2(100, 0));
+    // The third one doesn't fit on the right, and goes below the shortest allocation.
+    let third = atlas.allocate(size2(100, 20)).unwrap();
+    assert_eq!(third.rectangle.min, point2(0, 30));
+    assert_eq!(atlas[first.id], first.rectangle);
+
+    let mut sizes = Vec::new();
+    for i in 0..200 {
+        sizes.push(size2(5 + (i * 7) % 23, 5 + (i * 13) % 17));
+    }
+    for size in sizes {
+        atlas.allocate(size).unwrap();
+    }
+
+    let mut rects = Vec::new();
+    atlas.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+    check_allocated_rectangles(&atlas, &rects);
+
+    atlas.clear();
+    assert!(atlas.is_empty());
+    assert!(!atlas.is_valid(first.id));
+}
@@ -0,0 +1,315 @@
+use crate::allocator::*;
+use crate::{Rectangle, Size};
+use euclid::point2;
+
+use std::num::Wrapping;
+
+/// A texture atlas allocator that divides the atlas into a grid of cells of the same size.
+///
+/// Each allocation takes a whole cell, regardless of the requested size, as long as it fits.
+/// The free cells are tracked with a bitset, so allocating and deallocating are cheap and
+/// don't need any per-allocation bookkeeping beyond a generation counter.
+///
+/// This is a good fit for caches of items that all have the same size (for example shadow map
+/// pages or icons), for which the guillotine tree would be overkill.
+///
+/// The `padding` option is not used since the cell size is fixed, the other options are specific
+/// to the guillotine allocator.
+pub struct SlabAllocator {
+    // One bit per cell, set if the cell is allocated.
+    allocated_cells: Vec<u64>,
+    generations: Vec<Wrapping<Generation>>,
+    size: Size,
+    cell_size: Size,
+    columns: i32,
+    cell_count: usize,
+    allocation_count: usize,
+    // Hint to find free cells faster: no word before this one has a free cell.
+    first_free_word: usize,
+}
+
+impl SlabAllocator {
+    /// Create a slab allocator with cells of the provided size.
+    ///
+    /// The cell size is rounded up to the alignment of the options. The parts of the atlas
+    /// that can't hold a full cell on the right and bottom edges are not used.
+    pub fn new(size: Size, cell_size: Size) -> Self {
+        Self::with_options(size, cell_size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a slab allocator with cells of the provided size and the provided options.
+    pub fn with_options(size: Size, mut cell_size: Size, options: &AllocatorOptions) -> Self {
+        assert!(options.is_valid());
+        assert!(cell_size.width > 0);
+        assert!(cell_size.height > 0);
+
+        adjust_size(options.alignment.width, &mut cell_size.width);
+        adjust_size(options.alignment.height, &mut cell_size.height);
+
+        let columns = size.width / cell_size.width;
+        let rows = size.height / cell_size.height;
+        let cell_count = (columns * rows) as usize;
+        assert!(cell_count <= IDX_MASK as usize);
+
+        SlabAllocator {
+            allocated_cells: vec![0; cell_count.div_ceil(64)],
+            generations: vec![Wrapping(0); cell_count],
+            size,
+            cell_size,
+            columns,
+            cell_count,
+            allocation_count: 0,
+            first_free_word: 0,
+        }
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The size of the cells, after alignment.
+    pub fn cell_size(&self) -> Size {
+        self.cell_size
+    }
+
+    /// The total number of cells.
+    pub fn cell_count(&self) -> usize {
+        self.cell_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocation_count == 0
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.allocation_count
+    }
+
+    /// Sum of the areas of the allocated cells.
+    pub fn allocated_space(&self) -> i64 {
+        self.allocation_count as i64 * area(self.cell_size)
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Ids that were allocated before clearing are invalidated.
+    pub fn clear(&mut self) {
+        for cell in 0..self.cell_count {
+            if self.is_allocated(cell) {
+                self.generations[cell] += Wrapping(1);
+            }
+        }
+
+        for word in &mut self.allocated_cells {
+            *word = 0;
+        }
+        self.allocation_count = 0;
+        self.first_free_word = 0;
+    }
+
+    /// Allocate a cell for a rectangle of the requested size.
+    ///
+    /// Returns `None` if the requested size doesn't fit in a cell or if all cells are allocated.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty()
+            || requested_size.width > self.cell_size.width
+            || requested_size.height > self.cell_size.height {
+            return None;
+        }
+
+        let word_index = self.allocated_cells[self.first_free_word..]
+            .iter()
+            .position(|&word| word != u64::MAX)?
+            + self.first_free_word;
+        let cell = word_index * 64 + (!self.allocated_cells[word_index]).trailing_zeros() as usize;
+        if cell >= self.cell_count {
+            return None;
+        }
+
+        self.allocated_cells[word_index] |= 1 << (cell % 64);
+        self.first_free_word = word_index;
+        self.allocation_count += 1;
+
+        let rect = self.cell_rect(cell);
+        Some(Allocation {
+            id: AllocId::new(AllocIndex(cell as u32), self.generations[cell].0),
+            rectangle: rect,
+            content: Rectangle {
+                min: rect.min,
+                max: rect.min + requested_size.to_vector(),
+            },
+            rotated: false,
+        })
+    }
+
+    /// Resize the atlas without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the width or height of the atlas. The number of
+    /// columns can't change without moving the allocations, so only the new rows of cells are
+    /// added and the extra width is not used.
+    pub fn grow(&mut self, new_size: Size) {
+        assert!(new_size.width >= self.size.width);
+        assert!(new_size.height >= self.size.height);
+
+        self.size = new_size;
+
+        let rows = new_size.height / self.cell_size.height;
+        self.cell_count = (self.columns * rows) as usize;
+        assert!(self.cell_count <= IDX_MASK as usize);
+
+        self.allocated_cells.resize(self.cell_count.div_ceil(64), 0);
+        self.generations.resize(self.cell_count, Wrapping(0));
+    }
+
+    /// Deallocate a cell.
+    pub fn deallocate(&mut self, id: AllocId) {
+        assert!(self.is_valid(id));
+        let cell = id.index().index();
+
+        self.allocated_cells[cell / 64] &= !(1 << (cell % 64));
+        self.generations[cell] += Wrapping(1);
+        self.first_free_word = self.first_free_word.min(cell / 64);
+        self.allocation_count -= 1;
+    }
+
+    /// Returns true if the id refers to a live allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        let cell = id.index().index();
+        cell < self.cell_count
+            && self.is_allocated(cell)
+            && id.matches_generation(self.generations[cell].0)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    ///
+    /// This is the rectangle of the whole cell.
+    pub fn get(&self, id: AllocId) -> Option<Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(self.cell_rect(id.index().index()))
+    }
+
+    /// Invoke a callback for each allocated cell in the atlas.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for cell in 0..self.cell_count {
+            if self.is_allocated(cell) {
+                let id = AllocId::new(AllocIndex(cell as u32), self.generations[cell].0);
+                callback(id, &self.cell_rect(cell));
+            }
+        }
+    }
+
+    /// Invoke a callback for each free cell in the atlas.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        for cell in 0..self.cell_count {
+            if !self.is_allocated(cell) {
+                callback(&self.cell_rect(cell));
+            }
+        }
+    }
+
+    fn is_allocated(&self, cell: usize) -> bool {
+        self.allocated_cells[cell / 64] & (1 << (cell % 64)) != 0
+    }
+
+    fn cell_rect(&self, cell: usize) -> Rectangle {
+        let column = cell as i32 % self.columns;
+        let row = cell as i32 / self.columns;
+        let min = point2(column * self.cell_size.width, row * self.cell_size.height);
+
+        Rectangle {
+            min,
+            max: min + self.cell_size.to_vector(),
+        }
+    }
+}
+
+impl DynamicAtlas for SlabAllocator {
+    fn size(&self) -> Size {
+        SlabAllocator::size(self)
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        SlabAllocator::allocate(self, requested_size)
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        SlabAllocator::deallocate(self, id);
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        SlabAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        SlabAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        SlabAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        SlabAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+impl SvgDump for SlabAllocator {
+    fn dump_size(&self) -> Size {
+        self.size
+    }
+
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        self.for_each_allocated_rectangle(|_, rect| callback(rect, NodeKind::Alloc));
+        self.for_each_free_rectangle(|rect| callback(rect, NodeKind::Free));
+    }
+}
+
+#[test]
+fn slab_allocator() {
+    use euclid::size2;
+
+    let mut atlas = SlabAllocator::new(size2(100, 64), size2(16, 16));
+    // The right edge is too narrow for a full column of cells.
+    assert_eq!(atlas.cell_count(), 6 * 4);
+
+    let mut ids = Vec::new();
+    for _ in 0..24 {
+        let alloc = atlas.allocate(size2(10, 16)).unwrap();
+        assert_eq!(alloc.rectangle.size(), size2(16, 16));
+        assert_eq!(alloc.content.size(), size2(10, 16));
+        ids.push(alloc.id);
+    }
+    assert!(atlas.allocate(size2(1, 1)).is_none());
+    assert!(SlabAllocator::new(size2(100, 64), size2(16, 16)).allocate(size2(17, 1)).is_none());
+
+    let mut rects = Vec::new();
+    atlas.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+    check_allocated_rectangles(&atlas, &rects);
+    // The cells form a grid.
+    assert!(rects.iter().all(|rect| rect.min.x % 16 == 0 && rect.min.y % 16 == 0));
+
+    // Freed cells are reused, and the old ids are invalidated.
+    let rect = atlas.get(ids[7]).unwrap();
+    atlas.deallocate(ids[7]);
+    assert!(!atlas.is_valid(ids[7]));
+    let alloc = atlas.allocate(size2(16, 16)).unwrap();
+    assert_eq!(alloc.rectangle, rect);
+    assert!(!atlas.is_valid(ids[7]));
+    ids[7] = alloc.id;
+
+    for id in ids {
+        atlas.deallocate(id);
+    }
+    assert!(atlas.is_empty());
+}
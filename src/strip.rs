@@ -0,0 +1,183 @@
+use crate::allocator::*;
+use euclid::size2;
+
+use std::ops::Range;
+
+/// An allocator for ranges within a single line, for example a one texel high texture.
+///
+/// This is a thin layer over an `AtlasAllocator` with a height of one and a horizontal root:
+/// all of the nodes of the tree are in a single sibling list, which keeps the bookkeeping
+/// minimal while reusing the same fast coalescing of free ranges.
+///
+/// Only the width of the `alignment` option is used, the padding is applied on both ends of the
+/// ranges and rotation is not supported.
+pub struct StripAllocator {
+    atlas: AtlasAllocator,
+    // Applied here rather than by the atlas, which would also pad the height of the strip.
+    padding: i32,
+}
+
+/// A range allocated by a `StripAllocator`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StripAllocation {
+    pub id: AllocId,
+    /// The allocated range, including the padding.
+    pub range: Range<i32>,
+    /// The part of the allocated range that is meant to hold the content, excluding the
+    /// padding.
+    pub content: Range<i32>,
+}
+
+impl StripAllocator {
+    /// Create a strip allocator with default options.
+    pub fn new(length: i32) -> Self {
+        StripAllocator::with_options(length, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a strip allocator with the provided options.
+    pub fn with_options(length: i32, options: &AllocatorOptions) -> Self {
+        assert!(options.padding >= 0);
+        let padding = options.padding;
+        let options = AllocatorOptions {
+            alignment: size2(options.alignment.width, 1),
+            padding: 0,
+            allow_rotation: false,
+            ..*options
+        };
+
+        let mut atlas = AtlasAllocator::with_options(size2(length, 1), &options);
+        atlas.set_horizontal_root();
+
+        StripAllocator { atlas, padding }
+    }
+
+    /// The total length of the strip.
+    pub fn length(&self) -> i32 {
+        self.atlas.size().width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.atlas.is_empty()
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.atlas.allocation_count()
+    }
+
+    /// Sum of the lengths of the allocated ranges, including their padding.
+    pub fn allocated_length(&self) -> i32 {
+        self.atlas.allocated_space() as i32
+    }
+
+    /// Allocate a range of the requested length.
+    pub fn allocate(&mut self, length: i32) -> Option<StripAllocation> {
+        if length <= 0 {
+            return None;
+        }
+
+        let alloc = self.atlas.allocate(size2(length + self.padding * 2, 1))?;
+        let range = alloc.rectangle.x_range();
+
+        Some(StripAllocation {
+            id: alloc.id,
+            content: range.start + self.padding..range.end - self.padding,
+            range,
+        })
+    }
+
+    /// Deallocate a range.
+    pub fn deallocate(&mut self, id: AllocId) {
+        self.atlas.deallocate(id);
+    }
+
+    /// Returns true if the id refers to a live allocation of this strip.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        self.atlas.is_valid(id)
+    }
+
+    /// Returns the range of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<Range<i32>> {
+        self.atlas.get(id).map(|rect| rect.x_range())
+    }
+
+    /// Extend the strip without changing the allocations.
+    ///
+    /// This method is not allowed to shrink the strip.
+    pub fn grow(&mut self, new_length: i32) {
+        self.atlas.grow(size2(new_length, 1));
+    }
+
+    /// Drop all ranges, clearing the strip to its initial state.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.atlas.set_horizontal_root();
+    }
+
+    /// Invoke a callback for each allocated range.
+    pub fn for_each_allocated_range<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, Range<i32>),
+    {
+        self.atlas.for_each_allocated_rectangle(|id, rect| callback(id, rect.x_range()));
+    }
+
+    /// Invoke a callback for each free range.
+    pub fn for_each_free_range<F>(&self, mut callback: F)
+    where
+        F: FnMut(Range<i32>),
+    {
+        self.atlas.for_each_free_rectangle(|rect| callback(rect.x_range()));
+    }
+}
+
+#[test]
+fn strip_allocator() {
+    let mut strip = StripAllocator::new(256);
+
+    let a = strip.allocate(100).unwrap();
+    let b = strip.allocate(50).unwrap();
+    let c = strip.allocate(100).unwrap();
+    assert_eq!(a.range, 0..100);
+    assert_eq!(strip.get(b.id), Some(b.range.clone()));
+    assert_eq!(strip.allocated_length(), 250);
+    assert!(strip.allocate(10).is_none());
+
+    // Free ranges are merged with their neighbors.
+    strip.deallocate(a.id);
+    strip.deallocate(b.id);
+    assert_eq!(strip.allocate(150).unwrap().range, 0..150);
+
+    let mut free = Vec::new();
+    strip.for_each_free_range(|range| free.push(range));
+    assert_eq!(free, vec![250..256]);
+
+    strip.grow(300);
+    assert_eq!(strip.length(), 300);
+    assert!(strip.allocate(50).is_some());
+    assert!(strip.is_valid(c.id));
+
+    strip.clear();
+    assert!(strip.is_empty());
+    assert!(!strip.is_valid(c.id));
+}
+
+#[test]
+fn strip_padding() {
+    let options = AllocatorOptions { padding: 1, alignment: size2(4, 4), ..DEFAULT_OPTIONS };
+    let mut strip = StripAllocator::with_options(256, &options);
+
+    let a = strip.allocate(10).unwrap();
+    assert_eq!(a.range, 0..12);
+    assert_eq!(a.content, 1..11);
+
+    // The padded length is rounded up to the alignment.
+    let b = strip.allocate(7).unwrap();
+    assert_eq!(b.range, 12..24);
+    assert_eq!(b.content, 13..23);
+    assert_eq!(strip.allocated_length(), 24);
+
+    assert!(strip.allocate(0).is_none());
+    assert!(strip.allocate(255).is_none());
+    assert!(strip.allocate(230).is_some());
+}
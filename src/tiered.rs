@@ -0,0 +1,362 @@
+use crate::allocator::*;
+use crate::slab::SlabAllocator;
+use crate::{Point, Rectangle, Size};
+use euclid::size2;
+
+use std::num::Wrapping;
+
+// Number of cells along each side of a slab region.
+const CELLS_PER_REGION: i32 = 8;
+
+/// An atlas allocator that packs small allocations into slab regions carved out of a
+/// guillotine atlas.
+///
+/// Requests whose padded width and height are both below the `small_size_threshold` option
+/// are rounded up to a size class (the next power of two on each axis) and allocated in a
+/// region dedicated to that size class, which is itself allocated in the underlying
+/// `AtlasAllocator`. Larger requests are allocated directly in the atlas. This prevents lots
+/// of tiny allocations (such as glyphs) from fragmenting the large free rectangles of the atlas,
+/// at the expense of some wasted space in the cells.
+///
+/// Regions are returned to the atlas when all of their cells are deallocated. If a new region
+/// can't be allocated, small requests fall back to the atlas.
+pub struct TieredAtlasAllocator {
+    atlas: AtlasAllocator,
+    regions: Vec<Option<SlabRegion>>,
+    slots: Vec<Slot>,
+    generations: Vec<Wrapping<Generation>>,
+    free_slots: Vec<AllocIndex>,
+    options: AllocatorOptions,
+}
+
+struct SlabRegion {
+    id: AllocId,
+    origin: Point,
+    cell_size: Size,
+    slab: SlabAllocator,
+}
+
+#[derive(Copy, Clone)]
+enum Target {
+    Atlas(AllocId),
+    Slab { region: usize, id: AllocId },
+    None,
+}
+
+struct Slot {
+    target: Target,
+    rect: Rectangle,
+}
+
+impl TieredAtlasAllocator {
+    /// Create a tiered allocator with default options.
+    pub fn new(size: Size) -> Self {
+        Self::with_options(size, &DEFAULT_OPTIONS)
+    }
+
+    /// Create a tiered allocator with the provided options.
+    ///
+    /// The options are used by the underlying atlas allocator, and `small_size_threshold`
+    /// decides which requests go into slab regions.
+    pub fn with_options(size: Size, options: &AllocatorOptions) -> Self {
+        TieredAtlasAllocator {
+            atlas: AtlasAllocator::with_options(size, options),
+            regions: Vec::new(),
+            slots: Vec::new(),
+            generations: Vec::new(),
+            free_slots: Vec::new(),
+            options: *options,
+        }
+    }
+
+    /// The underlying atlas allocator, in which the slab regions are allocated.
+    pub fn atlas(&self) -> &AtlasAllocator {
+        &self.atlas
+    }
+
+    /// The total size of the atlas.
+    pub fn size(&self) -> Size {
+        self.atlas.size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free_slots.len() == self.slots.len()
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    /// Number of slab regions currently allocated in the atlas.
+    pub fn region_count(&self) -> usize {
+        self.regions.iter().flatten().count()
+    }
+
+    /// Drop all rectangles, clearing the atlas to its initial state.
+    ///
+    /// Ids that were allocated before clearing are invalidated.
+    pub fn clear(&mut self) {
+        self.atlas.clear();
+        self.regions.clear();
+
+        self.free_slots.clear();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if !matches!(slot.target, Target::None) {
+                self.generations[index] += Wrapping(1);
+            }
+            slot.target = Target::None;
+            self.free_slots.push(AllocIndex(index as u32));
+        }
+    }
+
+    /// Allocate a rectangle in a slab region if it is small, otherwise in the atlas.
+    pub fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        let padding = self.options.padding;
+        let padded_size = size2(requested_size.width + padding * 2, requested_size.height + padding * 2);
+        let threshold = self.options.small_size_threshold;
+
+        if padded_size.width < threshold && padded_size.height < threshold {
+            let cell_size = size2(
+                (padded_size.width as u32).next_power_of_two() as i32,
+                (padded_size.height as u32).next_power_of_two() as i32,
+            );
+
+            if let Some((region, alloc)) = self.allocate_in_slab(cell_size, padded_size) {
+                let origin = self.regions[region].as_ref().unwrap().origin.to_vector();
+                let rect = alloc.rectangle.translate(origin);
+                let content = Rectangle {
+                    min: rect.min + size2(padding, padding).to_vector(),
+                    max: rect.min + size2(padding, padding).to_vector() + requested_size.to_vector(),
+                };
+
+                return Some(self.add_slot(Target::Slab { region, id: alloc.id }, rect, content, false));
+            }
+        }
+
+        let alloc = self.atlas.allocate(requested_size)?;
+
+        Some(self.add_slot(Target::Atlas(alloc.id), alloc.rectangle, alloc.content, alloc.rotated))
+    }
+
+    /// Resize the atlas without changing the allocations, see `AtlasAllocator::grow`.
+    pub fn grow(&mut self, new_size: Size) {
+        self.atlas.grow(new_size);
+    }
+
+    /// Deallocate a rectangle.
+    pub fn deallocate(&mut self, id: AllocId) {
+        assert!(self.is_valid(id));
+        let index = id.index();
+
+        match self.slots[index.index()].target {
+            Target::Atlas(id) => {
+                self.atlas.deallocate(id);
+            }
+            Target::Slab { region, id } => {
+                let slab_region = self.regions[region].as_mut().unwrap();
+                slab_region.slab.deallocate(id);
+                if slab_region.slab.is_empty() {
+                    // Give the space back to the atlas.
+                    self.atlas.deallocate(slab_region.id);
+                    self.regions[region] = None;
+                }
+            }
+            Target::None => unreachable!(),
+        }
+
+        self.slots[index.index()].target = Target::None;
+        self.generations[index.index()] += Wrapping(1);
+        self.free_slots.push(index);
+    }
+
+    /// Returns true if the id refers to a live allocation of this atlas.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        let index = id.index().index();
+        index < self.slots.len()
+            && !matches!(self.slots[index].target, Target::None)
+            && id.matches_generation(self.generations[index].0)
+    }
+
+    /// Returns the rectangle of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Rectangle> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.slots[id.index().index()].rect)
+    }
+
+    /// Invoke a callback for each allocated rectangle, including the ones in slab regions.
+    pub fn for_each_allocated_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Rectangle),
+    {
+        for (index, slot) in self.slots.iter().enumerate() {
+            if !matches!(slot.target, Target::None) {
+                callback(AllocId::new(AllocIndex(index as u32), self.generations[index].0), &slot.rect);
+            }
+        }
+    }
+
+    /// Invoke a callback for each free rectangle of the atlas and each free cell of the slab
+    /// regions.
+    pub fn for_each_free_rectangle<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Rectangle),
+    {
+        self.atlas.for_each_free_rectangle(&mut callback);
+        for region in self.regions.iter().flatten() {
+            let origin = region.origin.to_vector();
+            region.slab.for_each_free_rectangle(|rect| callback(&rect.translate(origin)));
+        }
+    }
+
+    // Allocate a cell in a region of the provided cell size, allocating a new region if needed.
+    fn allocate_in_slab(&mut self, cell_size: Size, size: Size) -> Option<(usize, Allocation)> {
+        for (index, region) in self.regions.iter_mut().enumerate() {
+            if let Some(region) = region {
+                if region.cell_size != cell_size {
+                    continue;
+                }
+                if let Some(alloc) = region.slab.allocate(size) {
+                    return Some((index, alloc));
+                }
+            }
+        }
+
+        let region_size = size2(cell_size.width * CELLS_PER_REGION, cell_size.height * CELLS_PER_REGION);
+        let region_alloc = self.atlas.allocate(region_size)?;
+        let mut region = SlabRegion {
+            id: region_alloc.id,
+            origin: region_alloc.rectangle.min,
+            cell_size,
+            slab: SlabAllocator::with_options(region_alloc.rectangle.size(), cell_size, &self.options),
+        };
+        let alloc = region.slab.allocate(size).unwrap();
+
+        let index = match self.regions.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.regions.push(None);
+                self.regions.len() - 1
+            }
+        };
+        self.regions[index] = Some(region);
+
+        Some((index, alloc))
+    }
+
+    fn add_slot(&mut self, target: Target, rect: Rectangle, content: Rectangle, rotated: bool) -> Allocation {
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot { target: Target::None, rect: Rectangle::zero() });
+                self.generations.push(Wrapping(0));
+                AllocIndex(self.slots.len() as u32 - 1)
+            }
+        };
+        self.slots[index.index()] = Slot { target, rect };
+
+        Allocation {
+            id: AllocId::new(index, self.generations[index.index()].0),
+            rectangle: rect,
+            content,
+            rotated,
+        }
+    }
+}
+
+impl std::ops::Index<AllocId> for TieredAtlasAllocator {
+    type Output = Rectangle;
+    fn index(&self, index: AllocId) -> &Rectangle {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+impl DynamicAtlas for TieredAtlasAllocator {
+    fn size(&self) -> Size {
+        TieredAtlasAllocator::size(self)
+    }
+
+    fn allocate(&mut self, requested_size: Size) -> Option<Allocation> {
+        TieredAtlasAllocator::allocate(self, requested_size)
+    }
+
+    fn deallocate(&mut self, id: AllocId) {
+        TieredAtlasAllocator::deallocate(self, id);
+    }
+
+    fn grow(&mut self, new_size: Size) {
+        TieredAtlasAllocator::grow(self, new_size);
+    }
+
+    fn clear(&mut self) {
+        TieredAtlasAllocator::clear(self);
+    }
+
+    fn is_empty(&self) -> bool {
+        TieredAtlasAllocator::is_empty(self)
+    }
+
+    fn for_each_free_rectangle(&self, callback: &mut dyn FnMut(&Rectangle)) {
+        TieredAtlasAllocator::for_each_free_rectangle(self, callback);
+    }
+}
+
+impl SvgDump for TieredAtlasAllocator {
+    fn dump_size(&self) -> Size {
+        self.atlas.size()
+    }
+
+    // The slab regions are drawn as allocated rectangles of the atlas, with their cells on top.
+    fn dump_rectangles(&self, callback: &mut dyn FnMut(&Rectangle, NodeKind)) {
+        self.atlas.dump_rectangles(callback);
+        for region in self.regions.iter().flatten() {
+            let origin = region.origin.to_vector();
+            region.slab.dump_rectangles(&mut |rect, kind| callback(&rect.translate(origin), kind));
+        }
+    }
+}
+
+#[test]
+fn tiered_atlas_allocator() {
+    let mut atlas = TieredAtlasAllocator::new(size2(1024, 1024));
+
+    let mut glyphs = Vec::new();
+    for i in 0..200 {
+        glyphs.push(atlas.allocate(size2(8 + i % 5, 10 + i % 3)).unwrap().id);
+    }
+    let large = atlas.allocate(size2(500, 300)).unwrap();
+
+    // All of the glyphs fit in 16x16 cells, grouped in a few regions.
+    assert_eq!(atlas.region_count(), 4);
+    assert_eq!(atlas.atlas().allocation_count(), 5);
+    assert_eq!(atlas.allocation_count(), 201);
+
+    let mut rects = Vec::new();
+    atlas.for_each_allocated_rectangle(|_, rect| rects.push(*rect));
+    check_allocated_rectangles(&atlas, &rects);
+    // Each glyph lies in a slab region allocated from the underlying atlas.
+    let mut regions = Vec::new();
+    atlas.atlas().for_each_allocated_rectangle(|_, rect| regions.push(*rect));
+    for rect in &rects {
+        assert!(regions.iter().any(|region| region.contains_box(rect)));
+    }
+
+    // Regions are released once all of their cells are deallocated.
+    for id in glyphs.drain(..) {
+        atlas.deallocate(id);
+    }
+    assert_eq!(atlas.region_count(), 0);
+    assert_eq!(atlas[large.id], large.rectangle);
+
+    atlas.deallocate(large.id);
+    assert!(atlas.is_empty());
+    assert!(atlas.atlas().is_empty());
+    assert!(!atlas.is_valid(large.id));
+}
@@ -0,0 +1,389 @@
+use crate::allocator::*;
+use euclid::point3;
+
+use std::num::Wrapping;
+
+pub type Size3 = euclid::default::Size3D<i32>;
+pub type Box3 = euclid::default::Box3D<i32>;
+
+/// An allocator for boxes in a 3D texture, such as sparse voxel bricks or 3D lookup tables.
+///
+/// This uses the guillotine algorithm extended to three axes: the free box that best fits
+/// the request is split into the allocated box and up to three free boxes along the x, y and
+/// z axes. The splits are recorded in a tree, and deallocated boxes are merged back into their
+/// parent once all of its children are free.
+///
+/// Unlike `AtlasAllocator`, free boxes are only merged with the boxes they were split from.
+pub struct VolumeAllocator {
+    nodes: Vec<VolumeNode>,
+    generations: Vec<Wrapping<Generation>>,
+    // May contain stale entries, which are skipped and removed when searching.
+    free_nodes: Vec<AllocIndex>,
+    unused_nodes: Vec<AllocIndex>,
+    size: Size3,
+    allocated_volume: i64,
+    allocation_count: usize,
+}
+
+#[derive(Clone)]
+struct VolumeNode {
+    volume: Box3,
+    kind: NodeKind,
+    parent: AllocIndex,
+    // The first child is the allocated box, the others are the leftovers of the split.
+    children: [AllocIndex; 4],
+}
+
+/// A box allocated by a `VolumeAllocator`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VolumeAllocation {
+    pub id: AllocId,
+    pub volume: Box3,
+}
+
+/// A box that was moved by `VolumeAllocator::rearrange`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VolumeChange {
+    pub old: VolumeAllocation,
+    pub new: VolumeAllocation,
+}
+
+/// The changes made by `VolumeAllocator::rearrange`, see `ChangeList`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeChangeList {
+    pub changes: Vec<VolumeChange>,
+    pub failures: Vec<VolumeAllocation>,
+}
+
+impl VolumeAllocator {
+    /// Create a volume allocator.
+    pub fn new(size: Size3) -> Self {
+        assert!(size.width > 0);
+        assert!(size.height > 0);
+        assert!(size.depth > 0);
+
+        let mut allocator = VolumeAllocator {
+            nodes: Vec::new(),
+            generations: Vec::new(),
+            free_nodes: Vec::new(),
+            unused_nodes: Vec::new(),
+            size,
+            allocated_volume: 0,
+            allocation_count: 0,
+        };
+        allocator.clear();
+
+        allocator
+    }
+
+    /// The total size of the volume.
+    pub fn size(&self) -> Size3 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocation_count == 0
+    }
+
+    /// Number of live allocations.
+    pub fn allocation_count(&self) -> usize {
+        self.allocation_count
+    }
+
+    /// Sum of the volumes of the allocated boxes.
+    pub fn allocated_volume(&self) -> i64 {
+        self.allocated_volume
+    }
+
+    /// Drop all boxes, clearing the allocator to its initial state.
+    pub fn clear(&mut self) {
+        // The generations are kept so that the ids of the dropped allocations stay invalid when
+        // the nodes are reused.
+        for (node, generation) in self.nodes.iter().zip(self.generations.iter_mut()) {
+            if node.kind == NodeKind::Alloc {
+                *generation += Wrapping(1);
+            }
+        }
+
+        self.nodes.clear();
+        self.nodes.push(VolumeNode {
+            volume: Box3::from_size(self.size),
+            kind: NodeKind::Free,
+            parent: AllocIndex::NONE,
+            children: [AllocIndex::NONE; 4],
+        });
+        if self.generations.is_empty() {
+            self.generations.push(Wrapping(0));
+        }
+        self.free_nodes.clear();
+        self.free_nodes.push(AllocIndex(0));
+        self.unused_nodes.clear();
+        self.allocated_volume = 0;
+        self.allocation_count = 0;
+    }
+
+    /// Allocate a box in the volume.
+    pub fn allocate(&mut self, requested_size: Size3) -> Option<VolumeAllocation> {
+        if requested_size.is_empty() {
+            return None;
+        }
+
+        // Best fit: pick the smallest free box that can hold the requested size.
+        let mut chosen = None;
+        let mut chosen_volume = i64::MAX;
+        let mut i = 0;
+        while i < self.free_nodes.len() {
+            let id = self.free_nodes[i];
+            let node = &self.nodes[id.index()];
+            if node.kind != NodeKind::Free {
+                self.free_nodes.swap_remove(i);
+                continue;
+            }
+
+            let size = node.volume.size();
+            if size.width >= requested_size.width
+                && size.height >= requested_size.height
+                && size.depth >= requested_size.depth
+                && volume(size) < chosen_volume
+            {
+                chosen = Some(id);
+                chosen_volume = volume(size);
+            }
+            i += 1;
+        }
+
+        let id = chosen?;
+        let position = self.free_nodes.iter().position(|&free| free == id).unwrap();
+        self.free_nodes.swap_remove(position);
+        let free = self.nodes[id.index()].volume;
+        let min = free.min;
+        let max = min + requested_size.to_vector();
+
+        // Split the remaining space along the x, then y, then z axis.
+        let leftovers = [
+            Box3 { min: point3(max.x, min.y, min.z), max: free.max },
+            Box3 { min: point3(min.x, max.y, min.z), max: point3(max.x, free.max.y, free.max.z) },
+            Box3 { min: point3(min.x, min.y, max.z), max: point3(max.x, max.y, free.max.z) },
+        ];
+
+        let allocated = if leftovers.iter().all(|leftover| leftover.is_empty()) {
+            self.nodes[id.index()].kind = NodeKind::Alloc;
+            id
+        } else {
+            self.nodes[id.index()].kind = NodeKind::Container;
+            let allocated = self.new_node(Box3 { min, max }, NodeKind::Alloc, id);
+            let mut children = [allocated, AllocIndex::NONE, AllocIndex::NONE, AllocIndex::NONE];
+            for (child, leftover) in children[1..].iter_mut().zip(leftovers.iter()) {
+                if !leftover.is_empty() {
+                    *child = self.new_node(*leftover, NodeKind::Free, id);
+                    self.free_nodes.push(*child);
+                }
+            }
+            self.nodes[id.index()].children = children;
+
+            allocated
+        };
+
+        self.allocated_volume += volume(requested_size);
+        self.allocation_count += 1;
+
+        Some(self.allocation(allocated))
+    }
+
+    /// Deallocate a box.
+    pub fn deallocate(&mut self, id: AllocId) {
+        assert!(self.is_valid(id));
+        let mut index = id.index();
+
+        self.generations[index.index()] += Wrapping(1);
+        self.allocated_volume -= volume(self.nodes[index.index()].volume.size());
+        self.allocation_count -= 1;
+        self.nodes[index.index()].kind = NodeKind::Free;
+
+        // Merge the free boxes back into their parent.
+        loop {
+            let parent = self.nodes[index.index()].parent;
+            if parent.is_none() {
+                break;
+            }
+
+            let children = self.nodes[parent.index()].children;
+            let all_free = children
+                .iter()
+                .all(|child| child.is_none() || self.nodes[child.index()].kind == NodeKind::Free);
+            if !all_free {
+                break;
+            }
+
+            for child in children.iter().filter(|child| child.is_some()) {
+                self.nodes[child.index()].kind = NodeKind::Unused;
+                self.unused_nodes.push(*child);
+            }
+            let node = &mut self.nodes[parent.index()];
+            node.kind = NodeKind::Free;
+            node.children = [AllocIndex::NONE; 4];
+            index = parent;
+        }
+
+        self.free_nodes.push(index);
+    }
+
+    /// Returns true if the id refers to a live allocation.
+    pub fn is_valid(&self, id: AllocId) -> bool {
+        let index = id.index().index();
+        index < self.nodes.len()
+            && self.nodes[index].kind == NodeKind::Alloc
+            && id.matches_generation(self.generations[index].0)
+    }
+
+    /// Returns the box of an allocation, or `None` if the id is not valid.
+    pub fn get(&self, id: AllocId) -> Option<&Box3> {
+        if !self.is_valid(id) {
+            return None;
+        }
+
+        Some(&self.nodes[id.index().index()].volume)
+    }
+
+    /// Invoke a callback for each allocated box.
+    pub fn for_each_allocated_box<F>(&self, mut callback: F)
+    where
+        F: FnMut(AllocId, &Box3),
+    {
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Alloc {
+                callback(self.allocation(AllocIndex(index as u32)).id, &node.volume);
+            }
+        }
+    }
+
+    /// Invoke a callback for each free box.
+    pub fn for_each_free_box<F>(&self, mut callback: F)
+    where
+        F: FnMut(&Box3),
+    {
+        for node in &self.nodes {
+            if node.kind == NodeKind::Free {
+                callback(&node.volume);
+            }
+        }
+    }
+
+    /// Recompute the allocations and return the list of the changes.
+    ///
+    /// As with `AtlasAllocator::rearrange`, previous ids and boxes are not valid anymore after
+    /// this operation, and the allocations that could not be placed back are reported as
+    /// failures.
+    pub fn rearrange(&mut self) -> VolumeChangeList {
+        let mut allocs = Vec::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.kind == NodeKind::Alloc {
+                allocs.push(self.allocation(AllocIndex(index as u32)));
+            }
+        }
+        allocs.sort_by_key(|alloc| std::cmp::Reverse(volume(alloc.volume.size())));
+
+        self.clear();
+
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+        for old in allocs {
+            match self.allocate(old.volume.size()) {
+                Some(new) => changes.push(VolumeChange { old, new }),
+                None => failures.push(old),
+            }
+        }
+
+        VolumeChangeList { changes, failures }
+    }
+
+    fn new_node(&mut self, volume: Box3, kind: NodeKind, parent: AllocIndex) -> AllocIndex {
+        let node = VolumeNode {
+            volume,
+            kind,
+            parent,
+            children: [AllocIndex::NONE; 4],
+        };
+
+        if let Some(index) = self.unused_nodes.pop() {
+            self.nodes[index.index()] = node;
+            return index;
+        }
+
+        assert!((self.nodes.len() as AllocIdRepr) < IDX_MASK);
+        self.nodes.push(node);
+        if self.generations.len() < self.nodes.len() {
+            self.generations.push(Wrapping(0));
+        }
+
+        AllocIndex(self.nodes.len() as u32 - 1)
+    }
+
+    fn allocation(&self, index: AllocIndex) -> VolumeAllocation {
+        VolumeAllocation {
+            id: AllocId::new(index, self.generations[index.index()].0),
+            volume: self.nodes[index.index()].volume,
+        }
+    }
+}
+
+impl std::ops::Index<AllocId> for VolumeAllocator {
+    type Output = Box3;
+    fn index(&self, index: AllocId) -> &Box3 {
+        self.get(index).expect("Invalid allocation id")
+    }
+}
+
+fn volume(size: Size3) -> i64 {
+    size.width as i64 * size.height as i64 * size.depth as i64
+}
+
+#[test]
+fn volume_allocator() {
+    use euclid::size3;
+
+    let mut allocator = VolumeAllocator::new(size3(64, 64, 64));
+
+    for i in 0..50 {
+        allocator.allocate(size3(4 + i % 5, 8, 2 + i % 3)).unwrap();
+    }
+    assert_eq!(allocator.allocation_count(), 50);
+
+    let mut boxes = Vec::new();
+    allocator.for_each_allocated_box(|_, b| boxes.push(*b));
+    for (i, a) in boxes.iter().enumerate() {
+        assert!(Box3::from_size(allocator.size()).contains_box(a));
+        for b in &boxes[i + 1..] {
+            assert!(!a.intersects(b));
+        }
+    }
+
+    let mut total = allocator.allocated_volume();
+    allocator.for_each_free_box(|b| total += volume(b.size()));
+    assert_eq!(total, volume(allocator.size()));
+
+    // Rearranging keeps all of the allocations.
+    let changes = allocator.rearrange();
+    assert_eq!(changes.changes.len(), 50);
+    assert!(changes.failures.is_empty());
+    for change in &changes.changes {
+        assert!(!allocator.is_valid(change.old.id));
+    }
+
+    // Deallocating everything merges the boxes back into a single free box.
+    for change in &changes.changes {
+        assert_eq!(allocator[change.new.id], change.new.volume);
+        allocator.deallocate(change.new.id);
+    }
+    assert!(allocator.is_empty());
+    let full = allocator.allocate(size3(64, 64, 64)).unwrap();
+    assert_eq!(full.volume, Box3::from_size(size3(64, 64, 64)));
+    assert!(allocator.allocate(size3(1, 1, 1)).is_none());
+
+    // Clearing invalidates the ids, even though the nodes are reused.
+    allocator.clear();
+    assert!(!allocator.is_valid(full.id));
+    let a = allocator.allocate(size3(64, 64, 64)).unwrap();
+    assert_ne!(a.id, full.id);
+    assert!(!allocator.is_valid(full.id));
+}